@@ -0,0 +1,112 @@
+//!
+//! Gemがソースからダウンロードできるかを確認します
+//!
+use futures::future::join_all;
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use crate::client;
+use crate::options::InstallOptions;
+use crate::parser::{Gem, GemfileData};
+
+///
+/// Gemのダウンロードの可否
+///
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Availability {
+    /// ダウンロードできる
+    Available,
+    /// Gemは存在するが、指定されたバージョンが存在しない
+    VersionMissing,
+    /// Gemが存在しない
+    GemMissing,
+    /// 通信に失敗したため確認できなかった
+    Unreachable(String),
+}
+
+///
+/// Gemfileに含まれるすべてのGemがダウンロードできるかを確認する
+///
+/// 本体はダウンロードせず、HEADリクエストとAPIのみで確認する
+///
+/// * gemfile_data - Gemfileの読み込み済みデータ
+/// * source - 確認するソースのURL
+/// * options - インストール処理のオプション
+///
+/// return - Gemごとのダウンロードの可否
+///
+pub async fn check_availability(gemfile_data: &GemfileData, source: &str, options: &InstallOptions) -> Vec<(Gem, Availability)> {
+    let client = match client::build_client(options) {
+        Ok(client) => client,
+        Err(error) => {
+            return gemfile_data.gems.iter()
+                .map(|gem| (gem.clone(), Availability::Unreachable(error.to_string())))
+                .collect();
+        }
+    };
+
+    let tasks: Vec<_> = gemfile_data.gems.iter().map(|gem| {
+        let client = &client;
+        async move {
+            let gem_name = format!("{}-{}", gem.name, gem.version);
+
+            // .gemファイルが存在するかを確認
+            let url = format!("{}/downloads/{}.gem", source, gem_name);
+            let response = match client::head(client, &url, &gem_name, options).await {
+                Ok(response) => response,
+                Err(error) => return (gem.clone(), Availability::Unreachable(error.to_string())),
+            };
+            if response.status().is_success() {
+                return (gem.clone(), Availability::Available);
+            }
+
+            // Gem自体が存在するかをAPIで確認
+            let url = options.version_url(source, &gem.name);
+            let availability = match client::head(client, &url, &gem_name, options).await {
+                Ok(response) if response.status().is_success() => Availability::VersionMissing,
+                Ok(response) if response.status() == StatusCode::NOT_FOUND => Availability::GemMissing,
+                Ok(response) => Availability::Unreachable(format!("Unexpected status {}", response.status())),
+                Err(error) => Availability::Unreachable(error.to_string()),
+            };
+            (gem.clone(), availability)
+        }
+    }).collect();
+
+    join_all(tasks).await
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::availability::{check_availability, Availability};
+    use crate::options::InstallOptions;
+    use crate::parser::{Gem, GemfileData};
+    use crate::test_util::{MockResponse, MockServer};
+
+    ///
+    /// ダウンロードの可否の確認のテスト
+    ///
+    #[tokio::test]
+    pub async fn check_availability_test() {
+        let server = MockServer::start(|request| {
+            match request.path.as_str() {
+                "/downloads/rake-13.0.1.gem" => MockResponse::new(200, "gem"),
+                "/api/v1/gems/rake.json" => MockResponse::new(200, "{\"version\":\"13.2.1\"}"),
+                "/api/v1/gems/rspec.json" => MockResponse::new(200, "{\"version\":\"3.13.0\"}"),
+                _ => MockResponse::not_found(),
+            }
+        }).await;
+
+        let gem = |name: &str, version: &str| Gem { name: name.to_string(), version: version.to_string(), ..Default::default() };
+        let gemfile_data = GemfileData {
+            source: server.url.clone(),
+            gems: vec![gem("rake", "13.0.1"), gem("rspec", "9.9.9"), gem("missing", "1.0.0")],
+            ..Default::default()
+        };
+        let result = check_availability(&gemfile_data, &server.url, &InstallOptions { allow_insecure: true, ..Default::default() }).await;
+
+        let statuses: Vec<Availability> = result.into_iter().map(|(_, availability)| availability).collect();
+        assert_eq!(statuses, vec![Availability::Available, Availability::VersionMissing, Availability::GemMissing]);
+
+        // 本体はダウンロードされていないか
+        assert!(server.requests().iter().all(|request| request.method == "HEAD"));
+    }
+}
@@ -0,0 +1,267 @@
+//!
+//! 解凍処理で共有する、上限のあるバッファのプールを扱います
+//!
+use std::fmt::{Debug, Formatter};
+use std::io::{BufRead, Read};
+use std::ops::{Deref, DerefMut};
+use std::sync::{Condvar, Mutex};
+
+/// デフォルトの1つのバッファの大きさ
+pub const DEFAULT_BUFFER_SIZE: usize = 64 * 1024;
+
+/// 1つのバッファの最小の大きさ(読み込みと書き込みに分けて使用するため)
+const MIN_BUFFER_SIZE: usize = 2;
+
+///
+/// プールの状態
+///
+#[derive(Debug, Default)]
+struct PoolState {
+    /// 再利用できるバッファ
+    free: Vec<Vec<u8>>,
+    /// 貸し出し中のバッファの数
+    in_use: usize,
+    /// 同時に貸し出したバッファの最大の数
+    peak: usize,
+}
+
+///
+/// 合計の大きさに上限のあるバッファのプール
+///
+/// 解凍処理は終わるまでバッファを1つ借り続けるため、同時に行う解凍の数(展開器の状態を含む)もバッファの数までに抑えられる。
+/// すべてのバッファが使用中の場合は、返却されるまで待つ
+///
+pub struct BufferPool {
+    /// 1つのバッファの大きさ
+    buffer_size: usize,
+    /// 同時に貸し出せるバッファの数
+    capacity: usize,
+    /// プールの状態
+    state: Mutex<PoolState>,
+    /// バッファが返却されたことの通知
+    returned: Condvar,
+}
+
+impl BufferPool {
+    ///
+    /// プールを作成する
+    ///
+    /// * total_bytes - すべてのバッファの合計の大きさの上限。1つのバッファより小さい場合も1つは貸し出す
+    /// * buffer_size - 1つのバッファの大きさ(2バイト未満の場合は2バイト)
+    ///
+    /// return - バッファのプール
+    ///
+    pub fn new(total_bytes: usize, buffer_size: usize) -> BufferPool {
+        let buffer_size = buffer_size.max(MIN_BUFFER_SIZE);
+        BufferPool {
+            buffer_size,
+            capacity: (total_bytes / buffer_size).max(1),
+            state: Mutex::new(PoolState::default()),
+            returned: Condvar::new(),
+        }
+    }
+
+    ///
+    /// バッファを借りる。すべて使用中の場合は返却されるまで待つ
+    ///
+    /// 待機中はスレッドを止めるため、非同期のタスクからは`spawn_blocking`の中で呼び出す
+    ///
+    /// return - 破棄されるとプールに返却されるバッファ
+    ///
+    pub fn acquire(&self) -> PooledBuffer<'_> {
+        let mut state = self.state.lock().unwrap_or_else(|error| error.into_inner());
+        while state.in_use >= self.capacity {
+            state = self.returned.wait(state).unwrap_or_else(|error| error.into_inner());
+        }
+        state.in_use += 1;
+        state.peak = state.peak.max(state.in_use);
+        let buffer = state.free.pop().unwrap_or_else(|| vec![0; self.buffer_size]);
+        PooledBuffer { pool: self, buffer }
+    }
+
+    ///
+    /// 同時に貸し出したバッファの最大の数を取得する
+    ///
+    pub fn peak_in_use(&self) -> usize {
+        self.state.lock().unwrap_or_else(|error| error.into_inner()).peak
+    }
+
+    ///
+    /// バッファを返却する
+    ///
+    fn release(&self, buffer: Vec<u8>) {
+        let mut state = self.state.lock().unwrap_or_else(|error| error.into_inner());
+        state.in_use -= 1;
+        state.free.push(buffer);
+        self.returned.notify_one();
+    }
+}
+
+impl Debug for BufferPool {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BufferPool")
+            .field("buffer_size", &self.buffer_size)
+            .field("capacity", &self.capacity)
+            .finish()
+    }
+}
+
+///
+/// プールから借りたバッファ
+///
+pub struct PooledBuffer<'a> {
+    /// 返却先のプール
+    pool: &'a BufferPool,
+    /// バッファの本体
+    buffer: Vec<u8>,
+}
+
+impl Deref for PooledBuffer<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.buffer
+    }
+}
+
+impl DerefMut for PooledBuffer<'_> {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        &mut self.buffer
+    }
+}
+
+impl Drop for PooledBuffer<'_> {
+    fn drop(&mut self) {
+        self.pool.release(std::mem::take(&mut self.buffer));
+    }
+}
+
+///
+/// 指定したバッファを使用して読み込む`BufRead`
+///
+/// `BufReader`と異なり自身ではバッファを確保しないため、プールから借りたバッファで展開器の入力を読み込める
+///
+pub struct SliceReader<'a, R> {
+    /// 読み込み元
+    inner: R,
+    /// 読み込みに使用するバッファ
+    buffer: &'a mut [u8],
+    /// バッファ内の次に返す位置
+    position: usize,
+    /// バッファ内の読み込み済みの長さ
+    filled: usize,
+}
+
+impl<'a, R: Read> SliceReader<'a, R> {
+    ///
+    /// 読み込み元とバッファを指定して作成する
+    ///
+    /// * inner - 読み込み元
+    /// * buffer - 読み込みに使用するバッファ
+    ///
+    /// return - バッファを使用して読み込む`BufRead`
+    ///
+    pub fn new(inner: R, buffer: &'a mut [u8]) -> SliceReader<'a, R> {
+        SliceReader { inner, buffer, position: 0, filled: 0 }
+    }
+}
+
+impl<R: Read> Read for SliceReader<'_, R> {
+    fn read(&mut self, output: &mut [u8]) -> std::io::Result<usize> {
+        // バッファが空で、要求がバッファ以上の大きさの場合は直接読み込む
+        if self.position >= self.filled && output.len() >= self.buffer.len() {
+            return self.inner.read(output);
+        }
+        let available = self.fill_buf()?;
+        let length = available.len().min(output.len());
+        output[..length].copy_from_slice(&available[..length]);
+        self.consume(length);
+        Ok(length)
+    }
+}
+
+impl<R: Read> BufRead for SliceReader<'_, R> {
+    fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
+        if self.position >= self.filled {
+            self.filled = self.inner.read(self.buffer)?;
+            self.position = 0;
+        }
+        Ok(&self.buffer[self.position..self.filled])
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.position = (self.position + amount).min(self.filled);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, Read};
+    use std::sync::Arc;
+    use crate::buffer_pool::{BufferPool, SliceReader};
+    use crate::install_gems_with_options;
+    use crate::options::InstallOptions;
+    use crate::parser::{Gem, GemfileData};
+    use crate::test_util::{test_directory, GemBuilder, MockResponse, MockServer};
+
+    ///
+    /// 小さなプールを共有して並列にインストールするテスト
+    ///
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    pub async fn shared_buffer_pool_test() {
+        let directory = test_directory("shared_buffer_pool");
+        // バッファより大きな内容を複数回に分けて解凍する
+        let contents: Vec<Vec<u8>> = (0..8).map(|index| (0..64 * 1024).map(|byte| (byte * (index + 1)) as u8).collect()).collect();
+        let gems: Vec<Vec<u8>> = contents.iter().enumerate()
+            .map(|(index, content)| GemBuilder::new(&format!("pooled{}", index), "1.0.0").file("lib/data.bin", content).build())
+            .collect();
+        let server = MockServer::start(move |request| {
+            (0..gems.len())
+                .find(|index| request.path == format!("/downloads/pooled{}-1.0.0.gem", index))
+                .map(|index| MockResponse::new(200, gems[index].clone()))
+                .unwrap_or_else(MockResponse::not_found)
+        }).await;
+        let gemfile_data = GemfileData {
+            source: server.url.clone(),
+            gems: (0..contents.len())
+                .map(|index| Gem { name: format!("pooled{}", index), version: "1.0.0".to_string(), ..Default::default() })
+                .collect(),
+            ..Default::default()
+        };
+
+        // バッファを1つだけ貸し出すプール
+        let pool = Arc::new(BufferPool::new(1024, 1024));
+        let options = InstallOptions { allow_insecure: true, verify_checksums: false, buffer_pool: Some(Arc::clone(&pool)), ..Default::default() };
+        let info = install_gems_with_options(gemfile_data, &directory.join("gems"), &directory.join("cache"), &options).await.unwrap();
+
+        // すべて正しく解凍され、バッファは同時に1つしか使用されないか
+        assert_eq!(info.installed.len(), contents.len());
+        for (index, content) in contents.iter().enumerate() {
+            let path = directory.join(format!("gems/pooled{}-1.0.0/lib/data.bin", index));
+            assert_eq!(&std::fs::read(path).unwrap(), content);
+        }
+        assert_eq!(pool.peak_in_use(), 1);
+    }
+
+    ///
+    /// 指定したバッファで読み込むテスト
+    ///
+    #[test]
+    pub fn slice_reader_test() {
+        let content: Vec<u8> = (0..100).collect();
+        let mut buffer = [0; 8];
+        let mut reader = SliceReader::new(content.as_slice(), &mut buffer);
+
+        // バッファの大きさずつ読み込まれるか
+        assert_eq!(reader.fill_buf().unwrap(), &content[..8]);
+        reader.consume(3);
+        let mut output = [0; 4];
+        assert_eq!(reader.read(&mut output).unwrap(), 4);
+        assert_eq!(output, [3, 4, 5, 6]);
+
+        // 残りをすべて読み込めるか
+        let mut rest = Vec::new();
+        reader.read_to_end(&mut rest).unwrap();
+        assert_eq!(rest, content[7..]);
+    }
+}
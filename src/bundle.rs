@@ -0,0 +1,149 @@
+//!
+//! オフラインでインストールするためのGemのバンドルを作成します
+//!
+use std::error::Error;
+use std::fs::{copy, create_dir_all};
+use std::path::{Path, PathBuf};
+use crate::cache::find_content_addressed;
+use crate::layout::CACHE_DIRECTORY;
+use crate::InstallInfo;
+
+///
+/// インストールしたGemの.gemファイルを1つのディレクトリにまとめる
+///
+/// 作成したディレクトリは`install_from_cache_dir`でオフラインのインストールに使用できる
+///
+/// * info - インストール処理の結果
+/// * cache_directory - インストール時に使用したキャッシュディレクトリ
+/// * dest - .gemファイルをまとめるディレクトリ
+///
+/// return - 処理の結果
+///
+pub fn export_bundle(info: &InstallInfo, cache_directory: &Path, dest: &Path) -> Result<(), Box<dyn Error>> {
+    if !dest.exists() {
+        create_dir_all(dest)?;
+    }
+
+    for gem in &info.installed {
+        let file_name = format!("{}-{}.gem", gem.name, gem.version);
+        let mut gem_path = cache_directory.join(&file_name);
+        // 内容で管理する構成のキャッシュは索引から探す
+        if !gem_path.exists() {
+            let Some(path) = find_content_addressed(cache_directory, &format!("{}-{}", gem.name, gem.version)) else {
+                return Err(format!("{} not found in cache", file_name).into());
+            };
+            gem_path = path;
+        }
+        copy(&gem_path, dest.join(&file_name))?;
+    }
+
+    Ok(())
+}
+
+///
+/// ダウンロードした.gemファイルをインストール先の`cache/`にコピーする
+///
+/// * install_dictionary - Gemのインストール先のディレクトリ
+/// * gem_path - ダウンロードした.gemファイルのパス
+/// * name - Gemの名前
+/// * version - Gemのバージョン
+///
+/// return - コピー後のファイルのパス
+///
+pub fn vendor_gem(install_dictionary: &Path, gem_path: &Path, name: &str, version: &str) -> Result<PathBuf, Box<dyn Error>> {
+    let file_name = format!("{}-{}.gem", name, version);
+    let vendor_directory = install_dictionary.join(CACHE_DIRECTORY);
+    if !vendor_directory.exists() {
+        create_dir_all(&vendor_directory)?;
+    }
+
+    let path = vendor_directory.join(file_name);
+    copy(gem_path, &path)?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bundle::export_bundle;
+    use crate::layout::Layout;
+    use crate::options::InstallOptions;
+    use crate::parser::{Gem, GemfileData};
+    use crate::test_util::{test_directory, GemBuilder, MockResponse, MockServer};
+    use crate::{install_from_cache_dir, install_gems_with_options};
+
+    ///
+    /// バンドルの作成とオフラインのインストールのテスト
+    ///
+    #[tokio::test]
+    pub async fn export_bundle_test() {
+        let directory = test_directory("export_bundle");
+        let gems = [
+            ("rake", GemBuilder::new("rake", "13.0.1").build()),
+            ("net-http", GemBuilder::new("net-http", "0.4.1").build()),
+        ];
+        let server = MockServer::start(move |request| {
+            gems.iter()
+                .find(|(name, _)| request.path.starts_with(&format!("/downloads/{}-", name)))
+                .map(|(_, body)| MockResponse::new(200, body.clone()))
+                .unwrap_or_else(MockResponse::not_found)
+        }).await;
+
+        // インストール
+        let gemfile_data = GemfileData {
+            source: server.url.clone(),
+            gems: vec![
+                Gem { name: "rake".to_string(), version: "13.0.1".to_string(), ..Default::default() },
+                Gem { name: "net-http".to_string(), version: "0.4.1".to_string(), ..Default::default() },
+            ],
+            ..Default::default()
+        };
+        let options = InstallOptions { allow_insecure: true, verify_checksums: false, ..Default::default() };
+        let cache_directory = directory.join("cache");
+        let info = install_gems_with_options(gemfile_data, &directory.join("gems"), &cache_directory, &options).await.unwrap();
+
+        // バンドルを作成
+        let bundle_directory = directory.join("bundle");
+        export_bundle(&info, &cache_directory, &bundle_directory).unwrap();
+        assert!(bundle_directory.join("rake-13.0.1.gem").exists());
+        assert!(bundle_directory.join("net-http-0.4.1.gem").exists());
+
+        // バンドルからオフラインでインストール
+        let requests = server.requests().len();
+        let offline_directory = directory.join("offline");
+        let offline_info = install_from_cache_dir(&bundle_directory, &offline_directory, &directory.join("offline_cache")).await.unwrap();
+        let mut installed = offline_info.install_gems.clone();
+        installed.sort();
+        assert_eq!(installed, vec!["net-http-0.4.1".to_string(), "rake-13.0.1".to_string()]);
+        assert!(offline_directory.join("rake-13.0.1/lib/rake.rb").exists());
+        assert_eq!(server.requests().len(), requests);
+    }
+
+    ///
+    /// .gemファイルをインストール先に保存するテスト
+    ///
+    #[tokio::test]
+    pub async fn vendor_gems_test() {
+        let directory = test_directory("vendor_gems");
+        let body = GemBuilder::new("vendored", "1.0.0").build();
+        let expected = body.clone();
+        let server = MockServer::start(move |_| MockResponse::new(200, body.clone())).await;
+        let gemfile_data = GemfileData {
+            source: server.url.clone(),
+            gems: vec![Gem { name: "vendored".to_string(), version: "1.0.0".to_string(), ..Default::default() }],
+            ..Default::default()
+        };
+
+        // 指定しない場合は保存されないか
+        let install_directory = directory.join("plain");
+        let options = InstallOptions { allow_insecure: true, verify_checksums: false, ..Default::default() };
+        install_gems_with_options(gemfile_data.clone(), &install_directory, &directory.join("cache"), &options).await.unwrap();
+        assert!(!install_directory.join("cache").exists());
+
+        // 指定した場合は本体と共に保存されるか
+        let install_directory = directory.join("vendor");
+        let options = InstallOptions { allow_insecure: true, verify_checksums: false, vendor_gems: true, layout: Layout::Nested, ..Default::default() };
+        install_gems_with_options(gemfile_data, &install_directory, &directory.join("cache"), &options).await.unwrap();
+        assert!(install_directory.join("vendored/1.0.0/lib/vendored.rb").exists());
+        assert_eq!(std::fs::read(install_directory.join("cache/vendored-1.0.0.gem")).unwrap(), expected);
+    }
+}
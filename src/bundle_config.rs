@@ -0,0 +1,232 @@
+//!
+//! Bundlerの設定ファイル(`.bundle/config`)を扱います
+//!
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use tokio::fs::{create_dir_all, read_to_string, write};
+use crate::options::InstallOptions;
+
+/// インストールしないグループを指定するキー
+pub const BUNDLE_WITHOUT: &str = "BUNDLE_WITHOUT";
+
+/// インストール先を指定するキー
+pub const BUNDLE_PATH: &str = "BUNDLE_PATH";
+
+/// RubyGems.orgのミラーを指定するキー
+pub const BUNDLE_MIRROR_RUBYGEMS: &str = "BUNDLE_MIRROR__HTTPS://RUBYGEMS__ORG/";
+
+/// 設定ファイルを置くディレクトリ
+const BUNDLE_CONFIG_DIRECTORY: &str = ".bundle";
+
+/// 設定ファイルの名前
+const BUNDLE_CONFIG_FILE: &str = "config";
+
+/// ミラーを設定しないデフォルトのソース
+const DEFAULT_SOURCE: &str = "https://rubygems.org";
+
+///
+/// `.bundle/config`の内容
+///
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BundleConfig {
+    /// キーと値の一覧
+    pub values: BTreeMap<String, String>,
+}
+
+impl BundleConfig {
+    ///
+    /// `.bundle/config`の文字列を読み込む
+    ///
+    /// * text - 設定ファイルの内容
+    ///
+    /// return - 読み込んだ設定
+    ///
+    pub fn parse(text: &str) -> BundleConfig {
+        let values = text.lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && *line != "---" && !line.starts_with('#'))
+            // ミラーのキーにはURLの`:`が含まれるため、`: `を優先して区切る
+            .filter_map(|line| line.split_once(": ").or_else(|| line.split_once(':')))
+            .map(|(key, value)| {
+                let value = value.trim();
+                let value = value.strip_prefix('"').and_then(|value| value.strip_suffix('"'))
+                    .or_else(|| value.strip_prefix('\'').and_then(|value| value.strip_suffix('\'')))
+                    .unwrap_or(value);
+                (key.trim().to_string(), value.to_string())
+            })
+            .collect();
+        BundleConfig { values }
+    }
+
+    ///
+    /// `.bundle/config`を読み込む
+    ///
+    /// * path - 設定ファイルのパス
+    ///
+    /// return - 成功すると読み込んだ設定を返す
+    ///
+    pub async fn load(path: &Path) -> Result<BundleConfig, Box<dyn Error>> {
+        Ok(BundleConfig::parse(&read_to_string(path).await?))
+    }
+
+    ///
+    /// `.bundle/config`の形式の文字列に変換する
+    ///
+    pub fn to_config_string(&self) -> String {
+        let mut text = "---\n".to_string();
+        for (key, value) in &self.values {
+            text.push_str(&format!("{}: \"{}\"\n", key, value));
+        }
+        text
+    }
+
+    ///
+    /// 値を取得する
+    ///
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.values.get(key).map(String::as_str)
+    }
+
+    ///
+    /// `BUNDLE_WITHOUT`に指定されたグループの一覧を取得する
+    ///
+    /// グループは`:`または空白で区切られる(例: `development:test`)
+    ///
+    pub fn without_groups(&self) -> Vec<String> {
+        self.get(BUNDLE_WITHOUT)
+            .map(|value| value.split([':', ' '])
+                .map(|group| group.trim().to_string())
+                .filter(|group| !group.is_empty())
+                .collect())
+            .unwrap_or_default()
+    }
+
+    ///
+    /// 設定をインストール処理のオプションに反映する
+    ///
+    /// * options - 反映先のオプション
+    ///
+    pub fn apply(&self, options: &mut InstallOptions) {
+        for group in self.without_groups() {
+            if !options.without_groups.contains(&group) {
+                options.without_groups.push(group);
+            }
+        }
+    }
+}
+
+///
+/// インストールに使用した設定を`.bundle/config`に書き出す
+///
+/// 後から`bundle`を実行した際に同じインストール先・ミラー・除外グループが使用されるようにする
+///
+/// * options - インストールに使用したオプション
+/// * dest - `.bundle`を作成するディレクトリ(通常はGemfileのあるディレクトリ)
+/// * install_path - Gemのインストール先のディレクトリ
+/// * source - Gemを取得したソース。RubyGems.org以外の場合はミラーとして設定する
+///
+/// return - 書き出した設定ファイルのパス
+///
+pub async fn write_bundle_config(options: &InstallOptions, dest: &Path, install_path: &Path, source: &str) -> Result<PathBuf, Box<dyn Error>> {
+    let mut config = BundleConfig::default();
+    config.values.insert(BUNDLE_PATH.to_string(), install_path.display().to_string());
+    if !options.without_groups.is_empty() {
+        config.values.insert(BUNDLE_WITHOUT.to_string(), options.without_groups.join(":"));
+    }
+    let source = source.trim_end_matches('/');
+    if source != DEFAULT_SOURCE {
+        config.values.insert(BUNDLE_MIRROR_RUBYGEMS.to_string(), format!("{}/", source));
+    }
+
+    let directory = dest.join(BUNDLE_CONFIG_DIRECTORY);
+    create_dir_all(&directory).await?;
+    let path = directory.join(BUNDLE_CONFIG_FILE);
+    write(&path, config.to_config_string()).await?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::write;
+    use std::path::Path;
+    use crate::bundle_config::{write_bundle_config, BundleConfig, BUNDLE_MIRROR_RUBYGEMS};
+    use crate::install_gems_with_options;
+    use crate::options::InstallOptions;
+    use crate::parser::GemfileData;
+    use crate::test_util::{test_directory, GemBuilder, MockResponse, MockServer};
+
+    ///
+    /// BUNDLE_WITHOUTに指定されたグループを除いてインストールするかのテスト
+    ///
+    #[tokio::test]
+    pub async fn bundle_without_test() {
+        let directory = test_directory("bundle_without");
+        let config_path = directory.join("config");
+        write(&config_path, "---\nBUNDLE_PATH: \"vendor/bundle\"\nBUNDLE_WITHOUT: \"development:test\"\n").unwrap();
+
+        let names = ["rails", "rspec", "pry", "puma"];
+        let gems: Vec<Vec<u8>> = names.iter().map(|name| GemBuilder::new(name, "1.0.0").build()).collect();
+        let server = MockServer::start(move |request| {
+            names.iter().zip(gems.iter())
+                .find(|(name, _)| request.path == format!("/downloads/{}-1.0.0.gem", name))
+                .map(|(_, gem)| MockResponse::new(200, gem.clone()))
+                .unwrap_or_else(MockResponse::not_found)
+        }).await;
+        let gemfile = format!(r#"source "{}"
+gem "rails", "1.0.0"
+group :test do
+  gem "rspec", "1.0.0"
+end
+group :development, :test do
+  gem "pry", "1.0.0"
+end
+group :development, :production do
+  gem "puma", "1.0.0"
+end
+"#, server.url);
+        let gemfile_data = GemfileData::parse(&gemfile).await.unwrap();
+
+        // 設定を読み込んで反映
+        let config = BundleConfig::load(&config_path).await.unwrap();
+        assert_eq!(config.get("BUNDLE_PATH"), Some("vendor/bundle"));
+        let mut options = InstallOptions { allow_insecure: true, verify_checksums: false, ..Default::default() };
+        config.apply(&mut options);
+        assert_eq!(options.without_groups, vec!["development".to_string(), "test".to_string()]);
+
+        // 除外されたグループのみに属するGemはインストールされないか
+        let info = install_gems_with_options(gemfile_data, &directory.join("gems"), &directory.join("cache"), &options).await.unwrap();
+        let mut installed = info.install_gems.clone();
+        installed.sort();
+        assert_eq!(installed, vec!["puma-1.0.0".to_string(), "rails-1.0.0".to_string()]);
+        assert_eq!(server.request_count("/downloads/rspec-1.0.0.gem"), 0);
+        assert_eq!(server.request_count("/downloads/pry-1.0.0.gem"), 0);
+    }
+
+    ///
+    /// `.bundle/config`の書き出しのテスト
+    ///
+    #[tokio::test]
+    pub async fn write_bundle_config_test() {
+        let directory = test_directory("write_bundle_config");
+        let options = InstallOptions {
+            without_groups: vec!["development".to_string(), "test".to_string()],
+            ..Default::default()
+        };
+        let path = write_bundle_config(&options, &directory, Path::new("vendor/bundle"), "https://mirror.example.com").await.unwrap();
+        assert_eq!(path, directory.join(".bundle/config"));
+
+        // 書き出した設定を読み込めるか
+        let config = BundleConfig::load(&path).await.unwrap();
+        assert_eq!(config.get("BUNDLE_PATH"), Some("vendor/bundle"));
+        assert_eq!(config.get("BUNDLE_WITHOUT"), Some("development:test"));
+        assert_eq!(config.get(BUNDLE_MIRROR_RUBYGEMS), Some("https://mirror.example.com/"));
+        assert_eq!(config.without_groups(), options.without_groups);
+
+        // RubyGems.orgの場合はミラーを設定しないか
+        let path = write_bundle_config(&InstallOptions::default(), &directory, Path::new("vendor/bundle"), "https://rubygems.org/").await.unwrap();
+        let config = BundleConfig::load(&path).await.unwrap();
+        assert_eq!(config.get(BUNDLE_MIRROR_RUBYGEMS), None);
+        assert_eq!(config.get("BUNDLE_WITHOUT"), None);
+    }
+}
@@ -0,0 +1,223 @@
+//!
+//! キャッシュディレクトリの管理
+//!
+use std::error::Error;
+use std::fs::{create_dir_all, read_dir, read_to_string, write};
+use std::path::{Path, PathBuf};
+use crate::cleanup::CleanupGuard;
+use crate::download::{file_sha256, split_gem_file_name};
+use crate::options::InstallOptions;
+use crate::parser::GemfileData;
+use crate::resolve_and_download;
+
+/// 内容で管理する構成で.gemファイルの本体を置くディレクトリ
+pub const OBJECTS_DIRECTORY: &str = "sha256";
+
+/// 内容で管理する構成で`{name}-{version}`からSHA256を引く索引のディレクトリ
+pub const INDEX_DIRECTORY: &str = "index";
+
+/// キャッシュされたGemの名前、バージョン、ファイルサイズ
+pub type CachedGem = (String, String, u64);
+
+///
+/// キャッシュディレクトリでの.gemファイルの構成
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CacheLayout {
+    /// `{name}-{version}.gem`に保存する
+    #[default]
+    NameVersion,
+    /// 本体を`sha256/{SHA256}.gem`に保存し、`index/{name}-{version}`にSHA256を記録する。同じ内容は1回だけ保存される
+    ContentAddressed,
+}
+
+///
+/// 内容で管理する構成のキャッシュから.gemファイルを探す
+///
+/// * cache_directory - Gemのダウンロード先のキャッシュディレクトリ
+/// * key - `{name}-{version}`の形式のGemの名前
+///
+/// return - 索引に記録されていて本体が存在する場合はそのパス
+///
+pub fn find_content_addressed(cache_directory: &Path, key: &str) -> Option<PathBuf> {
+    let sha256 = read_to_string(cache_directory.join(INDEX_DIRECTORY).join(key)).ok()?;
+    let path = object_path(cache_directory, sha256.trim());
+    path.is_file().then_some(path)
+}
+
+///
+/// ダウンロードが完了したファイルを内容で管理する構成で保存する
+///
+/// 同じ内容が保存済みの場合は新しいファイルを削除し、索引のみを更新する
+///
+/// * part - ダウンロードが完了した一時ファイル
+/// * cache_directory - Gemのダウンロード先のキャッシュディレクトリ
+/// * key - `{name}-{version}`の形式のGemの名前
+///
+/// return - 保存した本体のパス
+///
+pub(crate) fn persist_content_addressed(part: CleanupGuard, cache_directory: &Path, key: &str) -> Result<PathBuf, Box<dyn Error>> {
+    let sha256 = file_sha256(part.path())?;
+    let path = object_path(cache_directory, &sha256);
+    create_dir_all(cache_directory.join(OBJECTS_DIRECTORY))?;
+    if path.is_file() {
+        drop(part);
+    } else {
+        part.persist(&path)?;
+    }
+
+    let index_directory = cache_directory.join(INDEX_DIRECTORY);
+    create_dir_all(&index_directory)?;
+    write(index_directory.join(key), &sha256)?;
+    Ok(path)
+}
+
+///
+/// SHA256から本体のパスを作成する
+///
+fn object_path(cache_directory: &Path, sha256: &str) -> PathBuf {
+    cache_directory.join(OBJECTS_DIRECTORY).join(format!("{}.gem", sha256))
+}
+
+///
+/// キャッシュディレクトリにある.gemファイルの一覧を取得する
+///
+/// * cache_directory - Gemのダウンロード先のキャッシュディレクトリ
+///
+/// return - Gemの名前、バージョン、ファイルサイズの一覧(名前とバージョン順)
+///
+pub fn list_cached(cache_directory: &Path) -> Result<Vec<CachedGem>, Box<dyn Error>> {
+    let mut cached = Vec::new();
+    if !cache_directory.exists() {
+        return Ok(cached);
+    }
+
+    for entry in read_dir(cache_directory)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if !metadata.is_file() {
+            continue;
+        }
+        let file_name = entry.file_name().to_string_lossy().to_string();
+        if !file_name.ends_with(".gem") {
+            continue;
+        }
+        let Some((name, version)) = split_gem_file_name(&file_name) else {
+            continue;
+        };
+        cached.push((name, version, metadata.len()));
+    }
+    cached.sort();
+
+    Ok(cached)
+}
+
+///
+/// Gemfileのすべての.gemファイルを、解凍せずにキャッシュにダウンロードする
+///
+/// CIのキャッシュを作成する段階で使用し、後のインストールをネットワークを使用せずに行えるようにする
+///
+/// * gemfile_data - Gemfileの読み込み済みデータ
+/// * source - ダウンロード元のURL(`source ... do`のブロックで指定されたGemはそのソースを使用する)
+/// * cache_directory - ダウンロード先のキャッシュディレクトリ
+/// * options - インストール処理のオプション
+///
+/// return - キャッシュにある.gemファイルのパスの一覧
+///
+pub async fn warm_cache(mut gemfile_data: GemfileData, source: &str, cache_directory: &Path, options: &InstallOptions) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    gemfile_data.source = source.trim_end_matches('/').to_string();
+    let (_, downloaded) = resolve_and_download(gemfile_data, cache_directory, options).await?;
+    Ok(downloaded.into_iter().map(|gem| gem.path).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{create_dir_all, read_dir, write};
+    use crate::cache::{find_content_addressed, list_cached, warm_cache, CacheLayout, INDEX_DIRECTORY, OBJECTS_DIRECTORY};
+    use crate::download::download_gem_with_options;
+    use crate::options::InstallOptions;
+    use crate::parser::{Gem, GemfileData};
+    use crate::test_util::{test_directory, GemBuilder, MockResponse, MockServer};
+
+    ///
+    /// キャッシュされたGemの一覧のテスト
+    ///
+    #[test]
+    pub fn list_cached_test() {
+        let directory = test_directory("list_cached");
+        write(directory.join("rake-13.0.1.gem"), vec![0; 128]).unwrap();
+        write(directory.join("net-http-0.4.1.gem"), vec![0; 64]).unwrap();
+        // .gemファイル以外と解凍用のディレクトリは含まない
+        write(directory.join(".gemfile_downloader.lock"), "").unwrap();
+        create_dir_all(directory.join("rake-13.0.1")).unwrap();
+
+        let cached = list_cached(&directory).unwrap();
+        assert_eq!(cached, vec![
+            ("net-http".to_string(), "0.4.1".to_string(), 64),
+            ("rake".to_string(), "13.0.1".to_string(), 128),
+        ]);
+        assert!(list_cached(&directory.join("missing")).unwrap().is_empty());
+    }
+
+    ///
+    /// 内容で管理する構成のキャッシュのテスト
+    ///
+    #[tokio::test]
+    pub async fn content_addressed_test() {
+        let directory = test_directory("content_addressed");
+        // 別の名前で同じ内容を返す
+        let body = GemBuilder::new("shared", "1.0.0").build();
+        let server = MockServer::start(move |request| {
+            match request.path.as_str() {
+                "/downloads/shared-1.0.0.gem" | "/downloads/alias-2.0.0.gem" => MockResponse::new(200, body.clone()),
+                _ => MockResponse::not_found(),
+            }
+        }).await;
+        let options = InstallOptions { allow_insecure: true, verify_checksums: false, cache_layout: CacheLayout::ContentAddressed, ..Default::default() };
+
+        let shared = Gem { name: "shared".to_string(), version: "1.0.0".to_string(), ..Default::default() };
+        let alias = Gem { name: "alias".to_string(), version: "2.0.0".to_string(), ..Default::default() };
+        let shared_path = download_gem_with_options(&directory, &server.url, &shared, &options).await.unwrap();
+        let alias_path = download_gem_with_options(&directory, &server.url, &alias, &options).await.unwrap();
+
+        // 本体は1回だけ保存され、索引は名前ごとに作成されるか
+        assert_eq!(shared_path, alias_path);
+        assert_eq!(read_dir(directory.join(OBJECTS_DIRECTORY)).unwrap().count(), 1);
+        assert_eq!(read_dir(directory.join(INDEX_DIRECTORY)).unwrap().count(), 2);
+        assert_eq!(find_content_addressed(&directory, "alias-2.0.0"), Some(alias_path));
+        assert!(!directory.join("shared-1.0.0.gem").exists());
+
+        // 索引にある場合はダウンロードしないか
+        download_gem_with_options(&directory, &server.url, &shared, &options).await.unwrap();
+        assert_eq!(server.requests().len(), 2);
+    }
+
+    ///
+    /// 解凍せずにキャッシュへダウンロードするテスト
+    ///
+    #[tokio::test]
+    pub async fn warm_cache_test() {
+        let directory = test_directory("cache_warm_cache");
+        let names = ["alpha", "beta", "gamma"];
+        let gems: Vec<Vec<u8>> = names.iter().map(|name| GemBuilder::new(name, "1.0.0").build()).collect();
+        let server = MockServer::start(move |request| {
+            names.iter().zip(gems.iter())
+                .find(|(name, _)| request.path == format!("/downloads/{}-1.0.0.gem", name))
+                .map(|(_, gem)| MockResponse::new(200, gem.clone()))
+                .unwrap_or_else(MockResponse::not_found)
+        }).await;
+        // Gemfileのソースではなく指定したソースから取得する
+        let gemfile_data = GemfileData::parse_unresolved("source 'https://rubygems.org'\ngem 'alpha', '1.0.0'\ngem 'beta', '1.0.0'\ngem 'gamma', '1.0.0'\n").unwrap();
+        let options = InstallOptions { allow_insecure: true, verify_checksums: false, ..Default::default() };
+        let cache_directory = directory.join("cache");
+
+        let paths = warm_cache(gemfile_data, &server.url, &cache_directory, &options).await.unwrap();
+
+        // すべての.gemファイルがキャッシュにあり、解凍されていないか
+        assert_eq!(paths, names.iter().map(|name| cache_directory.join(format!("{}-1.0.0.gem", name))).collect::<Vec<_>>());
+        assert!(paths.iter().all(|path| path.is_file()));
+        let entries: Vec<String> = read_dir(&cache_directory).unwrap().map(|entry| entry.unwrap().file_name().to_string_lossy().to_string()).collect();
+        assert!(entries.iter().all(|entry| entry.ends_with(".gem")));
+        assert_eq!(list_cached(&cache_directory).unwrap().len(), 3);
+    }
+}
@@ -0,0 +1,88 @@
+//!
+//! キャッシュディレクトリの排他制御を行います
+//!
+use std::error::Error;
+use std::fs::{create_dir_all, File, OpenOptions, TryLockError};
+use std::path::Path;
+use std::time::{Duration, Instant};
+use tokio::time::sleep;
+use crate::error::GemfileError;
+
+/// キャッシュディレクトリに作成するロックファイルの名前
+pub const CACHE_LOCK_FILE: &str = ".gemfile_downloader.lock";
+
+/// ロックの取得を再試行する間隔
+const RETRY_INTERVAL: Duration = Duration::from_millis(20);
+
+///
+/// キャッシュディレクトリのロック
+///
+/// ドロップされるとロックが解放される
+///
+#[derive(Debug)]
+pub struct CacheLock {
+    /// ロックを保持しているファイル
+    _file: File,
+}
+
+///
+/// キャッシュディレクトリのロックを取得する
+///
+/// 他のプロセスがロックを保持している場合は、タイムアウトまで待機する
+///
+/// * cache_directory - キャッシュディレクトリ
+/// * timeout - ロックの取得を待つ最大の時間
+///
+/// return - 取得したロック
+///
+pub async fn acquire(cache_directory: &Path, timeout: Duration) -> Result<CacheLock, Box<dyn Error>> {
+    if !cache_directory.exists() {
+        create_dir_all(cache_directory)?;
+    }
+    let path = cache_directory.join(CACHE_LOCK_FILE);
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&path)?;
+
+    let started = Instant::now();
+    loop {
+        match file.try_lock() {
+            Ok(()) => return Ok(CacheLock { _file: file }),
+            Err(TryLockError::WouldBlock) => {
+                // タイムアウトした場合はエラー
+                if started.elapsed() >= timeout {
+                    return Err(GemfileError::LockTimeout { path }.into());
+                }
+                sleep(RETRY_INTERVAL).await;
+            }
+            Err(TryLockError::Error(error)) => return Err(error.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use crate::cache_lock::acquire;
+    use crate::error::GemfileError;
+    use crate::test_util::test_directory;
+
+    ///
+    /// ロックのタイムアウトのテスト
+    ///
+    #[tokio::test]
+    pub async fn lock_timeout_test() {
+        let directory = test_directory("cache_lock_timeout");
+        let lock = acquire(&directory, Duration::from_millis(100)).await.unwrap();
+
+        // ロック中は取得できないか
+        let error = acquire(&directory, Duration::from_millis(100)).await.unwrap_err();
+        assert!(matches!(error.downcast_ref::<GemfileError>(), Some(GemfileError::LockTimeout { .. })));
+
+        // 解放後は取得できるか
+        drop(lock);
+        assert!(acquire(&directory, Duration::from_millis(100)).await.is_ok());
+    }
+}
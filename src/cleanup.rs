@@ -0,0 +1,102 @@
+//!
+//! 処理の途中で中断された一時ファイルを削除します
+//!
+use std::error::Error;
+use std::fs::{create_dir_all, remove_dir_all, remove_file, rename, symlink_metadata};
+use std::path::{Path, PathBuf};
+
+/// ダウンロード中のファイルに付ける拡張子
+pub(crate) const PART_EXTENSION: &str = "part";
+
+///
+/// 破棄されるときに、完了していないファイルやディレクトリを削除するガード
+///
+/// Futureがキャンセルされた場合やエラーで処理を抜けた場合も、途中まで書き込んだ内容が残らないようにする
+///
+#[derive(Debug)]
+pub(crate) struct CleanupGuard {
+    /// 削除する対象のパス
+    path: PathBuf,
+    /// 破棄されるときに削除するか
+    armed: bool,
+}
+
+impl CleanupGuard {
+    ///
+    /// ガードを作成する
+    ///
+    /// * path - 処理が完了しなかった場合に削除するパス
+    ///
+    pub(crate) fn new(path: PathBuf) -> CleanupGuard {
+        CleanupGuard { path, armed: true }
+    }
+
+    ///
+    /// 対象のパスを取得する
+    ///
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    ///
+    /// 処理が完了したため、削除せずに残す
+    ///
+    pub(crate) fn keep(mut self) {
+        self.armed = false;
+    }
+
+    ///
+    /// 完了したファイルを最終的なパスに移動する
+    ///
+    /// * destination - 移動先のパス
+    ///
+    pub(crate) fn persist(mut self, destination: &Path) -> Result<(), Box<dyn Error>> {
+        rename(&self.path, destination)?;
+        self.armed = false;
+        Ok(())
+    }
+}
+
+impl Drop for CleanupGuard {
+    fn drop(&mut self) {
+        if !self.armed {
+            return;
+        }
+        let _ = remove_path(&self.path);
+    }
+}
+
+///
+/// ディレクトリを空の状態で作り直す
+///
+/// シンボリックリンクの場合はリンクのみを削除し、リンク先の内容は削除しない。
+/// 親のディレクトリがシンボリックリンクの場合は、リンク先に作成される
+///
+/// * directory - 作り直すディレクトリ
+///
+/// return - 処理の結果
+///
+pub(crate) fn recreate_directory(directory: &Path) -> Result<(), Box<dyn Error>> {
+    remove_path(directory)?;
+    create_dir_all(directory)?;
+    Ok(())
+}
+
+///
+/// ファイルやディレクトリを削除する。シンボリックリンクの場合はリンクのみを削除する
+///
+fn remove_path(path: &Path) -> Result<(), Box<dyn Error>> {
+    // リンク先をたどらずに種類を確認する(リンク切れのシンボリックリンクも対象にする)
+    let Ok(metadata) = symlink_metadata(path) else {
+        return Ok(());
+    };
+    if metadata.is_dir() {
+        remove_dir_all(path)?;
+    } else if metadata.is_symlink() && cfg!(windows) && path.is_dir() {
+        // Windowsのディレクトリへのシンボリックリンクはディレクトリとして削除する
+        std::fs::remove_dir(path)?;
+    } else {
+        remove_file(path)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,263 @@
+//!
+//! HTTPクライアントの作成とリクエスト処理
+//!
+use std::error::Error;
+use reqwest::header::LOCATION;
+use reqwest::redirect::Policy;
+use reqwest::{Client, ClientBuilder, Method, Response, StatusCode, Url};
+use crate::credentials::apply_credential;
+use crate::error::GemfileError;
+use crate::options::{InstallOptions, RetrySettings};
+
+///
+/// オプションに従ってHTTPクライアントを作成する
+///
+/// リダイレクトは`get`で処理するため、クライアントでは自動でたどらない。
+/// また、`Content-Encoding`の解凍は機能フラグによって挙動が変わるため無効にし、呼び出し側で処理する。
+/// `request_timeout`は接続と、レスポンスの各受信の待ち時間の上限として設定する
+///
+/// * options - インストール処理のオプション(`client`が設定されている場合はそれを共有する)
+///
+/// return - 作成したクライアント
+///
+pub(crate) fn build_client(options: &InstallOptions) -> Result<Client, Box<dyn Error>> {
+    // 接続やTLSのセッションを再利用するため、共有のクライアントがある場合は複製する(内部の接続プールは共有される)
+    if let Some(client) = &options.client {
+        return Ok(client.0.clone());
+    }
+    Ok(configure(Client::builder(), options).build().map_err(GemfileError::from)?)
+}
+
+///
+/// クライアントのビルダーにこのクレートのリクエストの方針を設定する
+///
+/// 呼び出し側が設定したビルダーでも、リダイレクトとgzipの解凍、待ち時間の上限はこちらの設定で上書きする
+///
+/// * builder - クライアントのビルダー
+/// * options - インストール処理のオプション
+///
+/// return - 設定したビルダー
+///
+pub(crate) fn configure(builder: ClientBuilder, options: &InstallOptions) -> ClientBuilder {
+    let builder = builder
+        .redirect(Policy::none())
+        .no_gzip();
+    match options.request_timeout {
+        Some(timeout) => builder.connect_timeout(timeout).read_timeout(timeout),
+        None => builder,
+    }
+}
+
+///
+/// リダイレクトをたどりながらGETリクエストを行う
+///
+/// * client - HTTPクライアント
+/// * url - リクエスト先のURL
+/// * gem - リクエスト対象のGem(エラーの表示に使用)
+/// * options - インストール処理のオプション
+///
+/// return - リダイレクト先の最終的なレスポンス
+///
+pub(crate) async fn get(client: &Client, url: &str, gem: &str, options: &InstallOptions) -> Result<Response, Box<dyn Error>> {
+    request(client, Method::GET, url, gem, options).await
+}
+
+///
+/// 失敗した場合に再試行しながらGETリクエストを行う
+///
+/// 通信のエラー、サーバーのエラー(5xx)、リクエストの制限(429)のみ再試行し、最後の試行の結果を返す
+///
+/// * client - HTTPクライアント
+/// * url - リクエスト先のURL
+/// * gem - リクエスト対象のGem(エラーの表示に使用)
+/// * options - インストール処理のオプション
+/// * retry - 再試行の設定
+///
+/// return - リダイレクト先の最終的なレスポンス
+///
+pub(crate) async fn get_with_retry(client: &Client, url: &str, gem: &str, options: &InstallOptions, retry: &RetrySettings) -> Result<Response, Box<dyn Error>> {
+    let mut attempt = 1;
+    loop {
+        // 待機中に結果を保持しないよう、再試行しない場合はブロックの中で返す
+        {
+            let result = get(client, url, gem, options).await;
+            let retryable = match &result {
+                Ok(response) => response.status().is_server_error() || response.status() == StatusCode::TOO_MANY_REQUESTS,
+                Err(error) => is_transient(error.as_ref()),
+            };
+            if !retryable || attempt >= retry.attempts {
+                return result;
+            }
+        }
+
+        tokio::time::sleep(retry.delay(attempt)).await;
+        attempt += 1;
+    }
+}
+
+///
+/// 再試行で回復する可能性のある一時的なエラーかを確認する
+///
+/// 接続の失敗(`Http`)と時間切れ(`Timeout`)は一時的なエラーとして扱う
+///
+/// * error - 確認するエラー
+///
+/// return - 一時的なエラーの場合はtrue
+///
+pub(crate) fn is_transient(error: &(dyn Error + 'static)) -> bool {
+    error.downcast_ref::<reqwest::Error>().is_some()
+        || matches!(error.downcast_ref::<GemfileError>(), Some(GemfileError::Http(_) | GemfileError::Timeout { .. }))
+}
+
+///
+/// リダイレクトをたどりながらHEADリクエストを行う
+///
+/// * client - HTTPクライアント
+/// * url - リクエスト先のURL
+/// * gem - リクエスト対象のGem(エラーの表示に使用)
+/// * options - インストール処理のオプション
+///
+/// return - リダイレクト先の最終的なレスポンス
+///
+pub(crate) async fn head(client: &Client, url: &str, gem: &str, options: &InstallOptions) -> Result<Response, Box<dyn Error>> {
+    request(client, Method::HEAD, url, gem, options).await
+}
+
+///
+/// リダイレクトをたどりながらリクエストを行う
+///
+async fn request(client: &Client, method: Method, url: &str, gem: &str, options: &InstallOptions) -> Result<Response, Box<dyn Error>> {
+    let mut url = Url::parse(url)?;
+    check_secure(&url, options)?;
+    append_source_queries(&mut url, options);
+    let mut response = apply_credential(client.request(method.clone(), url.clone()), &url, options).send().await
+        .map_err(GemfileError::from)?;
+    let mut hops = 0;
+
+    while response.status().is_redirection() {
+        let Some(location) = response.headers().get(LOCATION) else {
+            break;
+        };
+        // 上限を超える場合はエラー
+        if hops >= options.max_redirects {
+            return Err(GemfileError::TooManyRedirects {
+                gem: gem.to_string(),
+                hops,
+            }.into());
+        }
+
+        // 相対パスにも対応するため、現在のURLを基準に解決
+        let next_url = response.url().join(location.to_str()?)?;
+        check_secure(&next_url, options)?;
+        response = apply_credential(client.request(method.clone(), next_url.clone()), &next_url, options).send().await
+            .map_err(GemfileError::from)?;
+        hops += 1;
+    }
+
+    Ok(response)
+}
+
+///
+/// ソースに設定されたクエリパラメータをURLに付与する
+///
+/// URLに既にクエリがある場合は末尾に追加する
+///
+/// * url - リクエスト先のURL
+/// * options - インストール処理のオプション
+///
+fn append_source_queries(url: &mut Url, options: &InstallOptions) {
+    let queries: Vec<_> = options.source_queries.iter()
+        .filter(|query| is_under_source(url, &query.source))
+        .collect();
+    if queries.is_empty() {
+        return;
+    }
+
+    let mut pairs = url.query_pairs_mut();
+    for query in queries {
+        pairs.append_pair(&query.name, &query.value);
+    }
+}
+
+///
+/// URLがソースの配下にあるかを確認する
+///
+/// 文字列の前方一致ではなく、スキーム、ホスト、ポートが一致し、パスがセグメント単位でソースのパスから始まるかで判定する
+/// (例: `https://gems.example.com`に対して`https://gems.example.com.evil/`や`https://gems.example.co`は一致しない)
+///
+/// * url - リクエスト先のURL
+/// * source - ソースのURL
+///
+/// return - ソースの配下にある場合はtrue
+///
+fn is_under_source(url: &Url, source: &str) -> bool {
+    let Ok(source) = Url::parse(source) else {
+        return false;
+    };
+    if url.scheme() != source.scheme() || url.host_str() != source.host_str() || url.port_or_known_default() != source.port_or_known_default() {
+        return false;
+    }
+    let segments = |url: &Url| -> Vec<String> {
+        url.path_segments()
+            .map(|segments| segments.filter(|segment| !segment.is_empty()).map(str::to_string).collect())
+            .unwrap_or_default()
+    };
+    segments(url).starts_with(&segments(&source))
+}
+
+///
+/// TLSを使用しないURLが許可されているかを確認する
+///
+/// * url - リクエスト先のURL
+/// * options - インストール処理のオプション
+///
+/// return - 許可されていない場合はエラー
+///
+fn check_secure(url: &Url, options: &InstallOptions) -> Result<(), GemfileError> {
+    if url.scheme() == "http" && !options.allow_insecure {
+        return Err(GemfileError::InsecureSource {
+            url: url.to_string(),
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::Url;
+    use crate::client::append_source_queries;
+    use crate::options::{InstallOptions, SourceQuery};
+
+    ///
+    /// ソースの配下にあるURLのみにクエリパラメータが付与されるかのテスト
+    ///
+    #[test]
+    pub fn append_source_queries_test() {
+        let options = InstallOptions {
+            source_queries: vec![
+                SourceQuery { source: "https://gems.example.com".to_string(), name: "api_key".to_string(), value: "root".to_string() },
+                SourceQuery { source: "https://mirror.example.com/private/".to_string(), name: "api_key".to_string(), value: "private".to_string() },
+            ],
+            ..Default::default()
+        };
+        let append = |url: &str| {
+            let mut url = Url::parse(url).unwrap();
+            append_source_queries(&mut url, &options);
+            url.query().map(str::to_string)
+        };
+
+        // ソースの配下にあるURLには付与されるか
+        assert_eq!(append("https://gems.example.com/downloads/rack-1.0.0.gem").as_deref(), Some("api_key=root"));
+        assert_eq!(append("https://gems.example.com:443/downloads/rack-1.0.0.gem").as_deref(), Some("api_key=root"));
+        assert_eq!(append("https://mirror.example.com/private/downloads/rack-1.0.0.gem").as_deref(), Some("api_key=private"));
+
+        // ホスト名やパスが前方一致するだけのURLには付与されないか
+        assert_eq!(append("https://gems.example.com.evil/downloads/rack-1.0.0.gem"), None);
+        assert_eq!(append("https://gems.example.co/downloads/rack-1.0.0.gem"), None);
+        assert_eq!(append("https://mirror.example.com/private-other/downloads/rack-1.0.0.gem"), None);
+
+        // スキームやポートが異なるURLには付与されないか
+        assert_eq!(append("http://gems.example.com/downloads/rack-1.0.0.gem"), None);
+        assert_eq!(append("https://gems.example.com:8443/downloads/rack-1.0.0.gem"), None);
+    }
+}
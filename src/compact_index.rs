@@ -0,0 +1,116 @@
+//!
+//! Compact Index(`/info/{gem}`)からGemのバージョンの一覧を取得します
+//!
+use std::error::Error;
+use crate::client;
+use crate::error::GemfileError;
+use crate::options::InstallOptions;
+use crate::resolution::Dependency;
+
+/// Compact Indexでyankされたバージョンを示す接頭辞
+pub const YANKED_PREFIX: char = '-';
+
+///
+/// Compact Indexに記載されたバージョン
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexedVersion {
+    /// バージョン
+    pub version: String,
+    /// プラットフォーム。Noneの場合は`ruby`
+    pub platform: Option<String>,
+    /// yankされているか
+    pub yanked: bool,
+    /// 実行時の依存関係
+    pub dependencies: Vec<Dependency>,
+}
+
+///
+/// Compact IndexからGemのバージョンの一覧を取得する
+///
+/// * source - ソースのURL
+/// * gem_name - Gemの名前
+/// * options - インストール処理のオプション
+///
+/// return - 成功するとバージョンの一覧を返す
+///
+pub async fn fetch_info(source: &str, gem_name: &str, options: &InstallOptions) -> Result<Vec<IndexedVersion>, Box<dyn Error>> {
+    let url = format!("{}/info/{}", source.trim_end_matches('/'), gem_name);
+    let client = client::build_client(options)?;
+    let response = client::get_with_retry(&client, &url, gem_name, options, &options.retry.version_api).await?;
+    // status codeを確認
+    if response.status() != 200 {
+        return Err(format!("Failed to get compact index {} (status {})", url, response.status()).into());
+    }
+    Ok(parse_info(&response.text().await.map_err(GemfileError::from)?))
+}
+
+///
+/// `/info/{gem}`の本文を解析する
+///
+/// 各行は`バージョン[-プラットフォーム] 依存関係|要件`の形式で、
+/// yankされたバージョンは先頭に`-`が付与される
+///
+/// * text - `/info/{gem}`の本文
+///
+/// return - バージョンの一覧
+///
+pub fn parse_info(text: &str) -> Vec<IndexedVersion> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && *line != "---")
+        .map(|line| {
+            let (yanked, line) = match line.strip_prefix(YANKED_PREFIX) {
+                Some(line) => (true, line),
+                None => (false, line),
+            };
+            let (version, rest) = line.split_once(' ').unwrap_or((line, ""));
+            let (version, platform) = match version.split_once('-') {
+                Some((version, platform)) => (version, Some(platform.to_string())),
+                None => (version, None),
+            };
+            let dependencies = rest.split('|').next().unwrap_or("")
+                .split(',')
+                .filter_map(|dependency| dependency.trim().split_once(':'))
+                .map(|(name, requirement)| Dependency {
+                    name: name.to_string(),
+                    requirement: requirement.replace('&', ", "),
+                })
+                .collect();
+
+            IndexedVersion {
+                version: version.to_string(),
+                platform,
+                yanked,
+                dependencies,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::compact_index::parse_info;
+    use crate::resolution::Dependency;
+
+    ///
+    /// `/info/{gem}`の解析のテスト
+    ///
+    #[test]
+    pub fn parse_info_test() {
+        let text = "---\n1.0.0 |checksum:aaa\n1.1.0 rack:>= 2.0&< 4,json:>= 0|checksum:bbb,ruby:>= 2.7\n1.1.0-java |checksum:ccc\n-1.2.0 |checksum:ddd\n";
+        let versions = parse_info(text);
+
+        assert_eq!(versions.len(), 4);
+        assert_eq!(versions[0].version, "1.0.0");
+        assert!(versions[0].dependencies.is_empty());
+        assert_eq!(versions[1].dependencies, vec![
+            Dependency { name: "rack".to_string(), requirement: ">= 2.0, < 4".to_string() },
+            Dependency { name: "json".to_string(), requirement: ">= 0".to_string() },
+        ]);
+        assert_eq!(versions[2].platform.as_deref(), Some("java"));
+        assert!(!versions[1].yanked);
+        assert!(versions[3].yanked);
+        assert_eq!(versions[3].version, "1.2.0");
+    }
+}
@@ -0,0 +1,205 @@
+//!
+//! 環境変数、または設定した関数からソースごとの認証情報を読み込みます
+//!
+use std::env;
+use std::sync::Arc;
+use reqwest::RequestBuilder;
+use reqwest::Url;
+use crate::options::InstallOptions;
+
+/// 認証情報を読み込む環境変数の接頭辞
+pub const TOKEN_ENV_PREFIX: &str = "GEMFILE_DL_TOKEN_";
+
+/// ソースのホスト名から、環境変数と同じ形式の認証情報の値を返す関数
+pub type CredentialLookup = Arc<dyn Fn(&str) -> Option<String> + Send + Sync>;
+
+///
+/// ソースの認証情報
+///
+#[derive(Debug, Clone, PartialEq)]
+pub enum Credential {
+    /// Bearerトークン
+    Bearer(String),
+    /// Basic認証のユーザー名とパスワード
+    Basic {
+        /// ユーザー名
+        username: String,
+        /// パスワード
+        password: String,
+    },
+}
+
+///
+/// ホスト名から認証情報を読み込む環境変数名を作成する
+///
+/// Bundlerと同じく`.`は`__`、`-`は`___`に置き換えて大文字にする
+/// (例: `gems.example-corp.com` → `GEMFILE_DL_TOKEN_GEMS__EXAMPLE___CORP__COM`)
+///
+/// * host - ソースのホスト名
+///
+/// return - 環境変数名
+///
+pub fn env_var_name(host: &str) -> String {
+    let host = host.to_uppercase()
+        .replace('-', "___")
+        .replace('.', "__");
+    format!("{}{}", TOKEN_ENV_PREFIX, host)
+}
+
+///
+/// 環境変数からホストの認証情報を読み込む
+///
+/// 値が`user:password`の形式の場合はBasic認証、それ以外はBearerトークンとして扱う
+///
+/// * host - ソースのホスト名
+///
+/// return - 設定されている場合は認証情報を返す
+///
+pub fn credential_for_host(host: &str) -> Option<Credential> {
+    parse_credential(env::var(env_var_name(host)).ok()?)
+}
+
+///
+/// オプションの関数、または環境変数からホストの認証情報を読み込む
+///
+/// * host - ソースのホスト名
+/// * options - インストール処理のオプション(`credential_lookup`を使用する)
+///
+/// return - 設定されている場合は認証情報を返す
+///
+pub fn credential_for_host_with_options(host: &str, options: &InstallOptions) -> Option<Credential> {
+    match &options.credential_lookup {
+        Some(credential_lookup) => parse_credential(credential_lookup(host)?),
+        None => credential_for_host(host),
+    }
+}
+
+///
+/// 認証情報の値を変換する
+///
+/// * value - `user:password`、またはBearerトークン
+///
+/// return - 空の場合はNone
+///
+fn parse_credential(value: String) -> Option<Credential> {
+    if value.is_empty() {
+        return None;
+    }
+
+    match value.split_once(':') {
+        Some((username, password)) => Some(Credential::Basic {
+            username: username.to_string(),
+            password: password.to_string(),
+        }),
+        None => Some(Credential::Bearer(value)),
+    }
+}
+
+///
+/// リクエスト先のホストに認証情報があれば付与する
+///
+/// * builder - リクエストのビルダー
+/// * url - リクエスト先のURL
+/// * options - インストール処理のオプション
+///
+/// return - 認証情報を付与したビルダー
+///
+pub(crate) fn apply_credential(builder: RequestBuilder, url: &Url, options: &InstallOptions) -> RequestBuilder {
+    let Some(credential) = url.host_str().and_then(|host| credential_for_host_with_options(host, options)) else {
+        return builder;
+    };
+
+    match credential {
+        Credential::Bearer(token) => builder.bearer_auth(token),
+        Credential::Basic { username, password } => builder.basic_auth(username, Some(password)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use crate::credentials::{credential_for_host_with_options, env_var_name, Credential};
+    use crate::download::download_gem_with_options;
+    use crate::options::InstallOptions;
+    use crate::parser::Gem;
+    use crate::test_util::{test_directory, GemBuilder, MockResponse, MockServer};
+
+    ///
+    /// 環境変数名の作成のテスト
+    ///
+    #[test]
+    pub fn env_var_name_test() {
+        assert_eq!(env_var_name("rubygems.org"), "GEMFILE_DL_TOKEN_RUBYGEMS__ORG");
+        assert_eq!(env_var_name("gems.example-corp.com"), "GEMFILE_DL_TOKEN_GEMS__EXAMPLE___CORP__COM");
+    }
+
+    ///
+    /// 設定した関数のトークンが送信されるかのテスト
+    ///
+    #[tokio::test]
+    pub async fn token_from_lookup_test() {
+        let directory = test_directory("token_from_lookup");
+        let body = GemBuilder::new("private", "1.0.0").build();
+        let server = MockServer::start(move |request| {
+            // トークンがない場合は拒否
+            if request.header("authorization") != Some("Bearer secret-token") {
+                return MockResponse::new(401, "Unauthorized");
+            }
+            MockResponse::new(200, body.clone())
+        }).await;
+
+        // モックサーバーは127.0.0.1で起動している
+        let gem = Gem { name: "private".to_string(), version: "1.0.0".to_string(), ..Default::default() };
+        let options = InstallOptions { allow_insecure: true, verify_checksums: false, ..Default::default() }
+            .credential_lookup(Arc::new(|host| (host == "127.0.0.1").then(|| "secret-token".to_string())));
+        let result = download_gem_with_options(&directory, &server.url, &gem, &options).await;
+        assert!(result.is_ok());
+        assert_eq!(server.requests()[0].header("authorization"), Some("Bearer secret-token"));
+    }
+
+    ///
+    /// 環境変数のトークンがリクエストに付与されるかのテスト
+    ///
+    #[tokio::test]
+    pub async fn token_from_env_test() {
+        let directory = test_directory("token_from_env");
+        let body = GemBuilder::new("private", "1.0.0").build();
+        let server = MockServer::start(move |request| {
+            // トークンがない場合は拒否
+            if request.header("authorization") != Some("Bearer env-token") {
+                return MockResponse::new(401, "Unauthorized");
+            }
+            MockResponse::new(200, body.clone())
+        }).await;
+
+        // 他のテストの127.0.0.1へのリクエストに影響しないよう、localhostのホスト名で接続する
+        let source = server.url.replace("127.0.0.1", "localhost");
+        let name = env_var_name("localhost");
+        assert_eq!(name, "GEMFILE_DL_TOKEN_LOCALHOST");
+        std::env::set_var(&name, "env-token");
+        let gem = Gem { name: "private".to_string(), version: "1.0.0".to_string(), ..Default::default() };
+        let options = InstallOptions { allow_insecure: true, verify_checksums: false, ..Default::default() };
+        let result = download_gem_with_options(&directory, &source, &gem, &options).await;
+        std::env::remove_var(&name);
+        assert!(result.is_ok());
+        assert_eq!(server.requests()[0].header("authorization"), Some("Bearer env-token"));
+    }
+
+    ///
+    /// 関数の値から認証情報を読み込むテスト
+    ///
+    #[test]
+    pub fn credential_lookup_test() {
+        let options = InstallOptions::default().credential_lookup(Arc::new(|host| match host {
+            "basic.example.com" => Some("user:pass".to_string()),
+            "bearer.example.com" => Some("token".to_string()),
+            _ => Some(String::new()),
+        }));
+        assert_eq!(credential_for_host_with_options("basic.example.com", &options), Some(Credential::Basic {
+            username: "user".to_string(),
+            password: "pass".to_string(),
+        }));
+        assert_eq!(credential_for_host_with_options("bearer.example.com", &options), Some(Credential::Bearer("token".to_string())));
+        assert_eq!(credential_for_host_with_options("other.example.com", &options), None);
+    }
+}
@@ -0,0 +1,110 @@
+//!
+//! 2つのGemfileの違いを比較します
+//!
+use std::error::Error;
+use serde::{Deserialize, Serialize};
+use crate::parser::{Gem, GemfileData};
+
+///
+/// バージョンの制約が変更されたGem
+///
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ConstraintChange {
+    /// Gemの名前
+    pub name: String,
+    /// 変更前の制約。指定がない場合は空
+    pub old: String,
+    /// 変更後の制約。指定がない場合は空
+    pub new: String,
+}
+
+///
+/// 2つのGemfileの違い
+///
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct GemfileDiff {
+    /// 追加されたGemの一覧
+    pub added: Vec<Gem>,
+    /// 削除されたGemの一覧
+    pub removed: Vec<Gem>,
+    /// 制約が変更されたGemの一覧
+    pub changed: Vec<ConstraintChange>,
+}
+
+impl GemfileDiff {
+    ///
+    /// 違いがないかを確認する
+    ///
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+///
+/// 2つのGemfileを比較する
+///
+/// バージョンは取得せず、Gemfileに書かれた制約の文字列をそのまま比較する
+///
+/// * old - 変更前のGemfileの内容
+/// * new - 変更後のGemfileの内容
+///
+/// return - 追加・削除・制約が変更されたGemの一覧
+///
+pub fn diff_gemfiles(old: &str, new: &str) -> Result<GemfileDiff, Box<dyn Error>> {
+    let old = GemfileData::parse_unresolved(old)?;
+    let new = GemfileData::parse_unresolved(new)?;
+    let find = |gemfile_data: &GemfileData, name: &str| gemfile_data.gems.iter().find(|gem| gem.name == name).cloned();
+
+    let mut diff = GemfileDiff::default();
+    for gem in &new.gems {
+        match find(&old, &gem.name) {
+            None => diff.added.push(gem.clone()),
+            Some(old_gem) if old_gem.requirements != gem.requirements => diff.changed.push(ConstraintChange {
+                name: gem.name.clone(),
+                old: old_gem.requirement(),
+                new: gem.requirement(),
+            }),
+            Some(_) => {}
+        }
+    }
+    diff.removed = old.gems.iter()
+        .filter(|gem| find(&new, &gem.name).is_none())
+        .cloned()
+        .collect();
+
+    Ok(diff)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::diff::{diff_gemfiles, ConstraintChange};
+
+    ///
+    /// Gemfileの比較のテスト
+    ///
+    #[test]
+    pub fn diff_gemfiles_test() {
+        let old = "source 'https://rubygems.org'
+gem 'rails', '~> 7.0.0'
+gem 'puma', '~> 6.0'
+gem 'sass-rails'
+";
+        let new = "source 'https://rubygems.org'
+gem 'rails', '~> 7.1.0'
+gem 'puma', '~> 6.0'
+gem 'bootsnap', require: false
+";
+        let diff = diff_gemfiles(old, new).unwrap();
+
+        assert_eq!(diff.added.len(), 1);
+        assert_eq!(diff.added[0].name, "bootsnap");
+        assert_eq!(diff.removed.len(), 1);
+        assert_eq!(diff.removed[0].name, "sass-rails");
+        assert_eq!(diff.changed, vec![ConstraintChange {
+            name: "rails".to_string(),
+            old: "~> 7.0.0".to_string(),
+            new: "~> 7.1.0".to_string(),
+        }]);
+        assert!(diff_gemfiles(old, old).unwrap().is_empty());
+    }
+}
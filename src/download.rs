@@ -2,11 +2,133 @@
 //! Gemのダウンロード処理
 //!
 use std::error::Error;
-use std::fs::{exists, File};
-use std::io::copy;
+use std::fs::{canonicalize, exists, File};
+use std::future::Future;
+use std::io::{copy, Read};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use flate2::read::MultiGzDecoder;
+use reqwest::header::CONTENT_ENCODING;
+use reqwest::Response;
+use ring::digest::{digest, Context, Digest, SHA256};
 use tokio::fs::create_dir_all;
-use crate::parser::Gem;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::watch;
+use crate::cache::{find_content_addressed, persist_content_addressed, CacheLayout};
+use crate::client;
+use crate::error::{is_out_of_space, GemfileError};
+use crate::gem_version::{fetch_checksummed_versions, fetch_versions};
+use crate::cleanup::{CleanupGuard, PART_EXTENSION};
+use crate::options::InstallOptions;
+use crate::parser::{Gem, GemSource};
+
+/// ローカルのディレクトリをソースとして指定する際の接頭辞
+pub const LOCAL_SOURCE_PREFIX: &str = "file://";
+/// 照合済みのSHA256を.gemファイルの隣に記録するファイルの拡張子
+const VERIFIED_EXTENSION: &str = "sha256";
+
+///
+/// 並列に実行するダウンロード全体で共有する制限
+///
+/// 受信したバイト数を合計し、上限を超えた場合やディスクの空き容量が不足した場合は、実行中のダウンロードも中断させる
+///
+pub(crate) struct DownloadLimits {
+    /// ダウンロードの合計サイズの上限
+    max_total_bytes: Option<u64>,
+    /// 受信した合計のバイト数
+    downloaded_bytes: AtomicU64,
+    /// ディスクの空き容量が不足したか
+    out_of_space: AtomicBool,
+    /// ダウンロードの合計サイズが上限を超えたか
+    budget_exceeded: AtomicBool,
+    /// 実行中のダウンロードに中断を通知する
+    stop: watch::Sender<bool>,
+}
+
+impl DownloadLimits {
+    ///
+    /// 制限を作成する
+    ///
+    /// * max_total_bytes - ダウンロードの合計サイズの上限
+    ///
+    /// return - 作成した制限
+    ///
+    pub(crate) fn new(max_total_bytes: Option<u64>) -> DownloadLimits {
+        DownloadLimits {
+            max_total_bytes,
+            downloaded_bytes: AtomicU64::new(0),
+            out_of_space: AtomicBool::new(false),
+            budget_exceeded: AtomicBool::new(false),
+            stop: watch::Sender::new(false),
+        }
+    }
+
+    ///
+    /// 新しいダウンロードを開始せずに中断するかを確認する
+    ///
+    /// return - 容量が不足したか、合計サイズが上限を超えた場合はtrue
+    ///
+    pub(crate) fn is_stopped(&self) -> bool {
+        self.is_out_of_space() || self.is_budget_exceeded()
+    }
+
+    ///
+    /// ディスクの空き容量が不足したかを確認する
+    ///
+    /// return - 容量が不足した場合はtrue
+    ///
+    pub(crate) fn is_out_of_space(&self) -> bool {
+        self.out_of_space.load(Ordering::SeqCst)
+    }
+
+    ///
+    /// ダウンロードの合計サイズが上限を超えたかを確認する
+    ///
+    /// return - 上限を超えた場合はtrue
+    ///
+    pub(crate) fn is_budget_exceeded(&self) -> bool {
+        self.budget_exceeded.load(Ordering::SeqCst)
+    }
+
+    ///
+    /// エラーがディスクの空き容量の不足によるものであれば記録し、実行中のダウンロードを中断させる
+    ///
+    /// * error - 発生したエラー
+    ///
+    pub(crate) fn record_error(&self, error: &(dyn Error + 'static)) {
+        if is_out_of_space(error) {
+            self.out_of_space.store(true, Ordering::SeqCst);
+            self.stop.send_replace(true);
+        }
+    }
+
+    ///
+    /// 受信したバイト数を加算し、上限を超えた場合は実行中のダウンロードを中断させる
+    ///
+    /// * size - 受信したバイト数
+    /// * gem - ダウンロード中のGemの名前とバージョン
+    ///
+    /// return - 上限を超えた場合はエラー
+    ///
+    fn add_bytes(&self, size: u64, gem: &str) -> Result<(), Box<dyn Error>> {
+        let total = self.downloaded_bytes.fetch_add(size, Ordering::SeqCst) + size;
+        match self.max_total_bytes {
+            Some(max_total_bytes) if total > max_total_bytes => {
+                self.budget_exceeded.store(true, Ordering::SeqCst);
+                self.stop.send_replace(true);
+                Err(format!("Download budget of {} bytes exceeded while downloading {}", max_total_bytes, gem).into())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    ///
+    /// 中断が通知されるまで待機する
+    ///
+    async fn stopped(&self) {
+        let _ = self.stop.subscribe().wait_for(|stopped| *stopped).await;
+    }
+}
 
 ///
 /// ダウンロードを行う
@@ -17,37 +139,503 @@ use crate::parser::Gem;
 ///
 /// return - ダウンロード処理の結果
 ///
-pub async fn download_gem(directory: &Path, source: &str, gem: &Gem) -> Result<PathBuf, Box<dyn Error>> {
-    // urlの作成
-    let url = format!("{}/downloads/{}-{}.gem", source, gem.name, gem.version);
-    // ファイル名の作成
-    let filename = format!("{}-{}.gem", gem.name, gem.version);
+pub async fn download_gem(directory: &Path, source: &str, gem: &Gem) -> Result<PathBuf, GemfileError> {
+    download_gem_with_options(directory, source, gem, &InstallOptions::default()).await
+}
+
+///
+/// オプションを指定してダウンロードを行う
+///
+/// `verify_checksums`が有効な場合は、照合したSHA256を.gemファイルの隣に記録し、
+/// ロックファイルのチェックサムがないキャッシュもバージョン一覧のAPIに問い合わせずに使用する
+///
+/// * directory - ダウンロード先のディレクトリ
+/// * source - ダウンロード元のURL
+/// * gem - ダウンロードするGemのデータ
+/// * options - インストール処理のオプション
+///
+/// return - ダウンロード処理の結果
+///
+pub async fn download_gem_with_options(directory: &Path, source: &str, gem: &Gem, options: &InstallOptions) -> Result<PathBuf, GemfileError> {
+    Ok(download_gem_with_limits(directory, source, gem, options, &DownloadLimits::new(options.max_total_bytes)).await?)
+}
+
+///
+/// 他のダウンロードと共有する制限の中でダウンロードを行う
+///
+/// * directory - ダウンロード先のディレクトリ
+/// * source - ダウンロード元のURL
+/// * gem - ダウンロードするGemのデータ
+/// * options - インストール処理のオプション
+/// * limits - 受信したバイト数を合計し、中断を通知する制限
+///
+/// return - ダウンロード処理の結果
+///
+pub(crate) async fn download_gem_with_limits(directory: &Path, source: &str, gem: &Gem, options: &InstallOptions, limits: &DownloadLimits) -> Result<PathBuf, Box<dyn Error>> {
+    check_registry_gem(gem)?;
+    // Gemごとに指定されたキャッシュディレクトリがある場合はそちらに保存する
+    let directory = &options.gem_cache_directory(directory, &gem.name);
+    // ファイル名の作成(プラットフォーム向けの.gemファイルと通常の.gemファイルを別のキャッシュとして扱う)
+    let key = gem.full_name();
+    let filename = format!("{}.gem", key);
+
+    // キャッシュがある場合は、ダウンロードせずにそのまま使用する(`force_download`の場合は常にダウンロードする)
+    let path = directory.join(&filename);
+    let cached = match options.cache_layout {
+        _ if options.force_download => None,
+        CacheLayout::NameVersion => path.is_file().then(|| path.clone()),
+        CacheLayout::ContentAddressed => find_content_addressed(directory, &key),
+    };
+    if let Some(cached) = cached {
+        match &gem.checksum {
+            // チェックサムが分かっている場合は、一致するときのみクライアントを作成せずに使用する
+            Some(checksum) => {
+                if file_sha256(&cached)?.eq_ignore_ascii_case(checksum) {
+                    return Ok(cached);
+                }
+            }
+            // 以前に照合したSHA256と一致する場合は、バージョン一覧のAPIに問い合わせずに使用する
+            None if options.verify_checksums => {
+                let actual = file_sha256(&cached)?;
+                if is_verified(&cached, &actual) {
+                    return Ok(cached);
+                }
+                if verify_checksum(source, gem, &actual, options).await.is_ok() {
+                    record_verified(&cached, &actual)?;
+                    return Ok(cached);
+                }
+            }
+            None => return Ok(cached),
+        }
+    }
+
+    // ローカルのディレクトリがソースの場合はコピーする
+    if let Some(local_directory) = source.strip_prefix(LOCAL_SOURCE_PREFIX) {
+        return copy_local_gem(&Path::new(local_directory).join(&filename), directory).await;
+    }
+
+    // ダウンロード(受信の途中で失敗した場合やチェックサムが一致しない場合も、リクエストからやり直す)
+    let (part, gem, verified) = retry_download(options, || download_part(directory, source, gem, options, limits)).await?;
+    // プラットフォーム向けの.gemファイルを取得した場合は、プラットフォームを含めた名前で保存する
+    let key = gem.full_name();
+    let path = match options.cache_layout {
+        CacheLayout::ContentAddressed => persist_content_addressed(part, directory, &key)?,
+        CacheLayout::NameVersion => {
+            let path = directory.join(format!("{}.gem", key));
+            part.persist(&path)?;
+            path
+        }
+    };
+    if let Some(verified) = verified {
+        record_verified(&path, &verified)?;
+    }
+
+    // Ok
+    Ok(path)
+}
+
+///
+/// .gemファイルを一時ファイルにダウンロードし、チェックサムを照合する
+///
+/// * directory - ダウンロード先のディレクトリ
+/// * source - ダウンロード元のURL
+/// * gem - ダウンロードするGemのデータ
+/// * options - インストール処理のオプション
+/// * limits - 受信したバイト数を合計し、中断を通知する制限
+///
+/// return - 照合済みの一時ファイル、取得したプラットフォームを設定したGemのデータ、照合したSHA256(照合しない場合はNone)
+///
+async fn download_part(directory: &Path, source: &str, gem: &Gem, options: &InstallOptions, limits: &DownloadLimits) -> Result<(CleanupGuard, Gem, Option<String>), Box<dyn Error>> {
+    // プラットフォーム向けの.gemファイルのみの場合はそちらを取得する
+    // 他のダウンロードで中断された場合は、レスポンスを待たずに中止する
+    let cancelled = || format!("Download of {} was cancelled", gem.full_name());
+    let (mut response, gzip_encoded, gem) = tokio::select! {
+        result = request_gem_or_platform_variant(source, gem, options) => result?,
+        _ = limits.stopped() => return Err(cancelled().into()),
+    };
+    let key = gem.full_name();
+    let filename = format!("{}.gem", key);
+
+    // 途中で中断された場合に残らないよう、一時ファイルに書き込む
+    if !exists(directory)? {
+        create_dir_all(directory).await?;
+    }
+    let part = CleanupGuard::new(directory.join(format!("{}.{}", filename, PART_EXTENSION)));
+    // .gemファイル全体をメモリに読み込まず、受信した部分ごとにファイルに書き込む
+    let mut out = tokio::fs::File::create(part.path()).await?;
+    // 受信した時点で合計サイズに加算し、上限を超えた場合や他のダウンロードで中断された場合はその場で中止する
+    loop {
+        let chunk = tokio::select! {
+            chunk = response.chunk() => chunk.map_err(GemfileError::from)?,
+            _ = limits.stopped() => return Err(cancelled().into()),
+        };
+        let Some(chunk) = chunk else {
+            break;
+        };
+        limits.add_bytes(chunk.len() as u64, &key)?;
+        out.write_all(&chunk).await?;
+    }
+    out.flush().await?;
+    drop(out);
+
+    // 転送時に圧縮されている場合は、元の.gemファイル(tar)に戻す(解凍した内容も別の一時ファイルに書き込む)
+    if gzip_encoded {
+        let decoded = CleanupGuard::new(directory.join(format!("{}.decoded.{}", filename, PART_EXTENSION)));
+        copy(&mut MultiGzDecoder::new(File::open(part.path())?), &mut File::create(decoded.path())?)?;
+        decoded.persist(part.path())?;
+    }
+
+    // 保存する前にチェックサムを照合する。一致しない場合は一時ファイルを削除する
+    if !options.verify_checksums {
+        return Ok((part, gem, None));
+    }
+    let actual = file_sha256(part.path())?;
+    verify_checksum(source, &gem, &actual, options).await?;
+    Ok((part, gem, Some(actual)))
+}
+
+///
+/// 照合済みのSHA256を記録するファイルのパスを作成する
+///
+fn verified_path(path: &Path) -> PathBuf {
+    let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(format!(".{}", VERIFIED_EXTENSION));
+    path.with_file_name(file_name)
+}
+
+///
+/// キャッシュの.gemファイルが以前に照合したものから変わっていないかを確認する
+///
+/// * path - .gemファイルのパス
+/// * actual - .gemファイルのSHA256
+///
+/// return - 記録したSHA256と一致する場合はtrue
+///
+fn is_verified(path: &Path, actual: &str) -> bool {
+    std::fs::read_to_string(verified_path(path)).is_ok_and(|verified| verified.trim().eq_ignore_ascii_case(actual))
+}
+
+///
+/// 照合したSHA256を.gemファイルの隣に記録する
+///
+/// * path - .gemファイルのパス
+/// * actual - 照合した.gemファイルのSHA256
+///
+fn record_verified(path: &Path, actual: &str) -> Result<(), Box<dyn Error>> {
+    std::fs::write(verified_path(path), actual)?;
+    Ok(())
+}
+
+///
+/// リクエストから受信、チェックサムの照合までを1回のダウンロードとして再試行する
+///
+/// 一時的な通信のエラー、サーバーのエラー(5xx)、リクエストの制限(429)、チェックサムの不一致を再試行する
+///
+/// * options - インストール処理のオプション(`retry.download`を使用する)
+/// * download - 1回のダウンロードを行う処理
+///
+/// return - 最後の試行の結果
+///
+async fn retry_download<T, F, Fut>(options: &InstallOptions, mut download: F) -> Result<T, Box<dyn Error>>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, Box<dyn Error>>>,
+{
+    let retry = &options.retry.download;
+    let mut attempt = 1;
+    loop {
+        let result = download().await;
+        let retryable = match &result {
+            Ok(_) => false,
+            Err(error) => client::is_transient(error.as_ref()) || matches!(
+                error.downcast_ref::<GemfileError>(),
+                Some(GemfileError::Download { status: 429 | 500..=599, .. } | GemfileError::ChecksumMismatch { .. })
+            ),
+        };
+        if !retryable || attempt >= retry.attempts {
+            return result;
+        }
+
+        tokio::time::sleep(retry.delay(attempt)).await;
+        attempt += 1;
+    }
+}
+
+///
+/// .gemファイルをダウンロードし、ファイルに保存せずに内容を返す
+///
+/// * source - ダウンロード元のURL
+/// * gem - ダウンロードするGemのデータ
+/// * options - インストール処理のオプション
+///
+/// return - .gemファイルの内容
+///
+pub async fn fetch_gem(source: &str, gem: &Gem, options: &InstallOptions) -> Result<Vec<u8>, Box<dyn Error>> {
+    check_registry_gem(gem)?;
+    // ローカルのディレクトリがソースの場合は読み込む
+    if let Some(local_directory) = source.strip_prefix(LOCAL_SOURCE_PREFIX) {
+        let path = Path::new(local_directory).join(format!("{}.gem", gem.full_name()));
+        return Ok(tokio::fs::read(path).await?);
+    }
+
+    // 受信の途中で失敗した場合やチェックサムが一致しない場合も、リクエストからやり直す
+    retry_download(options, || async {
+        let (response, gzip_encoded, gem) = request_gem_or_platform_variant(source, gem, options).await?;
+        let mut bytes = response.bytes().await.map_err(GemfileError::from)?.to_vec();
+        // 転送時に圧縮されている場合は、元の.gemファイル(tar)に戻す
+        if gzip_encoded {
+            let mut decoded = Vec::new();
+            MultiGzDecoder::new(bytes.as_slice()).read_to_end(&mut decoded)?;
+            bytes = decoded;
+        }
 
-    // ダウンロード
-    let response = reqwest::get(&url).await?;
+        if options.verify_checksums {
+            verify_checksum(source, &gem, &to_hex(digest(&SHA256, &bytes)), options).await?;
+        }
+        Ok(bytes)
+    }).await
+}
+
+///
+/// レジストリからダウンロードできるGemかを確認する
+///
+/// * gem - ダウンロードするGemのデータ
+///
+/// return - Gitのリポジトリやローカルのディレクトリから取得するGemの場合はエラー
+///
+fn check_registry_gem(gem: &Gem) -> Result<(), Box<dyn Error>> {
+    match &gem.origin {
+        GemSource::Registry => Ok(()),
+        GemSource::Git { url, .. } => Err(format!("{} is sourced from git repository {} and cannot be downloaded from a registry", gem.name, url).into()),
+        GemSource::Path(path) => Err(format!("{} is sourced from local path {} and cannot be downloaded from a registry", gem.name, path.display()).into()),
+    }
+}
+
+///
+/// ダウンロードした内容のSHA256を期待する値と照合する
+///
+/// ロックファイルから読み込んだチェックサムがある場合はそれを、ない場合はバージョン一覧のAPIの`sha`を使用する
+///
+/// * source - ダウンロード元のURL
+/// * gem - ダウンロードしたGemのデータ
+/// * actual - ダウンロードした内容のSHA256
+/// * options - インストール処理のオプション
+///
+/// return - 一致しない場合は`ChecksumMismatch`を返す
+///
+async fn verify_checksum(source: &str, gem: &Gem, actual: &str, options: &InstallOptions) -> Result<(), Box<dyn Error>> {
+    let expected = match &gem.checksum {
+        Some(checksum) => checksum.clone(),
+        None => fetch_checksum(source, gem, options).await?,
+    };
+    if !expected.eq_ignore_ascii_case(actual) {
+        return Err(Box::new(GemfileError::ChecksumMismatch {
+            gem: format!("{}-{}", gem.name, gem.version),
+            expected,
+            actual: actual.to_string(),
+        }));
+    }
+    Ok(())
+}
+
+///
+/// バージョン一覧のAPIから.gemファイルのSHA256を取得する
+///
+/// `version_endpoint`を変更していてもチェックサムは標準のバージョン一覧のAPIから取得し、
+/// 取得できない場合は`verify_checksums(false)`が必要であることをエラーで知らせる
+///
+/// * source - APIのURL
+/// * gem - 対象のGemのデータ
+/// * options - インストール処理のオプション
+///
+/// return - 16進数で表したSHA256
+///
+async fn fetch_checksum(source: &str, gem: &Gem, options: &InstallOptions) -> Result<String, Box<dyn Error>> {
+    let platform = gem.platform();
+    let versions = match fetch_checksummed_versions(source, &gem.name, options).await {
+        Ok(versions) => versions,
+        Err(error) if options.has_custom_version_endpoint() => {
+            return Err(format!("No checksum for {}-{} ({}); the custom version_endpoint does not provide checksums, so set verify_checksums(false) or pin the checksums in Gemfile.lock", gem.name, gem.version, error).into());
+        }
+        Err(error) => return Err(error),
+    };
+    versions.into_iter()
+        .filter(|version| version.number == gem.version)
+        .filter(|version| match &platform {
+            Some(platform) => &version.platform == platform,
+            None => version.is_ruby(),
+        })
+        .find_map(|version| version.sha)
+        .ok_or_else(|| format!("No checksum for {}-{}", gem.name, gem.version).into())
+}
+
+///
+/// .gemファイルをリクエストし、ステータスコードを確認する
+///
+/// * source - ダウンロード元のURL
+/// * gem - ダウンロードするGemのデータ
+/// * options - インストール処理のオプション
+///
+/// return - レスポンスと、転送時にgzip圧縮されているか
+///
+async fn request_gem(source: &str, gem: &Gem, options: &InstallOptions) -> Result<(Response, bool), Box<dyn Error>> {
+    // urlの作成(プラットフォームが指定されている場合はそのプラットフォーム向けの.gemファイル)
+    let url = format!("{}/downloads/{}.gem", source, gem.full_name());
+    let client = client::build_client(options)?;
+    // 再試行は本文の受信を含めて`retry_download`で行う
+    let response = client::get(&client, &url, &format!("{}-{}", gem.name, gem.version), options).await?;
     // ステータスコードを確認
     if response.status() != 200 {
-        return Err("Failed to download".into());
+        return Err(Box::new(GemfileError::Download { url, status: response.status().as_u16() }));
     }
-    let bytes = response.bytes().await?;
+    let gzip_encoded = response.headers().get(CONTENT_ENCODING)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.trim().eq_ignore_ascii_case("gzip"));
+    Ok((response, gzip_encoded))
+}
 
-    // ファイルに書き込み
+///
+/// .gemファイルをリクエストし、存在しない場合は実行中のプラットフォーム向けの.gemファイルをリクエストする
+///
+/// ネイティブ拡張をビルド済みのGem(例: `nokogiri-1.16.0-x86_64-linux.gem`)は、プラットフォームに依存しない.gemファイルがない場合がある。
+/// その場合はバージョン一覧のAPIの`platform`から、`local_platforms`に一致するものを選択する
+///
+/// * source - ダウンロード元のURL
+/// * gem - ダウンロードするGemのデータ
+/// * options - インストール処理のオプション
+///
+/// return - レスポンス、転送時にgzip圧縮されているか、取得したプラットフォームを設定したGemのデータ
+///
+async fn request_gem_or_platform_variant(source: &str, gem: &Gem, options: &InstallOptions) -> Result<(Response, bool, Gem), Box<dyn Error>> {
+    let error = match request_gem(source, gem, options).await {
+        Ok((response, gzip_encoded)) => return Ok((response, gzip_encoded, gem.clone())),
+        Err(error) => error,
+    };
+    // プラットフォームが指定されている場合や、404以外のエラーの場合はそのまま返す
+    let not_found = matches!(error.downcast_ref::<GemfileError>(), Some(GemfileError::Download { status: 404, .. }));
+    if !not_found || gem.platform().is_some() {
+        return Err(error);
+    }
+
+    let Ok(versions) = fetch_versions(source, &gem.name, options).await else {
+        return Err(error);
+    };
+    let Some(platform) = local_platforms().into_iter()
+        .find(|platform| versions.iter().any(|version| version.number == gem.version && &version.platform == platform)) else {
+        return Err(error);
+    };
+    let variant = Gem { resolved_platform: Some(platform), ..gem.clone() };
+    let (response, gzip_encoded) = request_gem(source, &variant, options).await?;
+    Ok((response, gzip_encoded, variant))
+}
+
+///
+/// 実行中の環境で使用できる.gemファイルのプラットフォームを取得する
+///
+/// RubyGemsのプラットフォームの名前(例: `x86_64-linux`、`arm64-darwin`)で、優先する順に返す
+///
+/// return - プラットフォームの一覧
+///
+pub fn local_platforms() -> Vec<String> {
+    let arch = std::env::consts::ARCH;
+    match std::env::consts::OS {
+        "linux" if cfg!(target_env = "musl") => vec![format!("{}-linux-musl", arch)],
+        "linux" => vec![format!("{}-linux", arch), format!("{}-linux-gnu", arch)],
+        "macos" => {
+            let arch = if arch == "aarch64" { "arm64" } else { arch };
+            vec![format!("{}-darwin", arch)]
+        }
+        "windows" => {
+            let arch = if arch == "x86_64" { "x64" } else { arch };
+            vec![format!("{}-mingw-ucrt", arch), format!("{}-mingw32", arch)]
+        }
+        os => vec![format!("{}-{}", arch, os)],
+    }
+}
+
+///
+/// ローカルにある.gemファイルをダウンロード先のディレクトリにコピーする
+///
+/// * gem_path - コピー元の.gemファイルのパス
+/// * directory - コピー先のディレクトリ
+///
+/// return - コピー後のファイルのパス
+///
+async fn copy_local_gem(gem_path: &Path, directory: &Path) -> Result<PathBuf, Box<dyn Error>> {
+    let Some(filename) = gem_path.file_name() else {
+        return Err(format!("Invalid gem path {}", gem_path.display()).into());
+    };
     if !exists(directory)? {
         create_dir_all(directory).await?;
     }
-    let path = directory.join(filename);
-    let mut out = File::create(&path)?;
-    copy(&mut bytes.as_ref(), &mut out)?;
 
-    // Ok
+    // 同じファイルの場合はコピーしない
+    let path = directory.join(filename);
+    if !exists(&path)? || canonicalize(gem_path)? != canonicalize(&path)? {
+        std::fs::copy(gem_path, &path)?;
+    }
     Ok(path)
 }
 
+///
+/// ファイルのSHA256を計算する
+///
+/// * path - 計算するファイルのパス
+///
+/// return - 16進数の小文字で表したSHA256
+///
+pub fn file_sha256(path: &Path) -> Result<String, Box<dyn Error>> {
+    let mut file = File::open(path)?;
+    let mut context = Context::new(&SHA256);
+    let mut buffer = [0u8; 8192];
+    loop {
+        let length = file.read(&mut buffer)?;
+        if length == 0 {
+            break;
+        }
+        context.update(&buffer[..length]);
+    }
+    Ok(to_hex(context.finish()))
+}
+
+///
+/// ダイジェストを16進数の小文字の文字列に変換する
+///
+fn to_hex(digest: Digest) -> String {
+    digest.as_ref().iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+///
+/// .gemファイルの名前をGemの名前とバージョンに分割する
+///
+/// 数字から始まる最初の`-`以降をバージョンとして扱う(例: `net-http-0.4.1` → `net-http`と`0.4.1`)
+///
+/// * file_name - 拡張子を含む、または含まないファイル名
+///
+/// return - Gemの名前とバージョン
+///
+pub fn split_gem_file_name(file_name: &str) -> Option<(String, String)> {
+    let stem = file_name.strip_suffix(".gem").unwrap_or(file_name);
+    let index = stem.char_indices()
+        .find(|(index, c)| *c == '-' && stem[index + 1..].starts_with(|next: char| next.is_ascii_digit()))
+        .map(|(index, _)| index)?;
+    Some((stem[..index].to_string(), stem[index + 1..].to_string()))
+}
+
 #[cfg(test)]
 mod tests {
     use std::path::Path;
-    use crate::download::download_gem;
-    use crate::parser::Gem;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+    use crate::cleanup::PART_EXTENSION;
+    use crate::download::{download_gem, download_gem_with_options, file_sha256, local_platforms, split_gem_file_name};
+    use crate::error::GemfileError;
+    use crate::gem_version::fetch_versions;
+    use crate::install_gems_with_options;
+    use crate::options::{GemCacheDirectory, InstallOptions, RetryPolicy, RetrySettings};
+    use crate::parser::{Gem, GemfileData};
+    use crate::test_util::{gzip, test_directory, GemBuilder, MockResponse, MockServer};
+    use crate::unpack_gem::unpack_gem;
 
     ///
     /// ダウンロードのテスト
@@ -60,6 +648,7 @@ mod tests {
         let gem = Gem {
             name: "rake".to_string(),
             version: "13.0.1".to_string(),
+            ..Default::default()
         };
 
         // ダウンロード
@@ -68,4 +657,591 @@ mod tests {
         //　ダウンロード処理が正常に終了しているか
         assert!(result.is_ok());
     }
-}
\ No newline at end of file
+
+    ///
+    /// リダイレクトのテスト
+    ///
+    #[tokio::test]
+    pub async fn redirect_test() {
+        let directory = test_directory("download_redirect");
+        let body = GemBuilder::new("moved", "1.0.0").build();
+        let server = MockServer::start(move |request| {
+            match request.path.as_str() {
+                "/downloads/moved-1.0.0.gem" => MockResponse::new(302, "").header("Location", "/files/moved-1.0.0.gem"),
+                "/files/moved-1.0.0.gem" => MockResponse::new(200, body.clone()),
+                // 無限にリダイレクトする
+                path if path.starts_with("/downloads/loop") || path.starts_with("/loop/") => {
+                    let location = format!("/loop/{}", path.len());
+                    MockResponse::new(301, "").header("Location", &location)
+                }
+                _ => MockResponse::not_found(),
+            }
+        }).await;
+        let options = InstallOptions { max_redirects: 3, allow_insecure: true, verify_checksums: false, ..Default::default() };
+
+        // 上限内のリダイレクトはたどられるか
+        let gem = Gem { name: "moved".to_string(), version: "1.0.0".to_string(), ..Default::default() };
+        let result = download_gem_with_options(&directory, &server.url, &gem, &options).await;
+        assert!(result.is_ok());
+
+        // 上限を超えるとエラーになるか
+        let gem = Gem { name: "loop".to_string(), version: "1.0.0".to_string(), ..Default::default() };
+        let error = download_gem_with_options(&directory, &server.url, &gem, &options).await.unwrap_err();
+        let GemfileError::TooManyRedirects { gem, hops } = &error else {
+            panic!("unexpected error: {}", error);
+        };
+        assert_eq!(gem, "loop-1.0.0");
+        assert_eq!(*hops, 3);
+        assert_eq!(server.requests().iter().filter(|request| request.path.contains("loop")).count(), 4);
+    }
+
+    ///
+    /// TLSを使用しないソースの許可のテスト
+    ///
+    #[tokio::test]
+    pub async fn insecure_source_test() {
+        let directory = test_directory("download_insecure");
+        let body = GemBuilder::new("plain", "1.0.0").build();
+        let server = MockServer::start(move |_| MockResponse::new(200, body.clone())).await;
+        let gem = Gem { name: "plain".to_string(), version: "1.0.0".to_string(), ..Default::default() };
+
+        // デフォルトでは拒否されるか
+        let error = download_gem_with_options(&directory, &server.url, &gem, &InstallOptions::default()).await.unwrap_err();
+        assert!(matches!(error, GemfileError::InsecureSource { .. }));
+        assert!(server.requests().is_empty());
+
+        // 許可した場合はダウンロードできるか
+        let options = InstallOptions { allow_insecure: true, verify_checksums: false, ..Default::default() };
+        let result = download_gem_with_options(&directory, &server.url, &gem, &options).await;
+        assert!(result.is_ok());
+    }
+
+    ///
+    /// .gemファイル名の分割のテスト
+    ///
+    #[test]
+    pub fn split_gem_file_name_test() {
+        let split = split_gem_file_name;
+        assert_eq!(split("rake-13.0.1.gem"), Some(("rake".to_string(), "13.0.1".to_string())));
+        assert_eq!(split("net-http-0.4.1"), Some(("net-http".to_string(), "0.4.1".to_string())));
+        assert_eq!(split("nokogiri-1.15.0-x86_64-linux.gem"), Some(("nokogiri".to_string(), "1.15.0-x86_64-linux".to_string())));
+        assert_eq!(split("invalid.gem"), None);
+    }
+
+    ///
+    /// ダウンロードの再試行のテスト
+    ///
+    #[tokio::test]
+    pub async fn download_retry_test() {
+        let directory = test_directory("download_retry");
+        let body = GemBuilder::new("flaky", "1.0.0").build();
+        let start_server = || {
+            let body = body.clone();
+            let count = AtomicUsize::new(0);
+            // 最初の2回は失敗する
+            MockServer::start(move |_| match count.fetch_add(1, Ordering::SeqCst) {
+                0 | 1 => MockResponse::new(503, "Service Unavailable"),
+                _ => MockResponse::new(200, body.clone()),
+            })
+        };
+        let gem = Gem { name: "flaky".to_string(), version: "1.0.0".to_string(), ..Default::default() };
+        let settings = |attempts| RetrySettings {
+            attempts,
+            base_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(5),
+            jitter: false,
+        };
+
+        // ダウンロードの設定で再試行されるか
+        let server = start_server().await;
+        let options = InstallOptions {
+            allow_insecure: true,
+            verify_checksums: false,
+            retry: RetryPolicy { version_api: settings(1), download: settings(3) },
+            ..Default::default()
+        };
+        assert!(download_gem_with_options(&directory, &server.url, &gem, &options).await.is_ok());
+        assert_eq!(server.requests().len(), 3);
+
+        // バージョンのAPIの設定はダウンロードに影響しないか
+        let server = start_server().await;
+        let options = InstallOptions {
+            allow_insecure: true,
+            verify_checksums: false,
+            retry: RetryPolicy { version_api: settings(3), download: settings(1) },
+            ..Default::default()
+        };
+        let error = download_gem_with_options(&directory.join("no_retry"), &server.url, &gem, &options).await.unwrap_err();
+        assert!(error.to_string().contains("status 503"));
+        assert_eq!(server.requests().len(), 1);
+
+        // リクエストの制限(429)は再試行し、存在しない(404)場合は再試行しないか
+        let body = GemBuilder::new("limited", "1.0.0").build();
+        let count = AtomicUsize::new(0);
+        let server = MockServer::start(move |request| match request.path.as_str() {
+            "/downloads/limited-1.0.0.gem" if count.fetch_add(1, Ordering::SeqCst) == 0 => MockResponse::new(429, "Too Many Requests"),
+            "/downloads/limited-1.0.0.gem" => MockResponse::new(200, body.clone()),
+            _ => MockResponse::not_found(),
+        }).await;
+        let options = InstallOptions {
+            allow_insecure: true,
+            verify_checksums: false,
+            retry: RetryPolicy { download: settings(3), ..Default::default() },
+            ..Default::default()
+        };
+        let gem = Gem { name: "limited".to_string(), version: "1.0.0".to_string(), ..Default::default() };
+        assert!(download_gem_with_options(&directory, &server.url, &gem, &options).await.is_ok());
+        assert_eq!(server.requests().len(), 2);
+        let gem = Gem { name: "missing".to_string(), version: "1.0.0".to_string(), ..Default::default() };
+        assert!(download_gem_with_options(&directory, &server.url, &gem, &options).await.is_err());
+        assert_eq!(server.request_count("/downloads/missing-1.0.0.gem"), 1);
+
+        // デフォルトでは3回まで試行するか
+        assert_eq!(RetryPolicy::default().download.attempts, 3);
+    }
+
+    ///
+    /// Content-Encodingでgzip圧縮されたダウンロードのテスト
+    ///
+    #[tokio::test]
+    pub async fn gzip_content_encoding_test() {
+        let directory = test_directory("download_gzip_encoding");
+        let body = GemBuilder::new("encoded", "1.0.0").build();
+        let encoded = gzip(&body);
+        let server = MockServer::start(move |_| {
+            MockResponse::new(200, encoded.clone()).header("Content-Encoding", "gzip")
+        }).await;
+        let gem = Gem { name: "encoded".to_string(), version: "1.0.0".to_string(), ..Default::default() };
+        let options = InstallOptions { allow_insecure: true, verify_checksums: false, ..Default::default() };
+
+        // 保存された.gemファイルが元のtarになっているか
+        let path = download_gem_with_options(&directory, &server.url, &gem, &options).await.unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), body);
+        assert!(unpack_gem(&path, &directory.join("unpacked")).is_ok());
+    }
+
+    ///
+    /// ダウンロードを中断した場合に一時ファイルが残らないかのテスト
+    ///
+    #[tokio::test]
+    pub async fn cancel_download_test() {
+        let directory = test_directory("download_cancel");
+        let body = GemBuilder::new("stalled", "1.0.0").build();
+        let server = MockServer::start(move |_| MockResponse::new(200, body.clone()).stall_after(512)).await;
+        let gem = Gem { name: "stalled".to_string(), version: "1.0.0".to_string(), ..Default::default() };
+        let options = InstallOptions { allow_insecure: true, ..Default::default() };
+
+        // 本文の途中でFutureを破棄する
+        let result = tokio::time::timeout(Duration::from_millis(500), download_gem_with_options(&directory, &server.url, &gem, &options)).await;
+        assert!(result.is_err());
+
+        // 一時ファイルも.gemファイルも残っていないか
+        let remaining: Vec<_> = std::fs::read_dir(&directory).unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        assert!(remaining.iter().all(|path| path.extension().is_none_or(|extension| extension != PART_EXTENSION)), "{:?}", remaining);
+        assert!(!directory.join("stalled-1.0.0.gem").exists());
+    }
+
+    ///
+    /// エラーの種類で分岐できるかのテスト
+    ///
+    #[tokio::test]
+    pub async fn typed_error_test() {
+        let directory = test_directory("download_typed_error");
+        let server = MockServer::start(|_| MockResponse::not_found()).await;
+        let gem = Gem { name: "missing".to_string(), version: "1.0.0".to_string(), ..Default::default() };
+
+        // ステータスコードを取り出せるか
+        let options = InstallOptions { allow_insecure: true, ..Default::default() };
+        let error = download_gem_with_options(&directory, &server.url, &gem, &options).await.unwrap_err();
+        match error {
+            GemfileError::Download { url, status } => {
+                assert_eq!(url, format!("{}/downloads/missing-1.0.0.gem", server.url));
+                assert_eq!(status, 404);
+            }
+            error => panic!("unexpected error: {}", error),
+        }
+
+        // 公開されている関数はGemfileErrorを直接返すか
+        let error = download_gem(&directory, &server.url, &gem).await.unwrap_err();
+        assert!(matches!(error, GemfileError::InsecureSource { .. }));
+        let error = unpack_gem(&directory.join("missing-1.0.0.gem"), &directory.join("unpacked")).unwrap_err();
+        assert!(matches!(error, GemfileError::Io(_)));
+    }
+
+    ///
+    /// チェックサムが一致するキャッシュがある場合にリクエストしないかのテスト
+    ///
+    #[tokio::test]
+    pub async fn warm_cache_test() {
+        let directory = test_directory("download_warm_cache");
+        let server = MockServer::start(|_| MockResponse::new(503, "Service Unavailable")).await;
+        let path = GemBuilder::new("warm", "1.0.0").write(&directory);
+        let checksum = file_sha256(&path).unwrap();
+        let options = InstallOptions {
+            allow_insecure: true,
+            retry: RetryPolicy { download: RetrySettings { attempts: 5, ..Default::default() }, ..Default::default() },
+            ..Default::default()
+        };
+
+        // 一致する場合はリクエストせずにすぐに返すか
+        let gem = Gem { name: "warm".to_string(), version: "1.0.0".to_string(), checksum: Some(checksum), ..Default::default() };
+        let started = std::time::Instant::now();
+        assert_eq!(download_gem_with_options(&directory, &server.url, &gem, &options).await.unwrap(), path);
+        assert!(started.elapsed() < Duration::from_millis(500));
+        assert!(server.requests().is_empty());
+
+        // 一致しない場合はダウンロードするか
+        let gem = Gem { name: "warm".to_string(), version: "1.0.0".to_string(), checksum: Some("0".repeat(64)), ..Default::default() };
+        let options = InstallOptions {
+            allow_insecure: true,
+            retry: RetryPolicy { download: RetrySettings { attempts: 1, ..Default::default() }, ..Default::default() },
+            ..Default::default()
+        };
+        assert!(download_gem_with_options(&directory, &server.url, &gem, &options).await.is_err());
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    ///
+    /// バージョン一覧のAPIのチェックサムと照合するテスト
+    ///
+    #[tokio::test]
+    pub async fn verify_checksum_test() {
+        let directory = test_directory("download_verify_checksum");
+        let body = GemBuilder::new("checked", "1.0.0").build();
+        let sha = ring::digest::digest(&ring::digest::SHA256, &body).as_ref().iter().map(|byte| format!("{:02x}", byte)).collect::<String>();
+        let api_sha = sha.clone();
+        let server = MockServer::start(move |request| {
+            match request.path.as_str() {
+                "/api/v1/versions/checked.json" => MockResponse::new(200, format!(
+                    r#"[{{"number":"1.0.0","platform":"java","sha":"{}"}},{{"number":"1.0.0","platform":"ruby","sha":"{}"}}]"#, "0".repeat(64), api_sha)),
+                "/downloads/checked-1.0.0.gem" => MockResponse::new(200, body.clone()),
+                // 途中で切れた内容を返す
+                "/downloads/checked-2.0.0.gem" => MockResponse::new(200, body[..body.len() / 2].to_vec()),
+                _ => MockResponse::not_found(),
+            }
+        }).await;
+        let options = InstallOptions { allow_insecure: true, verify_checksums: true, ..Default::default() };
+
+        // 一致する場合は保存されるか
+        let gem = Gem { name: "checked".to_string(), version: "1.0.0".to_string(), ..Default::default() };
+        assert!(download_gem_with_options(&directory, &server.url, &gem, &options).await.unwrap().is_file());
+
+        // 一致しない場合はエラーになり、ファイルが残らないか
+        let gem = Gem { name: "checked".to_string(), version: "2.0.0".to_string(), checksum: Some(sha.clone()), ..Default::default() };
+        let error = download_gem_with_options(&directory, &server.url, &gem, &options).await.unwrap_err();
+        let GemfileError::ChecksumMismatch { gem: name, expected, .. } = error else {
+            panic!("unexpected error: {}", error);
+        };
+        assert_eq!(name, "checked-2.0.0");
+        assert_eq!(expected, sha);
+        assert!(!directory.join("checked-2.0.0.gem").exists());
+        assert!(!directory.join(format!("checked-2.0.0.gem.{}", PART_EXTENSION)).exists());
+
+        // 無効にした場合は照合しないか
+        let options = InstallOptions { allow_insecure: true, verify_checksums: false, ..Default::default() };
+        assert!(download_gem_with_options(&directory, &server.url, &gem, &options).await.is_ok());
+    }
+
+    ///
+    /// キャッシュにあるGemを再ダウンロードしないテスト
+    ///
+    #[tokio::test]
+    pub async fn skip_cached_test() {
+        let directory = test_directory("download_skip_cached");
+        let body = GemBuilder::new("cached", "1.0.0").build();
+        let server = MockServer::start(move |_| MockResponse::new(200, body.clone())).await;
+        let gem = Gem { name: "cached".to_string(), version: "1.0.0".to_string(), ..Default::default() };
+        let options = InstallOptions { allow_insecure: true, verify_checksums: false, ..Default::default() };
+
+        // 2回目はキャッシュを使用するか
+        let path = download_gem_with_options(&directory, &server.url, &gem, &options).await.unwrap();
+        assert_eq!(download_gem_with_options(&directory, &server.url, &gem, &options).await.unwrap(), path);
+        assert_eq!(server.requests().len(), 1);
+
+        // 強制した場合は壊れたキャッシュもダウンロードし直すか
+        std::fs::write(&path, b"corrupted").unwrap();
+        let options = InstallOptions { allow_insecure: true, verify_checksums: false, force_download: true, ..Default::default() };
+        download_gem_with_options(&directory, &server.url, &gem, &options).await.unwrap();
+        assert_eq!(server.requests().len(), 2);
+        assert!(unpack_gem(&path, &directory.join("cached-1.0.0")).is_ok());
+    }
+
+    ///
+    /// デフォルトのオプションで、照合済みのキャッシュにバージョン一覧のAPIを使用しないかのテスト
+    ///
+    #[tokio::test]
+    pub async fn cached_verified_test() {
+        let directory = test_directory("download_cached_verified");
+        let body = GemBuilder::new("verified", "1.0.0").build();
+        let sha = ring::digest::digest(&ring::digest::SHA256, &body).as_ref().iter().map(|byte| format!("{:02x}", byte)).collect::<String>();
+        let server = MockServer::start(move |request| match request.path.as_str() {
+            "/api/v1/versions/verified.json" => MockResponse::new(200, format!(r#"[{{"number":"1.0.0","platform":"ruby","sha":"{}"}}]"#, sha)),
+            "/downloads/verified-1.0.0.gem" => MockResponse::new(200, body.clone()),
+            _ => MockResponse::not_found(),
+        }).await;
+        let gem = Gem { name: "verified".to_string(), version: "1.0.0".to_string(), ..Default::default() };
+        let options = InstallOptions { allow_insecure: true, ..Default::default() };
+
+        // 初回はバージョン一覧のAPIと照合し、2回目はキャッシュをAPIに問い合わせずに使用するか
+        let path = download_gem_with_options(&directory, &server.url, &gem, &options).await.unwrap();
+        assert_eq!(download_gem_with_options(&directory, &server.url, &gem, &options).await.unwrap(), path);
+        assert_eq!(server.request_count("/api/v1/versions/verified.json"), 1);
+        assert_eq!(server.request_count("/downloads/verified-1.0.0.gem"), 1);
+
+        // キャッシュが変更された場合は照合し直し、ダウンロードし直すか
+        std::fs::write(&path, b"corrupted").unwrap();
+        download_gem_with_options(&directory, &server.url, &gem, &options).await.unwrap();
+        assert_eq!(server.request_count("/downloads/verified-1.0.0.gem"), 2);
+        assert!(unpack_gem(&path, &directory.join("verified-1.0.0")).is_ok());
+    }
+
+    ///
+    /// プラットフォームが指定されたGemのダウンロードのテスト
+    ///
+    #[tokio::test]
+    pub async fn platform_gem_test() {
+        let directory = test_directory("download_platform_gem");
+        let native = GemBuilder::new("nokogiri", "1.15.0").build();
+        let generic = GemBuilder::new("json", "2.7.0").build();
+        let server = MockServer::start(move |request| match request.path.as_str() {
+            "/downloads/nokogiri-1.15.0-x86_64-linux.gem" => MockResponse::new(200, native.clone()),
+            "/downloads/json-2.7.0.gem" => MockResponse::new(200, generic.clone()),
+            _ => MockResponse::not_found(),
+        }).await;
+        let gemfile_data = GemfileData::parse_unresolved(&format!("source '{}'
+gem \"nokogiri\", \"1.15.0\", platforms: [:x86_64_linux]
+gem 'json', '2.7.0', platforms: [:mri, :jruby]
+", server.url)).unwrap();
+        let options = InstallOptions { allow_insecure: true, verify_checksums: false, ..Default::default() };
+
+        // プラットフォームを含むURLから取得されるか
+        let nokogiri = &gemfile_data.gems[0];
+        assert_eq!(nokogiri.platform(), Some("x86_64-linux".to_string()));
+        let path = download_gem_with_options(&directory, &server.url, nokogiri, &options).await.unwrap();
+        assert_eq!(path, directory.join("nokogiri-1.15.0-x86_64-linux.gem"));
+        assert_eq!(server.request_count("/downloads/nokogiri-1.15.0-x86_64-linux.gem"), 1);
+
+        // プラットフォームの指定がない場合は、プラットフォーム向けのキャッシュを使用しないか
+        let generic_nokogiri = Gem { platforms: Vec::new(), ..nokogiri.clone() };
+        assert!(download_gem_with_options(&directory, &server.url, &generic_nokogiri, &options).await.is_err());
+        assert_eq!(server.request_count("/downloads/nokogiri-1.15.0.gem"), 1);
+
+        // Rubyの実装の指定のみの場合は通常の.gemファイルを取得するか
+        let json = &gemfile_data.gems[1];
+        assert_eq!(json.platform(), None);
+        download_gem_with_options(&directory, &server.url, json, &options).await.unwrap();
+        assert_eq!(server.request_count("/downloads/json-2.7.0.gem"), 1);
+    }
+
+    ///
+    /// Gemごとにキャッシュディレクトリを変更するテスト
+    ///
+    #[tokio::test]
+    pub async fn gem_cache_directory_test() {
+        let directory = test_directory("download_gem_cache_directory");
+        let server = MockServer::start(|request| match request.path.as_str() {
+            "/downloads/rails-core-7.1.0.gem" => MockResponse::new(200, GemBuilder::new("rails-core", "7.1.0").build()),
+            "/downloads/rake-13.0.1.gem" => MockResponse::new(200, GemBuilder::new("rake", "13.0.1").build()),
+            _ => MockResponse::not_found(),
+        }).await;
+        let options = InstallOptions {
+            allow_insecure: true,
+            verify_checksums: false,
+            gem_cache_directories: vec![GemCacheDirectory { pattern: "rails-*".to_string(), directory: directory.join("rails_cache") }],
+            ..Default::default()
+        };
+
+        // 一致したGemは指定したディレクトリに保存されるか
+        let rails = Gem { name: "rails-core".to_string(), version: "7.1.0".to_string(), ..Default::default() };
+        let path = download_gem_with_options(&directory.join("cache"), &server.url, &rails, &options).await.unwrap();
+        assert_eq!(path, directory.join("rails_cache/rails-core-7.1.0.gem"));
+        assert!(path.is_file());
+
+        // 一致しないGemは通常のキャッシュディレクトリに保存されるか
+        let rake = Gem { name: "rake".to_string(), version: "13.0.1".to_string(), ..Default::default() };
+        let path = download_gem_with_options(&directory.join("cache"), &server.url, &rake, &options).await.unwrap();
+        assert_eq!(path, directory.join("cache/rake-13.0.1.gem"));
+        assert!(!directory.join("rails_cache/rake-13.0.1.gem").exists());
+    }
+
+    ///
+    /// プラットフォーム向けの.gemファイルのみがあるGemのダウンロードのテスト
+    ///
+    #[tokio::test]
+    pub async fn platform_variant_test() {
+        let directory = test_directory("download_platform_variant");
+        let platform = local_platforms()[0].clone();
+        let native = GemBuilder::new("nokogiri", "1.16.0").metadata(&format!("platform: {}\n", platform)).build();
+        let versions = format!("[{{\"number\":\"1.16.0\",\"platform\":\"java\"}},{{\"number\":\"1.16.0\",\"platform\":\"{}\"}}]", platform);
+        let variant_path = format!("/downloads/nokogiri-1.16.0-{}.gem", platform);
+        let server = MockServer::start(move |request| match request.path.as_str() {
+            "/api/v1/versions/nokogiri.json" => MockResponse::new(200, versions.clone()),
+            path if path == variant_path => MockResponse::new(200, native.clone()),
+            _ => MockResponse::not_found(),
+        }).await;
+        let gemfile_data = GemfileData::parse_unresolved(&format!("source '{}'\ngem 'nokogiri', '1.16.0'\n", server.url)).unwrap();
+        let options = InstallOptions { allow_insecure: true, verify_checksums: false, ..Default::default() };
+
+        // 通常の.gemファイルがない場合に、実行中のプラットフォーム向けの.gemファイルを取得するか
+        let info = install_gems_with_options(gemfile_data, &directory.join("gems"), &directory.join("cache"), &options).await.unwrap();
+        assert_eq!(info.install_gems, vec!["nokogiri-1.16.0".to_string()]);
+        assert_eq!(info.installed[0].platform.as_deref(), Some(platform.as_str()));
+        assert_eq!(server.request_count("/downloads/nokogiri-1.16.0.gem"), 1);
+        assert_eq!(server.request_count(&format!("/downloads/nokogiri-1.16.0-{}.gem", platform)), 1);
+
+        // プラットフォームを含めた名前でキャッシュされ、通常の.gemファイルとして扱われないか
+        assert!(directory.join(format!("cache/nokogiri-1.16.0-{}.gem", platform)).is_file());
+        assert!(!directory.join("cache/nokogiri-1.16.0.gem").exists());
+
+        // 一致するプラットフォームがない場合は元のエラーを返すか
+        let gem = Gem { name: "missing".to_string(), version: "1.0.0".to_string(), ..Default::default() };
+        let error = download_gem_with_options(&directory.join("cache"), &server.url, &gem, &options).await.unwrap_err();
+        assert!(matches!(error, GemfileError::Download { status: 404, .. }));
+    }
+
+    ///
+    /// 複数回に分けて受信する大きな.gemファイルのダウンロードのテスト
+    ///
+    #[tokio::test]
+    pub async fn large_download_test() {
+        let directory = test_directory("download_large");
+        // 圧縮されにくい数MBの内容
+        let mut seed: u32 = 1;
+        let content: Vec<u8> = (0..4 * 1024 * 1024).map(|_| {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            (seed >> 16) as u8
+        }).collect();
+        let body = GemBuilder::new("large", "1.0.0").file("lib/large.bin", &content).build();
+        let encoded = gzip(&body);
+        let server = MockServer::start(move |request| match request.path.as_str() {
+            "/downloads/large-1.0.0.gem" => MockResponse::new(200, body.clone()),
+            "/encoded/downloads/large-1.0.0.gem" => MockResponse::new(200, encoded.clone()).header("Content-Encoding", "gzip"),
+            _ => MockResponse::not_found(),
+        }).await;
+        let gem = Gem { name: "large".to_string(), version: "1.0.0".to_string(), ..Default::default() };
+        let options = InstallOptions { allow_insecure: true, verify_checksums: false, ..Default::default() };
+
+        // 通常の転送とgzip圧縮された転送のどちらも元の内容で保存され、一時ファイルが残らないか
+        for (source, name) in [(server.url.clone(), "plain"), (format!("{}/encoded", server.url), "encoded")] {
+            let path = download_gem_with_options(&directory.join(name), &source, &gem, &options).await.unwrap();
+            assert!(unpack_gem(&path, &directory.join(name).join("unpacked")).is_ok());
+            assert_eq!(file_sha256(&path).unwrap(), file_sha256(&directory.join("plain/large-1.0.0.gem")).unwrap());
+            let leftovers = std::fs::read_dir(directory.join(name)).unwrap()
+                .filter(|entry| entry.as_ref().unwrap().path().extension().is_some_and(|extension| extension == PART_EXTENSION))
+                .count();
+            assert_eq!(leftovers, 0);
+        }
+    }
+
+    ///
+    /// 応答しないサーバーへのリクエストが時間内に打ち切られるかのテスト
+    ///
+    #[tokio::test]
+    pub async fn request_timeout_test() {
+        let directory = test_directory("download_request_timeout");
+        let body = GemBuilder::new("stalled", "1.0.0").build();
+        let server = MockServer::start(move |request| match request.path.as_str() {
+            "/downloads/slow-1.0.0.gem" | "/api/v1/versions/slow.json" => MockResponse::new(200, "[]").delay(Duration::from_secs(5)),
+            "/downloads/stalled-1.0.0.gem" => MockResponse::new(200, body.clone()).stall_after(512),
+            _ => MockResponse::not_found(),
+        }).await;
+        let options = InstallOptions::new(&directory, &directory)
+            .allow_insecure(true)
+            .request_timeout(Duration::from_millis(200))
+            .retry(RetryPolicy {
+                version_api: RetrySettings { attempts: 1, ..Default::default() },
+                download: RetrySettings { attempts: 2, base_delay: Duration::from_millis(1), ..Default::default() },
+            });
+        let is_timeout = |error: &(dyn std::error::Error + 'static)| matches!(error.downcast_ref::<GemfileError>(), Some(GemfileError::Timeout { .. }));
+
+        // レスポンスが返らない場合はTimeoutになり、再試行されるか
+        let started = std::time::Instant::now();
+        let gem = Gem { name: "slow".to_string(), version: "1.0.0".to_string(), ..Default::default() };
+        let error = download_gem_with_options(&directory, &server.url, &gem, &options).await.unwrap_err();
+        assert!(is_timeout(&error), "{}", error);
+        assert_eq!(server.request_count("/downloads/slow-1.0.0.gem"), 2);
+
+        // 本文の途中で受信が止まった場合もTimeoutになるか
+        let gem = Gem { name: "stalled".to_string(), version: "1.0.0".to_string(), ..Default::default() };
+        let error = download_gem_with_options(&directory, &server.url, &gem, &options).await.unwrap_err();
+        assert!(is_timeout(&error), "{}", error);
+
+        // バージョンのAPIにも適用されるか
+        let error = fetch_versions(&server.url, "slow", &options).await.unwrap_err();
+        assert!(is_timeout(error.as_ref()), "{}", error);
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    ///
+    /// 接続できないサーバーへのダウンロードが再試行されるかのテスト
+    ///
+    #[tokio::test]
+    pub async fn connection_refused_retry_test() {
+        let directory = test_directory("download_connection_refused");
+        // 空いているポートを確保して閉じ、少し後にそのポートでサーバーを起動する
+        let address = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().to_string();
+        let body = GemBuilder::new("refused", "1.0.0").build();
+        let server_address = address.clone();
+        let server = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(150)).await;
+            MockServer::start_at(&server_address, move |_| MockResponse::new(200, body.clone())).await
+        });
+        let gem = Gem { name: "refused".to_string(), version: "1.0.0".to_string(), ..Default::default() };
+        let options = InstallOptions {
+            allow_insecure: true,
+            verify_checksums: false,
+            retry: RetryPolicy {
+                download: RetrySettings { attempts: 20, base_delay: Duration::from_millis(50), max_delay: Duration::from_millis(100), jitter: false },
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        // 接続が拒否されても再試行し、起動したサーバーから取得できるか
+        let path = download_gem_with_options(&directory, &format!("http://{}", address), &gem, &options).await.unwrap();
+        assert!(path.is_file());
+        assert_eq!(server.await.unwrap().request_count("/downloads/refused-1.0.0.gem"), 1);
+
+        // 再試行しない設定ではエラーになるか
+        let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().to_string();
+        let options = InstallOptions { retry: RetryPolicy { download: RetrySettings { attempts: 1, ..Default::default() }, ..Default::default() }, ..options };
+        let error = download_gem_with_options(&directory.join("closed"), &format!("http://{}", closed), &gem, &options).await.unwrap_err();
+        assert!(matches!(error, GemfileError::Http(_)), "{}", error);
+    }
+
+    ///
+    /// 本文の受信の途中で切断された場合に再試行するテスト
+    ///
+    #[tokio::test]
+    pub async fn truncated_body_retry_test() {
+        let directory = test_directory("truncated_body_retry");
+        let body = GemBuilder::new("truncated", "1.0.0").build();
+        let response_body = body.clone();
+        let count = AtomicUsize::new(0);
+        // 最初の応答は本文の途中で接続を閉じる
+        let server = MockServer::start(move |_| match count.fetch_add(1, Ordering::SeqCst) {
+            0 => MockResponse::new(200, response_body.clone()).truncate_after(16),
+            _ => MockResponse::new(200, response_body.clone()),
+        }).await;
+        let gem = Gem { name: "truncated".to_string(), version: "1.0.0".to_string(), ..Default::default() };
+        let settings = |attempts| RetrySettings {
+            attempts,
+            base_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(5),
+            jitter: false,
+        };
+        let options = InstallOptions {
+            allow_insecure: true,
+            verify_checksums: false,
+            retry: RetryPolicy { version_api: settings(1), download: settings(2) },
+            ..Default::default()
+        };
+
+        // リクエストからやり直し、完全な.gemファイルが保存されるか
+        let path = download_gem_with_options(&directory, &server.url, &gem, &options).await.unwrap();
+        assert_eq!(std::fs::read(path).unwrap(), body);
+        assert_eq!(server.requests().len(), 2);
+    }
+}
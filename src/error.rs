@@ -0,0 +1,295 @@
+//!
+//! インストール処理のエラー
+//!
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+///
+/// インストール処理のエラー
+///
+#[derive(Debug)]
+pub enum GemfileError {
+    /// ディスクの空き容量が不足した
+    OutOfSpace {
+        /// 容量が不足する前にインストールが完了したGemの一覧
+        completed: Vec<String>,
+    },
+    /// ダウンロードの合計サイズが上限を超えた
+    BudgetExceeded {
+        /// 上限を超える前にインストールが完了したGemの一覧
+        completed: Vec<String>,
+    },
+    /// リダイレクトの回数が上限を超えた
+    TooManyRedirects {
+        /// ダウンロードしていたGem
+        gem: String,
+        /// たどったリダイレクトの回数
+        hops: usize,
+    },
+    /// 許可されていないTLSを使用しないソース
+    InsecureSource {
+        /// リクエスト先のURL
+        url: String,
+    },
+    /// キャッシュディレクトリのロックを時間内に取得できなかった
+    LockTimeout {
+        /// ロックファイルのパス
+        path: PathBuf,
+    },
+    /// APIが成功のステータスで不正なJSONを返した(メンテナンス画面のHTMLなど)
+    InvalidApiResponse {
+        /// 取得していたGem
+        gem: String,
+        /// レスポンスの本文の先頭部分
+        snippet: String,
+    },
+    /// 展開先のパスが長さの上限を超えた
+    PathTooLong {
+        /// tar内のエントリのパス
+        entry: String,
+        /// 展開先のパスの長さ
+        length: usize,
+        /// パスの長さの上限
+        limit: usize,
+    },
+    /// アーカイブのエントリの数が上限を超えた
+    EntryLimitExceeded {
+        /// エントリの数の上限
+        limit: usize,
+    },
+    /// Gemの展開先のディレクトリが既に存在する(`ExistingDirectory::Error`の場合)
+    DirectoryExists {
+        /// 展開先のディレクトリ
+        path: PathBuf,
+    },
+    /// アーカイブのエントリが展開先のディレクトリの外を指している
+    PathTraversal {
+        /// tar内のエントリのパス
+        entry: String,
+    },
+    /// パスを引数に取らない関数で、オプションのディレクトリが設定されていない
+    MissingDirectory {
+        /// 設定されていないオプションの名前(`install_directory`または`cache_directory`)
+        option: String,
+    },
+    /// Gemfileのパースに失敗した
+    Parse {
+        /// エラーの内容
+        message: String,
+    },
+    /// ダウンロードが成功のステータスを返さなかった
+    Download {
+        /// リクエスト先のURL
+        url: String,
+        /// ステータスコード
+        status: u16,
+    },
+    /// .gemファイルの解凍に失敗した
+    UnpackGem {
+        /// .gemファイルのパス
+        path: PathBuf,
+        /// エラーの内容
+        message: String,
+    },
+    /// 本体の.tar.gzの解凍に失敗した
+    UnpackTarGz {
+        /// .tar.gzファイルのパス
+        path: PathBuf,
+        /// エラーの内容
+        message: String,
+    },
+    /// バージョンのAPIが成功のステータスを返さなかった
+    VersionApi {
+        /// 取得していたGem
+        gem_name: String,
+    },
+    /// ダウンロードした.gemファイルのSHA256が期待する値と一致しない
+    ChecksumMismatch {
+        /// ダウンロードしたGem
+        gem: String,
+        /// 期待するSHA256
+        expected: String,
+        /// ダウンロードした内容のSHA256
+        actual: String,
+    },
+    /// 展開したファイルがマニフェストに記録されたSHA256と一致しない
+    FileDigestMismatch {
+        /// 一致しなかった、または存在しなかったファイルの一覧
+        files: Vec<String>,
+    },
+    /// 接続やレスポンスの受信が時間内に完了しなかった
+    Timeout {
+        /// リクエスト先のURL
+        url: String,
+    },
+    /// HTTPのリクエストに失敗した
+    Http(reqwest::Error),
+    /// ファイルの読み書きに失敗した
+    Io(std::io::Error),
+    /// その他のエラー
+    Other {
+        /// エラーの内容
+        message: String,
+    },
+}
+
+impl Display for GemfileError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GemfileError::OutOfSpace { completed } => {
+                write!(f, "Out of disk space (completed: {})", completed.join(", "))
+            }
+            GemfileError::BudgetExceeded { completed } => {
+                write!(f, "Download budget exceeded (completed: {})", completed.join(", "))
+            }
+            GemfileError::TooManyRedirects { gem, hops } => {
+                write!(f, "Too many redirects while downloading {} ({} hops)", gem, hops)
+            }
+            GemfileError::InsecureSource { url } => {
+                write!(f, "Insecure source {} is not allowed (set allow_insecure to use http)", url)
+            }
+            GemfileError::LockTimeout { path } => {
+                write!(f, "Timed out waiting for cache lock {}", path.display())
+            }
+            GemfileError::InvalidApiResponse { gem, snippet } => {
+                write!(f, "Invalid API response for {}: {}", gem, snippet)
+            }
+            GemfileError::PathTooLong { entry, length, limit } => {
+                write!(f, "Path for entry {} is too long ({} > {} characters)", entry, length, limit)
+            }
+            GemfileError::EntryLimitExceeded { limit } => write!(f, "Archive contains more than {} entries", limit),
+            GemfileError::DirectoryExists { path } => write!(f, "Directory {} already exists", path.display()),
+            GemfileError::PathTraversal { entry } => write!(f, "Entry {} escapes the destination directory", entry),
+            GemfileError::MissingDirectory { option } => {
+                write!(f, "InstallOptions.{} is not set (use InstallOptions::new to set the directories)", option)
+            }
+            GemfileError::Parse { message } => write!(f, "Failed to parse Gemfile: {}", message),
+            GemfileError::Download { url, status } => write!(f, "Failed to download {} (status {})", url, status),
+            GemfileError::UnpackGem { path, message } => write!(f, "Failed to unpack {}: {}", path.display(), message),
+            GemfileError::UnpackTarGz { path, message } => write!(f, "Failed to unpack {}: {}", path.display(), message),
+            GemfileError::VersionApi { gem_name } => write!(f, "Failed to get gem version {}", gem_name),
+            GemfileError::ChecksumMismatch { gem, expected, actual } => {
+                write!(f, "Checksum mismatch for {} (expected {}, got {})", gem, expected, actual)
+            }
+            GemfileError::FileDigestMismatch { files } => write!(f, "File digest mismatch: {}", files.join(", ")),
+            GemfileError::Timeout { url } => write!(f, "Request to {} timed out", url),
+            GemfileError::Http(error) => write!(f, "{}", error),
+            GemfileError::Io(error) => write!(f, "{}", error),
+            GemfileError::Other { message } => write!(f, "{}", message),
+        }
+    }
+}
+
+impl Error for GemfileError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            GemfileError::Http(error) => Some(error),
+            GemfileError::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for GemfileError {
+    fn from(error: std::io::Error) -> Self {
+        GemfileError::Io(error)
+    }
+}
+
+impl From<reqwest::Error> for GemfileError {
+    fn from(error: reqwest::Error) -> Self {
+        if error.is_timeout() {
+            return GemfileError::Timeout {
+                url: error.url().map(|url| url.to_string()).unwrap_or_default(),
+            };
+        }
+        GemfileError::Http(error)
+    }
+}
+
+impl From<Box<dyn Error>> for GemfileError {
+    fn from(error: Box<dyn Error>) -> Self {
+        let error = match error.downcast::<reqwest::Error>() {
+            Ok(error) => return GemfileError::from(*error),
+            Err(error) => error,
+        };
+        GemfileError::classify(error, |message| GemfileError::Other { message })
+    }
+}
+
+impl GemfileError {
+    ///
+    /// .gemファイルの解凍で発生したエラーを変換する
+    ///
+    /// * path - .gemファイルのパス
+    /// * error - 発生したエラー
+    ///
+    pub(crate) fn unpack_gem(path: &Path, error: Box<dyn Error>) -> GemfileError {
+        GemfileError::classify(error, |message| GemfileError::UnpackGem { path: path.to_path_buf(), message })
+    }
+
+    ///
+    /// .tar.gzファイルの解凍で発生したエラーを変換する
+    ///
+    /// * path - .tar.gzファイルのパス
+    /// * error - 発生したエラー
+    ///
+    pub(crate) fn unpack_tar_gz(path: &Path, error: Box<dyn Error>) -> GemfileError {
+        GemfileError::classify(error, |message| GemfileError::UnpackTarGz { path: path.to_path_buf(), message })
+    }
+
+    ///
+    /// GemfileErrorと入出力のエラーはそのまま、それ以外は指定した種類のエラーに変換する
+    ///
+    fn classify(error: Box<dyn Error>, other: impl FnOnce(String) -> GemfileError) -> GemfileError {
+        let error = match error.downcast::<GemfileError>() {
+            Ok(error) => return *error,
+            Err(error) => error,
+        };
+        match error.downcast::<std::io::Error>() {
+            Ok(error) => GemfileError::Io(*error),
+            Err(error) => other(error.to_string()),
+        }
+    }
+}
+
+///
+/// ディスクの空き容量不足によるエラーかを確認する
+///
+/// * error - 確認するエラー
+///
+/// return - 容量不足の場合はtrue
+///
+pub(crate) fn is_out_of_space(error: &(dyn Error + 'static)) -> bool {
+    let mut current = Some(error);
+    while let Some(error) = current {
+        if let Some(io_error) = error.downcast_ref::<std::io::Error>() {
+            if io_error.kind() == ErrorKind::StorageFull {
+                return true;
+            }
+        }
+        current = error.source();
+    }
+    false
+}
+
+///
+/// ソースに接続できなかったことによるエラーかを確認する
+///
+/// 接続の失敗と時間切れのみを対象にし、ステータスコードのエラーや不正な応答は含まない
+///
+/// * error - 確認するエラー
+///
+/// return - 接続できなかった場合はtrue
+///
+pub(crate) fn is_unreachable(error: &(dyn Error + 'static)) -> bool {
+    let is_connect = |error: &reqwest::Error| error.is_connect() || error.is_timeout();
+    match error.downcast_ref::<GemfileError>() {
+        Some(GemfileError::Timeout { .. }) => true,
+        Some(GemfileError::Http(error)) => is_connect(error),
+        Some(_) => false,
+        None => error.downcast_ref::<reqwest::Error>().is_some_and(is_connect),
+    }
+}
@@ -0,0 +1,166 @@
+//!
+//! インストール処理の進行状況を通知するイベント
+//!
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use serde::{Deserialize, Serialize};
+use crate::options::InstallOptions;
+
+/// イベントを受け取る関数
+pub type EventHandler = Arc<dyn Fn(InstallEvent) + Send + Sync>;
+
+///
+/// インストール処理のイベント
+///
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum InstallEvent {
+    /// Gemのインストールを開始した
+    Started {
+        /// `{name}-{version}`の形式のGem
+        gem: String,
+        /// インストールするGemの総数
+        total: usize,
+    },
+    /// .gemファイルのダウンロードが完了した
+    Downloaded {
+        /// `{name}-{version}`の形式のGem
+        gem: String,
+    },
+    /// 本体の解凍が完了した
+    Unpacked {
+        /// `{name}-{version}`の形式のGem
+        gem: String,
+    },
+    /// Gemのインストールが完了した
+    Installed {
+        /// `{name}-{version}`の形式のGem
+        gem: String,
+    },
+    /// Gemのインストールに失敗した
+    Failed {
+        /// `{name}-{version}`の形式のGem
+        gem: String,
+        /// エラーの内容
+        error: String,
+    },
+    /// 処理は継続するが、確認が必要な内容があった
+    Warning {
+        /// Gemの名前
+        gem: String,
+        /// 警告の内容
+        message: String,
+    },
+    /// すべてのインストール処理が終了した
+    Finished {
+        /// インストールが完了したGemの数
+        installed: usize,
+        /// インストールしようとしたGemの総数
+        total: usize,
+    },
+}
+
+///
+/// イベントを1行ずつのJSONとして書き込む関数を作成する
+///
+/// 書き込みに失敗したイベントは無視し、インストール処理は継続する
+///
+/// * writer - 書き込み先
+///
+/// return - `InstallOptions.on_event`に設定する関数
+///
+pub fn json_lines<W: Write + Send + 'static>(writer: W) -> EventHandler {
+    let writer = Mutex::new(writer);
+    Arc::new(move |event: InstallEvent| {
+        let Ok(line) = serde_json::to_string(&event) else {
+            return;
+        };
+        if let Ok(mut writer) = writer.lock() {
+            let _ = writeln!(writer, "{}", line);
+            let _ = writer.flush();
+        }
+    })
+}
+
+///
+/// 設定されている場合のみイベントを通知する
+///
+/// * options - インストール処理のオプション
+/// * event - イベントを作成する関数
+///
+pub(crate) fn emit(options: &InstallOptions, event: impl FnOnce() -> InstallEvent) {
+    if let Some(on_event) = &options.on_event {
+        on_event(event());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+    use crate::events::{json_lines, InstallEvent};
+    use crate::install_gems_with_options;
+    use crate::options::InstallOptions;
+    use crate::parser::{Gem, GemfileData};
+    use crate::test_util::{test_directory, GemBuilder, MockResponse, MockServer};
+
+    ///
+    /// 書き込まれた内容を共有するテスト用の書き込み先
+    ///
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    ///
+    /// イベントをJSON Linesで書き込むテスト
+    ///
+    #[tokio::test]
+    pub async fn json_lines_test() {
+        let directory = test_directory("json_lines");
+        let body = GemBuilder::new("good", "1.0.0").build();
+        let server = MockServer::start(move |request| {
+            match request.path.as_str() {
+                "/downloads/good-1.0.0.gem" => MockResponse::new(200, body.clone()),
+                _ => MockResponse::not_found(),
+            }
+        }).await;
+        let gemfile_data = GemfileData {
+            source: server.url.clone(),
+            gems: ["good", "missing"].iter()
+                .map(|name| Gem { name: name.to_string(), version: "1.0.0".to_string(), ..Default::default() })
+                .collect(),
+            ..Default::default()
+        };
+        let buffer = SharedBuffer::default();
+        let options = InstallOptions {
+            allow_insecure: true,
+            verify_checksums: false,
+            deterministic: true,
+            on_event: Some(json_lines(buffer.clone())),
+            ..Default::default()
+        };
+        install_gems_with_options(gemfile_data, &directory.join("gems"), &directory.join("cache"), &options).await.unwrap();
+
+        // 各行が期待するイベントにデシリアライズできるか
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let events: Vec<InstallEvent> = output.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert_eq!(events.len(), 7);
+        assert_eq!(events[0], InstallEvent::Started { gem: "good-1.0.0".to_string(), total: 2 });
+        assert_eq!(events[1], InstallEvent::Downloaded { gem: "good-1.0.0".to_string() });
+        assert_eq!(events[2], InstallEvent::Unpacked { gem: "good-1.0.0".to_string() });
+        assert_eq!(events[3], InstallEvent::Installed { gem: "good-1.0.0".to_string() });
+        assert_eq!(events[4], InstallEvent::Started { gem: "missing-1.0.0".to_string(), total: 2 });
+        assert!(matches!(&events[5], InstallEvent::Failed { gem, error } if gem == "missing-1.0.0" && error.contains("status 404")));
+        assert_eq!(events[6], InstallEvent::Finished { installed: 1, total: 2 });
+        assert!(output.starts_with("{\"event\":\"started\","));
+    }
+}
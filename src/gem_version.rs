@@ -2,7 +2,58 @@
 //! GemのバージョンをAPIから取得する
 //!
 use std::error::Error;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
+use crate::client;
+use crate::compact_index::IndexedVersion;
+use crate::error::GemfileError;
+use crate::options::InstallOptions;
+use crate::resolver::select_version_with_strategy;
+use crate::version::VersionRequirement;
+
+/// エラーに含めるレスポンスの本文の最大文字数
+const SNIPPET_LENGTH: usize = 200;
+
+/// バージョン一覧のAPIのパス。`{name}`はGemの名前に置き換える
+pub const VERSIONS_ENDPOINT: &str = "/api/v1/versions/{name}.json";
+
+///
+/// バージョン一覧のAPIの要素のDeserialize用の構造体
+///
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct ApiVersion {
+    /// バージョン
+    pub number: String,
+    /// プラットフォーム
+    #[serde(default)]
+    pub platform: String,
+    /// .gemファイルのSHA256
+    #[serde(default)]
+    pub sha: Option<String>,
+}
+
+///
+/// バージョンを取得するAPIのレスポンス
+///
+/// バージョン一覧のAPIは配列を、`version_endpoint`のAPIは`version`を含むオブジェクトを返す
+///
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(untagged)]
+enum VersionsResponse {
+    /// バージョンの一覧
+    List(Vec<ApiVersion>),
+    /// 1つのバージョン
+    Single(GemVersion),
+}
+
+impl ApiVersion {
+    ///
+    /// プラットフォームに依存しないバージョンかを確認する
+    ///
+    pub fn is_ruby(&self) -> bool {
+        self.platform.is_empty() || self.platform == "ruby"
+    }
+}
 
 ///
 /// GemのSerialize/Deserialize用の構造体
@@ -21,17 +72,288 @@ impl GemVersion {
     ///
     /// return - 成功するとGemのバージョンを返す
     ///
-    pub async fn get_version(source: &str, gem_name: &str) -> Result<GemVersion, Box<dyn Error>> {
+    pub async fn get_version(source: &str, gem_name: &str) -> Result<GemVersion, GemfileError> {
+        GemVersion::get_version_with_options(source, gem_name, &InstallOptions::default()).await
+    }
+
+    ///
+    /// オプションを指定してAPIからGemのバージョンを取得する
+    ///
+    /// * source - APIのURL
+    /// * gem_name - Gemの名前
+    /// * options - インストール処理のオプション
+    ///
+    /// return - 成功するとGemのバージョンを返す
+    ///
+    pub async fn get_version_with_options(source: &str, gem_name: &str, options: &InstallOptions) -> Result<GemVersion, GemfileError> {
         // urlを作成
-        let url = format!("{}/api/v1/gems/{}.json", source, gem_name);
-        let response = reqwest::get(&url).await?;
+        let url = options.version_url(source, gem_name);
+        let client = client::build_client(options)?;
+        let response = client::get_with_retry(&client, &url, gem_name, options, &options.retry.version_api).await?;
         // status codeを確認
         if response.status() != 200 {
-            return Err(format!("Failed to get gem version {}", gem_name).into());
+            return Err(GemfileError::VersionApi { gem_name: gem_name.to_string() });
         }
 
         // デシリアライズして返す
-        let gem_version: GemVersion = response.json().await?;
-        Ok(gem_version)
+        Ok(parse_response(gem_name, &response.text().await?)?)
+    }
+
+    ///
+    /// バージョン一覧のAPIから制約を満たす最新のバージョンを取得する
+    ///
+    /// `preferred_versions`に制約を満たすバージョンがある場合はそれを優先する
+    ///
+    /// * source - APIのURL
+    /// * gem_name - Gemの名前
+    /// * requirement - バージョンの制約
+    /// * options - インストール処理のオプション
+    ///
+    /// return - 成功すると制約を満たすバージョンを返す
+    ///
+    pub async fn get_matching_version(source: &str, gem_name: &str, requirement: &VersionRequirement, options: &InstallOptions) -> Result<GemVersion, Box<dyn Error>> {
+        let versions = fetch_versions(source, gem_name, options).await?;
+        match select_matching_version(&versions, gem_name, requirement, options) {
+            Some(version) => Ok(version),
+            None => Err(format!("No version of {} satisfies {}", gem_name, requirement).into()),
+        }
+    }
+}
+
+///
+/// バージョン一覧のAPIからすべてのバージョンを取得する
+///
+/// `version_endpoint`が変更されている場合はそのAPIから取得し、1つのバージョンのみを返すAPIにも対応する
+///
+/// * source - APIのURL
+/// * gem_name - Gemの名前
+/// * options - インストール処理のオプション
+///
+/// return - 成功するとバージョンの一覧を返す
+///
+pub async fn fetch_versions(source: &str, gem_name: &str, options: &InstallOptions) -> Result<Vec<ApiVersion>, Box<dyn Error>> {
+    match options.has_custom_version_endpoint() {
+        true => fetch_versions_from(&options.version_url(source, gem_name), gem_name, options).await,
+        false => fetch_checksummed_versions(source, gem_name, options).await,
+    }
+}
+
+///
+/// `version_endpoint`に関係なく、標準のバージョン一覧のAPIからチェックサムを含むバージョンの一覧を取得する
+///
+/// * source - APIのURL
+/// * gem_name - Gemの名前
+/// * options - インストール処理のオプション
+///
+/// return - 成功するとバージョンの一覧を返す
+///
+pub async fn fetch_checksummed_versions(source: &str, gem_name: &str, options: &InstallOptions) -> Result<Vec<ApiVersion>, Box<dyn Error>> {
+    fetch_versions_from(&format!("{}{}", source, VERSIONS_ENDPOINT.replace("{name}", gem_name)), gem_name, options).await
+}
+
+///
+/// 指定したURLのAPIからバージョンの一覧を取得する
+///
+/// * url - APIのURL
+/// * gem_name - Gemの名前
+/// * options - インストール処理のオプション
+///
+/// return - 成功するとバージョンの一覧を返す
+///
+async fn fetch_versions_from(url: &str, gem_name: &str, options: &InstallOptions) -> Result<Vec<ApiVersion>, Box<dyn Error>> {
+    let client = client::build_client(options)?;
+    let response = client::get_with_retry(&client, url, gem_name, options, &options.retry.version_api).await?;
+    if response.status() != 200 {
+        return Err(Box::new(GemfileError::VersionApi { gem_name: gem_name.to_string() }));
     }
-}
\ No newline at end of file
+
+    match parse_response(gem_name, &response.text().await.map_err(GemfileError::from)?)? {
+        VersionsResponse::List(versions) => Ok(versions),
+        VersionsResponse::Single(version) => Ok(vec![ApiVersion { number: version.version, platform: String::new(), sha: None }]),
+    }
+}
+
+///
+/// バージョンの一覧から制約を満たす最新のバージョンを選択する
+///
+/// * versions - バージョン一覧のAPIの結果
+/// * gem_name - Gemの名前
+/// * requirement - バージョンの制約
+/// * options - インストール処理のオプション(`preferred_versions`と`resolution_strategy`を使用する)
+///
+/// return - 選択したバージョン
+///
+pub fn select_matching_version(versions: &[ApiVersion], gem_name: &str, requirement: &VersionRequirement, options: &InstallOptions) -> Option<GemVersion> {
+    // yankされたバージョンはAPIに含まれないため、Compact Indexと同じ規則で選択する
+    let indexed: Vec<IndexedVersion> = versions.iter()
+        .filter(|version| version.is_ruby())
+        .map(|version| IndexedVersion {
+            version: version.number.clone(),
+            platform: None,
+            yanked: false,
+            dependencies: Vec::new(),
+        })
+        .collect();
+    let preferred = options.preferred_versions.get(gem_name).map(String::as_str);
+    select_version_with_strategy(&indexed, requirement, preferred, options.resolution_strategy).map(|version| GemVersion { version: version.to_string() })
+}
+
+///
+/// APIのレスポンスの本文をデシリアライズする
+///
+/// * gem_name - Gemの名前
+/// * body - レスポンスの本文
+///
+/// return - 不正なJSONの場合は本文の先頭部分を含むエラーを返す
+///
+fn parse_response<T: DeserializeOwned>(gem_name: &str, body: &str) -> Result<T, Box<dyn Error>> {
+    serde_json::from_str(body).map_err(|_| GemfileError::InvalidApiResponse {
+        gem: gem_name.to_string(),
+        snippet: body.trim().chars().take(SNIPPET_LENGTH).collect(),
+    }.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+    use crate::client;
+    use crate::download::download_gem_with_options;
+    use crate::error::GemfileError;
+    use crate::gem_version::GemVersion;
+    use crate::options::{InstallOptions, RetryPolicy, RetrySettings, SourceQuery};
+    use crate::parser::Gem;
+    use crate::test_util::{test_directory, GemBuilder, MockResponse, MockServer};
+
+    ///
+    /// ソースのクエリパラメータが付与されるかのテスト
+    ///
+    #[tokio::test]
+    pub async fn source_query_test() {
+        let directory = test_directory("source_query");
+        let body = GemBuilder::new("private", "1.0.0").build();
+        let server = MockServer::start(move |request| {
+            match request.path.as_str() {
+                "/api/v1/gems/private.json?api_key=secret" => MockResponse::new(200, "{\"version\":\"1.0.0\"}"),
+                "/downloads/private-1.0.0.gem?api_key=secret" => MockResponse::new(200, body.clone()),
+                "/nested/downloads/private-1.0.0.gem?mirror=1&api_key=secret" => MockResponse::new(200, body.clone()),
+                _ => MockResponse::new(401, "Unauthorized"),
+            }
+        }).await;
+        let options = InstallOptions {
+            allow_insecure: true,
+            verify_checksums: false,
+            source_queries: vec![SourceQuery {
+                source: server.url.clone(),
+                name: "api_key".to_string(),
+                value: "secret".to_string(),
+            }],
+            ..Default::default()
+        };
+
+        // バージョンのAPIに付与されるか
+        let version = GemVersion::get_version_with_options(&server.url, "private", &options).await.unwrap();
+        assert_eq!(version.version, "1.0.0");
+
+        // ダウンロードのURLに付与されるか
+        let gem = Gem { name: "private".to_string(), version: version.version, ..Default::default() };
+        assert!(download_gem_with_options(&directory, &server.url, &gem, &options).await.is_ok());
+
+        // 既にクエリがあるURLにも正しく付与されるか
+        let source = format!("{}/nested", server.url);
+        let gem_url = format!("{}/downloads/private-1.0.0.gem?mirror=1", source);
+        let http_client = client::build_client(&options).unwrap();
+        let response = client::get(&http_client, &gem_url, "private-1.0.0", &options).await.unwrap();
+        assert_eq!(response.status(), 200);
+    }
+
+    ///
+    /// APIが不正なJSONを返した場合のテスト
+    ///
+    #[tokio::test]
+    pub async fn invalid_api_response_test() {
+        let server = MockServer::start(|_| MockResponse::new(200, "<html><body>Down for maintenance</body></html>")).await;
+        let options = InstallOptions { allow_insecure: true, ..Default::default() };
+
+        let error = GemVersion::get_version_with_options(&server.url, "rake", &options).await.unwrap_err();
+        let GemfileError::InvalidApiResponse { gem, snippet } = &error else {
+            panic!("unexpected error: {}", error);
+        };
+        assert_eq!(gem, "rake");
+        assert!(snippet.contains("Down for maintenance"));
+        assert!(error.to_string().starts_with("Invalid API response for rake: <html>"));
+    }
+
+    ///
+    /// バージョンの取得の再試行のテスト
+    ///
+    #[tokio::test]
+    pub async fn version_retry_test() {
+        let start_server = || {
+            let count = AtomicUsize::new(0);
+            // 最初の2回は失敗する
+            MockServer::start(move |_| match count.fetch_add(1, Ordering::SeqCst) {
+                0 | 1 => MockResponse::new(502, "Bad Gateway"),
+                _ => MockResponse::new(200, "{\"version\":\"2.0.0\"}"),
+            })
+        };
+        let settings = |attempts| RetrySettings {
+            attempts,
+            base_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(5),
+            jitter: false,
+        };
+
+        // バージョンのAPIの設定で再試行されるか
+        let server = start_server().await;
+        let options = InstallOptions {
+            allow_insecure: true,
+            retry: RetryPolicy { version_api: settings(3), download: settings(1) },
+            ..Default::default()
+        };
+        let version = GemVersion::get_version_with_options(&server.url, "flaky", &options).await.unwrap();
+        assert_eq!(version.version, "2.0.0");
+        assert_eq!(server.requests().len(), 3);
+
+        // ダウンロードの設定はバージョンの取得に影響しないか
+        let server = start_server().await;
+        let options = InstallOptions {
+            allow_insecure: true,
+            retry: RetryPolicy { version_api: settings(2), download: settings(5) },
+            ..Default::default()
+        };
+        assert!(GemVersion::get_version_with_options(&server.url, "flaky", &options).await.is_err());
+        assert_eq!(server.requests().len(), 2);
+
+        // 待ち時間は上限を超えないか
+        assert_eq!(settings(5).delay(1), Duration::from_millis(1));
+        assert_eq!(settings(5).delay(10), Duration::from_millis(5));
+    }
+
+    ///
+    /// バージョンを取得するAPIのパスを変更するテスト
+    ///
+    #[tokio::test]
+    pub async fn version_endpoint_test() {
+        let server = MockServer::start(|request| {
+            match request.path.as_str() {
+                "/registry/gems/custom/latest" => MockResponse::new(200, "{\"version\":\"3.1.4\"}"),
+                _ => MockResponse::not_found(),
+            }
+        }).await;
+
+        // デフォルトのパスでは取得できないか
+        let options = InstallOptions { allow_insecure: true, ..Default::default() };
+        assert!(GemVersion::get_version_with_options(&server.url, "custom", &options).await.is_err());
+        assert_eq!(server.requests()[0].path, "/api/v1/gems/custom.json");
+
+        // テンプレートに合わせたパスで取得できるか
+        let options = InstallOptions {
+            allow_insecure: true,
+            version_endpoint: "/registry/gems/{name}/latest".to_string(),
+            ..Default::default()
+        };
+        let version = GemVersion::get_version_with_options(&server.url, "custom", &options).await.unwrap();
+        assert_eq!(version.version, "3.1.4");
+    }
+}
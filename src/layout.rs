@@ -0,0 +1,276 @@
+//!
+//! インストール先のディレクトリ構成
+//!
+use std::error::Error;
+use std::fs::{copy, create_dir_all, read_dir, remove_dir, remove_dir_all, rename, write};
+use std::path::{Path, PathBuf};
+use crate::metadata::{read_metadata, GemMetadata};
+use crate::InstallInfo;
+
+/// RubyGemsの構成でGemの本体を置くディレクトリ
+pub const GEMS_DIRECTORY: &str = "gems";
+
+/// RubyGemsの構成でgemspecを置くディレクトリ
+pub const SPECIFICATIONS_DIRECTORY: &str = "specifications";
+
+/// RubyGemsの構成で.gemファイルを置くディレクトリ
+pub const CACHE_DIRECTORY: &str = "cache";
+
+///
+/// インストール先でのGemのディレクトリ構成
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Layout {
+    /// `{name}-{version}/`に配置する
+    #[default]
+    NameVersion,
+    /// `{name}/{version}/`に配置する
+    Nested,
+    /// RubyGemsと同じ構成で、本体を`gems/{name}-{version}/`、gemspecを`specifications/`、.gemファイルを`cache/`に配置する
+    RubyGems,
+}
+
+impl Layout {
+    ///
+    /// Gemの本体を解凍するディレクトリを取得する
+    ///
+    /// * install_dictionary - Gemのインストール先のディレクトリ
+    /// * name - Gemの名前
+    /// * version - Gemのバージョン
+    ///
+    /// return - Gemのディレクトリ
+    ///
+    pub fn gem_directory(&self, install_dictionary: &Path, name: &str, version: &str) -> PathBuf {
+        match self {
+            Layout::NameVersion => install_dictionary.join(format!("{}-{}", name, version)),
+            Layout::Nested => install_dictionary.join(name).join(version),
+            Layout::RubyGems => install_dictionary.join(GEMS_DIRECTORY).join(format!("{}-{}", name, version)),
+        }
+    }
+
+    ///
+    /// 本体以外に構成に必要なファイルを書き込む
+    ///
+    /// RubyGemsの構成では、metadata.gzから作成したRubyのgemspecと.gemファイルを配置する
+    ///
+    /// * install_dictionary - Gemのインストール先のディレクトリ
+    /// * gem_path - ダウンロードした.gemファイルのパス
+    /// * name - Gemの名前
+    /// * version - Gemのバージョン
+    ///
+    /// return - 書き込み処理の結果
+    ///
+    pub fn write_extra_files(&self, install_dictionary: &Path, gem_path: &Path, name: &str, version: &str) -> Result<(), Box<dyn Error>> {
+        if *self != Layout::RubyGems {
+            return Ok(());
+        }
+
+        let specifications_directory = install_dictionary.join(SPECIFICATIONS_DIRECTORY);
+        create_dir_all(&specifications_directory)?;
+        // RubyGemsは`specifications/`のgemspecをRubyのコードとして読み込む
+        let metadata = GemMetadata { name: name.to_string(), version: version.to_string(), ..read_metadata(gem_path)? };
+        write(specifications_directory.join(format!("{}-{}.gemspec", name, version)), metadata.to_ruby_gemspec())?;
+
+        let cache_directory = install_dictionary.join(CACHE_DIRECTORY);
+        create_dir_all(&cache_directory)?;
+        copy(gem_path, cache_directory.join(format!("{}-{}.gem", name, version)))?;
+
+        Ok(())
+    }
+}
+
+///
+/// インストール済みのGemを別のディレクトリ構成に移動する
+///
+/// 再ダウンロードは行わず、ディレクトリを移動してインストール結果のパスを更新する。
+/// `path:`で指定したGemのようにインストール先の外にあるGemは移動しない。
+/// 途中まで移動した状態にならないよう、移動する前にすべてのGemのディレクトリが存在するかを確認する。
+/// `RubyGems`の構成はgemspecと.gemファイルも必要なため、移動元・移動先のどちらに指定した場合もエラーにする(再インストールする)
+///
+/// * install_dictionary - Gemのインストール先のディレクトリ
+/// * from - 現在のディレクトリ構成
+/// * to - 移動後のディレクトリ構成
+/// * info - インストール結果の情報
+///
+/// return - 移動処理の結果
+///
+pub fn migrate_layout(install_dictionary: &Path, from: Layout, to: Layout, info: &mut InstallInfo) -> Result<(), Box<dyn Error>> {
+    if from == to {
+        return Ok(());
+    }
+    if from == Layout::RubyGems || to == Layout::RubyGems {
+        return Err(format!("Migrating from {:?} to {:?} is not supported; reinstall the gems with the RubyGems layout instead", from, to).into());
+    }
+
+    // インストール先の外にあるGemを除き、移動元がすべて存在するかを先に確認する
+    let mut moves = Vec::new();
+    for (index, installed) in info.installed.iter().enumerate() {
+        if !installed.install_path.starts_with(install_dictionary) {
+            continue;
+        }
+        let old_directory = from.gem_directory(install_dictionary, &installed.name, &installed.version);
+        if !old_directory.exists() {
+            return Err(format!("Gem directory {} does not exist", old_directory.display()).into());
+        }
+        moves.push((index, old_directory, to.gem_directory(install_dictionary, &installed.name, &installed.version)));
+    }
+
+    for (index, old_directory, new_directory) in moves {
+        // 移動先を用意
+        if new_directory.exists() {
+            remove_dir_all(&new_directory)?;
+        }
+        if let Some(parent) = new_directory.parent() {
+            create_dir_all(parent)?;
+        }
+        rename(&old_directory, &new_directory)?;
+
+        // 空になった親ディレクトリを削除
+        if let Some(parent) = old_directory.parent() {
+            if parent != install_dictionary && read_dir(parent)?.next().is_none() {
+                remove_dir(parent)?;
+            }
+        }
+
+        // Gemfileのパスを更新
+        for gemfile in info.find_gemfiles.iter_mut() {
+            if let Ok(relative) = gemfile.gemfile_path.strip_prefix(&old_directory) {
+                gemfile.gemfile_path = new_directory.join(relative);
+            }
+        }
+        info.installed[index].install_path = new_directory;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::read_to_string;
+    use crate::install_gems_with_options;
+    use crate::layout::{migrate_layout, Layout};
+    use crate::options::InstallOptions;
+    use crate::parser::{Gem, GemfileData};
+    use crate::test_util::{test_directory, GemBuilder, MockResponse, MockServer};
+
+    ///
+    /// RubyGemsと同じ構成でのインストールのテスト
+    ///
+    #[tokio::test]
+    pub async fn rubygems_layout_test() {
+        let directory = test_directory("rubygems_layout");
+        let install_directory = directory.join("root");
+        let body = GemBuilder::new("layout", "2.0.0")
+            .metadata("dependencies:\n- !ruby/object:Gem::Dependency\n  name: rack\n  requirement: !ruby/object:Gem::Requirement\n    requirements:\n    - - \"~>\"\n      - !ruby/object:Gem::Version\n        version: '2.0'\n  type: :runtime\n")
+            .build();
+        let expected = body.clone();
+        let server = MockServer::start(move |request| {
+            match request.path.as_str() {
+                "/downloads/layout-2.0.0.gem" => MockResponse::new(200, body.clone()),
+                _ => MockResponse::not_found(),
+            }
+        }).await;
+        let gemfile_data = GemfileData {
+            source: server.url.clone(),
+            gems: vec![Gem { name: "layout".to_string(), version: "2.0.0".to_string(), ..Default::default() }],
+            ..Default::default()
+        };
+        let options = InstallOptions { allow_insecure: true, verify_checksums: false, layout: Layout::RubyGems, ..Default::default() };
+        let info = install_gems_with_options(gemfile_data, &install_directory, &directory.join("cache"), &options).await.unwrap();
+
+        // 3つの場所に配置されているか
+        assert_eq!(info.installed[0].install_path, install_directory.join("gems/layout-2.0.0"));
+        assert!(install_directory.join("gems/layout-2.0.0/lib/layout.rb").exists());
+        let gemspec = read_to_string(install_directory.join("specifications/layout-2.0.0.gemspec")).unwrap();
+        assert!(gemspec.contains("Gem::Specification.new do |s|"));
+        assert!(gemspec.contains("s.name = \"layout\".freeze"));
+        assert!(gemspec.contains("s.version = \"2.0.0\".freeze"));
+        assert!(gemspec.contains("s.add_runtime_dependency(\"rack\".freeze, [\"~> 2.0\".freeze])"));
+        assert_eq!(std::fs::read(install_directory.join("cache/layout-2.0.0.gem")).unwrap(), expected);
+    }
+
+    ///
+    /// ディレクトリ構成の移動のテスト
+    ///
+    #[tokio::test]
+    pub async fn migrate_layout_test() {
+        let directory = test_directory("migrate_layout");
+        let install_directory = directory.join("install");
+        let body = GemBuilder::new("moving", "1.0.0").file("Gemfile", b"source 'https://rubygems.org'\n").build();
+        let server = MockServer::start(move |request| {
+            match request.path.as_str() {
+                "/downloads/moving-1.0.0.gem" => MockResponse::new(200, body.clone()),
+                _ => MockResponse::not_found(),
+            }
+        }).await;
+        let gemfile_data = GemfileData {
+            source: server.url.clone(),
+            gems: vec![Gem { name: "moving".to_string(), version: "1.0.0".to_string(), ..Default::default() }],
+            ..Default::default()
+        };
+        let options = InstallOptions { allow_insecure: true, verify_checksums: false, layout: Layout::Nested, ..Default::default() };
+
+        // Nestedでインストール
+        let mut info = install_gems_with_options(gemfile_data, &install_directory, &directory.join("cache"), &options).await.unwrap();
+        assert!(install_directory.join("moving/1.0.0/lib/moving.rb").exists());
+
+        // NameVersionに移動
+        migrate_layout(&install_directory, Layout::Nested, Layout::NameVersion, &mut info).unwrap();
+        let new_directory = install_directory.join("moving-1.0.0");
+        assert_eq!(read_to_string(new_directory.join("lib/moving.rb")).unwrap(), "module moving\nend\n");
+        assert!(!install_directory.join("moving").exists());
+        assert_eq!(info.installed[0].install_path, new_directory);
+        assert_eq!(info.find_gemfiles[0].gemfile_path, new_directory.join("Gemfile"));
+
+        // RubyGemsの構成への移動とRubyGemsの構成からの移動は、何も変更せずにエラーになるか
+        let error = migrate_layout(&install_directory, Layout::NameVersion, Layout::RubyGems, &mut info).unwrap_err();
+        assert!(error.to_string().contains("RubyGems"));
+        assert!(migrate_layout(&install_directory, Layout::RubyGems, Layout::NameVersion, &mut info).is_err());
+        assert!(new_directory.exists());
+        assert!(!install_directory.join("gems").exists());
+        assert!(!install_directory.join("specifications").exists());
+        assert_eq!(info.installed[0].install_path, new_directory);
+    }
+
+    ///
+    /// `path:`のGemを含むインストール結果の移動と、移動前の確認のテスト
+    ///
+    #[tokio::test]
+    pub async fn migrate_layout_validation_test() {
+        let directory = test_directory("migrate_layout_validation");
+        let install_directory = directory.join("install");
+        let local_directory = directory.join("local_gem");
+        std::fs::create_dir_all(&local_directory).unwrap();
+        let bodies = [("alpha", GemBuilder::new("alpha", "1.0.0").build()), ("beta", GemBuilder::new("beta", "1.0.0").build())];
+        let server = MockServer::start(move |request| {
+            bodies.iter()
+                .find(|(name, _)| request.path == format!("/downloads/{}-1.0.0.gem", name))
+                .map(|(_, body)| MockResponse::new(200, body.clone()))
+                .unwrap_or_else(MockResponse::not_found)
+        }).await;
+        let gemfile_data = GemfileData::parse_unresolved(&format!(
+            "source '{}'\ngem 'alpha', '1.0.0'\ngem 'beta', '1.0.0'\ngem 'local_gem', path: '{}'\n",
+            server.url, local_directory.display(),
+        )).unwrap();
+        let options = InstallOptions { allow_insecure: true, verify_checksums: false, ..Default::default() };
+        let info = install_gems_with_options(gemfile_data, &install_directory, &directory.join("cache"), &options).await.unwrap();
+        assert_eq!(info.installed.len(), 3);
+
+        // 移動元が1つでも存在しない場合は、どのGemも移動せずにエラーになるか
+        let mut broken = info.clone();
+        std::fs::rename(install_directory.join("beta-1.0.0"), directory.join("beta-1.0.0")).unwrap();
+        assert!(migrate_layout(&install_directory, Layout::NameVersion, Layout::Nested, &mut broken).is_err());
+        assert!(install_directory.join("alpha-1.0.0").exists());
+        assert!(!install_directory.join("alpha").exists());
+        std::fs::rename(directory.join("beta-1.0.0"), install_directory.join("beta-1.0.0")).unwrap();
+
+        // `path:`のGemは移動せず、その他のGemを移動するか
+        let mut info = info;
+        migrate_layout(&install_directory, Layout::NameVersion, Layout::Nested, &mut info).unwrap();
+        assert!(install_directory.join("alpha/1.0.0").exists());
+        assert!(install_directory.join("beta/1.0.0").exists());
+        let local = info.installed.iter().find(|installed| installed.name == "local_gem").unwrap();
+        assert_eq!(local.install_path, local_directory);
+        assert!(local_directory.exists());
+    }
+}
@@ -1,27 +1,188 @@
+use std::borrow::Cow;
+use std::collections::BTreeSet;
 use std::error::Error;
+use std::future::Future;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use futures::future::join_all;
+use futures::future::{join_all, try_join_all};
 use serde::{Deserialize, Serialize};
-use tokio::fs::read_to_string;
-use tokio::sync::Mutex;
-use crate::parser::GemfileData;
+use tokio::fs::{read_dir, read_to_string};
+use tokio::sync::{Mutex, Semaphore};
+use crate::cleanup::CleanupGuard;
+use crate::error::GemfileError;
+use crate::events::{EventHandler, InstallEvent};
+use crate::options::{ExistingDirectory, HttpClient, InstallOptions};
+use crate::download::{split_gem_file_name, DownloadLimits, LOCAL_SOURCE_PREFIX};
+use crate::parser::{Gem, GemSource, GemfileData};
+use crate::resolution::{Resolution, ResolvedGem};
+use crate::unpack_gem::GemSignature;
 
 pub mod parser;
 pub mod download;
 pub mod unpack_gem;
 pub mod unpack_tar_gz;
 pub mod gem_version;
+pub mod options;
+pub mod error;
+pub mod availability;
+mod client;
+mod cleanup;
+pub mod credentials;
+pub mod resolution;
+pub mod cache;
+pub mod cache_lock;
+pub mod bundle;
+pub mod bundle_config;
+pub mod events;
+pub mod state;
+pub mod diff;
+pub mod lockfile;
+pub mod version;
+pub mod metadata;
+pub mod compact_index;
+pub mod resolver;
+pub mod layout;
+pub mod buffer_pool;
+#[cfg(feature = "zip")]
+pub mod zip_sink;
+
+#[cfg(test)]
+pub(crate) mod test_util;
 
 ///
 /// インストール結果の情報
 ///
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
 pub struct InstallInfo {
     // インストールしたGemの一覧
     pub install_gems: Vec<String>,
+    // インストールしたGemごとの詳細な情報
+    pub installed: Vec<InstalledGemInfo>,
     // Gemfileが含まれていた場合、すべてのGem名とGemfileのパス
     pub find_gemfiles: Vec<FindGemFileInfo>,
+    // ネイティブ拡張のビルドが必要なGemの一覧
+    #[serde(default)]
+    pub requires_build: Vec<String>,
+    // インストールに失敗したGemの一覧
+    #[serde(default)]
+    pub failed_gems: Vec<FailedGemInfo>,
+    // ダウンロードの対象外としてインストールしなかったGemの一覧
+    #[serde(default)]
+    pub skipped_gems: Vec<SkippedGemInfo>,
+}
+
+impl InstallInfo {
+    ///
+    /// 見つかったGemfileのパスを重複を除いて並び替えた一覧を取得する
+    ///
+    /// return - Gemfileのパスの一覧
+    ///
+    pub fn all_gemfile_paths(&self) -> Vec<PathBuf> {
+        let paths: BTreeSet<PathBuf> = self.find_gemfiles.iter()
+            .map(|find_gemfile| find_gemfile.gemfile_path.clone())
+            .collect();
+        paths.into_iter().collect()
+    }
+
+    ///
+    /// インストールしたGemの`lib`ディレクトリの一覧を取得する
+    ///
+    /// Rubyの`$LOAD_PATH`に追加するディレクトリとして使用する。`lib`ディレクトリがないGemは含めない
+    ///
+    /// * install_dictionary - Gemのインストール先のディレクトリ。インストール先からの相対パスで記録されたGemに使用する
+    ///
+    /// return - `lib`ディレクトリのパスの一覧
+    ///
+    pub fn load_paths(&self, install_dictionary: &Path) -> Vec<PathBuf> {
+        self.installed.iter()
+            .map(|gem| match gem.install_path.is_absolute() || gem.install_path.starts_with(install_dictionary) {
+                true => gem.install_path.join("lib"),
+                false => install_dictionary.join(&gem.install_path).join("lib"),
+            })
+            .filter(|path| path.is_dir())
+            .collect()
+    }
+}
+
+///
+/// インストールしたGemの情報
+///
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InstalledGemInfo {
+    // Gemの名前
+    pub name: String,
+    // Gemのバージョン
+    pub version: String,
+    // Gemの本体を解凍したディレクトリ
+    pub install_path: PathBuf,
+    // 署名の情報
+    pub signature: GemSignature,
+    // ネイティブ拡張を含み、ビルドが必要か
+    #[serde(default)]
+    pub has_native_extension: bool,
+    // ダウンロードした.gemファイルのSHA256(16進数)
+    #[serde(default)]
+    pub sha256: String,
+    // プラットフォーム向けにビルドされた.gemファイルの場合はそのプラットフォーム(例: `x86_64-linux`)
+    #[serde(default)]
+    pub platform: Option<String>,
+}
+
+///
+/// ダウンロードした.gemファイルの情報
+///
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DownloadedGem {
+    // Gemの名前
+    pub name: String,
+    // Gemのバージョン
+    pub version: String,
+    // ダウンロードした.gemファイルのパス
+    pub path: PathBuf,
+    // .gemファイルのSHA256(16進数)
+    pub sha256: String,
+    // プラットフォーム向けにビルドされた.gemファイルの場合はそのプラットフォーム(例: `x86_64-linux`)
+    pub platform: Option<String>,
+}
+
+///
+/// インストールの処理の段階
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InstallStage {
+    /// .gemファイルのダウンロード
+    Download,
+    /// .gemファイルの解凍
+    UnpackGem,
+    /// 本体の.tar.gzの解凍
+    UnpackTarGz,
+    /// 構成に必要なその他のファイルの配置
+    Finalize,
+}
+
+///
+/// インストールに失敗したGemの情報
+///
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FailedGemInfo {
+    // Gemの名前とバージョン
+    pub gem_name: String,
+    // 失敗した段階
+    pub stage: InstallStage,
+    // エラーのメッセージ
+    pub error: String,
+}
+
+///
+/// インストールしなかったGemの情報
+///
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SkippedGemInfo {
+    // Gemの名前
+    pub gem_name: String,
+    // インストールしなかった理由
+    pub reason: String,
 }
 
 ///
@@ -44,12 +205,41 @@ pub struct FindGemFileInfo {
 ///
 /// return -  インストール処理の結果
 ///
-pub async fn install_from_gemfile_file(gemfile: &Path, install_dictionary: &Path, cache_directory: &Path) -> Result<InstallInfo, Box<dyn Error>> {
+pub async fn install_from_gemfile_file(gemfile: &Path, install_dictionary: &Path, cache_directory: &Path) -> Result<InstallInfo, GemfileError> {
+    install_from_gemfile_file_with_options(gemfile, &InstallOptions::new(install_dictionary, cache_directory)).await
+}
+
+///
+/// オプションを指定して、Gemfileを読み込みGemのインストールを行う
+///
+/// インストール先とキャッシュのディレクトリは`options`の`install_directory`と`cache_directory`を使用する
+///
+/// * gemfile - Gemfileのパス
+/// * options - インストール処理のオプション(`InstallOptions::new`で作成する)
+///
+/// return - インストール処理の結果
+///
+pub async fn install_from_gemfile_file_with_options(gemfile: &Path, options: &InstallOptions) -> Result<InstallInfo, GemfileError> {
     // Gemfileの内容を取得
     let gemfile_context = read_to_string(gemfile).await?;
+    let mut gemfile_data = parser::GemfileData::parse_unresolved(&gemfile_context)
+        .map_err(|error| GemfileError::Parse { message: error.to_string() })?;
+
+    // `path:`の相対パスはGemfileのあるディレクトリからのパスとする
+    let gemfile_directory = gemfile.parent().unwrap_or(Path::new(""));
+    gemfile_data.gems.iter_mut().for_each(|gem| gem.rebase_path(gemfile_directory));
+
+    // Gemfile.lockがある場合は、Gemfileの制約よりも記録されたバージョンを優先する
+    let lockfile_path = lockfile::lockfile_path(gemfile);
+    if lockfile_path.is_file() {
+        let lockfile = lockfile::Lockfile::parse(&read_to_string(&lockfile_path).await?)
+            .map_err(|error| GemfileError::Parse { message: error.to_string() })?;
+        lockfile.pin(&mut gemfile_data);
+    }
 
     // Gemのダウンロード
-    install_from_gemfile_literal(&gemfile_context, install_dictionary, cache_directory).await
+    let (install_directory, cache_directory) = options.directories()?;
+    install_gems_with_options(gemfile_data, install_directory, cache_directory, options).await
 }
 
 ///
@@ -61,10 +251,158 @@ pub async fn install_from_gemfile_file(gemfile: &Path, install_dictionary: &Path
 ///
 /// return - インストール処理の結果
 ///
-pub async fn install_from_gemfile_literal(gemfile_context: &str, install_dictionary: &Path, cache_directory: &Path) -> Result<InstallInfo, Box<dyn Error>> {
+pub async fn install_from_gemfile_literal(gemfile_context: &str, install_dictionary: &Path, cache_directory: &Path) -> Result<InstallInfo, GemfileError> {
+    install_from_gemfile_literal_with_options(gemfile_context, &InstallOptions::new(install_dictionary, cache_directory)).await
+}
+
+///
+/// オプションを指定して、Gemfileの文字列のデータからGemのインストールを行う
+///
+/// インストール先とキャッシュのディレクトリは`options`の`install_directory`と`cache_directory`を使用する
+///
+/// * gemfile_context - Gemfileの内容
+/// * options - インストール処理のオプション(`InstallOptions::new`で作成する)
+///
+/// return - インストール処理の結果
+///
+pub async fn install_from_gemfile_literal_with_options(gemfile_context: &str, options: &InstallOptions) -> Result<InstallInfo, GemfileError> {
     // パース
-    let gemfile_data = parser::GemfileData::parse(gemfile_context).await?;
+    let gemfile_data = parser::GemfileData::parse_unresolved(gemfile_context)
+        .map_err(|error| GemfileError::Parse { message: error.to_string() })?;
 
+    let (install_directory, cache_directory) = options.directories()?;
+    install_gems_with_options(gemfile_data, install_directory, cache_directory, options).await
+}
+
+///
+/// Gemfile.lockを読み込み、記録されたバージョンのGemのインストールを行う
+///
+/// * lock_path - Gemfile.lockのパス
+/// * install_dictionary - Gemのインストール先のディレクトリ
+/// * cache_directory - Gemのダウンロード先のキャッシュディレクトリ
+///
+/// return - インストール処理の結果
+///
+pub async fn install_from_lockfile(lock_path: &Path, install_dictionary: &Path, cache_directory: &Path) -> Result<InstallInfo, GemfileError> {
+    install_from_lockfile_with_options(lock_path, &InstallOptions::new(install_dictionary, cache_directory)).await
+}
+
+///
+/// オプションを指定して、Gemfile.lockを読み込み記録されたバージョンのGemのインストールを行う
+///
+/// インストール先とキャッシュのディレクトリは`options`の`install_directory`と`cache_directory`を使用する
+///
+/// * lock_path - Gemfile.lockのパス
+/// * options - インストール処理のオプション(`InstallOptions::new`で作成する)
+///
+/// return - インストール処理の結果
+///
+pub async fn install_from_lockfile_with_options(lock_path: &Path, options: &InstallOptions) -> Result<InstallInfo, GemfileError> {
+    let lockfile = lockfile::Lockfile::parse(&read_to_string(lock_path).await?)
+        .map_err(|error| GemfileError::Parse { message: error.to_string() })?;
+
+    let (install_directory, cache_directory) = options.directories()?;
+    install_gems_with_options(lockfile.to_gemfile_data(false), install_directory, cache_directory, options).await
+}
+
+///
+/// .gemファイルが置かれたディレクトリから、ネットワークを使用せずにGemのインストールを行う
+///
+/// `vendor/cache`や`bundle::export_bundle`で作成したディレクトリに含まれるすべての.gemファイルをインストールする
+///
+/// * gem_directory - .gemファイルが置かれたディレクトリ
+/// * install_dictionary - Gemのインストール先のディレクトリ
+/// * cache_directory - Gemの解凍に使用するキャッシュディレクトリ
+///
+/// return - インストール処理の結果
+///
+pub async fn install_from_cache_dir(gem_directory: &Path, install_dictionary: &Path, cache_directory: &Path) -> Result<InstallInfo, GemfileError> {
+    // ディレクトリ内の.gemファイルからGemの一覧を作成
+    let mut gems = Vec::new();
+    let mut entries = read_dir(gem_directory).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let file_name = entry.file_name().to_string_lossy().to_string();
+        if !file_name.ends_with(".gem") {
+            continue;
+        }
+        let Some((name, version)) = split_gem_file_name(&file_name) else {
+            continue;
+        };
+        gems.push(Gem { name, version, ..Default::default() });
+    }
+    gems.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let gemfile_data = GemfileData {
+        source: format!("{}{}", LOCAL_SOURCE_PREFIX, gem_directory.display()),
+        gems,
+        ..Default::default()
+    };
+    install_gems(gemfile_data, install_dictionary, cache_directory).await
+}
+
+///
+/// 複数の.gemファイルをまとめたtarから、ネットワークを使用せずにGemのインストールを行う
+///
+/// tarに含まれる.gemファイルをキャッシュディレクトリに取り出してインストールする。
+/// Gemfileを指定した場合は、Gemfileの制約を満たす最新の.gemファイルのみをインストールし、制約を満たすものがない場合はエラーにする
+///
+/// * tarball - .gemファイルをまとめたtarのパス
+/// * gemfile_data - インストールするGemを絞り込むGemfileの読み込み済みデータ。Noneの場合はすべてをインストールする
+/// * install_dictionary - Gemのインストール先のディレクトリ
+/// * cache_directory - .gemファイルを取り出すキャッシュディレクトリ
+///
+/// return - インストール処理の結果
+///
+pub async fn install_from_gem_tarball(tarball: &Path, gemfile_data: Option<GemfileData>, install_dictionary: &Path, cache_directory: &Path) -> Result<InstallInfo, GemfileError> {
+    std::fs::create_dir_all(cache_directory)?;
+
+    // .gemファイルのみを取り出す(ディレクトリの構成は無視する)
+    let mut bundled = Vec::new();
+    let mut archive = tar::Archive::new(std::fs::File::open(tarball)?);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let Some(file_name) = entry.path()?.file_name().map(|file_name| file_name.to_string_lossy().to_string()) else {
+            continue;
+        };
+        if !file_name.ends_with(".gem") {
+            continue;
+        }
+        let Some((name, version)) = split_gem_file_name(&file_name) else {
+            continue;
+        };
+        entry.unpack(cache_directory.join(&file_name))?;
+        bundled.push(Gem { name, version, ..Default::default() });
+    }
+    bundled.sort_by(|a, b| a.name.cmp(&b.name));
+
+    // Gemfileが指定された場合は制約を満たすものを選択する
+    let gems = match gemfile_data {
+        None => bundled,
+        Some(gemfile_data) => gemfile_data.gems.into_iter()
+            .map(|gem| {
+                let requirement = gem.version_requirement();
+                let selected = bundled.iter()
+                    .filter(|bundled| bundled.name == gem.name)
+                    .filter_map(|bundled| version::Version::parse(&bundled.version).ok())
+                    .filter(|bundled| if gem.version.is_empty() || !gem.is_exact() {
+                        requirement.matches(bundled)
+                    } else {
+                        bundled.as_str() == gem.version
+                    })
+                    .max();
+                let Some(selected) = selected else {
+                    return Err(GemfileError::Other { message: format!("{} {} not found in {}", gem.name, requirement, tarball.display()) });
+                };
+                Ok(Gem { version: selected.to_string(), source: None, ..gem })
+            })
+            .collect::<Result<Vec<Gem>, GemfileError>>()?,
+    };
+
+    let gemfile_data = GemfileData {
+        source: format!("{}{}", LOCAL_SOURCE_PREFIX, cache_directory.display()),
+        gems,
+        ..Default::default()
+    };
     install_gems(gemfile_data, install_dictionary, cache_directory).await
 }
 
@@ -77,80 +415,483 @@ pub async fn install_from_gemfile_literal(gemfile_context: &str, install_diction
 ///
 /// return - インストール処理の結果
 ///
-pub async fn install_gems(gemfile_data: GemfileData, install_dictionary: &Path, cache_directory: &Path) -> Result<InstallInfo, Box<dyn Error>>{
+pub async fn install_gems(gemfile_data: GemfileData, install_dictionary: &Path, cache_directory: &Path) -> Result<InstallInfo, GemfileError>{
+    install_gems_with_options(gemfile_data, install_dictionary, cache_directory, &InstallOptions::default()).await
+}
+
+///
+/// 進行状況を通知しながらGemのインストールを行う
+///
+/// * gemfile_data - Gemfileの読み込み済みデータ
+/// * install_dictionary - Gemのインストール先のディレクトリ
+/// * cache_directory - Gemのダウンロード先のキャッシュディレクトリ
+/// * on_event - 各Gemの処理の段階ごとに呼び出される関数。Noneの場合は`install_gems`と同じ
+///
+/// return - インストール処理の結果
+///
+pub async fn install_gems_with_progress(gemfile_data: GemfileData, install_dictionary: &Path, cache_directory: &Path, on_event: Option<EventHandler>) -> Result<InstallInfo, GemfileError> {
+    let options = InstallOptions { on_event, ..Default::default() };
+    install_gems_with_options(gemfile_data, install_dictionary, cache_directory, &options).await
+}
+
+///
+/// オプションを指定してGemのインストールを行う
+///
+/// * gemfile_data - Gemfileの読み込み済みデータ
+/// * install_dictionary - Gemのインストール先のディレクトリ
+/// * cache_directory - Gemのダウンロード先のキャッシュディレクトリ
+/// * options - インストール処理のオプション
+///
+/// return - インストール処理の結果
+///
+pub async fn install_gems_with_options(mut gemfile_data: GemfileData, install_dictionary: &Path, cache_directory: &Path, options: &InstallOptions) -> Result<InstallInfo, GemfileError>{
+    // シンボリックリンクのディレクトリはリンク先に書き込むため、リンク切れの場合は作成できない
+    for directory in [install_dictionary, cache_directory] {
+        if directory.is_symlink() && !directory.exists() {
+            return Err(GemfileError::Other { message: format!("Symbolic link {} points to a missing directory", directory.display()) });
+        }
+    }
+
+    gemfile_data.gems.retain(|gem| options.includes_gem(gem));
+    // Gitのリポジトリから取得するGemはレジストリからダウンロードせず、理由を記録する
+    let skipped_gems: Vec<SkippedGemInfo> = gemfile_data.gems.iter()
+        .filter_map(|gem| match &gem.origin {
+            GemSource::Git { url, .. } => Some(SkippedGemInfo {
+                gem_name: gem.name.clone(),
+                reason: format!("Sourced from git repository {}, which is not downloaded", url),
+            }),
+            GemSource::Registry | GemSource::Path(_) => None,
+        })
+        .collect();
+    for skipped in &skipped_gems {
+        events::emit(options, || InstallEvent::Warning { gem: skipped.gem_name.clone(), message: skipped.reason.clone() });
+    }
+    // ローカルのディレクトリを参照するGemはダウンロードせず、そのディレクトリをインストール先として記録する
+    let mut local_labels = Vec::new();
+    let mut local_installed = Vec::new();
+    let mut local_gemfiles = Vec::new();
+    let mut local_failed = Vec::new();
+    for gem in &gemfile_data.gems {
+        let GemSource::Path(path) = &gem.origin else {
+            continue;
+        };
+        let label = if gem.version.is_empty() { gem.name.clone() } else { format!("{}-{}", gem.name, gem.version) };
+        if !path.is_dir() {
+            local_failed.push(FailedGemInfo {
+                gem_name: label,
+                stage: InstallStage::Download,
+                error: format!("Local path {} does not exist", path.display()),
+            });
+            continue;
+        }
+        if let Some(gemfile_path) = options.gemfile_names.iter().map(|name| path.join(name)).find(|gemfile_path| gemfile_path.is_file()) {
+            local_gemfiles.push(FindGemFileInfo { gem_name: label.clone(), gemfile_path });
+        }
+        local_installed.push(InstalledGemInfo {
+            name: gem.name.clone(),
+            version: gem.version.clone(),
+            install_path: path.clone(),
+            signature: GemSignature::default(),
+            has_native_extension: false,
+            sha256: String::new(),
+            platform: None,
+        });
+        local_labels.push(label);
+    }
+    // バージョンを解決し、ダウンロードの準備をする
+    let DownloadPlan { options, _cache_lock, semaphore, limits, gemfile_data } = prepare_download(gemfile_data, cache_directory, options).await?;
+    let options: &InstallOptions = &options;
+    // Gemfile.lockに記録するため、解決したバージョンを残す
+    let resolved_gems = options.write_lockfile.as_ref().map(|_| gemfile_data.gems.clone());
 
     // インストールしたGemの一覧
-    let installed_gems: Arc<Mutex<Vec<String>>> = Arc::new(Mutex::new(Vec::new()));
+    let installed_gems: Arc<Mutex<Vec<String>>> = Arc::new(Mutex::new(local_labels));
+    // インストールしたGemの詳細な情報
+    let installed: Arc<Mutex<Vec<InstalledGemInfo>>> = Arc::new(Mutex::new(local_installed));
     // インストールしたGemに含まれていたGemfileのパス
-    let gemfiles: Arc<Mutex<Vec<FindGemFileInfo>>> = Arc::new(Mutex::new(Vec::new()));
+    let gemfiles: Arc<Mutex<Vec<FindGemFileInfo>>> = Arc::new(Mutex::new(local_gemfiles));
+    // インストールに失敗したGem
+    let failed_gems: Arc<Mutex<Vec<FailedGemInfo>>> = Arc::new(Mutex::new(local_failed));
+    // インストールしなかったGem
+    let skipped_gems: Arc<Mutex<Vec<SkippedGemInfo>>> = Arc::new(Mutex::new(skipped_gems));
+    // ブロッキング用のスレッドで解凍する際に渡すオプション
+    let shared_options = Arc::new(options.clone());
 
     // gemをすべてダウンロード
+    let total = gemfile_data.gems.len();
     let tasks: Vec<_> = gemfile_data.gems.into_iter().map(|gem| {
+        let shared_options = Arc::clone(&shared_options);
         let installed_gems = Arc::clone(&installed_gems);
+        let installed = Arc::clone(&installed);
         let gemfiles = Arc::clone(&gemfiles);
-        let source = gemfile_data.source.clone();
+        let failed_gems = Arc::clone(&failed_gems);
+        let skipped_gems = Arc::clone(&skipped_gems);
+        let limits = &limits;
+        let source = gem.source.clone().unwrap_or_else(|| gemfile_data.source.clone());
+        let semaphore = &semaphore;
 
         async move {
-            // ダウンロード
-            let download_result = download::download_gem(cache_directory, &source, &gem).await;
-            let Ok(download_result) = download_result else {
-                return;
-            };
-            let gem_name = download_result.file_stem();
-            let Some(gem_name) = gem_name else {
-                return;
-            };
-
-            // キャッシュディレクトリ
-            let cache_directory =  &cache_directory.join(gem_name);
+            let _permit = semaphore.acquire().await?;
+
+            // 容量が不足している場合は新しいダウンロードを開始しない
+            if limits.is_stopped() {
+                return Ok(());
+            }
+
+            let label = format!("{}-{}", gem.name, gem.version);
+            events::emit(options, || InstallEvent::Started { gem: label.clone(), total });
+
             // gemの本体を置くディレクトリ
-            let gems_directory = &install_dictionary.join(gem_name);
-
-            // .gemを解凍
-            let gz_result = unpack_gem::unpack_gem(&download_result, cache_directory);
-            let Ok(gz_result) = gz_result else {
-                return;
-            };
-
-            // .tar.gzを解凍
-            let tar_gz_result = unpack_tar_gz::unpack_tar_gz(&gz_result, cache_directory, gems_directory);
-            let Ok(tar_gz_result) = tar_gz_result else {
-                return;
-            };
-
-            let gem_name = gem_name.to_string_lossy().to_string();
-            // インストール一覧に追加
-            installed_gems.lock().await.push(gem_name.clone());
-
-            // gemfileのパスを追加
-            if let Some(gemfile) = tar_gz_result {
-                gemfiles.lock().await.push(FindGemFileInfo{
-                    gem_name,
-                    gemfile_path: gemfile,
+            let gems_directory = &options.gem_directory(install_dictionary, &gem, &source);
+            // 既存のディレクトリを残す場合はダウンロードせずに理由を記録する
+            if options.on_existing == ExistingDirectory::Skip && gems_directory.exists() {
+                let reason = format!("Directory {} already exists", gems_directory.display());
+                events::emit(options, || InstallEvent::Warning { gem: gem.name.clone(), message: reason.clone() });
+                skipped_gems.lock().await.push(SkippedGemInfo { gem_name: gem.name.clone(), reason });
+                return Ok(());
+            }
+
+            // 現在の処理の段階
+            let mut stage = InstallStage::Download;
+            let result: Result<(), Box<dyn Error>> = async {
+                if options.on_existing == ExistingDirectory::Error && gems_directory.exists() {
+                    return Err(GemfileError::DirectoryExists { path: gems_directory.clone() }.into());
+                }
+
+                // ダウンロード
+                let download_result = download::download_gem_with_limits(cache_directory, &source, &gem, options, limits).await?;
+                events::emit(options, || InstallEvent::Downloaded { gem: label.clone() });
+                // Gemfile.lockのチェックサムと照合するためにハッシュを記録
+                let sha256 = download::file_sha256(&download_result)?;
+
+                // キャッシュディレクトリ(内容で管理する構成でもGemの名前で解凍する)
+                let cache_directory =  &cache_directory.join(&label);
+
+                // .gemを解凍
+                stage = InstallStage::UnpackGem;
+                let (download_path, unpack_directory, unpack_options) = (download_result.clone(), cache_directory.clone(), Arc::clone(&shared_options));
+                let (gz_result, signature, has_native_extension, platform) = run_blocking(move || {
+                    // プールが指定されている場合は、解凍が終わるまでバッファを借りて同時に行う解凍の数を抑える
+                    let _pooled = unpack_options.buffer_pool.as_ref().map(|buffer_pool| buffer_pool.acquire());
+                    let gz_result = unpack_gem::unpack_gem_with_payload(&download_path, &unpack_directory, unpack_options.payload_name.as_deref())?;
+
+                    // 署名の有無を確認
+                    let signature = unpack_gem::read_signature(&unpack_directory).unwrap_or_default();
+                    // ネイティブ拡張の有無と、取得した.gemファイルのプラットフォームを確認
+                    let metadata = metadata::read_metadata(&download_path).ok();
+                    let has_native_extension = metadata.as_ref().is_some_and(|metadata| metadata.has_native_extension());
+                    let platform = metadata.and_then(|metadata| metadata.binary_platform());
+                    Ok((gz_result, signature, has_native_extension, platform))
+                }).await?;
+
+                // .tar.gzを解凍(インストールが完了しなかった場合は展開途中のディレクトリを削除する)
+                stage = InstallStage::UnpackTarGz;
+                let extracting = CleanupGuard::new(gems_directory.clone());
+                let (unpack_directory, install_directory) = (cache_directory.clone(), gems_directory.clone());
+                let tar_gz_result = run_blocking(move || {
+                    unpack_tar_gz::unpack_tar_gz_with_options(&gz_result, &unpack_directory, &install_directory, &shared_options)
+                }).await?;
+                events::emit(options, || InstallEvent::Unpacked { gem: label.clone() });
+
+                // 構成に必要なその他のファイルを配置
+                stage = InstallStage::Finalize;
+                options.layout.write_extra_files(install_dictionary, &download_result, &gem.name, &gem.version)?;
+                // .gemファイルをインストール先にも保存
+                if options.vendor_gems {
+                    bundle::vendor_gem(install_dictionary, &download_result, &gem.name, &gem.version)?;
+                }
+
+                extracting.keep();
+
+                let gem_name = label.clone();
+                // インストール一覧に追加
+                installed_gems.lock().await.push(gem_name.clone());
+                installed.lock().await.push(InstalledGemInfo {
+                    name: gem.name.clone(),
+                    version: gem.version.clone(),
+                    install_path: gems_directory.clone(),
+                    signature,
+                    has_native_extension,
+                    sha256,
+                    platform,
                 });
+                events::emit(options, || InstallEvent::Installed { gem: label.clone() });
+
+                // gemfileのパスを追加
+                if let Some(gemfile) = tar_gz_result {
+                    gemfiles.lock().await.push(FindGemFileInfo{
+                        gem_name,
+                        gemfile_path: gemfile,
+                    });
+                }
+
+                Ok(())
+            }.await;
+
+            // 失敗したことを記録して通知(容量不足の場合は他のダウンロードも中断させる)
+            if let Err(error) = &result {
+                limits.record_error(error.as_ref());
+                failed_gems.lock().await.push(FailedGemInfo {
+                    gem_name: label.clone(),
+                    stage,
+                    error: error.to_string(),
+                });
+                events::emit(options, || InstallEvent::Failed { gem: label.clone(), error: error.to_string() });
             }
+            result
         }
     }).collect();
-    join_all(tasks).await;
+    run_tasks(tasks, options).await?;
 
     // Arcを外す
     let Ok(installed_gems) = Arc::try_unwrap(installed_gems) else {
-        return Err("installed_gems unwrap error".into());
+        return Err(GemfileError::Other { message: "installed_gems unwrap error".to_string() });
+    };
+    let Ok(installed) = Arc::try_unwrap(installed) else {
+        return Err(GemfileError::Other { message: "installed unwrap error".to_string() });
     };
     let Ok(gemfiles) = Arc::try_unwrap(gemfiles) else {
-        return Err("gemfiles unwrap error".into());
+        return Err(GemfileError::Other { message: "gemfiles unwrap error".to_string() });
+    };
+    let Ok(failed_gems) = Arc::try_unwrap(failed_gems) else {
+        return Err(GemfileError::Other { message: "failed_gems unwrap error".to_string() });
+    };
+    let Ok(skipped_gems) = Arc::try_unwrap(skipped_gems) else {
+        return Err(GemfileError::Other { message: "skipped_gems unwrap error".to_string() });
     };
 
+    // 容量が不足した場合は完了したGemの一覧と共にエラーを返す
+    if limits.is_out_of_space() {
+        return Err(GemfileError::OutOfSpace {
+            completed: installed_gems.into_inner(),
+        });
+    }
+    // ダウンロードの上限を超えた場合も同様にエラーを返す
+    if limits.is_budget_exceeded() {
+        return Err(GemfileError::BudgetExceeded {
+            completed: installed_gems.into_inner(),
+        });
+    }
+
+    let installed = installed.into_inner();
+    events::emit(options, || InstallEvent::Finished { installed: installed.len(), total });
+    let mut requires_build: Vec<String> = installed.iter()
+        .filter(|gem| gem.has_native_extension)
+        .map(|gem| format!("{}-{}", gem.name, gem.version))
+        .collect();
+    requires_build.sort();
+
+    // すべて成功した場合のみGemfile.lockを書き込む
+    let failed_gems = failed_gems.into_inner();
+    if let (Some(path), Some(gems), true) = (&options.write_lockfile, &resolved_gems, failed_gems.is_empty()) {
+        lockfile::write_lockfile(path, gems, &gemfile_data.source).await?;
+    }
+
     Ok(InstallInfo{
         install_gems: installed_gems.into_inner(),
+        installed,
         find_gemfiles: gemfiles.into_inner(),
+        requires_build,
+        failed_gems,
+        skipped_gems: skipped_gems.into_inner(),
     })
 }
 
+///
+/// バージョンの解決と.gemファイルのダウンロードのみを行う
+///
+/// 解凍は行わないため、独自の方法で展開する場合に使用する。
+/// `confirm`と`max_total_bytes`はインストールと同様に適用し、依存関係はダウンロードした.gemファイルから読み込む
+///
+/// * gemfile_data - Gemfileの読み込み済みデータ
+/// * cache_directory - Gemのダウンロード先のキャッシュディレクトリ
+/// * options - インストール処理のオプション
+///
+/// return - バージョンを解決した結果とダウンロードしたGemの一覧
+///
+pub async fn resolve_and_download(mut gemfile_data: GemfileData, cache_directory: &Path, options: &InstallOptions) -> Result<(Resolution, Vec<DownloadedGem>), GemfileError> {
+    // `groups`や`platforms`の指定で除外されたGemは含めない(Gitやローカルのディレクトリから取得するGemは`prepare_download`で除外する)
+    gemfile_data.gems.retain(|gem| options.includes_gem(gem));
+    let DownloadPlan { options, _cache_lock, semaphore, limits, gemfile_data } = prepare_download(gemfile_data, cache_directory, options).await?;
+    let options: &InstallOptions = &options;
+
+    // 解決した順序のままダウンロード
+    let results = join_all(gemfile_data.gems.iter().map(|gem| {
+        let source = gem.source.clone().unwrap_or_else(|| gemfile_data.source.clone());
+        let (semaphore, limits) = (&semaphore, &limits);
+        async move {
+            let _permit = semaphore.acquire().await?;
+            // 容量の不足や上限により中断した場合は新しいダウンロードを開始しない
+            if limits.is_stopped() {
+                return Ok(None);
+            }
+            let result: Result<_, Box<dyn Error>> = async {
+                let path = download::download_gem_with_limits(cache_directory, &source, gem, options, limits).await?;
+                let sha256 = download::file_sha256(&path)?;
+                let metadata = metadata::read_metadata(&path).ok();
+                let resolved = ResolvedGem {
+                    name: gem.name.clone(),
+                    version: gem.version.clone(),
+                    source,
+                    dependencies: metadata.as_ref().map(|metadata| metadata.dependencies.clone()).unwrap_or_default(),
+                };
+                let downloaded = DownloadedGem {
+                    name: gem.name.clone(),
+                    version: gem.version.clone(),
+                    path,
+                    sha256,
+                    platform: metadata.and_then(|metadata| metadata.binary_platform()),
+                };
+                Ok(Some((resolved, downloaded)))
+            }.await;
+            if let Err(error) = &result {
+                limits.record_error(error.as_ref());
+            }
+            result
+        }
+    })).await;
+
+    // 容量が不足した場合や上限を超えた場合は、ダウンロードが完了したGemの一覧と共にエラーを返す
+    let completed = || results.iter()
+        .filter_map(|result| match result {
+            Ok(Some((resolved, _))) => Some(format!("{}-{}", resolved.name, resolved.version)),
+            _ => None,
+        })
+        .collect();
+    if limits.is_out_of_space() {
+        return Err(GemfileError::OutOfSpace { completed: completed() });
+    }
+    if limits.is_budget_exceeded() {
+        return Err(GemfileError::BudgetExceeded { completed: completed() });
+    }
+
+    let (gems, downloaded) = results.into_iter()
+        .collect::<Result<Vec<_>, _>>()?
+        .into_iter()
+        .flatten()
+        .unzip();
+    Ok((Resolution { source: gemfile_data.source, gems }, downloaded))
+}
+
+///
+/// インストールとダウンロードのみの処理で共通する、ダウンロードの前の準備をした結果
+///
+/// キャッシュのロックを含むため、ダウンロードが終わるまで保持する
+///
+struct DownloadPlan<'a> {
+    /// すべてのリクエストで共有するクライアントを設定したオプション
+    options: Cow<'a, InstallOptions>,
+    /// 他のプロセスと同時にキャッシュを書き換えないためのロック
+    _cache_lock: Option<cache_lock::CacheLock>,
+    /// バージョンの取得とダウンロードで共通の同時実行数の制限
+    semaphore: Semaphore,
+    /// ディスクの空き容量の不足とダウンロードの合計サイズの上限(実行中のダウンロードも中断する)
+    limits: DownloadLimits,
+    /// バージョンを解決し、`confirm`で承認されたGemのみを含むデータ
+    gemfile_data: GemfileData,
+}
+
+///
+/// ダウンロードの前に行う共通の処理
+///
+/// 共有のクライアントの作成とキャッシュのロックを行い、レジストリから取得するGemのバージョンを解決して`confirm`で確認する
+///
+/// * gemfile_data - Gemfileの読み込み済みデータ(レジストリ以外から取得するGemは除く)
+/// * cache_directory - Gemのダウンロード先のキャッシュディレクトリ
+/// * options - インストール処理のオプション
+///
+/// return - ダウンロードの準備をした結果
+///
+async fn prepare_download<'a>(mut gemfile_data: GemfileData, cache_directory: &Path, options: &'a InstallOptions) -> Result<DownloadPlan<'a>, Box<dyn Error>> {
+    // すべてのリクエストで接続を再利用するため、クライアントを1つだけ作成する
+    let options = match options.client {
+        Some(_) => Cow::Borrowed(options),
+        None => Cow::Owned(InstallOptions { client: Some(HttpClient(client::build_client(options)?)), ..options.clone() }),
+    };
+
+    // 他のプロセスと同時にキャッシュを書き換えないようにロック
+    let cache_lock = match options.cache_lock_timeout {
+        Some(timeout) => Some(cache_lock::acquire(cache_directory, timeout).await?),
+        None => None,
+    };
+
+    // バージョンが決まっていないGemのバージョンを並列に取得
+    let semaphore = options.semaphore();
+    gemfile_data.gems.retain(|gem| gem.is_registry());
+    gemfile_data.resolve_versions_with(&options, &semaphore).await?;
+
+    // 解決したGemの一覧を確認し、承認されたGemのみをダウンロードする(依存関係はダウンロードの前のため含まない)
+    if let Some(confirm) = &options.confirm {
+        let resolved: Vec<ResolvedGem> = gemfile_data.gems.iter()
+            .map(|gem| ResolvedGem {
+                name: gem.name.clone(),
+                version: gem.version.clone(),
+                source: gem.source.clone().unwrap_or_else(|| gemfile_data.source.clone()),
+                dependencies: Vec::new(),
+            })
+            .collect();
+        let approved = confirm(&resolved);
+        gemfile_data.gems.retain(|gem| approved.iter().any(|approved| approved.name == gem.name && approved.version == gem.version));
+    }
+
+    let limits = DownloadLimits::new(options.max_total_bytes);
+    Ok(DownloadPlan { options, _cache_lock: cache_lock, semaphore, limits, gemfile_data })
+}
+
+///
+/// 解凍のようにスレッドを止める処理を、非同期のタスクを止めないようブロッキング用のスレッドで実行する
+///
+/// * task - 実行する処理
+///
+/// return - 処理の結果
+///
+async fn run_blocking<T: Send + 'static>(task: impl FnOnce() -> Result<T, GemfileError> + Send + 'static) -> Result<T, GemfileError> {
+    tokio::task::spawn_blocking(task).await
+        .map_err(|error| GemfileError::Other { message: error.to_string() })?
+}
+
+///
+/// インストールのタスクをすべて実行する
+///
+/// * tasks - 実行するタスク
+/// * options - インストール処理のオプション
+///
+/// return - `fail_fast`が有効な場合は最初に失敗したタスクのエラー
+///
+async fn run_tasks<F: Future<Output = Result<(), Box<dyn Error>>>>(tasks: Vec<F>, options: &InstallOptions) -> Result<(), Box<dyn Error>> {
+    // テストでは宣言順に実行して順序を固定する
+    #[cfg(test)]
+    if options.deterministic {
+        for task in tasks {
+            let result = task.await;
+            if options.fail_fast {
+                result?;
+            }
+        }
+        return Ok(());
+    }
+
+    if options.fail_fast {
+        // 最初のエラーで残りのタスクを破棄してキャンセルする
+        try_join_all(tasks).await?;
+    } else {
+        join_all(tasks).await;
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
-    use std::path::Path;
-    use crate::install_from_gemfile_literal;
+    use std::path::{Path, PathBuf};
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+    use crate::{install_from_gem_tarball, install_from_gemfile_file_with_options, install_from_gemfile_literal, install_from_gemfile_literal_with_options, install_from_lockfile_with_options, install_gems_with_options, install_gems_with_progress, resolve_and_download, FindGemFileInfo, InstallInfo, InstallStage};
+    use crate::events::{EventHandler, InstallEvent};
+    use crate::error::GemfileError;
+    use crate::options::{ExistingDirectory, HttpClient, InstallOptions};
+    use crate::parser::{Gem, GemSource, GemfileData};
+    use crate::resolution::{Dependency, ResolvedGem};
+    use crate::test_util::{build_tar, gzip, test_directory, GemBuilder, MockResponse, MockServer};
 
     ///
     /// Gemsのダウンロードのテスト
@@ -181,4 +922,971 @@ end";
             println!("gemfile: {:?}", find_gemfile.gemfile_path);
         });
     }
-}
\ No newline at end of file
+
+    ///
+    /// 署名されたGemのインストールのテスト
+    ///
+    #[tokio::test]
+    pub async fn install_signed_gem_test() {
+        let directory = test_directory("install_signed_gem");
+        let signed = GemBuilder::new("signed", "1.0.0")
+            .entry("data.tar.gz.sig", b"signature")
+            .build();
+        let unsigned = GemBuilder::new("unsigned", "1.0.0").build();
+        let server = MockServer::start(move |request| {
+            match request.path.as_str() {
+                "/downloads/signed-1.0.0.gem" => MockResponse::new(200, signed.clone()),
+                "/downloads/unsigned-1.0.0.gem" => MockResponse::new(200, unsigned.clone()),
+                _ => MockResponse::not_found(),
+            }
+        }).await;
+
+        let gemfile_data = GemfileData {
+            source: server.url.clone(),
+            gems: vec![
+                Gem { name: "signed".to_string(), version: "1.0.0".to_string(), ..Default::default() },
+                Gem { name: "unsigned".to_string(), version: "1.0.0".to_string(), ..Default::default() },
+            ],
+            ..Default::default()
+        };
+        let options = InstallOptions { allow_insecure: true, verify_checksums: false, ..Default::default() };
+        let info = install_gems_with_options(gemfile_data, &directory.join("gems"), &directory.join("cache"), &options).await.unwrap();
+
+        // 署名の有無が記録されているか
+        assert_eq!(info.installed.len(), 2);
+        let signed = info.installed.iter().find(|gem| gem.name == "signed").unwrap();
+        assert!(signed.signature.signed);
+        let unsigned = info.installed.iter().find(|gem| gem.name == "unsigned").unwrap();
+        assert!(!unsigned.signature.signed);
+    }
+
+    ///
+    /// 宣言順にタスクを実行するテスト
+    ///
+    #[tokio::test]
+    pub async fn deterministic_order_test() {
+        let directory = test_directory("deterministic_order");
+        let names = ["zeta", "alpha", "mu", "beta"];
+        let gems: Vec<Vec<u8>> = names.iter().map(|name| GemBuilder::new(name, "1.0.0").build()).collect();
+        let server = MockServer::start(move |request| {
+            names.iter().zip(gems.iter())
+                .find(|(name, _)| request.path == format!("/downloads/{}-1.0.0.gem", name))
+                .map(|(_, gem)| MockResponse::new(200, gem.clone()))
+                .unwrap_or_else(MockResponse::not_found)
+        }).await;
+
+        let gemfile_data = GemfileData {
+            source: server.url.clone(),
+            gems: names.iter()
+                .map(|name| Gem { name: name.to_string(), version: "1.0.0".to_string(), ..Default::default() })
+                .collect(),
+            ..Default::default()
+        };
+        let options = InstallOptions { allow_insecure: true, verify_checksums: false, deterministic: true, ..Default::default() };
+        let info = install_gems_with_options(gemfile_data, &directory.join("gems"), &directory.join("cache"), &options).await.unwrap();
+
+        // ダウンロードのリクエストが宣言順に届いているか
+        let requested: Vec<String> = server.requests().into_iter().map(|request| request.path).collect();
+        let expected: Vec<String> = names.iter().map(|name| format!("/downloads/{}-1.0.0.gem", name)).collect();
+        assert_eq!(requested, expected);
+
+        // インストールの完了も宣言順になっているか
+        let expected: Vec<String> = names.iter().map(|name| format!("{}-1.0.0", name)).collect();
+        assert_eq!(info.install_gems, expected);
+    }
+
+    ///
+    /// ディスクの空き容量が不足した場合のテスト
+    ///
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    pub async fn out_of_space_test() {
+        let directory = test_directory("out_of_space");
+        let cache_directory = directory.join("cache");
+        std::fs::create_dir_all(&cache_directory).unwrap();
+        // 書き込むと常にENOSPCになる/dev/fullをダウンロード中の一時ファイルにする
+        std::os::unix::fs::symlink("/dev/full", cache_directory.join("full-1.0.0.gem.part")).unwrap();
+
+        // すべてのGemを同時にダウンロードし、`full`は`first`の完了後に、`stalled`は途中まで受信した状態で応答を止める
+        let names = ["first", "full", "stalled"];
+        let gems: Vec<Vec<u8>> = names.iter().map(|name| GemBuilder::new(name, "1.0.0").build()).collect();
+        let server = MockServer::start(move |request| {
+            let response = |index: usize| MockResponse::new(200, gems[index].clone());
+            match request.path.as_str() {
+                "/downloads/first-1.0.0.gem" => response(0),
+                "/downloads/full-1.0.0.gem" => response(1).delay(Duration::from_millis(500)),
+                "/downloads/stalled-1.0.0.gem" => response(2).stall_after(16),
+                _ => MockResponse::not_found(),
+            }
+        }).await;
+
+        let gemfile_data = GemfileData {
+            source: server.url.clone(),
+            gems: names.iter()
+                .map(|name| Gem { name: name.to_string(), version: "1.0.0".to_string(), ..Default::default() })
+                .collect(),
+            ..Default::default()
+        };
+        let options = InstallOptions { allow_insecure: true, verify_checksums: false, ..Default::default() };
+        // 受信途中のダウンロードも中断され、応答が止まったままのダウンロードを待たずに終了するか
+        let result = tokio::time::timeout(
+            Duration::from_secs(10),
+            install_gems_with_options(gemfile_data, &directory.join("gems"), &cache_directory, &options),
+        ).await.unwrap();
+
+        // 容量不足のエラーと完了したGemの一覧が返されるか
+        let error = result.unwrap_err();
+        let GemfileError::OutOfSpace { completed } = &error else {
+            panic!("unexpected error: {}", error);
+        };
+        assert_eq!(completed, &vec!["first-1.0.0".to_string()]);
+        assert!(!cache_directory.join("stalled-1.0.0.gem").exists());
+    }
+
+    ///
+    /// ダウンロードの合計サイズの上限のテスト
+    ///
+    #[tokio::test]
+    pub async fn max_total_bytes_test() {
+        let directory = test_directory("max_total_bytes");
+        // 圧縮されにくい内容で、上限を大きく超えるGem
+        let mut seed: u32 = 1;
+        let content: Vec<u8> = (0..1024 * 1024).map(|_| {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            (seed >> 16) as u8
+        }).collect();
+        let first = GemBuilder::new("first", "1.0.0").build();
+        let large = GemBuilder::new("large", "1.0.0").file("lib/large.bin", &content).build();
+        let stalled = GemBuilder::new("stalled", "1.0.0").build();
+        let gem_size = first.len() as u64;
+        // すべてのGemを同時にダウンロードし、`large`は`first`の完了後に、`stalled`は途中まで受信した状態で応答を止める
+        let server = MockServer::start(move |request| match request.path.as_str() {
+            "/downloads/first-1.0.0.gem" => MockResponse::new(200, first.clone()),
+            "/downloads/large-1.0.0.gem" => MockResponse::new(200, large.clone()).delay(Duration::from_millis(500)),
+            "/downloads/stalled-1.0.0.gem" => MockResponse::new(200, stalled.clone()).stall_after(16),
+            _ => MockResponse::not_found(),
+        }).await;
+
+        let gemfile_data = GemfileData {
+            source: server.url.clone(),
+            gems: ["first", "large", "stalled"].iter()
+                .map(|name| Gem { name: name.to_string(), version: "1.0.0".to_string(), ..Default::default() })
+                .collect(),
+            ..Default::default()
+        };
+        let options = InstallOptions {
+            allow_insecure: true,
+            verify_checksums: false,
+            max_total_bytes: Some(gem_size * 2),
+            ..Default::default()
+        };
+        // 受信途中のダウンロードも中断され、応答が止まったままのダウンロードを待たずに終了するか
+        let result = tokio::time::timeout(
+            Duration::from_secs(10),
+            install_gems_with_options(gemfile_data, &directory.join("gems"), &directory.join("cache"), &options),
+        ).await.unwrap();
+
+        // 上限を超えたエラーと完了したGemの一覧が返されるか
+        let error = result.unwrap_err();
+        let GemfileError::BudgetExceeded { completed } = &error else {
+            panic!("unexpected error: {}", error);
+        };
+        assert_eq!(completed, &vec!["first-1.0.0".to_string()]);
+
+        // 上限を超えたGemは受信の途中で中止され、保存されていないか
+        assert!(!directory.join("cache/large-1.0.0.gem").exists());
+        assert!(!directory.join("cache/stalled-1.0.0.gem").exists());
+    }
+
+    ///
+    /// Gemfileのパスの一覧のテスト
+    ///
+    #[test]
+    pub fn all_gemfile_paths_test() {
+        let find_gemfile = |gem_name: &str, path: &str| FindGemFileInfo {
+            gem_name: gem_name.to_string(),
+            gemfile_path: PathBuf::from(path),
+        };
+        let info = InstallInfo {
+            find_gemfiles: vec![
+                find_gemfile("b-1.0.0", "gems/b-1.0.0/Gemfile"),
+                find_gemfile("a-1.0.0", "gems/a-1.0.0/Gemfile"),
+                find_gemfile("b-1.0.0", "gems/b-1.0.0/Gemfile"),
+            ],
+            ..Default::default()
+        };
+
+        // 重複が除かれて並び替えられているか
+        assert_eq!(info.all_gemfile_paths(), vec![
+            PathBuf::from("gems/a-1.0.0/Gemfile"),
+            PathBuf::from("gems/b-1.0.0/Gemfile"),
+        ]);
+    }
+
+    ///
+    /// 同じキャッシュへの同時インストールがロックで直列化されるかのテスト
+    ///
+    #[tokio::test]
+    pub async fn cache_lock_install_test() {
+        let directory = test_directory("cache_lock_install");
+        let body = GemBuilder::new("shared", "1.0.0").build();
+        let server = MockServer::start(move |_| {
+            MockResponse::new(200, body.clone()).delay(Duration::from_millis(200))
+        }).await;
+
+        let gemfile_data = GemfileData {
+            source: server.url.clone(),
+            gems: vec![Gem { name: "shared".to_string(), version: "1.0.0".to_string(), ..Default::default() }],
+            ..Default::default()
+        };
+        let options = InstallOptions {
+            allow_insecure: true,
+            verify_checksums: false,
+            cache_lock_timeout: Some(Duration::from_secs(10)),
+            ..Default::default()
+        };
+        let cache_directory = directory.join("cache");
+        let first_directory = directory.join("first");
+        let second_directory = directory.join("second");
+        let (first, second) = tokio::join!(
+            install_gems_with_options(gemfile_data.clone(), &first_directory, &cache_directory, &options),
+            install_gems_with_options(gemfile_data.clone(), &second_directory, &cache_directory, &options),
+        );
+
+        // 両方のインストールが成功し、内容が壊れていないか
+        for (info, install_directory) in [(first.unwrap(), first_directory), (second.unwrap(), second_directory)] {
+            assert_eq!(info.install_gems, vec!["shared-1.0.0".to_string()]);
+            let content = std::fs::read_to_string(install_directory.join("shared-1.0.0/lib/shared.rb")).unwrap();
+            assert_eq!(content, "module shared\nend\n");
+        }
+
+        // ダウンロードが同時に行われず、後のインストールはロックの解放後にキャッシュを使用するか
+        assert_eq!(server.requests().len(), 1);
+        assert_eq!(server.max_in_flight(), 1);
+    }
+
+    ///
+    /// 最初の失敗で残りをキャンセルするテスト
+    ///
+    #[tokio::test]
+    pub async fn fail_fast_test() {
+        let directory = test_directory("fail_fast");
+        let slow = GemBuilder::new("slow", "1.0.0").build();
+        let server = MockServer::start(move |request| {
+            match request.path.as_str() {
+                "/downloads/bad-first-1.0.0.gem" => MockResponse::not_found(),
+                "/downloads/bad-second-1.0.0.gem" => MockResponse::not_found().delay(Duration::from_secs(2)),
+                "/downloads/slow-1.0.0.gem" => MockResponse::new(200, slow.clone()).delay(Duration::from_secs(2)),
+                _ => MockResponse::not_found(),
+            }
+        }).await;
+
+        let gemfile_data = GemfileData {
+            source: server.url.clone(),
+            gems: ["slow", "bad-first", "bad-second"].iter()
+                .map(|name| Gem { name: name.to_string(), version: "1.0.0".to_string(), ..Default::default() })
+                .collect(),
+            ..Default::default()
+        };
+        let options = InstallOptions { allow_insecure: true, fail_fast: true, ..Default::default() };
+        let started = Instant::now();
+        let result = install_gems_with_options(gemfile_data, &directory.join("gems"), &directory.join("cache"), &options).await;
+
+        // 最初の失敗のみが返されるか
+        let error = result.unwrap_err().to_string();
+        assert!(error.contains("bad-first"), "{}", error);
+
+        // 残りのダウンロードを待たずにキャンセルされているか
+        assert!(started.elapsed() < Duration::from_secs(2));
+        assert!(!directory.join("gems/slow-1.0.0").exists());
+    }
+
+    ///
+    /// バージョンの取得が並列に行われ、ダウンロードと同じ上限で制限されるかのテスト
+    ///
+    #[tokio::test]
+    pub async fn concurrent_resolution_test() {
+        let directory = test_directory("concurrent_resolution");
+        let names = ["alpha", "beta", "gamma", "delta"];
+        let gems: Vec<Vec<u8>> = names.iter().map(|name| GemBuilder::new(name, "1.0.0").build()).collect();
+        let start_server = || {
+            let gems = gems.clone();
+            MockServer::start(move |request| {
+                if request.path.starts_with("/info/") {
+                    return MockResponse::new(200, "---\n1.0.0 |checksum:a\n").delay(Duration::from_millis(200));
+                }
+                names.iter().zip(gems.iter())
+                    .find(|(name, _)| request.path == format!("/downloads/{}-1.0.0.gem", name))
+                    .map(|(_, gem)| MockResponse::new(200, gem.clone()))
+                    .unwrap_or_else(MockResponse::not_found)
+            })
+        };
+        let gemfile = |url: &str| format!("source '{}'\ngem 'alpha'\ngem 'beta'\ngem 'gamma'\ngem 'delta'\n", url);
+
+        // 制限しない場合はすべてのバージョンを同時に取得するか
+        let server = start_server().await;
+        let gemfile_data = GemfileData::parse_unresolved(&gemfile(&server.url)).unwrap();
+        assert!(gemfile_data.gems.iter().all(|gem| gem.version.is_empty()));
+        let options = InstallOptions { allow_insecure: true, verify_checksums: false, ..Default::default() };
+        let info = install_gems_with_options(gemfile_data, &directory.join("unbounded"), &directory.join("cache"), &options).await.unwrap();
+        assert_eq!(info.install_gems.len(), 4);
+        assert_eq!(server.max_in_flight(), 4);
+
+        // 上限を指定した場合はその数までに制限されるか
+        let server = start_server().await;
+        let gemfile_data = GemfileData::parse_unresolved(&gemfile(&server.url)).unwrap();
+        let options = InstallOptions { allow_insecure: true, concurrency: Some(2), ..Default::default() };
+        let info = install_gems_with_options(gemfile_data, &directory.join("bounded"), &directory.join("cache"), &options).await.unwrap();
+        assert!(info.installed.iter().all(|gem| gem.version == "1.0.0"));
+        assert_eq!(server.max_in_flight(), 2);
+    }
+
+    ///
+    /// ネイティブ拡張を含むGemが報告されるかのテスト
+    ///
+    #[tokio::test]
+    pub async fn requires_build_test() {
+        let directory = test_directory("requires_build");
+        let native = GemBuilder::new("native", "1.0.0").metadata("extensions:\n- ext/native/extconf.rb\n").build();
+        let pure = GemBuilder::new("pure", "1.0.0").metadata("extensions: []\n").build();
+        let server = MockServer::start(move |request| {
+            match request.path.as_str() {
+                "/downloads/native-1.0.0.gem" => MockResponse::new(200, native.clone()),
+                "/downloads/pure-1.0.0.gem" => MockResponse::new(200, pure.clone()),
+                _ => MockResponse::not_found(),
+            }
+        }).await;
+        let gemfile_data = GemfileData {
+            source: server.url.clone(),
+            gems: ["native", "pure"].iter()
+                .map(|name| Gem { name: name.to_string(), version: "1.0.0".to_string(), ..Default::default() })
+                .collect(),
+            ..Default::default()
+        };
+        let options = InstallOptions { allow_insecure: true, verify_checksums: false, deterministic: true, ..Default::default() };
+        let info = install_gems_with_options(gemfile_data, &directory.join("gems"), &directory.join("cache"), &options).await.unwrap();
+
+        assert!(info.installed[0].has_native_extension);
+        assert!(!info.installed[1].has_native_extension);
+        assert_eq!(info.requires_build, vec!["native-1.0.0".to_string()]);
+    }
+
+    ///
+    /// 展開先のディレクトリを決める関数のテスト
+    ///
+    #[tokio::test]
+    pub async fn dest_namer_test() {
+        let directory = test_directory("dest_namer");
+        let rake = GemBuilder::new("rake", "13.0.1").file("lib/rake.rb", b"").build();
+        let rack = GemBuilder::new("rack", "3.0.0").file("lib/rack.rb", b"").build();
+        let server = MockServer::start(move |request| {
+            match request.path.as_str() {
+                "/downloads/rake-13.0.1.gem" => MockResponse::new(200, rake.clone()),
+                "/downloads/rack-3.0.0.gem" => MockResponse::new(200, rack.clone()),
+                _ => MockResponse::not_found(),
+            }
+        }).await;
+        let gemfile_data = GemfileData {
+            source: server.url.clone(),
+            gems: vec![
+                Gem { name: "rake".to_string(), version: "13.0.1".to_string(), ..Default::default() },
+                Gem { name: "rack".to_string(), version: "3.0.0".to_string(), ..Default::default() },
+            ],
+            ..Default::default()
+        };
+        // 名前の最初の文字で分類する
+        let options = InstallOptions {
+            allow_insecure: true,
+            verify_checksums: false,
+            deterministic: true,
+            dest_namer: Some(Arc::new(|gem: &ResolvedGem| {
+                PathBuf::from("by-letter").join(&gem.name[..1]).join(&gem.name)
+            })),
+            ..Default::default()
+        };
+        let install_directory = directory.join("gems");
+        let info = install_gems_with_options(gemfile_data, &install_directory, &directory.join("cache"), &options).await.unwrap();
+
+        assert!(install_directory.join("by-letter/r/rake/lib/rake.rb").exists());
+        assert!(install_directory.join("by-letter/r/rack/lib/rack.rb").exists());
+        assert!(!install_directory.join("rake-13.0.1").exists());
+        assert_eq!(info.installed[0].install_path, install_directory.join("by-letter/r/rake"));
+    }
+
+    ///
+    /// インストールに失敗したGemが段階と共に記録されるかのテスト
+    ///
+    #[tokio::test]
+    pub async fn failed_gems_test() {
+        let directory = test_directory("failed_gems");
+        let good = GemBuilder::new("good", "1.0.0").build();
+        let broken_payload = build_tar(&[
+            ("metadata.gz".to_string(), gzip(b"--- {}\n")),
+            ("data.tar.gz".to_string(), b"not gzip".to_vec()),
+        ]);
+        let server = MockServer::start(move |request| {
+            match request.path.as_str() {
+                "/downloads/good-1.0.0.gem" => MockResponse::new(200, good.clone()),
+                "/downloads/not-gem-1.0.0.gem" => MockResponse::new(200, "not a gem"),
+                "/downloads/broken-payload-1.0.0.gem" => MockResponse::new(200, broken_payload.clone()),
+                _ => MockResponse::not_found(),
+            }
+        }).await;
+        let gemfile_data = GemfileData {
+            source: server.url.clone(),
+            gems: ["good", "missing", "not-gem", "broken-payload"].iter()
+                .map(|name| Gem { name: name.to_string(), version: "1.0.0".to_string(), ..Default::default() })
+                .collect(),
+            ..Default::default()
+        };
+        let options = InstallOptions { allow_insecure: true, verify_checksums: false, deterministic: true, ..Default::default() };
+        let info = install_gems_with_options(gemfile_data, &directory.join("gems"), &directory.join("cache"), &options).await.unwrap();
+
+        // 成功したGemはそのまま返されるか
+        assert_eq!(info.install_gems, vec!["good-1.0.0".to_string()]);
+        // 失敗したGemが段階と共に記録されているか
+        let stages: Vec<(&str, InstallStage)> = info.failed_gems.iter()
+            .map(|failed| (failed.gem_name.as_str(), failed.stage))
+            .collect();
+        assert_eq!(stages, vec![
+            ("missing-1.0.0", InstallStage::Download),
+            ("not-gem-1.0.0", InstallStage::UnpackGem),
+            ("broken-payload-1.0.0", InstallStage::UnpackTarGz),
+        ]);
+        assert!(info.failed_gems[0].error.contains("status 404"));
+    }
+
+    ///
+    /// 進行状況の通知のテスト
+    ///
+    #[tokio::test]
+    pub async fn install_progress_test() {
+        let directory = test_directory("install_progress");
+        let source_directory = directory.join("source");
+        GemBuilder::new("local", "1.0.0").write(&source_directory);
+        let gemfile_data = GemfileData {
+            source: format!("file://{}", source_directory.display()),
+            gems: ["local", "absent"].iter()
+                .map(|name| Gem { name: name.to_string(), version: "1.0.0".to_string(), ..Default::default() })
+                .collect(),
+            ..Default::default()
+        };
+        let events = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let received = Arc::clone(&events);
+        let on_event: EventHandler = Arc::new(move |event| received.lock().unwrap().push(event));
+
+        let info = install_gems_with_progress(gemfile_data, &directory.join("gems"), &directory.join("cache"), Some(on_event)).await.unwrap();
+        assert_eq!(info.install_gems, vec!["local-1.0.0".to_string()]);
+
+        // Gemごとの段階と失敗が通知されているか
+        let events = events.lock().unwrap();
+        let local: Vec<&InstallEvent> = events.iter()
+            .filter(|event| matches!(event, InstallEvent::Started { gem, .. } | InstallEvent::Downloaded { gem } | InstallEvent::Unpacked { gem } if gem == "local-1.0.0"))
+            .collect();
+        assert_eq!(local, vec![
+            &InstallEvent::Started { gem: "local-1.0.0".to_string(), total: 2 },
+            &InstallEvent::Downloaded { gem: "local-1.0.0".to_string() },
+            &InstallEvent::Unpacked { gem: "local-1.0.0".to_string() },
+        ]);
+        assert!(events.iter().any(|event| matches!(event, InstallEvent::Failed { gem, .. } if gem == "absent-1.0.0")));
+        assert_eq!(events.last(), Some(&InstallEvent::Finished { installed: 1, total: 2 }));
+    }
+
+    ///
+    /// .gemファイルをまとめたtarからのインストールのテスト
+    ///
+    #[tokio::test]
+    pub async fn install_from_gem_tarball_test() {
+        let directory = test_directory("install_from_gem_tarball");
+        let tarball = directory.join("gems.tar");
+        std::fs::write(&tarball, build_tar(&[
+            ("gems/first-1.0.0.gem".to_string(), GemBuilder::new("first", "1.0.0").build()),
+            ("gems/second-2.0.0.gem".to_string(), GemBuilder::new("second", "2.0.0").build()),
+            ("gems/second-2.1.0.gem".to_string(), GemBuilder::new("second", "2.1.0").build()),
+            ("README".to_string(), b"not a gem".to_vec()),
+        ])).unwrap();
+
+        // 指定しない場合はすべてインストールされるか
+        let info = install_from_gem_tarball(&tarball, None, &directory.join("all"), &directory.join("cache")).await.unwrap();
+        let mut installed = info.install_gems.clone();
+        installed.sort();
+        assert_eq!(installed, vec!["first-1.0.0".to_string(), "second-2.0.0".to_string(), "second-2.1.0".to_string()]);
+        assert!(info.failed_gems.is_empty());
+
+        // Gemfileの制約を満たすもののみがインストールされるか
+        let gemfile_data = GemfileData::parse_unresolved("gem 'first'\ngem 'second', '< 2.1'\n").unwrap();
+        let info = install_from_gem_tarball(&tarball, Some(gemfile_data), &directory.join("selected"), &directory.join("cache")).await.unwrap();
+        let mut installed = info.install_gems.clone();
+        installed.sort();
+        assert_eq!(installed, vec!["first-1.0.0".to_string(), "second-2.0.0".to_string()]);
+        assert!(directory.join("selected/second-2.0.0/lib/second.rb").exists());
+
+        // tarに含まれないGemはエラーになるか
+        let gemfile_data = GemfileData::parse_unresolved("gem 'third'\n").unwrap();
+        assert!(install_from_gem_tarball(&tarball, Some(gemfile_data), &directory.join("missing"), &directory.join("cache")).await.is_err());
+    }
+
+    ///
+    /// シンボリックリンクのインストール先とキャッシュディレクトリのテスト
+    ///
+    #[cfg(unix)]
+    #[tokio::test]
+    pub async fn symlinked_directories_test() {
+        // リンク先は絶対パスで指定する
+        let directory = std::path::absolute(test_directory("symlinked_directories")).unwrap();
+        let body = GemBuilder::new("linked", "1.0.0").build();
+        let server = MockServer::start(move |_| MockResponse::new(200, body.clone())).await;
+        let gemfile_data = GemfileData {
+            source: server.url.clone(),
+            gems: vec![Gem { name: "linked".to_string(), version: "1.0.0".to_string(), ..Default::default() }],
+            ..Default::default()
+        };
+        std::fs::create_dir_all(directory.join("real/gems")).unwrap();
+        std::fs::create_dir_all(directory.join("real/cache")).unwrap();
+        std::os::unix::fs::symlink(directory.join("real/gems"), directory.join("gems")).unwrap();
+        std::os::unix::fs::symlink(directory.join("real/cache"), directory.join("cache")).unwrap();
+        // 展開先のGemのディレクトリがシンボリックリンクの場合
+        std::fs::create_dir_all(directory.join("elsewhere")).unwrap();
+        std::fs::write(directory.join("elsewhere/keep.txt"), "keep").unwrap();
+        std::os::unix::fs::symlink(directory.join("elsewhere"), directory.join("real/cache/linked-1.0.0")).unwrap();
+        let options = InstallOptions { allow_insecure: true, verify_checksums: false, ..Default::default() };
+
+        // 2回インストールしても、リンクを残したままリンク先に書き込まれるか
+        for _ in 0..2 {
+            let info = install_gems_with_options(gemfile_data.clone(), &directory.join("gems"), &directory.join("cache"), &options).await.unwrap();
+            assert_eq!(info.install_gems, vec!["linked-1.0.0".to_string()]);
+            assert!(directory.join("gems").is_symlink());
+            assert!(directory.join("cache").is_symlink());
+            assert!(directory.join("real/gems/linked-1.0.0/lib/linked.rb").exists());
+            assert!(directory.join("real/cache/linked-1.0.0.gem").exists());
+        }
+        // Gemのディレクトリのリンクは置き換えられ、リンク先の内容は削除されないか
+        assert!(!directory.join("real/cache/linked-1.0.0").is_symlink());
+        assert!(directory.join("elsewhere/keep.txt").exists());
+
+        // リンク切れの場合はエラーになるか
+        std::os::unix::fs::symlink(directory.join("missing"), directory.join("dangling")).unwrap();
+        let error = install_gems_with_options(gemfile_data, &directory.join("dangling"), &directory.join("cache"), &options).await.unwrap_err();
+        assert!(error.to_string().contains("missing directory"));
+    }
+
+    ///
+    /// 指定したHTTPクライアントがすべてのリクエストで使用されるかのテスト
+    ///
+    #[tokio::test]
+    pub async fn shared_client_test() {
+        let directory = test_directory("shared_client");
+        let body = GemBuilder::new("shared", "1.0.0").build();
+        let server = MockServer::start(move |request| match request.path.as_str() {
+            "/info/shared" => MockResponse::new(200, "---\n1.0.0 |\n"),
+            "/downloads/shared-1.0.0.gem" => MockResponse::new(200, body.clone()),
+            _ => MockResponse::not_found(),
+        }).await;
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert("x-shared-client", "1".parse().unwrap());
+        let options = InstallOptions { allow_insecure: true, verify_checksums: false, ..Default::default() };
+        let client = HttpClient::new(reqwest::Client::builder().default_headers(headers), &options).unwrap();
+        let options = InstallOptions { client: Some(client), ..options };
+
+        let gemfile_data = GemfileData::parse_unresolved(&format!("source '{}'\ngem 'shared'\n", server.url)).unwrap();
+        let info = install_gems_with_options(gemfile_data, &directory.join("gems"), &directory.join("cache"), &options).await.unwrap();
+
+        // バージョンの解決とダウンロードの両方で使用されるか
+        assert_eq!(info.install_gems, vec!["shared-1.0.0".to_string()]);
+        assert_eq!(server.requests().len(), 2);
+        assert!(server.requests().iter().all(|request| request.header("x-shared-client") == Some("1")));
+    }
+
+    ///
+    /// 指定したHTTPクライアントでもリダイレクトの方針が適用されるかのテスト
+    ///
+    #[tokio::test]
+    pub async fn shared_client_redirect_test() {
+        let directory = test_directory("shared_client_redirect");
+        let body = GemBuilder::new("shared", "1.0.0").build();
+        let server = MockServer::start(move |request| match request.path.as_str() {
+            "/downloads/shared-1.0.0.gem" => MockResponse::new(302, "").header("Location", "/moved/shared-1.0.0.gem"),
+            "/moved/shared-1.0.0.gem" => MockResponse::new(200, body.clone()),
+            _ => MockResponse::not_found(),
+        }).await;
+        // リダイレクトを自動でたどる既定のビルダーを渡す
+        let options = InstallOptions { allow_insecure: true, max_redirects: 0, ..Default::default() };
+        let client = HttpClient::new(reqwest::Client::builder(), &options).unwrap();
+        let options = InstallOptions { client: Some(client), ..options };
+
+        // クライアントがリダイレクトをたどらず、回数の上限でエラーになるか
+        let gem = Gem { name: "shared".to_string(), version: "1.0.0".to_string(), ..Default::default() };
+        let error = crate::download::download_gem_with_options(&directory, &server.url, &gem, &options).await.unwrap_err();
+        assert!(matches!(error, GemfileError::TooManyRedirects { .. }), "{}", error);
+        assert_eq!(server.request_count("/moved/shared-1.0.0.gem"), 0);
+    }
+
+    ///
+    /// 解凍せずにバージョンの解決とダウンロードのみを行うテスト
+    ///
+    #[tokio::test]
+    pub async fn resolve_and_download_test() {
+        let directory = test_directory("resolve_and_download");
+        let alpha = GemBuilder::new("alpha", "1.2.0")
+            .metadata("dependencies:\n- !ruby/object:Gem::Dependency\n  name: rack\n  requirement: !ruby/object:Gem::Requirement\n    requirements:\n    - - \"~>\"\n      - !ruby/object:Gem::Version\n        version: '2.0'\n  type: :runtime\n")
+            .build();
+        let beta = GemBuilder::new("beta", "2.0.0").build();
+        let server = MockServer::start(move |request| match request.path.as_str() {
+            "/info/alpha" => MockResponse::new(200, "---\n1.1.0 |checksum:a\n1.2.0 |checksum:b\n"),
+            "/downloads/alpha-1.2.0.gem" => MockResponse::new(200, alpha.clone()),
+            "/downloads/beta-2.0.0.gem" => MockResponse::new(200, beta.clone()),
+            _ => MockResponse::not_found(),
+        }).await;
+        let gemfile_data = GemfileData::parse_unresolved(&format!("source '{}'\ngem 'alpha'\ngem 'beta', '2.0.0'\n", server.url)).unwrap();
+        let options = InstallOptions { allow_insecure: true, verify_checksums: false, ..Default::default() };
+
+        let (resolution, downloaded) = resolve_and_download(gemfile_data, &directory.join("cache"), &options).await.unwrap();
+
+        // 解決したバージョンが返されるか
+        assert_eq!(resolution.source, server.url);
+        let versions: Vec<(&str, &str)> = resolution.gems.iter().map(|gem| (gem.name.as_str(), gem.version.as_str())).collect();
+        assert_eq!(versions, vec![("alpha", "1.2.0"), ("beta", "2.0.0")]);
+        // 依存関係がダウンロードした.gemファイルから読み込まれるか
+        assert_eq!(resolution.gems[0].dependencies, vec![Dependency { name: "rack".to_string(), requirement: "~> 2.0".to_string() }]);
+        assert!(resolution.gems[1].dependencies.is_empty());
+
+        // ダウンロードしたファイルのパスが返され、解凍はされていないか
+        let paths: Vec<PathBuf> = downloaded.iter().map(|gem| gem.path.clone()).collect();
+        assert_eq!(paths, vec![directory.join("cache/alpha-1.2.0.gem"), directory.join("cache/beta-2.0.0.gem")]);
+        assert!(paths.iter().all(|path| path.exists()));
+        assert!(downloaded.iter().all(|gem| gem.sha256.len() == 64));
+        assert!(!directory.join("cache/alpha-1.2.0").exists());
+
+        // インストールと同様に`confirm`で承認されたGemのみをダウンロードするか
+        let gemfile_data = GemfileData::parse_unresolved(&format!("source '{}'\ngem 'alpha', '1.2.0'\ngem 'beta', '2.0.0'\n", server.url)).unwrap();
+        let confirm_options = InstallOptions {
+            force_download: true,
+            confirm: Some(Arc::new(|resolved: &[ResolvedGem]| resolved.iter().filter(|gem| gem.name == "beta").cloned().collect())),
+            ..options.clone()
+        };
+        let (resolution, downloaded) = resolve_and_download(gemfile_data.clone(), &directory.join("cache"), &confirm_options).await.unwrap();
+        assert_eq!(resolution.gems.len(), 1);
+        assert_eq!(downloaded[0].name, "beta");
+        assert_eq!(server.request_count("/downloads/alpha-1.2.0.gem"), 1);
+
+        // ダウンロードの合計サイズの上限を超えた場合はエラーになるか
+        let budget_options = InstallOptions { force_download: true, max_total_bytes: Some(16), ..options.clone() };
+        let error = resolve_and_download(gemfile_data, &directory.join("cache"), &budget_options).await.unwrap_err();
+        assert!(matches!(error, GemfileError::BudgetExceeded { .. }));
+    }
+
+    ///
+    /// ビルダーで作成したオプションでGemfileからインストールするテスト
+    ///
+    #[tokio::test]
+    pub async fn install_options_builder_test() {
+        let directory = test_directory("install_options_builder");
+        let names = ["alpha", "beta", "gamma"];
+        let gems: Vec<Vec<u8>> = names.iter().map(|name| GemBuilder::new(name, "1.0.0").build()).collect();
+        let server = MockServer::start(move |request| {
+            names.iter().zip(gems.iter())
+                .find(|(name, _)| request.path == format!("/downloads/{}-1.0.0.gem", name))
+                .map(|(_, gem)| MockResponse::new(200, gem.clone()).delay(Duration::from_millis(50)))
+                .unwrap_or_else(MockResponse::not_found)
+        }).await;
+        let gemfile = directory.join("Gemfile");
+        std::fs::write(&gemfile, format!("source '{}'\ngem 'alpha', '1.0.0'\ngem 'beta', '1.0.0'\ngem 'gamma', '1.0.0'\n", server.url)).unwrap();
+
+        let options = InstallOptions::new(directory.join("gems"), directory.join("cache"))
+            .concurrency(1)
+            .allow_insecure(true)
+            .verify_checksums(false);
+        assert_eq!(options.concurrency, Some(1));
+        // チェックサムの照合はデフォルトで有効で、明示的に無効にできるか
+        assert!(InstallOptions::default().verify_checksums);
+        assert!(!options.verify_checksums);
+        let info = install_from_gemfile_file_with_options(&gemfile, &options).await.unwrap();
+
+        // オプションのディレクトリにインストールされ、同時実行数が反映されるか
+        assert_eq!(info.installed.len(), 3);
+        assert!(info.installed.iter().all(|gem| gem.install_path.starts_with(directory.join("gems"))));
+        assert!(directory.join("cache/alpha-1.0.0.gem").exists());
+        assert_eq!(server.max_in_flight(), 1);
+
+        // ディレクトリを設定していないオプションでは、カレントディレクトリにインストールせずエラーになるか
+        let error = install_from_gemfile_file_with_options(&gemfile, &InstallOptions::default()).await.unwrap_err();
+        assert!(matches!(error, GemfileError::MissingDirectory { ref option } if option == "install_directory"));
+        let options = InstallOptions { install_directory: directory.join("gems"), ..Default::default() };
+        let error = install_from_gemfile_literal_with_options("gem 'alpha', '1.0.0'\n", &options).await.unwrap_err();
+        assert!(matches!(error, GemfileError::MissingDirectory { ref option } if option == "cache_directory"));
+        assert_eq!(server.requests().len(), 3);
+    }
+
+    ///
+    /// 指定したグループのみをインストールするテスト
+    ///
+    #[tokio::test]
+    pub async fn only_groups_test() {
+        let directory = test_directory("only_groups");
+        let names = ["rails", "puma", "rspec", "pry", "sidekiq"];
+        let gems: Vec<Vec<u8>> = names.iter().map(|name| GemBuilder::new(name, "1.0.0").build()).collect();
+        let server = MockServer::start(move |request| {
+            names.iter().zip(gems.iter())
+                .find(|(name, _)| request.path == format!("/downloads/{}-1.0.0.gem", name))
+                .map(|(_, gem)| MockResponse::new(200, gem.clone()))
+                .unwrap_or_else(MockResponse::not_found)
+        }).await;
+        let gemfile_data = GemfileData::parse_unresolved(&format!(r#"source "{}"
+gem "rails", "1.0.0"
+group :production do
+  gem "puma", "1.0.0"
+end
+group :development, :test do
+  gem "rspec", "1.0.0"
+  gem "pry", "1.0.0"
+end
+group :production, :jobs do
+  gem "sidekiq", "1.0.0"
+end
+"#, server.url)).unwrap();
+
+        // グループに属さないGemと指定したグループのGemのみがインストールされるか
+        let options = InstallOptions::new(directory.join("gems"), directory.join("cache"))
+            .allow_insecure(true)
+            .verify_checksums(false)
+            .only_groups(vec!["default".to_string(), "production".to_string()]);
+        let info = install_gems_with_options(gemfile_data.clone(), &options.install_directory, &options.cache_directory, &options).await.unwrap();
+        let mut installed = info.install_gems.clone();
+        installed.sort();
+        assert_eq!(installed, vec!["puma-1.0.0".to_string(), "rails-1.0.0".to_string(), "sidekiq-1.0.0".to_string()]);
+        assert_eq!(server.request_count("/downloads/rspec-1.0.0.gem"), 0);
+
+        // 両方に含まれるグループはwithoutが優先されるか
+        let options = options.without_groups(vec!["production".to_string()]);
+        let included: Vec<&str> = gemfile_data.gems.iter().filter(|gem| options.includes_gem(gem)).map(|gem| gem.name.as_str()).collect();
+        assert_eq!(included, vec!["rails"]);
+    }
+
+    ///
+    /// Gitのリポジトリから取得するGemを記録し、ダウンロードしないテスト
+    ///
+    #[tokio::test]
+    pub async fn git_sourced_gem_test() {
+        let directory = test_directory("git_sourced_gem");
+        let rake = GemBuilder::new("rake", "13.0.1").build();
+        let server = MockServer::start(move |request| match request.path.as_str() {
+            "/downloads/rake-13.0.1.gem" => MockResponse::new(200, rake.clone()),
+            _ => MockResponse::not_found(),
+        }).await;
+        let gemfile_data = GemfileData::parse_unresolved(&format!("source '{}'
+gem 'rake', '13.0.1'
+gem 'forked', git: 'https://example.com/org/forked.git', branch: 'main'
+gem 'hosted', github: 'org/hosted'
+", server.url)).unwrap();
+
+        // Gitのリポジトリと参照が記録されるか
+        assert_eq!(gemfile_data.gems[0].origin, GemSource::Registry);
+        assert_eq!(gemfile_data.gems[1].origin, GemSource::Git {
+            url: "https://example.com/org/forked.git".to_string(),
+            reference: Some("main".to_string()),
+        });
+        assert_eq!(gemfile_data.gems[2].origin, GemSource::Git {
+            url: "https://github.com/org/hosted.git".to_string(),
+            reference: None,
+        });
+
+        // レジストリに問い合わせず、理由と共にスキップされるか
+        let options = InstallOptions { allow_insecure: true, verify_checksums: false, ..Default::default() };
+        let info = install_gems_with_options(gemfile_data, &directory.join("gems"), &directory.join("cache"), &options).await.unwrap();
+        assert_eq!(info.install_gems, vec!["rake-13.0.1".to_string()]);
+        assert!(info.failed_gems.is_empty());
+        let skipped: Vec<&str> = info.skipped_gems.iter().map(|skipped| skipped.gem_name.as_str()).collect();
+        assert_eq!(skipped, vec!["forked", "hosted"]);
+        assert!(info.skipped_gems[0].reason.contains("https://example.com/org/forked.git"));
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    ///
+    /// ローカルのディレクトリを参照するGemのテスト
+    ///
+    #[tokio::test]
+    pub async fn path_gem_test() {
+        let directory = test_directory("path_gem");
+        let rake = GemBuilder::new("rake", "13.0.1").build();
+        let server = MockServer::start(move |request| match request.path.as_str() {
+            "/downloads/rake-13.0.1.gem" => MockResponse::new(200, rake.clone()),
+            _ => MockResponse::not_found(),
+        }).await;
+        // Gemfileを含むローカルのGem
+        let local = directory.join("mylib");
+        std::fs::create_dir_all(local.join("lib")).unwrap();
+        std::fs::write(local.join("Gemfile"), "gem 'rake'\n").unwrap();
+        let gemfile = directory.join("app/Gemfile");
+        std::fs::create_dir_all(gemfile.parent().unwrap()).unwrap();
+        std::fs::write(&gemfile, format!("source '{}'\ngem 'rake', '13.0.1'\ngem 'mylib', path: '../mylib'\ngem 'missing', path: '../missing'\n", server.url)).unwrap();
+
+        let options = InstallOptions::new(directory.join("gems"), directory.join("cache")).allow_insecure(true).verify_checksums(false);
+        let info = install_from_gemfile_file_with_options(&gemfile, &options).await.unwrap();
+
+        // Gemfileからの相対パスのディレクトリが参照され、ダウンロードされないか
+        let mylib = info.installed.iter().find(|gem| gem.name == "mylib").unwrap();
+        assert_eq!(mylib.install_path, directory.join("app/../mylib"));
+        assert!(info.install_gems.contains(&"rake-13.0.1".to_string()));
+        assert_eq!(server.requests().len(), 1);
+        // ローカルのGemに含まれるGemfileが見つかるか
+        assert_eq!(info.find_gemfiles, vec![FindGemFileInfo {
+            gem_name: "mylib".to_string(),
+            gemfile_path: directory.join("app/../mylib/Gemfile"),
+        }]);
+        // 存在しないディレクトリは失敗として記録されるか
+        assert_eq!(info.failed_gems.len(), 1);
+        assert_eq!(info.failed_gems[0].gem_name, "missing");
+    }
+
+    ///
+    /// Gemfile.lockに記録されたバージョンをインストールするテスト
+    ///
+    #[tokio::test]
+    pub async fn install_from_lockfile_test() {
+        let directory = test_directory("install_from_lockfile");
+        let rack = GemBuilder::new("rack", "1.0.0").build();
+        let rake = GemBuilder::new("rake", "13.0.1").build();
+        let server = MockServer::start(move |request| {
+            match request.path.as_str() {
+                // 制約を満たすより新しいバージョンが存在する
+                "/info/rack" => MockResponse::new(200, "---\n1.0.0 |\n1.1.0 |\n"),
+                "/downloads/rack-1.0.0.gem" => MockResponse::new(200, rack.clone()),
+                "/downloads/rake-13.0.1.gem" => MockResponse::new(200, rake.clone()),
+                _ => MockResponse::not_found(),
+            }
+        }).await;
+        let lockfile = format!("GEM\n  remote: {}/\n  specs:\n    rack (1.0.0)\n    rake (13.0.1)\n\nDEPENDENCIES\n  rack (~> 1.0)\n", server.url);
+        std::fs::write(directory.join("Gemfile.lock"), &lockfile).unwrap();
+        let options = InstallOptions::new(directory.join("gems"), directory.join("cache")).allow_insecure(true).verify_checksums(false);
+
+        // Gemfile.lockのみからすべてのGemをインストールできるか
+        let info = install_from_lockfile_with_options(&directory.join("Gemfile.lock"), &options).await.unwrap();
+        let mut installed = info.install_gems.clone();
+        installed.sort();
+        assert_eq!(installed, vec!["rack-1.0.0".to_string(), "rake-13.0.1".to_string()]);
+
+        // Gemfileと両方ある場合は、Gemfileの制約よりもGemfile.lockのバージョンを優先するか
+        let gemfile = directory.join("Gemfile");
+        std::fs::write(&gemfile, format!("source '{}'\ngem 'rack', '~> 1.0'\n", server.url)).unwrap();
+        let options = InstallOptions::new(directory.join("gems2"), directory.join("cache2")).allow_insecure(true).verify_checksums(false);
+        let info = install_from_gemfile_file_with_options(&gemfile, &options).await.unwrap();
+        assert_eq!(info.install_gems, vec!["rack-1.0.0".to_string()]);
+        assert_eq!(server.request_count("/info/rack"), 0);
+    }
+
+    ///
+    /// `$LOAD_PATH`に追加するディレクトリの一覧のテスト
+    ///
+    #[tokio::test]
+    pub async fn load_paths_test() {
+        let directory = test_directory("load_paths");
+        let rack = GemBuilder::new("rack", "1.0.0").build();
+        let server = MockServer::start(move |request| {
+            match request.path.as_str() {
+                "/downloads/rack-1.0.0.gem" => MockResponse::new(200, rack.clone()),
+                _ => MockResponse::not_found(),
+            }
+        }).await;
+        // `lib`ディレクトリのないローカルのGem
+        std::fs::create_dir_all(directory.join("local/bin")).unwrap();
+        let gemfile = directory.join("Gemfile");
+        std::fs::write(&gemfile, format!("source '{}'\ngem 'rack', '1.0.0'\ngem 'local', path: 'local'\n", server.url)).unwrap();
+        let options = InstallOptions::new(directory.join("gems"), directory.join("cache")).allow_insecure(true).verify_checksums(false);
+        let info = install_from_gemfile_file_with_options(&gemfile, &options).await.unwrap();
+        assert_eq!(info.installed.len(), 2);
+
+        // インストールしたGemの`lib`のみが含まれるか
+        let rack_path = &info.installed.iter().find(|gem| gem.name == "rack").unwrap().install_path;
+        assert_eq!(info.load_paths(&directory.join("gems")), vec![rack_path.join("lib")]);
+        assert!(rack_path.join("lib/rack.rb").exists());
+    }
+
+    ///
+    /// 展開先のディレクトリが既に存在する場合の動作のテスト
+    ///
+    #[tokio::test]
+    pub async fn on_existing_test() {
+        let directory = test_directory("on_existing");
+        let rack = GemBuilder::new("rack", "1.0.0").build();
+        let server = MockServer::start(move |request| {
+            match request.path.as_str() {
+                "/downloads/rack-1.0.0.gem" => MockResponse::new(200, rack.clone()),
+                _ => MockResponse::not_found(),
+            }
+        }).await;
+
+        for (mode, name) in [(ExistingDirectory::Overwrite, "overwrite"), (ExistingDirectory::Skip, "skip"), (ExistingDirectory::Error, "error")] {
+            // バージョンを含まないディレクトリに、別のバージョンが展開されている
+            let install_directory = directory.join(name);
+            std::fs::create_dir_all(install_directory.join("rack")).unwrap();
+            std::fs::write(install_directory.join("rack/VERSION"), "0.9.0").unwrap();
+            let gemfile_data = GemfileData::parse_unresolved(&format!("source '{}'\ngem 'rack', '1.0.0'\n", server.url)).unwrap();
+            let mut options = InstallOptions::new(&install_directory, directory.join("cache"))
+                .allow_insecure(true)
+                .verify_checksums(false)
+                .on_existing(mode);
+            options.dest_namer = Some(Arc::new(|gem: &ResolvedGem| PathBuf::from(&gem.name)));
+            let info = install_gems_with_options(gemfile_data, &install_directory, &directory.join("cache"), &options).await.unwrap();
+
+            let existing = install_directory.join("rack/VERSION").exists();
+            match mode {
+                // 既存のディレクトリを置き換えるか
+                ExistingDirectory::Overwrite => {
+                    assert_eq!(info.install_gems, vec!["rack-1.0.0".to_string()]);
+                    assert!(!existing);
+                    assert!(install_directory.join("rack/lib/rack.rb").exists());
+                }
+                // 既存のディレクトリを残し、理由を記録するか
+                ExistingDirectory::Skip => {
+                    assert!(info.install_gems.is_empty());
+                    assert!(existing);
+                    assert_eq!(info.skipped_gems.len(), 1);
+                    assert_eq!(info.skipped_gems[0].gem_name, "rack");
+                }
+                // 既存のディレクトリを残し、失敗として記録するか
+                ExistingDirectory::Error => {
+                    assert!(info.install_gems.is_empty());
+                    assert!(existing);
+                    assert_eq!(info.failed_gems.len(), 1);
+                    assert!(info.failed_gems[0].error.contains("already exists"));
+                }
+            }
+        }
+        // Overwriteでのみダウンロードするか
+        assert_eq!(server.request_count("/downloads/rack-1.0.0.gem"), 1);
+    }
+
+    ///
+    /// 解決したGemを確認し、承認されたGemのみをインストールするテスト
+    ///
+    #[tokio::test]
+    pub async fn confirm_resolution_test() {
+        let directory = test_directory("confirm_resolution");
+        let names = ["rack", "rake", "puma"];
+        let gems: Vec<Vec<u8>> = names.iter().map(|name| GemBuilder::new(name, "1.0.0").build()).collect();
+        let server = MockServer::start(move |request| {
+            names.iter().zip(gems.iter())
+                .find(|(name, _)| request.path == format!("/downloads/{}-1.0.0.gem", name))
+                .map(|(_, gem)| MockResponse::new(200, gem.clone()))
+                .unwrap_or_else(MockResponse::not_found)
+        }).await;
+        let gemfile_data = GemfileData::parse_unresolved(&format!("source '{}'\ngem 'rack', '1.0.0'\ngem 'rake', '1.0.0'\ngem 'puma', '1.0.0'\n", server.url)).unwrap();
+
+        // 解決したすべてのGemを受け取り、rakeを除く
+        let received = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let confirm_received = Arc::clone(&received);
+        let options = InstallOptions::new(directory.join("gems"), directory.join("cache"))
+            .allow_insecure(true)
+            .verify_checksums(false)
+            .confirm(Arc::new(move |resolved: &[ResolvedGem]| {
+                confirm_received.lock().unwrap().extend(resolved.iter().map(|gem| format!("{}-{}", gem.name, gem.version)));
+                resolved.iter().filter(|gem| gem.name != "rake").cloned().collect()
+            }));
+        let mut info = install_gems_with_options(gemfile_data, &directory.join("gems"), &directory.join("cache"), &options).await.unwrap();
+        info.install_gems.sort();
+
+        assert_eq!(*received.lock().unwrap(), vec!["rack-1.0.0".to_string(), "rake-1.0.0".to_string(), "puma-1.0.0".to_string()]);
+        assert_eq!(info.install_gems, vec!["puma-1.0.0".to_string(), "rack-1.0.0".to_string()]);
+        assert_eq!(server.request_count("/downloads/rake-1.0.0.gem"), 0);
+    }
+}
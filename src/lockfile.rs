@@ -0,0 +1,966 @@
+//!
+//! Gemfile.lockのテキストをパースします
+//!
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use futures::future::join_all;
+use serde::{Deserialize, Serialize};
+use tokio::fs::{read_to_string, write};
+use crate::download::local_platforms;
+use crate::error::GemfileError;
+use crate::gem_version::GemVersion;
+use crate::options::InstallOptions;
+use crate::InstallInfo;
+use crate::parser::{Gem, GemfileData};
+use crate::resolution::{Dependency, ResolvedGem};
+use crate::version::{Version, VersionRequirement};
+
+/// Bundler自身のGemの名前
+pub const BUNDLER_GEM: &str = "bundler";
+
+/// remoteが記録されていない場合のソース
+const DEFAULT_REMOTE: &str = "https://rubygems.org";
+
+/// `gems.rb`に対応するロックファイルの名前
+const GEMS_LOCKED: &str = "gems.locked";
+
+///
+/// Gemfile.lockに記録されたGemのデータ
+///
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LockedGem {
+    /// Gemの名前
+    pub name: String,
+    /// 固定されたバージョン
+    pub version: String,
+    /// プラットフォーム(指定がある場合)
+    pub platform: Option<String>,
+    /// 実行時の依存関係
+    #[serde(default)]
+    pub dependencies: Vec<Dependency>,
+    /// このGemが記録されたGEMセクションのremote
+    #[serde(default)]
+    pub remote: Option<String>,
+}
+
+impl LockedGem {
+    ///
+    /// CHECKSUMSのキーに使用する名前を取得する
+    ///
+    /// return - `name-version`。プラットフォームがある場合は`name-version-platform`
+    ///
+    pub fn full_name(&self) -> String {
+        match &self.platform {
+            Some(platform) => format!("{}-{}-{}", self.name, self.version, platform),
+            None => format!("{}-{}", self.name, self.version),
+        }
+    }
+}
+
+///
+/// Gemfile.lockのデータ
+///
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Lockfile {
+    /// 最初のGEMセクションのremote。Gemごとのremoteは`LockedGem::remote`に記録する
+    pub remote: Option<String>,
+    /// GEMセクションのspecsに記録されたGemのリスト
+    pub specs: Vec<LockedGem>,
+    /// BUNDLED WITHセクションに記録されたBundlerのバージョン
+    #[serde(default)]
+    pub bundled_with: Option<String>,
+    /// CHECKSUMSセクションに記録されたSHA256。キーは`LockedGem::full_name`と同じ形式
+    #[serde(default)]
+    pub checksums: BTreeMap<String, String>,
+    /// DEPENDENCIESセクションに記録された、Gemfileで直接指定されたGemの一覧
+    #[serde(default)]
+    pub dependencies: Vec<Dependency>,
+}
+
+///
+/// 新しいバージョンが存在するGemの情報
+///
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OutdatedGem {
+    /// Gemの名前
+    pub name: String,
+    /// Gemfile.lockで固定されているバージョン
+    pub current: String,
+    /// APIから取得した最新のバージョン
+    pub latest: String,
+}
+
+///
+/// Gemfileの制約から解決したバージョンとGemfile.lockのバージョンの違い
+///
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Drift {
+    /// Gemの名前
+    pub name: String,
+    /// Gemfile.lockで固定されているバージョン。記録されていない場合はNone
+    pub locked: Option<String>,
+    /// Gemfileの制約から解決したバージョン
+    pub resolved: String,
+}
+
+///
+/// GemfileとGemfile.lockの直接の依存関係の違い
+///
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct GemSetDiff {
+    /// Gemfileにのみ含まれ、Gemfile.lockに記録されていないGemの一覧
+    pub only_in_gemfile: Vec<String>,
+    /// Gemfile.lockにのみ記録され、Gemfileから削除されたGemの一覧
+    pub only_in_lockfile: Vec<String>,
+}
+
+impl GemSetDiff {
+    ///
+    /// 違いがないかを確認する
+    ///
+    pub fn is_empty(&self) -> bool {
+        self.only_in_gemfile.is_empty() && self.only_in_lockfile.is_empty()
+    }
+}
+
+///
+/// Gemfile.lockのチェックサムとの照合結果
+///
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChecksumResult {
+    /// Gemの名前とバージョン(`name-version`)
+    pub gem: String,
+    /// Gemfile.lockに記録されたSHA256
+    pub expected: String,
+    /// ダウンロードした.gemファイルのSHA256
+    pub actual: String,
+    /// 一致したか
+    pub matched: bool,
+}
+
+impl Lockfile {
+    ///
+    /// Gemfile.lockのテキストをパースします
+    ///
+    /// * data - Gemfile.lockの内容
+    ///
+    /// return - パースしたデータ
+    ///
+    pub fn parse(data: &str) -> Result<Lockfile, Box<dyn Error>> {
+        let mut lockfile = Lockfile::default();
+        // 現在のセクション名
+        let mut section = "";
+        // 現在のGEMセクションのremote
+        let mut remote: Option<String> = None;
+
+        for line in data.lines() {
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            // インデントがない行はセクションの開始
+            if !line.starts_with(' ') {
+                section = line.trim();
+                continue;
+            }
+            if section == "BUNDLED WITH" {
+                lockfile.bundled_with = Some(line.trim().to_string());
+                continue;
+            }
+            if section == "DEPENDENCIES" {
+                // Gitやパスで指定されたGemは末尾に`!`が付く
+                lockfile.dependencies.push(parse_dependency_line(line.trim().trim_end_matches('!')));
+                continue;
+            }
+            if section == "CHECKSUMS" {
+                if let Some((gem, sha256)) = parse_checksum_line(line.trim()) {
+                    lockfile.checksums.insert(gem, sha256);
+                }
+                continue;
+            }
+            if section != "GEM" {
+                continue;
+            }
+
+            let indent = line.len() - line.trim_start().len();
+            let content = line.trim();
+            if let Some(value) = content.strip_prefix("remote:") {
+                remote = Some(value.trim().to_string());
+                if lockfile.remote.is_none() {
+                    lockfile.remote = remote.clone();
+                }
+            } else if indent == 4 {
+                lockfile.specs.push(LockedGem { remote: remote.clone(), ..parse_spec_line(content)? });
+            } else if indent == 6 {
+                // 直前のGemの依存関係
+                if let Some(spec) = lockfile.specs.last_mut() {
+                    spec.dependencies.push(parse_dependency_line(content));
+                }
+            }
+        }
+
+        Ok(lockfile)
+    }
+
+    ///
+    /// Gemfile.lockのファイルを読み込んでパースします
+    ///
+    /// * path - Gemfile.lockのパス
+    ///
+    /// return - パースしたデータ
+    ///
+    pub async fn parse_file(path: &Path) -> Result<Lockfile, Box<dyn Error>> {
+        let data = read_to_string(path).await?;
+        Lockfile::parse(&data)
+    }
+
+    ///
+    /// Gemを取得するソースを取得する
+    ///
+    pub fn source(&self) -> String {
+        self.remote.as_deref().unwrap_or(DEFAULT_REMOTE).trim_end_matches('/').to_string()
+    }
+
+    ///
+    /// Gemを記録したGEMセクションのソースを取得する
+    ///
+    /// * spec - Gemfile.lockに記録されたGem
+    ///
+    /// return - ソースのURL。remoteが記録されていない場合は`source`
+    ///
+    pub fn spec_source(&self, spec: &LockedGem) -> String {
+        match &spec.remote {
+            Some(remote) => remote.trim_end_matches('/').to_string(),
+            None => self.source(),
+        }
+    }
+
+    ///
+    /// 実行中の環境でインストールするGemを、Gemごとに1つ選択する
+    ///
+    /// 複数のプラットフォームが記録されたGemは、`local_platforms`の順に一致するものを優先し、
+    /// 一致しない場合はプラットフォームに依存しないもの、それもない場合は最初に記録されたものを選択する
+    ///
+    /// return - 記録された順のGemの一覧
+    ///
+    pub fn local_specs(&self) -> Vec<&LockedGem> {
+        let platforms = local_platforms();
+        let mut selected: Vec<&LockedGem> = Vec::new();
+        for spec in &self.specs {
+            if selected.iter().any(|selected| selected.name == spec.name) {
+                continue;
+            }
+            let candidates: Vec<&LockedGem> = self.specs.iter().filter(|candidate| candidate.name == spec.name).collect();
+            let chosen = platforms.iter()
+                .find_map(|platform| candidates.iter().find(|candidate| candidate.platform.as_ref() == Some(platform)))
+                .or_else(|| candidates.iter().find(|candidate| candidate.platform.is_none()))
+                .unwrap_or(&candidates[0]);
+            selected.push(chosen);
+        }
+        selected
+    }
+
+    ///
+    /// 固定されたバージョンの一覧を解決済みのGemとして取得する
+    ///
+    /// BUNDLED WITHが記録されている場合は、そのバージョンのBundlerも含める
+    ///
+    /// return - 解決済みのGemの一覧
+    ///
+    pub fn resolution(&self) -> Vec<ResolvedGem> {
+        let mut resolved: Vec<ResolvedGem> = self.specs.iter()
+            .map(|spec| ResolvedGem {
+                name: spec.name.clone(),
+                version: spec.version.clone(),
+                source: self.spec_source(spec),
+                dependencies: spec.dependencies.clone(),
+            })
+            .collect();
+        if let Some(bundled_with) = &self.bundled_with {
+            resolved.push(ResolvedGem {
+                name: BUNDLER_GEM.to_string(),
+                version: bundled_with.clone(),
+                source: self.source(),
+                dependencies: Vec::new(),
+            });
+        }
+        resolved
+    }
+
+    ///
+    /// 固定されたバージョンをインストールするためのデータを作成する
+    ///
+    /// Gemごとに記録されたGEMセクションのremoteから取得し、プラットフォームは`local_specs`で選択したものを使用する
+    ///
+    /// * include_bundler - BUNDLED WITHに記録されたBundlerもインストールするか
+    ///
+    /// return - インストールに使用するGemfileのデータ
+    ///
+    pub fn to_gemfile_data(&self, include_bundler: bool) -> GemfileData {
+        let source = self.source();
+        let mut gems: Vec<Gem> = self.local_specs().into_iter()
+            .map(|spec| Gem {
+                name: spec.name.clone(),
+                version: spec.version.clone(),
+                source: Some(self.spec_source(spec)).filter(|spec_source| *spec_source != source),
+                checksum: self.checksums.get(&spec.full_name()).cloned(),
+                resolved_platform: spec.platform.clone(),
+                ..Default::default()
+            })
+            .collect();
+        if let (true, Some(bundled_with)) = (include_bundler, &self.bundled_with) {
+            gems.push(Gem {
+                name: BUNDLER_GEM.to_string(),
+                version: bundled_with.clone(),
+                ..Default::default()
+            });
+        }
+
+        let mut sources = vec![source.clone()];
+        for spec in &self.specs {
+            let spec_source = self.spec_source(spec);
+            if !sources.contains(&spec_source) {
+                sources.push(spec_source);
+            }
+        }
+        GemfileData {
+            sources,
+            source,
+            gems,
+            ..Default::default()
+        }
+    }
+
+    ///
+    /// GemfileのGemのバージョンを、Gemfile.lockに記録されたバージョンに固定する
+    ///
+    /// Gemfileの制約よりもGemfile.lockを優先する。記録されていないGemはGemfileの制約のまま
+    ///
+    /// * gemfile_data - Gemfileの読み込み済みデータ
+    ///
+    pub fn pin(&self, gemfile_data: &mut GemfileData) {
+        let specs = self.local_specs();
+        for gem in gemfile_data.gems.iter_mut().filter(|gem| gem.is_registry()) {
+            let Some(spec) = specs.iter().find(|spec| spec.name == gem.name) else {
+                continue;
+            };
+            gem.version = spec.version.clone();
+            gem.requirements = vec![format!("= {}", spec.version)];
+            gem.version_expression = None;
+            gem.resolved_platform = spec.platform.clone();
+            if let Some(checksum) = self.checksums.get(&spec.full_name()) {
+                gem.checksum = Some(checksum.clone());
+            }
+        }
+    }
+}
+
+///
+/// Gemfileに対応するロックファイルのパスを取得する
+///
+/// `gems.rb`の場合は`gems.locked`、それ以外は`Gemfile.lock`のように`.lock`を付けた名前にする
+///
+/// * gemfile - Gemfileのパス
+///
+/// return - ロックファイルのパス
+///
+pub fn lockfile_path(gemfile: &Path) -> PathBuf {
+    match gemfile.file_name().and_then(|name| name.to_str()) {
+        Some("gems.rb") => gemfile.with_file_name(GEMS_LOCKED),
+        Some(name) => gemfile.with_file_name(format!("{}.lock", name)),
+        None => gemfile.join("Gemfile.lock"),
+    }
+}
+
+///
+/// バージョンを解決したGemからGemfile.lockのテキストを作成する
+///
+/// このクレートで解決するGemfileに書かれたGemのみを記録し、依存関係は記録しない。
+/// ソースごとにGEMセクションを作成し、specsとDEPENDENCIESは名前順に並べる
+///
+/// * gems - バージョンを解決したGemの一覧
+/// * source - Gemfile全体のソース
+///
+/// return - Gemfile.lockの内容
+///
+pub fn lockfile_text(gems: &[Gem], source: &str) -> String {
+    // ソースごとにGemをまとめる
+    let mut sources: BTreeMap<&str, Vec<&Gem>> = BTreeMap::new();
+    for gem in gems {
+        sources.entry(gem.source.as_deref().unwrap_or(source).trim_end_matches('/')).or_default().push(gem);
+    }
+
+    let mut text = String::new();
+    for (remote, mut gems) in sources {
+        gems.sort_by(|a, b| a.name.cmp(&b.name));
+        text.push_str(&format!("GEM\n  remote: {}/\n  specs:\n", remote));
+        for gem in gems {
+            let version = match gem.platform() {
+                Some(platform) => format!("{}-{}", gem.version, platform),
+                None => gem.version.clone(),
+            };
+            text.push_str(&format!("    {} ({})\n", gem.name, version));
+        }
+        text.push('\n');
+    }
+
+    let mut dependencies: Vec<&Gem> = gems.iter().collect();
+    dependencies.sort_by(|a, b| a.name.cmp(&b.name));
+    text.push_str("DEPENDENCIES\n");
+    for gem in dependencies {
+        match gem.requirements.is_empty() {
+            true => text.push_str(&format!("  {}\n", gem.name)),
+            false => text.push_str(&format!("  {} ({})\n", gem.name, gem.requirement())),
+        }
+    }
+    text
+}
+
+///
+/// バージョンを解決したGemからGemfile.lockを作成する
+///
+/// * path - 書き込むGemfile.lockのパス
+/// * gems - バージョンを解決したGemの一覧
+/// * source - Gemfile全体のソース
+///
+/// return - 処理の結果
+///
+pub async fn write_lockfile(path: &Path, gems: &[Gem], source: &str) -> Result<(), Box<dyn Error>> {
+    write(path, lockfile_text(gems, source)).await?;
+    Ok(())
+}
+
+///
+/// specsの行をパースする
+///
+/// * line - `name (version)`の形式の行
+///
+/// return - パースしたGemのデータ
+///
+fn parse_spec_line(line: &str) -> Result<LockedGem, Box<dyn Error>> {
+    let Some((name, rest)) = line.split_once(" (") else {
+        return Err(format!("Invalid spec line {}", line).into());
+    };
+    let Some(version) = rest.strip_suffix(')') else {
+        return Err(format!("Invalid spec line {}", line).into());
+    };
+
+    // プラットフォーム付きのバージョン(例: 1.15.0-x86_64-linux)を分割
+    let (version, platform) = match version.split_once('-') {
+        Some((version, platform)) => (version.to_string(), Some(platform.to_string())),
+        None => (version.to_string(), None),
+    };
+
+    Ok(LockedGem {
+        name: name.trim().to_string(),
+        version,
+        platform,
+        dependencies: Vec::new(),
+        remote: None,
+    })
+}
+
+///
+/// specsの依存関係の行をパースする
+///
+/// * line - `name (requirement)`または`name`の形式の行
+///
+/// return - 依存関係。制約がない場合は`>= 0`
+///
+fn parse_dependency_line(line: &str) -> Dependency {
+    match line.split_once(" (") {
+        Some((name, requirement)) => Dependency {
+            name: name.trim().to_string(),
+            requirement: requirement.trim_end_matches(')').to_string(),
+        },
+        None => Dependency {
+            name: line.trim().to_string(),
+            requirement: ">= 0".to_string(),
+        },
+    }
+}
+
+///
+/// CHECKSUMSの行をパースする
+///
+/// * line - `name (version) sha256=...`の形式の行
+///
+/// return - `name-version`(プラットフォームがある場合は`name-version-platform`)とSHA256。SHA256が記録されていない場合はNone
+///
+fn parse_checksum_line(line: &str) -> Option<(String, String)> {
+    let (name, rest) = line.split_once(" (")?;
+    let (version, checksums) = rest.split_once(')')?;
+    // 複数のアルゴリズムはカンマ区切りで記録される
+    let sha256 = checksums.split(',')
+        .find_map(|checksum| checksum.trim().strip_prefix("sha256="))?;
+    Some((format!("{}-{}", name.trim(), version), sha256.to_lowercase()))
+}
+
+///
+/// GemfileがGemfile.lockと同じバージョンに解決されるかを、ダウンロードせずに確認する
+///
+/// * gemfile_data - Gemfileの読み込み済みデータ
+/// * lockfile - Gemfile.lockのデータ
+/// * source - バージョンを取得するソース
+///
+/// return - 解決したバージョンが固定されたバージョンと異なるGemの一覧
+///
+pub async fn check_lockfile_current(gemfile_data: &GemfileData, lockfile: &Lockfile, source: &str) -> Result<Vec<Drift>, GemfileError> {
+    check_lockfile_current_with_options(gemfile_data, lockfile, source, &InstallOptions::default()).await
+}
+
+///
+/// オプションを指定して、GemfileがGemfile.lockと同じバージョンに解決されるかを確認する
+///
+/// * gemfile_data - Gemfileの読み込み済みデータ
+/// * lockfile - Gemfile.lockのデータ
+/// * source - バージョンを取得するソース
+/// * options - インストール処理のオプション
+///
+/// return - 解決したバージョンが固定されたバージョンと異なるGemの一覧
+///
+pub async fn check_lockfile_current_with_options(gemfile_data: &GemfileData, lockfile: &Lockfile, source: &str, options: &InstallOptions) -> Result<Vec<Drift>, GemfileError> {
+    let resolver = options.version_resolver();
+    let semaphore = options.semaphore();
+    let semaphore = &semaphore;
+    let tasks: Vec<_> = gemfile_data.gems.iter()
+        // Gitやローカルのパスから取得するGemはレジストリに問い合わせない
+        .filter(|gem| gem.is_registry())
+        .map(|gem| async move {
+            let _permit = semaphore.acquire().await?;
+            let requirement = VersionRequirement::parse(&gem.requirement())?;
+            let source = gem.source.as_deref().unwrap_or(source);
+            resolver.resolve(source, &gem.name, &requirement, options).await.map(|resolved| (gem, resolved))
+        }).collect();
+
+    let mut drifts = Vec::new();
+    for result in join_all(tasks).await {
+        let (gem, resolved) = result?;
+        let locked = lockfile.specs.iter()
+            .find(|spec| spec.name == gem.name)
+            .map(|spec| spec.version.clone());
+        if locked.as_ref() != Some(&resolved) {
+            drifts.push(Drift {
+                name: gem.name.clone(),
+                locked,
+                resolved,
+            });
+        }
+    }
+
+    Ok(drifts)
+}
+
+///
+/// GemfileのGemとGemfile.lockのDEPENDENCIESに記録されたGemを比較する
+///
+/// * gemfile_data - Gemfileの読み込み済みデータ
+/// * lockfile - Gemfile.lockのデータ
+///
+/// return - 一方にのみ含まれるGemの名前の一覧(名前順)
+///
+pub fn lockfile_gem_set_diff(gemfile_data: &GemfileData, lockfile: &Lockfile) -> GemSetDiff {
+    let gemfile_names: BTreeSet<&str> = gemfile_data.gems.iter().map(|gem| gem.name.as_str()).collect();
+    let locked_names: BTreeSet<&str> = lockfile.dependencies.iter().map(|dependency| dependency.name.as_str()).collect();
+    GemSetDiff {
+        only_in_gemfile: gemfile_names.difference(&locked_names).map(|name| name.to_string()).collect(),
+        only_in_lockfile: locked_names.difference(&gemfile_names).map(|name| name.to_string()).collect(),
+    }
+}
+
+///
+/// インストールしたGemのSHA256をGemfile.lockのチェックサムと照合する
+///
+/// チェックサムが記録されていないGemは結果に含めない
+///
+/// * info - インストールの結果
+/// * lockfile - Gemfile.lockのデータ
+///
+/// return - 照合した結果の一覧
+///
+pub fn verify_against_lockfile(info: &InstallInfo, lockfile: &Lockfile) -> Vec<ChecksumResult> {
+    info.installed.iter()
+        .filter_map(|installed| {
+            let gem = match &installed.platform {
+                Some(platform) => format!("{}-{}-{}", installed.name, installed.version, platform),
+                None => format!("{}-{}", installed.name, installed.version),
+            };
+            let expected = lockfile.checksums.get(&gem)?.clone();
+            let matched = expected == installed.sha256;
+            Some(ChecksumResult {
+                gem,
+                expected,
+                actual: installed.sha256.clone(),
+                matched,
+            })
+        })
+        .collect()
+}
+
+///
+/// Gemfile.lockに記録されたGemのうち、新しいバージョンが存在するものを取得する
+///
+/// * lockfile - Gemfile.lockのデータ
+/// * source - APIのURL
+///
+/// return - 新しいバージョンが存在するGemの一覧
+///
+pub async fn outdated(lockfile: &Lockfile, source: &str) -> Result<Vec<OutdatedGem>, GemfileError> {
+    outdated_with_options(lockfile, source, &InstallOptions::default()).await
+}
+
+///
+/// オプションを指定して、新しいバージョンが存在するGemを取得する
+///
+/// * lockfile - Gemfile.lockのデータ
+/// * source - APIのURL
+/// * options - インストール処理のオプション
+///
+/// return - 新しいバージョンが存在するGemの一覧
+///
+pub async fn outdated_with_options(lockfile: &Lockfile, source: &str, options: &InstallOptions) -> Result<Vec<OutdatedGem>, GemfileError> {
+    // すべてのGemの最新バージョンを取得
+    let tasks: Vec<_> = lockfile.specs.iter().map(|spec| async move {
+        GemVersion::get_version_with_options(source, &spec.name, options).await.map(|latest| (spec, latest.version))
+    }).collect();
+
+    let mut outdated_gems = Vec::new();
+    for result in join_all(tasks).await {
+        let (spec, latest) = result?;
+        // 固定されたバージョンより新しい場合のみ追加
+        if Version::parse(&latest)? > Version::parse(&spec.version)? {
+            outdated_gems.push(OutdatedGem {
+                name: spec.name.clone(),
+                current: spec.version.clone(),
+                latest,
+            });
+        }
+    }
+
+    Ok(outdated_gems)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use crate::download::{file_sha256, local_platforms};
+    use crate::install_gems_with_options;
+    use crate::lockfile::{check_lockfile_current_with_options, lockfile_gem_set_diff, outdated_with_options, verify_against_lockfile, Drift, GemSetDiff, Lockfile, LockedGem, OutdatedGem};
+    use crate::parser::GemfileData;
+    use crate::options::InstallOptions;
+    use crate::resolution::Dependency;
+    use crate::test_util::{test_directory, GemBuilder, MockResponse, MockServer};
+
+    /// テスト用のGemfile.lock
+    const LOCKFILE: &str = "GEM
+  remote: https://rubygems.org/
+  specs:
+    nokogiri (1.15.0-x86_64-linux)
+      racc (~> 1.4)
+    racc (1.7.3)
+    rake (13.0.1)
+
+PLATFORMS
+  x86_64-linux
+
+DEPENDENCIES
+  nokogiri
+  rake (~> 13.0)
+
+BUNDLED WITH
+   2.4.10
+";
+
+    ///
+    /// Gemfile.lockのパースのテスト
+    ///
+    #[test]
+    pub fn parse_test() {
+        let lockfile = Lockfile::parse(LOCKFILE).unwrap();
+        assert_eq!(lockfile.remote.as_deref(), Some("https://rubygems.org/"));
+        assert_eq!(lockfile.specs.len(), 3);
+        assert_eq!(lockfile.specs[0].name, "nokogiri");
+        assert_eq!(lockfile.specs[0].version, "1.15.0");
+        assert_eq!(lockfile.specs[0].platform.as_deref(), Some("x86_64-linux"));
+        assert_eq!(lockfile.specs[2].name, "rake");
+        assert_eq!(lockfile.specs[2].version, "13.0.1");
+        assert_eq!(lockfile.specs[0].dependencies, vec![
+            Dependency { name: "racc".to_string(), requirement: "~> 1.4".to_string() },
+        ]);
+    }
+
+    ///
+    /// 複数のGEMセクションと複数のプラットフォームが記録されたGemfile.lockからインストールするテスト
+    ///
+    #[tokio::test]
+    pub async fn multiple_remotes_and_platforms_test() {
+        let directory = test_directory("lockfile_multiple_remotes_and_platforms");
+        let platform = local_platforms()[0].clone();
+        let nokogiri = GemBuilder::new("nokogiri", "1.15.0").metadata(&format!("platform: {}\n", platform)).build();
+        let internal = GemBuilder::new("internal", "0.3.0").build();
+        let sha256 = |name: &str, body: &[u8]| {
+            let path = directory.join(name);
+            std::fs::write(&path, body).unwrap();
+            file_sha256(&path).unwrap()
+        };
+        let (nokogiri_sha256, internal_sha256) = (sha256("nokogiri.gem", &nokogiri), sha256("internal.gem", &internal));
+        let nokogiri_path = format!("/downloads/nokogiri-1.15.0-{}.gem", platform);
+        let public = MockServer::start(move |request| match request.path.as_str() {
+            path if path == nokogiri_path => MockResponse::new(200, nokogiri.clone()),
+            _ => MockResponse::not_found(),
+        }).await;
+        let private = MockServer::start(move |request| match request.path.as_str() {
+            "/downloads/internal-0.3.0.gem" => MockResponse::new(200, internal.clone()),
+            _ => MockResponse::not_found(),
+        }).await;
+        let lockfile = Lockfile::parse(&format!("GEM
+  remote: {public}/
+  specs:
+    nokogiri (1.15.0)
+    nokogiri (1.15.0-java)
+    nokogiri (1.15.0-{platform})
+
+GEM
+  remote: {private}/
+  specs:
+    internal (0.3.0)
+
+CHECKSUMS
+  internal (0.3.0) sha256={internal_sha256}
+  nokogiri (1.15.0) sha256={zero}
+  nokogiri (1.15.0-{platform}) sha256={nokogiri_sha256}
+
+DEPENDENCIES
+  internal
+  nokogiri
+", public = public.url, private = private.url, zero = "0".repeat(64))).unwrap();
+
+        // Gemごとのremoteと、実行中のプラットフォーム向けのGemが1つだけ選択されるか
+        assert_eq!(lockfile.source(), public.url);
+        let gemfile_data = lockfile.to_gemfile_data(false);
+        assert_eq!(gemfile_data.gems.len(), 2);
+        assert_eq!(gemfile_data.gems[0].resolved_platform.as_deref(), Some(platform.as_str()));
+        assert_eq!(gemfile_data.gems[0].checksum.as_deref(), Some(nokogiri_sha256.as_str()));
+        assert_eq!(gemfile_data.gems[1].source.as_deref(), Some(private.url.as_str()));
+
+        // 記録されたチェックサムと照合しながら、それぞれのremoteから取得するか
+        let options = InstallOptions { allow_insecure: true, ..Default::default() };
+        let info = install_gems_with_options(gemfile_data, &directory.join("gems"), &directory.join("cache"), &options).await.unwrap();
+        assert!(info.failed_gems.is_empty(), "{:?}", info.failed_gems);
+        assert_eq!(info.installed.len(), 2);
+        assert_eq!(public.requests().len(), 1);
+        assert_eq!(private.requests().len(), 1);
+        assert!(verify_against_lockfile(&info, &lockfile).iter().all(|result| result.matched));
+        assert_eq!(verify_against_lockfile(&info, &lockfile).len(), 2);
+    }
+
+    ///
+    /// 新しいバージョンが存在するGemの取得のテスト
+    ///
+    #[tokio::test]
+    pub async fn outdated_test() {
+        let server = MockServer::start(|request| {
+            match request.path.as_str() {
+                "/api/v1/gems/nokogiri.json" => MockResponse::new(200, "{\"version\":\"1.16.0\"}"),
+                "/api/v1/gems/racc.json" => MockResponse::new(200, "{\"version\":\"1.7.3\"}"),
+                "/api/v1/gems/rake.json" => MockResponse::new(200, "{\"version\":\"13.2.1\"}"),
+                _ => MockResponse::not_found(),
+            }
+        }).await;
+
+        let lockfile = Lockfile::parse(LOCKFILE).unwrap();
+        let options = InstallOptions { allow_insecure: true, ..Default::default() };
+        let outdated_gems = outdated_with_options(&lockfile, &server.url, &options).await.unwrap();
+        assert_eq!(outdated_gems, vec![
+            OutdatedGem { name: "nokogiri".to_string(), current: "1.15.0".to_string(), latest: "1.16.0".to_string() },
+            OutdatedGem { name: "rake".to_string(), current: "13.0.1".to_string(), latest: "13.2.1".to_string() },
+        ]);
+
+        // ダウンロードが行われていないか
+        assert!(server.requests().iter().all(|request| !request.path.starts_with("/downloads/")));
+    }
+
+    ///
+    /// BUNDLED WITHのBundlerのバージョンのテスト
+    ///
+    #[tokio::test]
+    pub async fn bundled_with_test() {
+        let lockfile = Lockfile::parse(LOCKFILE).unwrap();
+        assert_eq!(lockfile.bundled_with.as_deref(), Some("2.4.10"));
+
+        // 解決済みのGemの一覧に含まれるか
+        let resolution = lockfile.resolution();
+        let bundler = resolution.iter().find(|gem| gem.name == "bundler").unwrap();
+        assert_eq!(bundler.version, "2.4.10");
+        assert_eq!(bundler.source, "https://rubygems.org");
+
+        // 指定した場合のみインストールの対象になるか
+        assert!(lockfile.to_gemfile_data(false).gems.iter().all(|gem| gem.name != "bundler"));
+        let directory = test_directory("bundled_with");
+        let body = GemBuilder::new("bundler", "2.4.10").build();
+        let server = MockServer::start(move |request| {
+            match request.path.as_str() {
+                "/downloads/bundler-2.4.10.gem" => MockResponse::new(200, body.clone()),
+                _ => MockResponse::not_found(),
+            }
+        }).await;
+        let lockfile = Lockfile::parse(&format!("GEM\n  remote: {}/\n  specs:\n\nBUNDLED WITH\n   2.4.10\n", server.url)).unwrap();
+        let gemfile_data = lockfile.to_gemfile_data(true);
+        let options = InstallOptions { allow_insecure: true, verify_checksums: false, ..Default::default() };
+        let info = install_gems_with_options(gemfile_data, &directory.join("gems"), &directory.join("cache"), &options).await.unwrap();
+        assert_eq!(info.install_gems, vec!["bundler-2.4.10".to_string()]);
+    }
+
+    ///
+    /// Gemfile.lockのチェックサムとの照合のテスト
+    ///
+    #[tokio::test]
+    pub async fn verify_against_lockfile_test() {
+        let directory = test_directory("verify_against_lockfile");
+        let rake = GemBuilder::new("rake", "13.0.1").build();
+        let rack = GemBuilder::new("rack", "3.0.0").build();
+        let cache = test_directory("verify_against_lockfile_source");
+        std::fs::write(cache.join("rake.gem"), &rake).unwrap();
+        let rake_sha256 = file_sha256(&cache.join("rake.gem")).unwrap();
+        let server = MockServer::start(move |request| {
+            match request.path.as_str() {
+                "/downloads/rake-13.0.1.gem" => MockResponse::new(200, rake.clone()),
+                "/downloads/rack-3.0.0.gem" => MockResponse::new(200, rack.clone()),
+                _ => MockResponse::not_found(),
+            }
+        }).await;
+
+        // rackのチェックサムは誤っている
+        let lockfile = Lockfile::parse(&format!("GEM
+  remote: {}/
+  specs:
+    rack (3.0.0)
+    rake (13.0.1)
+
+CHECKSUMS
+  rack (3.0.0) sha256={}
+  rake (13.0.1) sha256={}
+", server.url, "0".repeat(64), rake_sha256)).unwrap();
+        assert_eq!(lockfile.checksums.len(), 2);
+
+        let options = InstallOptions { allow_insecure: true, verify_checksums: false, ..Default::default() };
+        let info = install_gems_with_options(lockfile.to_gemfile_data(false), &directory.join("gems"), &directory.join("cache"), &options).await.unwrap();
+        let mut results = verify_against_lockfile(&info, &lockfile);
+        results.sort_by(|a, b| a.gem.cmp(&b.gem));
+
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].gem, "rack-3.0.0");
+        assert!(!results[0].matched);
+        assert_eq!(results[1].gem, "rake-13.0.1");
+        assert!(results[1].matched);
+        assert_eq!(results[1].actual, rake_sha256);
+    }
+
+    ///
+    /// Gemfile.lockが古くなっていないかの確認のテスト
+    ///
+    #[tokio::test]
+    pub async fn check_lockfile_current_test() {
+        let server = MockServer::start(|request| {
+            let response = match request.path.as_str() {
+                "/info/rake" => MockResponse::new(200, "---\n13.0.1 |checksum:a\n13.2.1 |checksum:b\n"),
+                "/info/rack" => MockResponse::new(200, "---\n2.2.8 |checksum:a\n3.0.0 |checksum:b\n"),
+                _ => MockResponse::not_found(),
+            };
+            response.delay(Duration::from_millis(100))
+        }).await;
+        let gemfile_data = GemfileData::parse_unresolved("gem 'rake', '~> 13.0'\ngem 'rack', '~> 2.2'\ngem 'forked', git: 'https://example.com/org/forked.git'\n").unwrap();
+        let lockfile = Lockfile::parse("GEM
+  remote: https://rubygems.org/
+  specs:
+    rack (2.2.8)
+    rake (13.0.1)
+").unwrap();
+        let options = InstallOptions { allow_insecure: true, concurrency: Some(1), ..Default::default() };
+
+        // rakeのみ新しいバージョンに解決され、Gitから取得するGemは問い合わせないか
+        let drifts = check_lockfile_current_with_options(&gemfile_data, &lockfile, &server.url, &options).await.unwrap();
+        assert_eq!(drifts, vec![Drift {
+            name: "rake".to_string(),
+            locked: Some("13.0.1".to_string()),
+            resolved: "13.2.1".to_string(),
+        }]);
+        // 本体はダウンロードしていないか
+        assert!(server.requests().iter().all(|request| request.path.starts_with("/info/")));
+        assert_eq!(server.request_count("/info/forked"), 0);
+        // 同時に問い合わせる数がconcurrencyに制限されているか
+        assert_eq!(server.max_in_flight(), 1);
+    }
+
+    ///
+    /// GemfileとGemfile.lockのGemの違いのテスト
+    ///
+    #[test]
+    pub fn lockfile_gem_set_diff_test() {
+        let lockfile = Lockfile::parse(LOCKFILE).unwrap();
+        assert_eq!(lockfile.dependencies, vec![
+            Dependency { name: "nokogiri".to_string(), requirement: ">= 0".to_string() },
+            Dependency { name: "rake".to_string(), requirement: "~> 13.0".to_string() },
+        ]);
+
+        // Gemfileに追加され、まだロックされていないGemが報告されるか
+        let gemfile_data = GemfileData::parse_unresolved("gem 'nokogiri'\ngem 'rake', '~> 13.0'\ngem 'puma', '~> 6.4'\n").unwrap();
+        assert_eq!(lockfile_gem_set_diff(&gemfile_data, &lockfile), GemSetDiff {
+            only_in_gemfile: vec!["puma".to_string()],
+            only_in_lockfile: Vec::new(),
+        });
+
+        // Gemfileから削除されたGemが報告されるか
+        let gemfile_data = GemfileData::parse_unresolved("gem 'rake', '~> 13.0'\n").unwrap();
+        assert_eq!(lockfile_gem_set_diff(&gemfile_data, &lockfile).only_in_lockfile, vec!["nokogiri".to_string()]);
+
+        // 同じ場合は違いがないか
+        let gemfile_data = GemfileData::parse_unresolved("gem 'nokogiri'\ngem 'rake', '~> 13.0'\n").unwrap();
+        assert!(lockfile_gem_set_diff(&gemfile_data, &lockfile).is_empty());
+    }
+
+    ///
+    /// インストール後にGemfile.lockを書き込むテスト
+    ///
+    #[tokio::test]
+    pub async fn write_lockfile_test() {
+        let directory = test_directory("write_lockfile");
+        let rack = GemBuilder::new("rack", "1.1.0").build();
+        let rake = GemBuilder::new("rake", "13.0.1").build();
+        let server = MockServer::start(move |request| {
+            match request.path.as_str() {
+                "/info/rack" => MockResponse::new(200, "---\n1.0.0 |\n1.1.0 |\n"),
+                "/downloads/rack-1.1.0.gem" => MockResponse::new(200, rack.clone()),
+                "/downloads/rake-13.0.1.gem" => MockResponse::new(200, rake.clone()),
+                _ => MockResponse::not_found(),
+            }
+        }).await;
+        let gemfile_data = GemfileData::parse_unresolved(&format!("source '{}'\ngem 'rake', '13.0.1'\ngem 'rack', '~> 1.0'\n", server.url)).unwrap();
+        let lockfile_path = directory.join("Gemfile.lock");
+        let options = InstallOptions::new(directory.join("gems"), directory.join("cache"))
+            .allow_insecure(true)
+            .verify_checksums(false)
+            .write_lockfile(&lockfile_path);
+        install_gems_with_options(gemfile_data, &directory.join("gems"), &directory.join("cache"), &options).await.unwrap();
+
+        // 解決したバージョンが名前順に記録されるか
+        let lockfile = Lockfile::parse(&std::fs::read_to_string(&lockfile_path).unwrap()).unwrap();
+        assert_eq!(lockfile.source(), server.url);
+        assert_eq!(lockfile.specs, vec![
+            LockedGem { name: "rack".to_string(), version: "1.1.0".to_string(), platform: None, dependencies: Vec::new(), remote: Some(format!("{}/", server.url)) },
+            LockedGem { name: "rake".to_string(), version: "13.0.1".to_string(), platform: None, dependencies: Vec::new(), remote: Some(format!("{}/", server.url)) },
+        ]);
+        assert_eq!(lockfile.dependencies, vec![
+            Dependency { name: "rack".to_string(), requirement: "~> 1.0".to_string() },
+            Dependency { name: "rake".to_string(), requirement: "13.0.1".to_string() },
+        ]);
+
+        // インストールに失敗したGemがある場合は書き込まないか
+        let failed_path = directory.join("failed.lock");
+        let gemfile_data = GemfileData::parse_unresolved(&format!("source '{}'\ngem 'missing', '1.0.0'\n", server.url)).unwrap();
+        let options = options.write_lockfile(&failed_path);
+        install_gems_with_options(gemfile_data, &directory.join("gems"), &directory.join("cache"), &options).await.unwrap();
+        assert!(!failed_path.exists());
+    }
+}
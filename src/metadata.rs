@@ -0,0 +1,396 @@
+//!
+//! .gemファイルのメタデータ(gemspec)を読み込みます
+//!
+use std::error::Error;
+use std::path::Path;
+use futures::future::join_all;
+use serde::{Deserialize, Serialize};
+use crate::download::fetch_gem;
+use crate::options::InstallOptions;
+use crate::parser::{Gem, GemfileData};
+use crate::resolution::Dependency;
+use crate::unpack_gem::{extract_gemspec, extract_gemspec_from_bytes};
+use crate::version::VersionRequirement;
+
+/// `fetch_all_metadata`のGemごとの結果
+pub type MetadataResult = (Gem, Result<GemMetadata, Box<dyn Error>>);
+
+///
+/// gemspecから読み込んだGemの情報
+///
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct GemMetadata {
+    /// Gemの名前
+    pub name: String,
+    /// Gemのバージョン
+    pub version: String,
+    /// プラットフォーム
+    pub platform: String,
+    /// 実行時の依存関係(開発時の依存関係は含まない)
+    pub dependencies: Vec<Dependency>,
+    /// ライセンスの一覧
+    pub licenses: Vec<String>,
+    /// ネイティブ拡張のビルドに使用するファイルの一覧(例: `ext/foo/extconf.rb`)
+    pub extensions: Vec<String>,
+}
+
+impl GemMetadata {
+    ///
+    /// ネイティブ拡張を含み、インストール後にビルドが必要かを確認する
+    ///
+    pub fn has_native_extension(&self) -> bool {
+        !self.extensions.is_empty()
+    }
+
+    ///
+    /// プラットフォーム向けにビルドされたGemのプラットフォームを取得する
+    ///
+    /// return - `x86_64-linux`などのプラットフォーム。プラットフォームに依存しない場合はNone
+    ///
+    pub fn binary_platform(&self) -> Option<String> {
+        match self.platform.as_str() {
+            "" | "ruby" => None,
+            platform => Some(platform.to_string()),
+        }
+    }
+
+    ///
+    /// RubyGemsが`specifications/`から読み込めるRubyのgemspecに変換する
+    ///
+    /// 読み込んだ項目のみを出力し、`require_paths`は`lib`とする
+    ///
+    /// return - `Gem::Specification.new`で定義するRubyのコード
+    ///
+    pub fn to_ruby_gemspec(&self) -> String {
+        let platform = self.binary_platform();
+        let mut lines = vec![
+            "# -*- encoding: utf-8 -*-".to_string(),
+            format!("# stub: {} {} {} lib", self.name, self.version, platform.as_deref().unwrap_or("ruby")),
+            String::new(),
+            "Gem::Specification.new do |s|".to_string(),
+            format!("  s.name = {}", ruby_string(&self.name)),
+            format!("  s.version = {}", ruby_string(&self.version)),
+        ];
+        if let Some(platform) = &platform {
+            lines.push(format!("  s.platform = {}", ruby_string(platform)));
+        }
+        lines.push(format!("  s.require_paths = {}", ruby_array(&["lib".to_string()])));
+        if !self.licenses.is_empty() {
+            lines.push(format!("  s.licenses = {}", ruby_array(&self.licenses)));
+        }
+        if !self.extensions.is_empty() {
+            lines.push(format!("  s.extensions = {}", ruby_array(&self.extensions)));
+        }
+        for dependency in &self.dependencies {
+            let requirements: Vec<String> = dependency.requirement.split(", ").map(str::to_string).collect();
+            lines.push(format!("  s.add_runtime_dependency({}, {})", ruby_string(&dependency.name), ruby_array(&requirements)));
+        }
+        lines.push("end".to_string());
+        lines.push(String::new());
+        lines.join("\n")
+    }
+
+    ///
+    /// gemspecのYAMLをパースする
+    ///
+    /// RubyGemsが出力する形式のみを対象とし、必要な項目のみを読み込む
+    ///
+    /// * yaml - metadata.gzを展開したYAMLの文字列
+    ///
+    /// return - 読み込んだ情報
+    ///
+    pub fn parse(yaml: &str) -> GemMetadata {
+        let mut metadata = GemMetadata::default();
+        // 現在の最上位のキー
+        let mut key = "";
+        // 依存関係の中の現在のキー
+        let mut dependency_key = "";
+        // 読み込み中の依存関係と種類
+        let mut dependency: Option<(Dependency, Vec<String>, bool)> = None;
+        // 読み込み中の制約の演算子
+        let mut operator: Option<String> = None;
+
+        for line in yaml.lines() {
+            let indent = line.len() - line.trim_start().len();
+            let content = line.trim();
+            if content.is_empty() || content.starts_with("---") {
+                continue;
+            }
+
+            // 最上位のキー
+            if indent == 0 && !content.starts_with('-') {
+                let Some((name, value)) = content.split_once(':') else {
+                    continue;
+                };
+                key = name;
+                let value = unquote(value.trim());
+                match key {
+                    "name" => metadata.name = value,
+                    "platform" => metadata.platform = value,
+                    _ => {}
+                }
+                continue;
+            }
+
+            match key {
+                "version" => {
+                    if let Some(version) = content.strip_prefix("version:") {
+                        metadata.version = unquote(version.trim());
+                    }
+                }
+                "licenses" | "extensions" => {
+                    let Some(item) = content.strip_prefix("- ") else {
+                        continue;
+                    };
+                    let list = if key == "licenses" { &mut metadata.licenses } else { &mut metadata.extensions };
+                    list.push(unquote(item.trim()));
+                }
+                "dependencies" => {
+                    // 新しい依存関係の開始
+                    if indent == 0 && content.starts_with("- ") {
+                        push_dependency(&mut metadata, dependency.take());
+                        dependency = Some((Dependency { name: String::new(), requirement: String::new() }, Vec::new(), true));
+                        continue;
+                    }
+                    let Some((current, requirements, runtime)) = dependency.as_mut() else {
+                        continue;
+                    };
+                    if indent == 2 {
+                        let Some((name, value)) = content.split_once(':') else {
+                            continue;
+                        };
+                        dependency_key = name;
+                        match name {
+                            "name" => current.name = unquote(value.trim()),
+                            "type" => *runtime = value.trim() == ":runtime",
+                            _ => {}
+                        }
+                    } else if dependency_key == "requirement" {
+                        // `- - ">="`の後の`version:`で1つの制約になる
+                        if let Some(value) = content.strip_prefix("- - ") {
+                            operator = Some(unquote(value.trim()));
+                        } else if let Some(version) = content.strip_prefix("version:") {
+                            if let Some(current_operator) = operator.take() {
+                                requirements.push(format!("{} {}", current_operator, unquote(version.trim())));
+                            }
+                        } else if content.strip_prefix("- ").is_some_and(|value| !value.starts_with('!')) {
+                            // インラインの形式(`- ">= 0"`)はそのまま制約として扱う
+                            requirements.push(unquote(content[2..].trim()));
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        push_dependency(&mut metadata, dependency);
+
+        metadata
+    }
+}
+
+///
+/// .gemファイルのメタデータを読み込む
+///
+/// 本体のデータは解凍せず、metadata.gzのみを読み込む
+///
+/// * gem_path - .gemファイルのパス
+///
+/// return - 読み込んだ情報
+///
+pub fn read_metadata(gem_path: &Path) -> Result<GemMetadata, Box<dyn Error>> {
+    Ok(GemMetadata::parse(&extract_gemspec(gem_path)?))
+}
+
+///
+/// Gemfileのすべてのメタデータを、本体を展開せずに並列に取得する
+///
+/// .gemファイルはディスクに保存せず、メモリ上でmetadata.gzのみを読み込む。
+/// 同時に行うリクエストの数は`concurrency`で制限する
+///
+/// * gemfile_data - Gemfileの読み込み済みデータ
+/// * source - Gemを取得するソース(`source`のブロックで指定されたGemはそのソースを使用する)
+/// * options - インストール処理のオプション
+///
+/// return - Gemとメタデータの取得結果の一覧(宣言順)
+///
+pub async fn fetch_all_metadata(gemfile_data: &GemfileData, source: &str, options: &InstallOptions) -> Vec<MetadataResult> {
+    let semaphore = options.semaphore();
+    let semaphore = &semaphore;
+    let tasks = gemfile_data.gems.iter()
+        .filter(|gem| options.includes_gem(gem))
+        .map(|gem| async move {
+            let mut gem = gem.clone();
+            let result = async {
+                let _permit = semaphore.acquire().await?;
+                let source = gem.source.as_deref().unwrap_or(source);
+                // バージョンが決まっていない場合は解決する
+                if gem.version.is_empty() {
+                    gem.version = options.version_resolver().resolve(source, &gem.name, &VersionRequirement::any(), options).await?;
+                }
+                let bytes = fetch_gem(source, &gem, options).await?;
+                Ok(GemMetadata::parse(&extract_gemspec_from_bytes(&bytes)?))
+            }.await;
+            (gem, result)
+        });
+    join_all(tasks).await
+}
+
+///
+/// 読み込みが終わった依存関係を追加する
+///
+fn push_dependency(metadata: &mut GemMetadata, dependency: Option<(Dependency, Vec<String>, bool)>) {
+    let Some((mut dependency, requirements, runtime)) = dependency else {
+        return;
+    };
+    if !runtime || dependency.name.is_empty() {
+        return;
+    }
+    dependency.requirement = if requirements.is_empty() { ">= 0".to_string() } else { requirements.join(", ") };
+    metadata.dependencies.push(dependency);
+}
+
+///
+/// Rubyの文字列リテラルに変換する(式の展開が行われないようにエスケープする)
+///
+fn ruby_string(value: &str) -> String {
+    let escaped = value.replace('\\', "\\\\").replace('"', "\\\"").replace('#', "\\#");
+    format!("\"{}\".freeze", escaped)
+}
+
+///
+/// Rubyの文字列の配列のリテラルに変換する
+///
+fn ruby_array(values: &[String]) -> String {
+    format!("[{}]", values.iter().map(|value| ruby_string(value)).collect::<Vec<_>>().join(", "))
+}
+
+///
+/// YAMLの引用符を外す
+///
+fn unquote(value: &str) -> String {
+    value.trim_matches(|c| c == '"' || c == '\'').to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use crate::metadata::{fetch_all_metadata, read_metadata, GemMetadata};
+    use crate::options::InstallOptions;
+    use crate::parser::GemfileData;
+    use crate::resolution::Dependency;
+    use crate::test_util::{test_directory, GemBuilder, MockResponse, MockServer};
+
+    ///
+    /// メタデータの読み込みのテスト
+    ///
+    #[test]
+    pub fn read_metadata_test() {
+        let directory = test_directory("read_metadata");
+        let gem_path = GemBuilder::new("native", "1.2.0")
+            .metadata(r#"platform: ruby
+dependencies:
+- !ruby/object:Gem::Dependency
+  name: rack
+  requirement: !ruby/object:Gem::Requirement
+    requirements:
+    - - ">="
+      - !ruby/object:Gem::Version
+        version: '2.0'
+    - - "<"
+      - !ruby/object:Gem::Version
+        version: '4'
+  type: :runtime
+  prerelease: false
+  version_requirements: !ruby/object:Gem::Requirement
+    requirements:
+    - - ">="
+      - !ruby/object:Gem::Version
+        version: '2.0'
+- !ruby/object:Gem::Dependency
+  name: rspec
+  requirement: !ruby/object:Gem::Requirement
+    requirements:
+    - - "~>"
+      - !ruby/object:Gem::Version
+        version: '3.0'
+  type: :development
+extensions:
+- ext/native/extconf.rb
+licenses:
+- MIT
+"#)
+            .write(&directory);
+
+        let metadata = read_metadata(&gem_path).unwrap();
+        assert_eq!(metadata.name, "native");
+        assert_eq!(metadata.version, "1.2.0");
+        assert_eq!(metadata.platform, "ruby");
+        assert_eq!(metadata.dependencies, vec![
+            Dependency { name: "rack".to_string(), requirement: ">= 2.0, < 4".to_string() },
+        ]);
+        assert_eq!(metadata.licenses, vec!["MIT".to_string()]);
+        assert_eq!(metadata.extensions, vec!["ext/native/extconf.rb".to_string()]);
+        assert!(metadata.has_native_extension());
+
+        // 拡張を含まないGem
+        let gem_path = GemBuilder::new("pure", "1.0.0").metadata("extensions: []\n").write(&directory);
+        assert!(!read_metadata(&gem_path).unwrap().has_native_extension());
+    }
+
+    ///
+    /// Gemfileのすべてのメタデータを並列に取得するテスト
+    ///
+    #[tokio::test]
+    pub async fn fetch_all_metadata_test() {
+        let native = GemBuilder::new("native", "1.0.0").metadata("extensions:\n- ext/native/extconf.rb\nlicenses:\n- MIT\n").build();
+        let pure = GemBuilder::new("pure", "2.0.0").metadata("licenses:\n- Apache-2.0\n").build();
+        let server = MockServer::start(move |request| {
+            match request.path.as_str() {
+                "/downloads/native-1.0.0.gem" => MockResponse::new(200, native.clone()).delay(Duration::from_millis(200)),
+                "/downloads/pure-2.0.0.gem" => MockResponse::new(200, pure.clone()).delay(Duration::from_millis(200)),
+                _ => MockResponse::not_found().delay(Duration::from_millis(200)),
+            }
+        }).await;
+        let gemfile_data = GemfileData::parse_unresolved("gem 'native', '1.0.0'\ngem 'pure', '2.0.0'\ngem 'missing', '1.0.0'\n").unwrap();
+        let options = InstallOptions { allow_insecure: true, verify_checksums: false, ..Default::default() };
+
+        let results = fetch_all_metadata(&gemfile_data, &server.url, &options).await;
+        assert_eq!(results.len(), 3);
+        let native = results[0].1.as_ref().unwrap();
+        assert_eq!(results[0].0.name, "native");
+        assert!(native.has_native_extension());
+        assert_eq!(native.licenses, vec!["MIT".to_string()]);
+        let pure = results[1].1.as_ref().unwrap();
+        assert_eq!(pure.version, "2.0.0");
+        assert!(!pure.has_native_extension());
+        assert!(results[2].1.is_err());
+
+        // 並列に取得されているか
+        assert_eq!(server.max_in_flight(), 3);
+    }
+
+    ///
+    /// Rubyのgemspecへの変換のテスト
+    ///
+    #[test]
+    pub fn to_ruby_gemspec_test() {
+        let metadata = GemMetadata {
+            name: "native".to_string(),
+            version: "1.2.0".to_string(),
+            platform: "x86_64-linux".to_string(),
+            dependencies: vec![Dependency { name: "racc".to_string(), requirement: ">= 1.4, < 2".to_string() }],
+            licenses: vec!["MIT".to_string()],
+            extensions: vec!["ext/\"#{quoted}\"/extconf.rb".to_string()],
+        };
+        let gemspec = metadata.to_ruby_gemspec();
+
+        assert!(gemspec.starts_with("# -*- encoding: utf-8 -*-\n# stub: native 1.2.0 x86_64-linux lib\n"));
+        assert!(gemspec.contains("Gem::Specification.new do |s|\n  s.name = \"native\".freeze\n  s.version = \"1.2.0\".freeze\n"));
+        assert!(gemspec.contains("  s.platform = \"x86_64-linux\".freeze\n"));
+        assert!(gemspec.contains("  s.licenses = [\"MIT\".freeze]\n"));
+        // 引用符と式の展開はエスケープされるか
+        assert!(gemspec.contains("  s.extensions = [\"ext/\\\"\\#{quoted}\\\"/extconf.rb\".freeze]\n"));
+        assert!(gemspec.contains("  s.add_runtime_dependency(\"racc\".freeze, [\">= 1.4\".freeze, \"< 2\".freeze])\n"));
+        assert!(gemspec.ends_with("end\n"));
+    }
+}
@@ -0,0 +1,825 @@
+//!
+//! インストール処理のオプション
+//!
+use std::collections::HashMap;
+use std::fmt::{Debug, Formatter};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use regex::Regex;
+use tokio::sync::Semaphore;
+use crate::buffer_pool::BufferPool;
+use crate::cache::CacheLayout;
+use crate::client;
+use crate::credentials::CredentialLookup;
+use crate::error::GemfileError;
+use crate::events::EventHandler;
+use crate::layout::Layout;
+use crate::parser::Gem;
+use crate::resolution::ResolvedGem;
+use crate::resolver::{ResolutionStrategy, RubyGemsResolver, VersionResolver};
+
+/// デフォルトのリダイレクトの最大回数
+pub const DEFAULT_MAX_REDIRECTS: usize = 10;
+
+/// デフォルトのダウンロードの最大の試行回数
+pub const DEFAULT_DOWNLOAD_ATTEMPTS: u32 = 3;
+
+/// デフォルトのバージョンを取得するAPIのパス。`{name}`はGemの名前に置き換える
+pub const DEFAULT_VERSION_ENDPOINT: &str = "/api/v1/gems/{name}.json";
+
+/// デフォルトのGemfileとして扱うファイル名
+pub const DEFAULT_GEMFILE_NAMES: [&str; 2] = ["Gemfile", "gems.rb"];
+
+/// デフォルトの1つのGemから展開するエントリの数の上限
+pub const DEFAULT_MAX_ENTRIES: usize = 100_000;
+
+/// デフォルトの接続と受信の待ち時間の上限
+pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// WindowsのMAX_PATHの文字数
+pub const WINDOWS_MAX_PATH: usize = 260;
+
+/// Gemごとの展開先のディレクトリを決める関数
+pub type DestNamer = Arc<dyn Fn(&ResolvedGem) -> PathBuf + Send + Sync>;
+
+/// バージョンを解決したGemの一覧を受け取り、インストールするGemを返す関数
+pub type ConfirmResolution = Arc<dyn Fn(&[ResolvedGem]) -> Vec<ResolvedGem> + Send + Sync>;
+
+///
+/// 特定のソースへのリクエストに付与するクエリパラメータ
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceQuery {
+    /// 対象のソースのURL。スキーム、ホスト、ポートが一致し、パスがこのURLの配下にあるリクエストに付与する
+    pub source: String,
+    /// パラメータ名
+    pub name: String,
+    /// パラメータの値
+    pub value: String,
+}
+
+///
+/// すべてのリクエストで共有するHTTPクライアント
+///
+/// リダイレクトの回数の上限や、リダイレクト先ごとのTLSの確認はこのクレートで行うため、
+/// 呼び出し側の設定の上からリダイレクトとgzipの解凍を無効にし、`request_timeout`を設定して作成する
+///
+#[derive(Debug, Clone)]
+pub struct HttpClient(pub(crate) reqwest::Client);
+
+impl HttpClient {
+    ///
+    /// 呼び出し側の設定を元にHTTPクライアントを作成する
+    ///
+    /// * builder - ヘッダーやプロキシなどを設定したクライアントのビルダー
+    /// * options - インストール処理のオプション(`request_timeout`を使用する)
+    ///
+    /// return - 作成したクライアント
+    ///
+    pub fn new(builder: reqwest::ClientBuilder, options: &InstallOptions) -> Result<HttpClient, GemfileError> {
+        Ok(HttpClient(client::configure(builder, options).build()?))
+    }
+}
+
+///
+/// 特定のGemの.gemファイルを保存するキャッシュディレクトリ
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct GemCacheDirectory {
+    /// 対象のGemの名前のパターン。`*`は任意の文字列に一致する(例: `rails-*`)
+    pub pattern: String,
+    /// .gemファイルを保存するディレクトリ
+    pub directory: PathBuf,
+}
+
+impl GemCacheDirectory {
+    ///
+    /// Gemの名前がパターンに一致するかを確認する
+    ///
+    /// * gem_name - Gemの名前
+    ///
+    /// return - 一致する場合はtrue
+    ///
+    pub fn matches(&self, gem_name: &str) -> bool {
+        let pattern = self.pattern.split('*').map(regex::escape).collect::<Vec<_>>().join(".*");
+        Regex::new(&format!("^{}$", pattern)).is_ok_and(|regex| regex.is_match(gem_name))
+    }
+}
+
+///
+/// リクエストの再試行の設定
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetrySettings {
+    /// 最初のリクエストを含む最大の試行回数。1の場合は再試行しない
+    pub attempts: u32,
+    /// 最初の再試行までの待ち時間。再試行ごとに2倍になる
+    pub base_delay: Duration,
+    /// 待ち時間の上限
+    pub max_delay: Duration,
+    /// 待ち時間をランダムに短くして、同時に再試行が集中しないようにするか
+    pub jitter: bool,
+}
+
+impl Default for RetrySettings {
+    fn default() -> Self {
+        RetrySettings {
+            attempts: 1,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(10),
+            jitter: true,
+        }
+    }
+}
+
+impl RetrySettings {
+    ///
+    /// 再試行の前の待ち時間を取得する
+    ///
+    /// * retry - 何回目の再試行か(1から始まる)
+    ///
+    /// return - 待ち時間
+    ///
+    pub fn delay(&self, retry: u32) -> Duration {
+        let delay = self.base_delay
+            .saturating_mul(2u32.saturating_pow(retry.saturating_sub(1)))
+            .min(self.max_delay);
+        if !self.jitter {
+            return delay;
+        }
+
+        // 待ち時間の50%から100%の間でばらつかせる
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|elapsed| elapsed.subsec_nanos())
+            .unwrap_or(0);
+        delay / 2 + delay.mul_f64(f64::from(nanos % 1000) / 2000.0)
+    }
+}
+
+///
+/// 種類ごとのリクエストの再試行の設定
+///
+/// バージョンの取得は冪等なため、ダウンロードとは別に設定できる
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    /// バージョンのAPIやCompact Indexへのリクエスト
+    pub version_api: RetrySettings,
+    /// .gemファイルのダウンロード(本文の受信とチェックサムの照合を含む)。デフォルトでは`DEFAULT_DOWNLOAD_ATTEMPTS`回まで試行する
+    pub download: RetrySettings,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            version_api: RetrySettings::default(),
+            download: RetrySettings { attempts: DEFAULT_DOWNLOAD_ATTEMPTS, ..Default::default() },
+        }
+    }
+}
+
+///
+/// Gemの展開先のディレクトリが既に存在する場合の動作
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ExistingDirectory {
+    /// 既存のディレクトリを削除して展開し直す
+    #[default]
+    Overwrite,
+    /// 既存のディレクトリを残し、そのGemはインストールしない
+    Skip,
+    /// そのGemのインストールを失敗として扱う
+    Error,
+}
+
+///
+/// インストール処理のオプション
+///
+#[derive(Clone)]
+pub struct InstallOptions {
+    /// Gemのインストール先のディレクトリ(`*_with_options`でパスを引数に取らない関数で使用する)。
+    /// デフォルトは空で、空のままそれらの関数を呼び出すと`MissingDirectory`のエラーになる
+    pub install_directory: PathBuf,
+    /// Gemのダウンロード先のキャッシュディレクトリ(`*_with_options`でパスを引数に取らない関数で使用する)。
+    /// デフォルトは空で、空のままそれらの関数を呼び出すと`MissingDirectory`のエラーになる
+    pub cache_directory: PathBuf,
+    /// .gemファイル内にある本体のデータのファイル名。Noneの場合は自動で探す
+    pub payload_name: Option<String>,
+    /// リダイレクトをたどる最大の回数
+    pub max_redirects: usize,
+    /// TLSを使用しない`http://`のソースを許可するか(テスト環境向け)
+    pub allow_insecure: bool,
+    /// キャッシュディレクトリのロックを待つ最大の時間。Noneの場合はロックしない
+    pub cache_lock_timeout: Option<Duration>,
+    /// APIとダウンロードのリクエストで、接続とレスポンスの受信を待つ最大の時間。Noneの場合は待ち続ける
+    /// (受信が途切れている時間に対する上限のため、大きなGemのダウンロード全体の時間は制限しない。`client`を指定した場合は作成した時点の設定を使用する)
+    pub request_timeout: Option<Duration>,
+    /// 最初にインストールに失敗した時点で残りのダウンロードをキャンセルし、そのエラーを返すか
+    pub fail_fast: bool,
+    /// ソースへのリクエストに付与するクエリパラメータ(例: `?api_key=`で認証するレジストリ)
+    pub source_queries: Vec<SourceQuery>,
+    /// インストール先でのGemのディレクトリ構成
+    pub layout: Layout,
+    /// リクエストの再試行の設定
+    pub retry: RetryPolicy,
+    /// インストール全体でダウンロードする合計のバイト数の上限。受信中に超えた時点で、実行中のものを含めて残りのダウンロードを中止する(キャッシュから使用した.gemファイルは含まない)
+    pub max_total_bytes: Option<u64>,
+    /// インストールしないグループの一覧。`only_groups`にも含まれる場合はこちらを優先する
+    pub without_groups: Vec<String>,
+    /// インストールするグループの一覧。空の場合はすべてのグループを対象にする
+    pub only_groups: Vec<String>,
+    /// インストール先のプラットフォーム(例: `mri`、`jruby`)。指定した場合は他のプラットフォーム向けのGemを除く
+    pub target_platform: Option<String>,
+    /// バージョンの取得とダウンロードを同時に行う最大の数。Noneの場合は制限しない
+    pub concurrency: Option<usize>,
+    /// バージョンの解決処理。Noneの場合はRubyGemsのAPIを使用する
+    pub resolver: Option<Arc<dyn VersionResolver>>,
+    /// ダウンロードした.gemファイルをインストール先の`cache/`にも保存するか
+    pub vendor_gems: bool,
+    /// インストール処理のイベントを受け取る関数(`events::json_lines`など)
+    pub on_event: Option<EventHandler>,
+    /// Gemの展開先のディレクトリを決める関数。相対パスはインストール先からのパスとし、`layout`より優先する
+    pub dest_namer: Option<DestNamer>,
+    /// バージョンの解決後、ダウンロードの前に呼び出す関数。返したGemのみをインストールする(対話的に確認する場合など)
+    pub confirm: Option<ConfirmResolution>,
+    /// 展開先のパスの長さの上限。Noneの場合は確認しない(Windowsでは`WINDOWS_MAX_PATH`を指定する)
+    pub max_path_length: Option<usize>,
+    /// 1つのGemの本体から展開するエントリの数の上限。Noneの場合は制限しない
+    pub max_entries: Option<usize>,
+    /// 上限を超えたパスをエラーにせず、Windowsの`\\?\`の接頭辞を付けて展開するか
+    pub long_path_prefix: bool,
+    /// バージョンを取得するAPIのパスのテンプレート。`{name}`はGemの名前に置き換える。
+    /// デフォルトから変更した場合は、インストール時のバージョンの解決にもCompact Indexとバージョン一覧のAPIの代わりに使用する。
+    /// チェックサムは変更後も標準のバージョン一覧のAPIから取得するため、そのAPIがないソースでは`verify_checksums`を無効にする
+    pub version_endpoint: String,
+    /// 解凍途中の.tarを置く一時ディレクトリ。Noneの場合はキャッシュディレクトリを使用する
+    pub temp_dir: Option<PathBuf>,
+    /// 解凍に使用するバッファを借りる共有のプール。Noneの場合は解凍ごとにバッファを確保する
+    pub buffer_pool: Option<Arc<BufferPool>>,
+    /// ダウンロードした.gemファイルのSHA256を、ロックファイルのチェックサムまたはバージョンのAPIの`sha`と照合するか。
+    /// デフォルトで有効で、チェックサムを取得できない場合もエラーにする。`sha`を提供しないミラーなどでは`false`にして無効にする
+    pub verify_checksums: bool,
+    /// キャッシュに.gemファイルがある場合も、常にダウンロードし直すか
+    pub force_download: bool,
+    /// キャッシュディレクトリでの.gemファイルの構成
+    pub cache_layout: CacheLayout,
+    /// Gemごとに.gemファイルを保存するキャッシュディレクトリ。最初に一致したものを使用し、一致しない場合は通常のキャッシュディレクトリを使用する
+    pub gem_cache_directories: Vec<GemCacheDirectory>,
+    /// 本体に`SHA256SUMS`のマニフェストが含まれる場合、展開した各ファイルを記録されたSHA256と照合するか。
+    /// マニフェストも同じ本体に含まれるため偶発的な破損のみを検出し、改ざんの検出には`verify_checksums`を使用する
+    pub verify_file_digests: bool,
+    /// Gemごとにファイルを展開せず、展開先のディレクトリ名に`.zip`を付けたzipファイルに書き込むか
+    #[cfg(feature = "zip")]
+    pub zip_output: bool,
+    /// Gemの名前ごとに優先するバージョン。制約を満たして取得できる場合は最新のバージョンより優先する
+    pub preferred_versions: HashMap<String, String>,
+    /// 制約を満たすバージョンが複数ある場合の選択方法。`Lowest`の場合は最も古いバージョンを選択する
+    pub resolution_strategy: ResolutionStrategy,
+    /// すべてのリクエストで共有するHTTPクライアント。Noneの場合はインストールごとに1つ作成する
+    pub client: Option<HttpClient>,
+    /// ソースのホスト名から認証情報を取得する関数。Noneの場合は環境変数(`GEMFILE_DL_TOKEN_*`)から読み込む
+    pub credential_lookup: Option<CredentialLookup>,
+    /// 展開したGemの中でGemfileとして扱うファイル名の一覧
+    pub gemfile_names: Vec<String>,
+    /// インストールがすべて成功した場合に、解決したバージョンを書き込むGemfile.lockのパス。Noneの場合は書き込まない
+    pub write_lockfile: Option<PathBuf>,
+    /// Gemの展開先のディレクトリが既に存在する場合の動作(別のバージョンが展開されている構成など)
+    pub on_existing: ExistingDirectory,
+    /// テスト用に、並列に実行せず宣言順にタスクを実行する
+    #[cfg(test)]
+    pub(crate) deterministic: bool,
+}
+
+impl Default for InstallOptions {
+    fn default() -> Self {
+        InstallOptions {
+            install_directory: PathBuf::new(),
+            cache_directory: PathBuf::new(),
+            payload_name: None,
+            max_redirects: DEFAULT_MAX_REDIRECTS,
+            allow_insecure: false,
+            cache_lock_timeout: None,
+            request_timeout: Some(DEFAULT_REQUEST_TIMEOUT),
+            fail_fast: false,
+            source_queries: Vec::new(),
+            layout: Layout::default(),
+            retry: RetryPolicy::default(),
+            max_total_bytes: None,
+            without_groups: Vec::new(),
+            only_groups: Vec::new(),
+            target_platform: None,
+            concurrency: None,
+            resolver: None,
+            vendor_gems: false,
+            on_event: None,
+            dest_namer: None,
+            confirm: None,
+            max_path_length: None,
+            max_entries: Some(DEFAULT_MAX_ENTRIES),
+            long_path_prefix: false,
+            version_endpoint: DEFAULT_VERSION_ENDPOINT.to_string(),
+            temp_dir: None,
+            buffer_pool: None,
+            verify_checksums: true,
+            force_download: false,
+            cache_layout: CacheLayout::default(),
+            gem_cache_directories: Vec::new(),
+            verify_file_digests: false,
+            #[cfg(feature = "zip")]
+            zip_output: false,
+            preferred_versions: HashMap::new(),
+            resolution_strategy: ResolutionStrategy::default(),
+            client: None,
+            credential_lookup: None,
+            gemfile_names: DEFAULT_GEMFILE_NAMES.iter().map(|name| name.to_string()).collect(),
+            write_lockfile: None,
+            on_existing: ExistingDirectory::default(),
+            #[cfg(test)]
+            deterministic: false,
+        }
+    }
+}
+
+impl Debug for InstallOptions {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        // 関数はDebugを実装していないため、設定の有無のみを出力する
+        let mut debug = f.debug_struct("InstallOptions");
+        debug
+            .field("install_directory", &self.install_directory)
+            .field("cache_directory", &self.cache_directory)
+            .field("payload_name", &self.payload_name)
+            .field("max_redirects", &self.max_redirects)
+            .field("allow_insecure", &self.allow_insecure)
+            .field("cache_lock_timeout", &self.cache_lock_timeout)
+            .field("request_timeout", &self.request_timeout)
+            .field("fail_fast", &self.fail_fast)
+            .field("source_queries", &self.source_queries)
+            .field("layout", &self.layout)
+            .field("retry", &self.retry)
+            .field("max_total_bytes", &self.max_total_bytes)
+            .field("without_groups", &self.without_groups)
+            .field("only_groups", &self.only_groups)
+            .field("target_platform", &self.target_platform)
+            .field("concurrency", &self.concurrency)
+            .field("resolver", &self.resolver)
+            .field("vendor_gems", &self.vendor_gems)
+            .field("on_event", &self.on_event.is_some())
+            .field("dest_namer", &self.dest_namer.is_some())
+            .field("confirm", &self.confirm.is_some())
+            .field("max_path_length", &self.max_path_length)
+            .field("max_entries", &self.max_entries)
+            .field("long_path_prefix", &self.long_path_prefix)
+            .field("version_endpoint", &self.version_endpoint)
+            .field("temp_dir", &self.temp_dir)
+            .field("buffer_pool", &self.buffer_pool)
+            .field("verify_checksums", &self.verify_checksums)
+            .field("force_download", &self.force_download)
+            .field("cache_layout", &self.cache_layout)
+            .field("gem_cache_directories", &self.gem_cache_directories)
+            .field("verify_file_digests", &self.verify_file_digests);
+        #[cfg(feature = "zip")]
+        debug.field("zip_output", &self.zip_output);
+        debug
+            .field("preferred_versions", &self.preferred_versions)
+            .field("resolution_strategy", &self.resolution_strategy)
+            .field("client", &self.client.is_some())
+            .field("credential_lookup", &self.credential_lookup.is_some())
+            .field("gemfile_names", &self.gemfile_names)
+            .field("write_lockfile", &self.write_lockfile)
+            .field("on_existing", &self.on_existing);
+        debug.finish()
+    }
+}
+
+impl PartialEq for InstallOptions {
+    fn eq(&self, other: &Self) -> bool {
+        // 関数と共有のオブジェクトは同じものを指すかを比較し、HTTPクライアントは比較できないため設定の有無のみを比較する
+        #[cfg(feature = "zip")]
+        if self.zip_output != other.zip_output {
+            return false;
+        }
+        self.install_directory == other.install_directory
+            && self.cache_directory == other.cache_directory
+            && self.payload_name == other.payload_name
+            && self.max_redirects == other.max_redirects
+            && self.allow_insecure == other.allow_insecure
+            && self.cache_lock_timeout == other.cache_lock_timeout
+            && self.request_timeout == other.request_timeout
+            && self.fail_fast == other.fail_fast
+            && self.source_queries == other.source_queries
+            && self.layout == other.layout
+            && self.retry == other.retry
+            && self.max_total_bytes == other.max_total_bytes
+            && self.without_groups == other.without_groups
+            && self.only_groups == other.only_groups
+            && self.target_platform == other.target_platform
+            && self.concurrency == other.concurrency
+            && same_arc(&self.resolver, &other.resolver)
+            && self.vendor_gems == other.vendor_gems
+            && same_arc(&self.on_event, &other.on_event)
+            && same_arc(&self.dest_namer, &other.dest_namer)
+            && same_arc(&self.confirm, &other.confirm)
+            && self.max_path_length == other.max_path_length
+            && self.max_entries == other.max_entries
+            && self.long_path_prefix == other.long_path_prefix
+            && self.version_endpoint == other.version_endpoint
+            && self.temp_dir == other.temp_dir
+            && same_arc(&self.buffer_pool, &other.buffer_pool)
+            && self.verify_checksums == other.verify_checksums
+            && self.force_download == other.force_download
+            && self.cache_layout == other.cache_layout
+            && self.gem_cache_directories == other.gem_cache_directories
+            && self.verify_file_digests == other.verify_file_digests
+            && self.preferred_versions == other.preferred_versions
+            && self.resolution_strategy == other.resolution_strategy
+            && self.client.is_some() == other.client.is_some()
+            && same_arc(&self.credential_lookup, &other.credential_lookup)
+            && self.gemfile_names == other.gemfile_names
+            && self.write_lockfile == other.write_lockfile
+            && self.on_existing == other.on_existing
+    }
+}
+
+///
+/// 共有のオブジェクトが両方とも未設定か、同じものを指すかを確認する
+///
+/// * a - 比較するオブジェクト
+/// * b - 比較するオブジェクト
+///
+/// return - 同じ場合はtrue
+///
+fn same_arc<T: ?Sized>(a: &Option<Arc<T>>, b: &Option<Arc<T>>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+        (None, None) => true,
+        _ => false,
+    }
+}
+
+impl InstallOptions {
+    ///
+    /// インストール先とキャッシュのディレクトリを指定してオプションを作成する
+    ///
+    /// その他の設定はデフォルトとし、`concurrency`などのメソッドで変更する
+    ///
+    /// * install_directory - Gemのインストール先のディレクトリ
+    /// * cache_directory - Gemのダウンロード先のキャッシュディレクトリ
+    ///
+    /// return - インストール処理のオプション
+    ///
+    pub fn new(install_directory: impl Into<PathBuf>, cache_directory: impl Into<PathBuf>) -> InstallOptions {
+        InstallOptions {
+            install_directory: install_directory.into(),
+            cache_directory: cache_directory.into(),
+            ..Default::default()
+        }
+    }
+
+    ///
+    /// バージョンの取得とダウンロードを同時に行う最大の数を設定する
+    ///
+    /// * concurrency - 同時に行う最大の数
+    ///
+    /// return - 変更したオプション
+    ///
+    pub fn concurrency(mut self, concurrency: usize) -> InstallOptions {
+        self.concurrency = Some(concurrency);
+        self
+    }
+
+    ///
+    /// ダウンロードした.gemファイルのSHA256を照合するかを設定する
+    ///
+    /// デフォルトでは照合するため、チェックサムを提供しないソースを使用する場合のみ`false`を指定する
+    ///
+    /// * verify_checksums - 照合する場合はtrue
+    ///
+    /// return - 変更したオプション
+    ///
+    pub fn verify_checksums(mut self, verify_checksums: bool) -> InstallOptions {
+        self.verify_checksums = verify_checksums;
+        self
+    }
+
+    ///
+    /// 展開した各ファイルをマニフェストのSHA256と照合するかを設定する
+    ///
+    /// 本体に含まれるマニフェストとの照合は、偶発的な破損のみを検出する
+    ///
+    /// * verify_file_digests - 照合する場合はtrue
+    ///
+    /// return - 変更したオプション
+    ///
+    pub fn verify_file_digests(mut self, verify_file_digests: bool) -> InstallOptions {
+        self.verify_file_digests = verify_file_digests;
+        self
+    }
+
+    ///
+    /// Gemごとにファイルを展開せず、zipファイルに書き込むかを設定する
+    ///
+    /// * zip_output - zipファイルに書き込む場合はtrue
+    ///
+    /// return - 変更したオプション
+    ///
+    #[cfg(feature = "zip")]
+    pub fn zip_output(mut self, zip_output: bool) -> InstallOptions {
+        self.zip_output = zip_output;
+        self
+    }
+
+    ///
+    /// リクエストの再試行の設定を変更する
+    ///
+    /// * retry - 再試行の設定
+    ///
+    /// return - 変更したオプション
+    ///
+    pub fn retry(mut self, retry: RetryPolicy) -> InstallOptions {
+        self.retry = retry;
+        self
+    }
+
+    ///
+    /// 最初の失敗で残りのダウンロードをキャンセルするかを設定する
+    ///
+    /// * fail_fast - キャンセルする場合はtrue
+    ///
+    /// return - 変更したオプション
+    ///
+    pub fn fail_fast(mut self, fail_fast: bool) -> InstallOptions {
+        self.fail_fast = fail_fast;
+        self
+    }
+
+    ///
+    /// `http://`のソースを許可するかを設定する
+    ///
+    /// * allow_insecure - 許可する場合はtrue
+    ///
+    /// return - 変更したオプション
+    ///
+    pub fn allow_insecure(mut self, allow_insecure: bool) -> InstallOptions {
+        self.allow_insecure = allow_insecure;
+        self
+    }
+
+    ///
+    /// キャッシュがある場合も常にダウンロードし直すかを設定する
+    ///
+    /// * force_download - ダウンロードし直す場合はtrue
+    ///
+    /// return - 変更したオプション
+    ///
+    pub fn force_download(mut self, force_download: bool) -> InstallOptions {
+        self.force_download = force_download;
+        self
+    }
+
+    ///
+    /// インストールしないグループを設定する
+    ///
+    /// * without_groups - インストールしないグループの一覧
+    ///
+    /// return - 変更したオプション
+    ///
+    pub fn without_groups(mut self, without_groups: Vec<String>) -> InstallOptions {
+        self.without_groups = without_groups;
+        self
+    }
+
+    ///
+    /// インストールするグループを設定する
+    ///
+    /// * only_groups - インストールするグループの一覧。空の場合はすべてのグループ
+    ///
+    /// return - 変更したオプション
+    ///
+    pub fn only_groups(mut self, only_groups: Vec<String>) -> InstallOptions {
+        self.only_groups = only_groups;
+        self
+    }
+
+    ///
+    /// インストール先でのGemのディレクトリ構成を設定する
+    ///
+    /// * layout - ディレクトリ構成
+    ///
+    /// return - 変更したオプション
+    ///
+    pub fn layout(mut self, layout: Layout) -> InstallOptions {
+        self.layout = layout;
+        self
+    }
+
+    ///
+    /// インストール処理のイベントを受け取る関数を設定する
+    ///
+    /// * on_event - イベントを受け取る関数
+    ///
+    /// return - 変更したオプション
+    ///
+    pub fn on_event(mut self, on_event: EventHandler) -> InstallOptions {
+        self.on_event = Some(on_event);
+        self
+    }
+
+    ///
+    /// バージョンの解決後に、インストールするGemを確認する関数を設定する
+    ///
+    /// * confirm - 解決したGemの一覧を受け取り、インストールするGemを返す関数
+    ///
+    /// return - 変更したオプション
+    ///
+    pub fn confirm(mut self, confirm: ConfirmResolution) -> InstallOptions {
+        self.confirm = Some(confirm);
+        self
+    }
+
+    ///
+    /// リクエストの接続とレスポンスの受信を待つ最大の時間を設定する
+    ///
+    /// * request_timeout - 待つ最大の時間
+    ///
+    /// return - 変更したオプション
+    ///
+    pub fn request_timeout(mut self, request_timeout: Duration) -> InstallOptions {
+        self.request_timeout = Some(request_timeout);
+        self
+    }
+
+    ///
+    /// 制約を満たすバージョンが複数ある場合の選択方法を設定する
+    ///
+    /// * resolution_strategy - バージョンの選択方法
+    ///
+    /// return - 変更したオプション
+    ///
+    pub fn resolution_strategy(mut self, resolution_strategy: ResolutionStrategy) -> InstallOptions {
+        self.resolution_strategy = resolution_strategy;
+        self
+    }
+
+    ///
+    /// Gemの展開先のディレクトリが既に存在する場合の動作を設定する
+    ///
+    /// * on_existing - 既に存在する場合の動作
+    ///
+    /// return - 変更したオプション
+    ///
+    pub fn on_existing(mut self, on_existing: ExistingDirectory) -> InstallOptions {
+        self.on_existing = on_existing;
+        self
+    }
+
+    ///
+    /// インストールがすべて成功した場合に、Gemfile.lockを書き込むパスを設定する
+    ///
+    /// * path - Gemfile.lockのパス
+    ///
+    /// return - 変更したオプション
+    ///
+    pub fn write_lockfile(mut self, path: impl Into<PathBuf>) -> InstallOptions {
+        self.write_lockfile = Some(path.into());
+        self
+    }
+
+    ///
+    /// ソースのホスト名から認証情報を取得する関数を設定する
+    ///
+    /// * credential_lookup - ホスト名を受け取り、環境変数と同じ形式の値を返す関数
+    ///
+    /// return - 変更したオプション
+    ///
+    pub fn credential_lookup(mut self, credential_lookup: CredentialLookup) -> InstallOptions {
+        self.credential_lookup = Some(credential_lookup);
+        self
+    }
+
+    ///
+    /// Gemをインストールの対象にするかを確認する
+    ///
+    /// `target_platform`が指定されている場合は、そのプラットフォーム向けのGemのみを対象にする。
+    /// Bundlerと同様に、グループに属さないGemは常に対象にする。
+    /// それ以外は`without_groups`に含まれないグループが残り、`only_groups`の指定がある場合はそのいずれかに属するGemを対象にする
+    /// (両方に含まれるグループは`without_groups`を優先して除外する)
+    ///
+    /// * gem - 確認するGem
+    ///
+    /// return - 対象の場合はtrue
+    ///
+    pub fn includes_gem(&self, gem: &Gem) -> bool {
+        // 対象のプラットフォーム向けではないGemは除く
+        if let Some(target) = &self.target_platform {
+            if !gem.matches_platform(target) {
+                return false;
+            }
+        }
+        if gem.groups.is_empty() {
+            return true;
+        }
+        let mut remaining = gem.groups.iter().filter(|group| !self.without_groups.contains(group));
+        if self.only_groups.is_empty() {
+            remaining.next().is_some()
+        } else {
+            remaining.any(|group| self.only_groups.contains(group))
+        }
+    }
+
+    ///
+    /// Gemの本体を展開するディレクトリを取得する
+    ///
+    /// `dest_namer`が設定されている場合はその結果を、それ以外は`layout`に従ったディレクトリを返す
+    ///
+    /// * install_dictionary - インストール先のディレクトリ
+    /// * gem - 展開するGem
+    /// * source - Gemを取得したソース
+    ///
+    /// return - 展開先のディレクトリ(`zip_output`の場合はzipファイルのパス)
+    ///
+    pub fn gem_directory(&self, install_dictionary: &Path, gem: &Gem, source: &str) -> PathBuf {
+        let directory = match &self.dest_namer {
+            Some(dest_namer) => install_dictionary.join(dest_namer(&ResolvedGem {
+                name: gem.name.clone(),
+                version: gem.version.clone(),
+                source: source.to_string(),
+                dependencies: Vec::new(),
+            })),
+            None => self.layout.gem_directory(install_dictionary, &gem.name, &gem.version),
+        };
+        #[cfg(feature = "zip")]
+        if self.zip_output {
+            return crate::zip_sink::zip_path(&directory);
+        }
+        directory
+    }
+
+    ///
+    /// Gemの.gemファイルを保存するキャッシュディレクトリを取得する
+    ///
+    /// * cache_directory - 通常のキャッシュディレクトリ
+    /// * gem_name - Gemの名前
+    ///
+    /// return - `gem_cache_directories`で一致したディレクトリ。一致しない場合は`cache_directory`
+    ///
+    pub fn gem_cache_directory(&self, cache_directory: &Path, gem_name: &str) -> PathBuf {
+        self.gem_cache_directories.iter()
+            .find(|gem_cache_directory| gem_cache_directory.matches(gem_name))
+            .map(|gem_cache_directory| gem_cache_directory.directory.clone())
+            .unwrap_or_else(|| cache_directory.to_path_buf())
+    }
+
+    ///
+    /// パスを引数に取らない関数で使用する、インストール先とキャッシュのディレクトリを取得する
+    ///
+    /// return - インストール先とキャッシュのディレクトリ。空の場合は`MissingDirectory`のエラー
+    ///
+    pub fn directories(&self) -> Result<(&Path, &Path), GemfileError> {
+        for (option, directory) in [("install_directory", &self.install_directory), ("cache_directory", &self.cache_directory)] {
+            if directory.as_os_str().is_empty() {
+                return Err(GemfileError::MissingDirectory { option: option.to_string() });
+            }
+        }
+        Ok((&self.install_directory, &self.cache_directory))
+    }
+
+    ///
+    /// `version_endpoint`からバージョンを取得するAPIのURLを作成する
+    ///
+    /// * source - ソースのURL
+    /// * gem_name - Gemの名前
+    ///
+    /// return - APIのURL
+    ///
+    pub fn version_url(&self, source: &str, gem_name: &str) -> String {
+        format!("{}{}", source, self.version_endpoint.replace("{name}", gem_name))
+    }
+
+    ///
+    /// `version_endpoint`がデフォルトから変更されているかを確認する
+    ///
+    pub fn has_custom_version_endpoint(&self) -> bool {
+        self.version_endpoint != DEFAULT_VERSION_ENDPOINT
+    }
+
+    ///
+    /// バージョンの解決に使用する処理を取得する
+    ///
+    /// return - `resolver`が設定されていない場合は`RubyGemsResolver`
+    ///
+    pub(crate) fn version_resolver(&self) -> &dyn VersionResolver {
+        match &self.resolver {
+            Some(resolver) => resolver.as_ref(),
+            None => &RubyGemsResolver,
+        }
+    }
+
+    ///
+    /// `concurrency`に従って同時に行う処理の数を制限するセマフォを作成する
+    ///
+    pub(crate) fn semaphore(&self) -> Semaphore {
+        Semaphore::new(self.concurrency.unwrap_or(Semaphore::MAX_PERMITS).max(1))
+    }
+}
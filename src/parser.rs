@@ -2,101 +2,742 @@
 //! Gemfileのテキストをパースします
 //!
 
+use std::collections::BTreeMap;
 use std::error::Error;
+use std::path::{Path, PathBuf};
+use futures::future::try_join_all;
 use regex::Regex;
+use reqwest::Url;
 use serde::{Deserialize, Serialize};
-use crate::gem_version::GemVersion;
+use tokio::sync::Semaphore;
+use crate::download::LOCAL_SOURCE_PREFIX;
+use crate::error::is_unreachable;
+use crate::events::{self, InstallEvent};
+use crate::options::InstallOptions;
+use crate::version::{Operator, VersionRequirement};
 
 // バージョンの正規表現
 const GEM_VERSION_REGEX: &str = "[0-9]+\\.[0-9]+\\.[0-9]+";
 
+// `platforms:`でRubyの実装を表す名前(.gemファイルのプラットフォームではない)
+const RUBY_ENGINE_PLATFORMS: [&str; 10] = ["ruby", "mri", "jruby", "truffleruby", "rbx", "windows", "mswin", "mswin64", "mingw", "x64_mingw"];
+
+// インストールに影響しないため、警告せずに無視する命令
+const IGNORED_DIRECTIVES: [&str; 2] = ["ruby ", "git_source"];
+
+// 条件によって中身を評価するかが変わるブロックのキーワード
+const CONDITIONAL_KEYWORDS: [&str; 3] = ["if ", "unless ", "case "];
+
+// endで閉じられるブロックを開始するキーワード
+const BLOCK_KEYWORDS: [&str; 6] = ["if ", "unless ", "case ", "while ", "until ", "begin"];
+
+///
+/// Gemの取得元
+///
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GemSource {
+    /// RubyGemsのAPIを持つレジストリ
+    #[default]
+    Registry,
+    /// `git:`または`github:`で指定されたリポジトリ(ダウンロードは行わない)
+    Git {
+        /// リポジトリのURL
+        url: String,
+        /// `ref:`、`branch:`、`tag:`で指定された参照。指定がない場合はNone
+        reference: Option<String>,
+    },
+    /// `path:`で指定されたローカルのディレクトリ(ダウンロードせずにそのディレクトリを参照する)
+    Path(PathBuf),
+}
+
 ///
 /// 各Gemのデータ
 ///
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
 pub struct Gem {
     /// Gemの名前
     pub name: String,
     // Gemのバージョン
     pub version: String,
+    // Gemfileに書かれた制約の一覧(例: `'~> 1.0', '>= 1.0.7'`)。すべてを満たすバージョンを選択する。指定がない場合は空
+    #[serde(default)]
+    pub requirements: Vec<String>,
+    // `require: false`などのキーワード引数
+    #[serde(default)]
+    pub options: BTreeMap<String, String>,
+    // 所属するグループの一覧。空の場合はグループに属さない
+    #[serde(default)]
+    pub groups: Vec<String>,
+    // `source ... do`のブロックで指定されたソース。Noneの場合はGemfile全体のソースを使用する
+    #[serde(default)]
+    pub source: Option<String>,
+    // .gemファイルのSHA256(Gemfile.lockのCHECKSUMSなど)。キャッシュが一致する場合はダウンロードしない
+    #[serde(default)]
+    pub checksum: Option<String>,
+    // `platforms :jruby do`のブロックや`platforms:`のキーワード引数で指定されたプラットフォーム(例: `jruby`、`x86_64_linux`)。空の場合はすべてのプラットフォーム
+    #[serde(default)]
+    pub platforms: Vec<String>,
+    // Gemの取得元。Gitの場合はレジストリからダウンロードしない
+    #[serde(default)]
+    pub origin: GemSource,
+    // バージョンの代わりに書かれたRubyの式(例: `Concurrent::VERSION`)。評価できないため最新のバージョンを使用する
+    #[serde(default)]
+    pub version_expression: Option<String>,
+    // `require: ['a', 'b']`で指定された、読み込むパスの一覧。`require: false`や指定がない場合は空
+    #[serde(default)]
+    pub require_paths: Vec<String>,
+    // ダウンロード時に選択した.gemファイルのプラットフォーム(例: `x86_64-linux`)。`platforms`の指定より優先する
+    #[serde(default)]
+    pub resolved_platform: Option<String>,
+}
+
+impl Gem {
+    ///
+    /// Gemfileに書かれた制約をカンマで区切った文字列を取得する
+    ///
+    /// return - `~> 1.0, >= 1.0.7`のような制約。指定がない場合は空
+    ///
+    pub fn requirement(&self) -> String {
+        self.requirements.join(", ")
+    }
+
+    ///
+    /// Gemfileに書かれた制約を演算子とバージョンの組み合わせに変換する
+    ///
+    /// return - 制約の指定がない場合や解釈できない場合はすべてのバージョンを許可する制約
+    ///
+    pub fn version_requirement(&self) -> VersionRequirement {
+        VersionRequirement::parse(&self.requirement()).unwrap_or_default()
+    }
+
+    ///
+    /// 制約が`=`のみで、1つのバージョンに決まるかを確認する
+    ///
+    pub fn is_exact(&self) -> bool {
+        self.version_requirement().constraints.iter().all(|constraint| constraint.operator == Operator::Equal)
+    }
+
+    ///
+    /// `platforms: [:x86_64_linux]`で指定された.gemファイルのプラットフォームを取得する
+    ///
+    /// `resolved_platform`が設定されている場合はそれを使用する。
+    /// それ以外は`:mri`や`:jruby`などのRubyの実装の指定は除き、最初に指定されたプラットフォームを使用する
+    ///
+    /// return - `x86_64-linux`などのプラットフォーム。指定がない場合はNone
+    ///
+    pub fn platform(&self) -> Option<String> {
+        if let Some(platform) = &self.resolved_platform {
+            return Some(platform.clone());
+        }
+        self.platforms.iter()
+            .find(|name| {
+                // `mri_31`のようなバージョン付きの指定も実装として扱う
+                let engine = name.trim_end_matches(|c: char| c.is_ascii_digit()).trim_end_matches('_');
+                !RUBY_ENGINE_PLATFORMS.contains(&engine)
+            })
+            .map(|name| match name.strip_prefix("x86_64_") {
+                // アーキテクチャ名の`_`は残す
+                Some(os) => format!("x86_64-{}", os.replace('_', "-")),
+                None => name.replace('_', "-"),
+            })
+    }
+
+    ///
+    /// レジストリからダウンロードするGemかを確認する
+    ///
+    pub fn is_registry(&self) -> bool {
+        self.origin == GemSource::Registry
+    }
+
+    ///
+    /// `path:`で指定された相対パスを、Gemfileのあるディレクトリからのパスにする
+    ///
+    /// * gemfile_directory - Gemfileのあるディレクトリ
+    ///
+    pub fn rebase_path(&mut self, gemfile_directory: &Path) {
+        if let GemSource::Path(path) = &mut self.origin {
+            if path.is_relative() {
+                *path = gemfile_directory.join(&*path);
+            }
+        }
+    }
+
+    ///
+    /// 対象のプラットフォームでインストールするGemかを確認する
+    ///
+    /// プラットフォームの指定がないGemはすべてのプラットフォームで対象にする。`ruby`はC言語のRuby(`mri`)を含む
+    ///
+    /// * target - 対象のプラットフォーム(例: `mri`、`jruby`、`x86_64_linux`)
+    ///
+    /// return - 対象の場合はtrue
+    ///
+    pub fn matches_platform(&self, target: &str) -> bool {
+        self.platforms.is_empty() || self.platforms.iter().any(|platform| {
+            let platform = platform.trim_end_matches(|c: char| c.is_ascii_digit()).trim_end_matches('_');
+            platform == target || (platform == "ruby" && target == "mri")
+        })
+    }
+
+    ///
+    /// プラットフォームを含めた.gemファイルの名前(拡張子を除く)を取得する
+    ///
+    /// return - `nokogiri-1.15.0-x86_64-linux`の形式。プラットフォームの指定がない場合は`名前-バージョン`
+    ///
+    pub fn full_name(&self) -> String {
+        match self.platform() {
+            Some(platform) => format!("{}-{}-{}", self.name, self.version, platform),
+            None => format!("{}-{}", self.name, self.version),
+        }
+    }
 }
 
 ///
 /// Gemfileのデータ
 ///
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
 pub struct GemfileData {
     // gemのダウンロードを行うソース
     pub source: String,
     // Gemのリスト
     pub gems: Vec<Gem>,
+    // `optional: true`が指定されたグループの一覧
+    #[serde(default)]
+    pub optional_groups: Vec<String>,
+    // 宣言されたすべてのソース(正規化して重複を除いたもの)
+    #[serde(default)]
+    pub sources: Vec<String>,
+    // 解釈できずに無視した行の一覧。パースは成功し、確認のための情報として使用する
+    #[serde(default)]
+    pub warnings: Vec<ParseWarning>,
+}
+
+///
+/// パース時に解釈できずに無視した内容
+///
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ParseWarning {
+    /// 1から始まる行番号
+    pub line_number: usize,
+    /// 行のテキスト
+    pub line: String,
+    /// 無視した理由
+    pub message: String,
+}
+
+impl ParseWarning {
+    ///
+    /// 警告を作成する
+    ///
+    /// * index - 0から始まる行の位置
+    /// * line - 行のテキスト
+    /// * message - 無視した理由
+    ///
+    fn new(index: usize, line: &str, message: impl Into<String>) -> ParseWarning {
+        ParseWarning { line_number: index + 1, line: line.trim().to_string(), message: message.into() }
+    }
+}
+
+///
+/// パース中のブロック
+///
+#[derive(Debug, Clone, PartialEq)]
+enum Block {
+    /// groupのブロックとグループ名の一覧
+    Group(Vec<String>),
+    /// platformsのブロックとプラットフォームの一覧
+    Platforms(Vec<String>),
+    /// sourceのブロックとソースのURL
+    Source(String),
+    /// groupではないブロック(if文など)
+    Other,
 }
 
 impl GemfileData {
     ///
     ///  Gemfileのテキストをパースします
     ///
+    /// バージョンが指定されていないGemはAPIから並列にバージョンを取得する
+    ///
     pub async fn parse(data: &str) -> Result<GemfileData, Box<dyn Error>>{
+        let mut gemfile_data = GemfileData::parse_unresolved(data)?;
+        gemfile_data.resolve_versions(&InstallOptions::default()).await?;
+        Ok(gemfile_data)
+    }
+
+    ///
+    /// Gemfileのテキストを、バージョンを取得せずにパースします
+    ///
+    /// バージョンが指定されていないGemは`version`が空になり、`resolve_versions`で取得する
+    ///
+    pub fn parse_unresolved(data: &str) -> Result<GemfileData, Box<dyn Error>>{
         // デフォルトの値を設定
         let mut source = "https://rubygems.org".to_string();
         let mut gems: Vec<Gem> = Vec::new();
+        let mut optional_groups: Vec<String> = Vec::new();
+        let mut sources: Vec<String> = Vec::new();
+        let mut warnings: Vec<ParseWarning> = Vec::new();
+        // 現在のブロックの階層
+        let mut blocks: Vec<Block> = Vec::new();
+        // =begin から =end までの複数行コメントの中かどうか
+        let mut in_block_comment = false;
+        let version_regex = Regex::new(GEM_VERSION_REGEX)?;
 
         // 行ごとに処理
-        for mut line in data.lines() {
-            // 行の前後の空白を削除
-            loop {
-                if !line.starts_with(" ") {
-                    break;
+        for (index, raw) in data.lines().enumerate() {
+            // 行頭のタブを含む空白を削除
+            let mut line = raw.trim_start();
+
+            // 複数行コメントは行頭の=begin/=endのみで判定する
+            if in_block_comment {
+                in_block_comment = !line.starts_with("=end");
+                continue;
+            }
+            if line.starts_with("=begin") {
+                in_block_comment = true;
+                continue;
+            }
+            // 行末までのコメントを取り除き、コメントのみの行はgemの宣言として扱わない
+            line = strip_comment(line);
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            // ブロックの終了
+            if line.trim_end() == "end" {
+                blocks.pop();
+                continue;
+            }
+
+            // groupのブロックの開始
+            if let Some(header) = line.strip_prefix("group ").and_then(strip_block_start) {
+                let mut names = Vec::new();
+                for argument in parse_arguments(header) {
+                    match argument {
+                        Argument::Literal(name) => names.push(name),
+                        Argument::Expression(name) if name.starts_with(':') => names.push(name[1..].to_string()),
+                        // `optional: true`の場合のみ任意のグループとし、式などはインストール対象として扱う
+                        Argument::Keyword(key, value) if key == "optional" && value == "true" => {
+                            optional_groups.extend(names.iter().cloned());
+                        }
+                        _ => {}
+                    }
                 }
-                line = &line[1..];
+                blocks.push(Block::Group(names));
+                continue;
+            }
+
+            // platformsのブロックの開始
+            if let Some(header) = line.strip_prefix("platforms ").or_else(|| line.strip_prefix("platform ")).and_then(strip_block_start) {
+                let names = parse_arguments(header).into_iter()
+                    .filter_map(|argument| match argument {
+                        Argument::Expression(name) => name.strip_prefix(':').map(|name| name.to_string()),
+                        Argument::Literal(name) => Some(name),
+                        _ => None,
+                    })
+                    .collect();
+                blocks.push(Block::Platforms(names));
+                continue;
+            }
+
+            // sourceのブロックの開始
+            if let Some(header) = line.strip_prefix("source ").and_then(strip_block_start) {
+                let Some(Argument::Literal(block_source)) = parse_arguments(header).into_iter().next() else {
+                    warnings.push(ParseWarning::new(index, raw, "Source block without a string literal URL is ignored"));
+                    blocks.push(Block::Other);
+                    continue;
+                };
+                let normalized = normalize_source(&block_source);
+                if !sources.contains(&normalized) {
+                    sources.push(normalized);
+                }
+                blocks.push(Block::Source(block_source.trim_end_matches('/').to_string()));
+                continue;
+            }
+
+            // その他のブロックの開始
+            let is_block = strip_block_start(line).is_some() || BLOCK_KEYWORDS.iter().any(|keyword| line.starts_with(keyword));
+            if is_block {
+                blocks.push(Block::Other);
+                let message = if CONDITIONAL_KEYWORDS.iter().any(|keyword| line.starts_with(keyword)) {
+                    "Condition is not evaluated; gems inside the block are always included"
+                } else {
+                    "Block is not evaluated; gems inside the block are always included"
+                };
+                warnings.push(ParseWarning::new(index, raw, message));
+            } else if !line.starts_with("source ") && !line.starts_with("gem ") && !IGNORED_DIRECTIVES.iter().any(|directive| line.starts_with(directive)) {
+                warnings.push(ParseWarning::new(index, raw, "Unsupported directive is ignored"));
             }
 
             // sourceの行の場合、sourceの値を取得
             if line.starts_with("source ") {
+                // URLを作成する際に`//`にならないよう、末尾のスラッシュを取り除く
                 source = line.replace("source ", "")
                     .replace("\"", "")
-                    .replace("'", "");
+                    .replace("'", "")
+                    .trim()
+                    .trim_end_matches('/')
+                    .to_string();
+
+                // 同じレジストリが重複しないように正規化して追加
+                let normalized = normalize_source(&source);
+                if !sources.contains(&normalized) {
+                    sources.push(normalized);
+                }
             }
             // gemの行の場合
-            if line.starts_with("gem "){
-                // 余分な個所を削除
-                let trimmed = line.replace("gem ", "")
-                    .replace("\"", "")
-                    .replace("~>", "")
-                    .replace(" ", "")
-                    .replace("\"", "")
-                    .replace("\'", "");
-                // カンマで分割
-                let splitted = trimmed.split(",").collect::<Vec<&str>>();
-                let version_regex = Regex::new(GEM_VERSION_REGEX)?;
-                // バージョンが指定されているかを確認
-                if splitted.len() >= 2 && version_regex.is_match(splitted[1]) {
-                    // バージョンを指定している場合はそのままgemを作成
-                    gems.push(Gem {
-                        name: splitted[0].to_string(),
-                        version: splitted[1].to_string(),
-                    });
-                } else if !splitted.is_empty() {
-                    // バージョン指定がされていない場合はAPIから取得
-                    let version = GemVersion::get_version(&source, splitted[0]).await?;
-
-                    // Gemのデータを追加
-                    gems.push(Gem {
-                        name: splitted[0].to_string(),
-                        version: version.version
-                    });
+            if let Some(arguments) = line.strip_prefix("gem ") {
+                // 引数に分割
+                let arguments = parse_arguments(arguments);
+                let Some(Argument::Literal(name)) = arguments.first() else {
+                    warnings.push(ParseWarning::new(index, raw, "Gem name is not a string literal; the gem is skipped"));
+                    continue;
+                };
+
+                // キーワード引数をオプションとして保持
+                let options: BTreeMap<String, String> = arguments.iter()
+                    .filter_map(|argument| match argument {
+                        Argument::Keyword(key, value) => Some((key.clone(), value.clone())),
+                        _ => None,
+                    })
+                    .collect();
+
+                // 所属するグループ(ブロックに加えて`group: :test`のキーワード引数でも指定できる)
+                let mut groups: Vec<String> = blocks.iter()
+                    .filter_map(|block| match block {
+                        Block::Group(names) => Some(names.clone()),
+                        _ => None,
+                    })
+                    .flatten()
+                    .collect();
+                for name in ["group", "groups"].iter().filter_map(|key| options.get(*key)).flat_map(|value| parse_symbol_names(value)) {
+                    if !groups.contains(&name) {
+                        groups.push(name);
+                    }
                 }
+                // ブロックとキーワード引数で指定されたプラットフォーム
+                let mut platforms: Vec<String> = blocks.iter()
+                    .filter_map(|block| match block {
+                        Block::Platforms(names) => Some(names.clone()),
+                        _ => None,
+                    })
+                    .flatten()
+                    .collect();
+                for name in ["platforms", "platform"].iter().filter_map(|key| options.get(*key)).flat_map(|value| parse_symbol_names(value)) {
+                    if !platforms.contains(&name) {
+                        platforms.push(name);
+                    }
+                }
+                // `require:`で指定されたパス(1つの文字列と配列のどちらでも指定できる)
+                let require_paths = match options.get("require").map(|value| value.trim()) {
+                    None | Some("false") | Some("true") | Some("nil") => Vec::new(),
+                    Some(value) => parse_symbol_names(value),
+                };
+                // 最も内側のsourceのブロック
+                let gem_source = blocks.iter().rev().find_map(|block| match block {
+                    Block::Source(block_source) => Some(block_source.clone()),
+                    _ => None,
+                });
+
+                // Gemfileに書かれたままの制約の一覧(`version: '~> 1.0'`のキーワード引数でも指定できる)
+                let mut requirements: Vec<String> = arguments.iter()
+                    .skip(1)
+                    .map_while(|argument| match argument {
+                        Argument::Literal(requirement) => Some(requirement.clone()),
+                        _ => None,
+                    })
+                    .collect();
+                if requirements.is_empty() {
+                    requirements.extend(options.get("version").cloned());
+                }
+                let requirements: Vec<String> = requirements.into_iter()
+                    .take_while(|requirement| VersionRequirement::parse(requirement).is_ok())
+                    .collect();
+                // バージョンの位置にある定数などの式は、制約の指定がないものとは区別して記録する
+                let version_expression = match arguments.get(1) {
+                    Some(Argument::Expression(expression)) => Some(expression.clone()),
+                    _ => None,
+                };
+                // `=`と`~>`の1つの制約のみ書かれたバージョンを使用し、それ以外は後で制約を満たすバージョンをAPIから取得する
+                let version = match VersionRequirement::parse(&requirements.join(", ")).unwrap_or_default().constraints.as_slice() {
+                    [constraint] if matches!(constraint.operator, Operator::Equal | Operator::Pessimistic) => constraint.version.to_string(),
+                    _ => String::new(),
+                };
+                let version = if version_regex.is_match(&version) { version } else { String::new() };
+
+                // Gitのリポジトリから取得するGem(`github: 'org/repo'`はGitHubのURLにする)
+                let repository = options.get("git").cloned()
+                    .or_else(|| options.get("github").map(|repository| format!("https://github.com/{}.git", repository)));
+                let origin = match (repository, options.get("path")) {
+                    (Some(url), _) => GemSource::Git {
+                        url,
+                        reference: ["ref", "branch", "tag"].iter().find_map(|key| options.get(*key).cloned()),
+                    },
+                    (None, Some(path)) => GemSource::Path(PathBuf::from(path)),
+                    (None, None) => GemSource::Registry,
+                };
+
+                // レジストリ以外から取得するGemや評価できないバージョンを報告する
+                if let Some(key) = ["git", "github"].into_iter().find(|key| options.contains_key(*key)) {
+                    warnings.push(ParseWarning::new(index, raw, format!("Gem {} is sourced from {}:, which is recorded but not downloaded", name, key)));
+                }
+                if let Some(expression) = &version_expression {
+                    warnings.push(ParseWarning::new(index, raw, format!("Version of {} is given as Ruby expression {}; the latest version is used", name, expression)));
+                }
+
+                // Gemのデータを追加
+                gems.push(Gem {
+                    name: name.to_string(),
+                    version,
+                    requirements,
+                    options,
+                    groups,
+                    source: gem_source,
+                    checksum: None,
+                    platforms,
+                    origin,
+                    version_expression,
+                    require_paths,
+                    resolved_platform: None,
+                });
             }
         }
 
-        Ok(GemfileData { source, gems })
+        Ok(GemfileData { source, gems, optional_groups, sources, warnings })
+    }
+
+    ///
+    /// バージョンが決まっていないGemのバージョンをAPIから並列に取得する
+    ///
+    /// `~>`で書かれたバージョンもバージョン一覧のAPIから制約を満たす最新のバージョンに置き換える
+    ///
+    /// * options - インストール処理のオプション(`concurrency`で同時に行うリクエスト数を制限する)
+    ///
+    /// return - 取得処理の結果
+    ///
+    pub async fn resolve_versions(&mut self, options: &InstallOptions) -> Result<(), Box<dyn Error>> {
+        self.resolve_versions_with(options, &options.semaphore()).await
+    }
+
+    ///
+    /// ダウンロードと共通のセマフォを使用してバージョンを取得する
+    ///
+    pub(crate) async fn resolve_versions_with(&mut self, options: &InstallOptions, semaphore: &Semaphore) -> Result<(), Box<dyn Error>> {
+        let default_source = &self.source;
+        let resolver = options.version_resolver();
+        let tasks = self.gems.iter_mut()
+            // Gitのリポジトリから取得するGemはレジストリに問い合わせない
+            .filter(|gem| gem.is_registry())
+            .filter(|gem| gem.version.is_empty() || !gem.is_exact())
+            // ローカルのディレクトリのソースにはバージョンの一覧がないため、書かれたバージョンを使用する
+            .filter(|gem| !gem.source.as_ref().unwrap_or(default_source).starts_with(LOCAL_SOURCE_PREFIX) || gem.version.is_empty())
+            .map(|gem| async move {
+                let _permit = semaphore.acquire().await?;
+                let source = gem.source.as_ref().unwrap_or(default_source);
+                let requirement = gem.version_requirement();
+                if gem.version.is_empty() {
+                    if let Some(expression) = &gem.version_expression {
+                        events::emit(options, || InstallEvent::Warning {
+                            gem: gem.name.clone(),
+                            message: format!("Version is given as Ruby expression {}; using the latest version", expression),
+                        });
+                    }
+                    gem.version = resolver.resolve(source, &gem.name, &requirement, options).await?;
+                    return Ok(());
+                }
+
+                // `~>`に書かれたバージョンは、制約を満たす最新のバージョンに置き換える(ソースに接続できない場合のみ書かれたバージョンのまま)
+                match resolver.resolve(source, &gem.name, &requirement, options).await {
+                    Ok(version) => gem.version = version,
+                    Err(error) if is_unreachable(error.as_ref()) => {
+                        events::emit(options, || InstallEvent::Warning {
+                            gem: gem.name.clone(),
+                            message: format!("Could not reach {} ({}); using version {}", source, error, gem.version),
+                        });
+                    }
+                    Err(error) => return Err(error),
+                }
+                Ok::<(), Box<dyn Error>>(())
+            });
+        try_join_all(tasks).await?;
+        Ok(())
     }
 }
 
+///
+/// ソースのURLを正規化する
+///
+/// ホスト名を小文字にし、末尾のスラッシュを取り除く
+///
+/// * source - ソースのURL
+///
+/// return - 正規化したURL
+///
+pub fn normalize_source(source: &str) -> String {
+    let source = source.trim();
+    match Url::parse(source) {
+        // Urlはホスト名を小文字に変換する
+        Ok(url) => url.as_str().trim_end_matches('/').to_string(),
+        Err(_) => source.trim_end_matches('/').to_string(),
+    }
+}
+
+///
+/// 文字列の外にある`#`から行末までのコメントを取り除く
+///
+/// * line - Gemfileの1行
+///
+/// return - コメントより前の部分
+///
+fn strip_comment(line: &str) -> &str {
+    // 文字列の中かどうか
+    let mut quote: Option<char> = None;
+    // 直前がエスケープの`\`かどうか
+    let mut escaped = false;
+
+    for (index, c) in line.char_indices() {
+        match quote {
+            Some(_) if escaped => escaped = false,
+            Some(_) if c == '\\' => escaped = true,
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None => match c {
+                '"' | '\'' => quote = Some(c),
+                '#' => return line[..index].trim_end(),
+                _ => {}
+            },
+        }
+    }
+    line
+}
+
+///
+/// ` do`で終わるブロックの開始行の場合、` do`より前の部分を返す
+///
+fn strip_block_start(line: &str) -> Option<&str> {
+    line.trim_end().strip_suffix(" do").map(|header| header.trim_end())
+}
+
+///
+/// gemの行の引数
+///
+#[derive(Debug, Clone, PartialEq)]
+enum Argument {
+    /// 文字列のリテラル
+    Literal(String),
+    /// `key: value`形式のキーワード引数
+    Keyword(String, String),
+    /// 定数や変数などのRubyの式
+    Expression(String),
+}
+
+///
+/// gemの行の引数をカンマで分割する
+///
+/// 文字列や括弧の中にあるカンマでは分割しない
+///
+/// * arguments - `gem `以降の文字列
+///
+/// return - 分割した引数の一覧
+///
+fn parse_arguments(arguments: &str) -> Vec<Argument> {
+    let mut tokens: Vec<String> = Vec::new();
+    let mut current = String::new();
+    // 文字列の中かどうか
+    let mut quote: Option<char> = None;
+    // 括弧の深さ
+    let mut depth = 0;
+
+    for c in arguments.chars() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None => match c {
+                '"' | '\'' => quote = Some(c),
+                '(' | '[' | '{' => depth += 1,
+                ')' | ']' | '}' => depth -= 1,
+                ',' if depth == 0 => {
+                    tokens.push(current.trim().to_string());
+                    current.clear();
+                    continue;
+                }
+                _ => {}
+            },
+        }
+        current.push(c);
+    }
+    if !current.trim().is_empty() {
+        tokens.push(current.trim().to_string());
+    }
+
+    tokens.iter().map(|token| to_argument(token)).collect()
+}
+
+///
+/// キーワード引数で指定されたグループやプラットフォームの名前を取得する
+///
+/// * value - `:test`や`[:development, :test]`などの値
+///
+/// return - 名前の一覧
+///
+fn parse_symbol_names(value: &str) -> Vec<String> {
+    let value = value.trim();
+    let value = value.strip_prefix('[').and_then(|value| value.strip_suffix(']')).unwrap_or(value);
+    value.split(',')
+        .map(|name| name.trim())
+        .map(|name| name.strip_prefix(':').map(|name| name.to_string()).or_else(|| unquote(name)).unwrap_or(name.to_string()))
+        .filter(|name| !name.is_empty())
+        .collect()
+}
+
+///
+/// 引数の文字列を分類する
+///
+fn to_argument(token: &str) -> Argument {
+    // 文字列のリテラル
+    if let Some(literal) = unquote(token) {
+        return Argument::Literal(literal);
+    }
+
+    // `key: value`形式のキーワード引数(`Foo::BAR`は除く)
+    if let Some((key, value)) = token.split_once(':') {
+        let is_key = !key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if is_key && !value.starts_with(':') {
+            let value = value.trim();
+            return Argument::Keyword(key.to_string(), unquote(value).unwrap_or(value.to_string()));
+        }
+    }
+
+    // `:key => value`形式のキーワード引数
+    if let Some((key, value)) = token.split_once("=>") {
+        if let Some(key) = key.trim().strip_prefix(':') {
+            let value = value.trim();
+            return Argument::Keyword(key.to_string(), unquote(value).unwrap_or(value.to_string()));
+        }
+    }
+
+    Argument::Expression(token.to_string())
+}
+
+///
+/// クォートで始まる文字列の場合、閉じクォートまでの中身を返す
+///
+fn unquote(token: &str) -> Option<String> {
+    ['"', '\''].iter().find_map(|quote| {
+        token.strip_prefix(*quote)
+            .and_then(|rest| rest.split_once(*quote))
+            .map(|(inner, _)| inner.to_string())
+    })
+}
 
 #[cfg(test)]
 mod tests {
-    use crate::parser::GemfileData;
+    use std::sync::{Arc, Mutex};
+    use crate::events::{EventHandler, InstallEvent};
+    use crate::options::InstallOptions;
+    use crate::parser::{normalize_source, GemfileData};
+    use crate::test_util::{MockResponse, MockServer};
+    use crate::version::Operator;
     #[tokio::test]
     pub async fn parse_test() {
         // パースをテスト
@@ -173,4 +814,515 @@ end").await;
         assert_eq!(gemfile_data.source, "https://rubygems.org");
         assert_eq!(gemfile_data.gems.len(), 17);
     }
-}
\ No newline at end of file
+
+    ///
+    /// オプションを変数で渡す3引数の形式のテスト
+    ///
+    #[test]
+    pub fn parse_options_variable_test() {
+        // バージョンをAPIから解決せず、書かれた内容のみを確認する
+        let gemfile_data = GemfileData::parse_unresolved("
+gem 'concurrent-ruby', '1.3.4', options
+gem 'concurrent-ruby-ext', '1.3.4', options.merge(platform: :mri)
+gem 'yard', '~> 0.9.0', require: false").unwrap();
+
+        assert_eq!(gemfile_data.gems.len(), 3);
+
+        // 名前とバージョンのみが取得され、変数はオプションにならないか
+        let gem = &gemfile_data.gems[0];
+        assert_eq!(gem.name, "concurrent-ruby");
+        assert_eq!(gem.version, "1.3.4");
+        assert!(gem.options.is_empty());
+        assert!(gemfile_data.gems[1].options.is_empty());
+
+        // キーワード引数はオプションとして取得されるか
+        let gem = &gemfile_data.gems[2];
+        assert_eq!(gem.version, "0.9.0");
+        assert_eq!(gem.requirement(), "~> 0.9.0");
+        assert_eq!(gem.options.get("require").map(String::as_str), Some("false"));
+    }
+
+    ///
+    /// オプションが混在するグループのテスト
+    ///
+    #[tokio::test]
+    pub async fn parse_group_options_test() {
+        let gemfile_data = GemfileData::parse("
+gem 'rake', '13.0.1'
+
+# made opt-in since it will not install on jruby 1.7
+group :coverage, optional: !ENV['COVERAGE'] do
+  gem 'simplecov', '0.16.1', require: false
+end
+
+group :documentation, optional: true do
+  gem 'yard', '0.9.0'
+end
+
+group :development, :test do
+  if ENV['EXTRA']
+    gem 'pry', '0.11.0'
+  end
+  gem 'rspec', '3.7.0'
+end").await.unwrap();
+
+        assert_eq!(gemfile_data.gems.len(), 5);
+        assert!(gemfile_data.gems[0].groups.is_empty());
+        // 式が指定されたoptionalは任意のグループとして扱われないか
+        assert_eq!(gemfile_data.gems[1].groups, vec!["coverage".to_string()]);
+        assert_eq!(gemfile_data.optional_groups, vec!["documentation".to_string()]);
+        assert_eq!(gemfile_data.gems[2].groups, vec!["documentation".to_string()]);
+        // if文のendでグループが閉じられていないか
+        assert_eq!(gemfile_data.gems[3].groups, vec!["development".to_string(), "test".to_string()]);
+        assert_eq!(gemfile_data.gems[4].groups, vec!["development".to_string(), "test".to_string()]);
+    }
+
+    ///
+    /// コメントや文字列の中のgemが宣言として扱われないかのテスト
+    ///
+    #[tokio::test]
+    pub async fn parse_commented_gem_test() {
+        let gemfile_data = GemfileData::parse("
+# gem 'commented'
+  #gem 'indented'
+description \"use gem wisely\"
+summary = 'gem rake'
+=begin
+gem 'block_commented'
+=end
+gem 'rake', '13.0.1'").await.unwrap();
+
+        // コメントと文字列からGemが作成されていないか
+        assert_eq!(gemfile_data.gems.len(), 1);
+        assert_eq!(gemfile_data.gems[0].name, "rake");
+    }
+
+    ///
+    /// ソースの正規化と重複の除去のテスト
+    ///
+    #[tokio::test]
+    pub async fn parse_sources_test() {
+        let gemfile_data = GemfileData::parse("
+source 'https://RubyGems.org/'
+source \"https://rubygems.org\"
+source 'https://gems.example.com/private/'").await.unwrap();
+
+        // 末尾のスラッシュと大文字小文字の違いが統合されているか
+        assert_eq!(gemfile_data.sources, vec![
+            "https://rubygems.org".to_string(),
+            "https://gems.example.com/private".to_string(),
+        ]);
+        assert_eq!(normalize_source("https://rubygems.org//"), "https://rubygems.org");
+    }
+
+    ///
+    /// sourceのブロックのテスト
+    ///
+    #[tokio::test]
+    pub async fn parse_source_block_test() {
+        let gemfile_data = GemfileData::parse("
+source 'https://rubygems.org'
+
+gem 'rake', '13.0.1'
+
+source 'https://gems.example.com' do
+  gem 'private_a', '1.0.0'
+  gem 'private_b', '2.0.0'
+end
+
+gem 'rack', '3.0.0'").await.unwrap();
+
+        let source = |name: &str| gemfile_data.gems.iter().find(|gem| gem.name == name).unwrap().source.clone();
+        // ブロック内のGemはブロックのソースを持つか
+        assert_eq!(source("private_a"), Some("https://gems.example.com".to_string()));
+        assert_eq!(source("private_b"), Some("https://gems.example.com".to_string()));
+        // ブロック外のGemはデフォルトのソースのままか
+        assert_eq!(source("rake"), None);
+        assert_eq!(source("rack"), None);
+        assert_eq!(gemfile_data.source, "https://rubygems.org");
+        assert_eq!(gemfile_data.sources, vec![
+            "https://rubygems.org".to_string(),
+            "https://gems.example.com".to_string(),
+        ]);
+    }
+
+    ///
+    /// 比較演算子を含む制約のテスト
+    ///
+    #[tokio::test]
+    pub async fn parse_operator_test() {
+        let server = MockServer::start(|request| match request.path.as_str() {
+            "/info/rake" => MockResponse::new(200, "---\n11.3.0 |\n12.3.3 |\n13.0.1 |\n"),
+            "/info/pg" => MockResponse::new(200, "---\n1.5.4 |\n2.0.0 |\n"),
+            "/info/thor" => MockResponse::new(200, "---\n1.2.1 |\n1.2.2 |\n1.3.0 |\n"),
+            "/info/json" => MockResponse::new(200, "---\n2.6.3 |\n2.7.0 |\n"),
+            "/info/rails" => MockResponse::new(200, "---\n7.0.8 |\n7.1.0 |\n7.1.3 |\n7.2.0 |\n"),
+            _ => MockResponse::not_found(),
+        }).await;
+        let mut gemfile_data = GemfileData::parse_unresolved(&format!("source '{}'
+gem 'rake', '>= 12.0'
+gem 'pg', '< 2.0'
+gem 'thor', '<= 1.2.2'
+gem 'json', '!= 2.7.0'
+gem 'puma', '= 6.4.0'
+gem 'rails', '~> 7.1.0'
+", server.url)).unwrap();
+
+        // 演算子とバージョンに分けて保持されるか
+        let gem = |gemfile_data: &GemfileData, name: &str| gemfile_data.gems.iter().find(|gem| gem.name == name).unwrap().clone();
+        let expected = [
+            ("rake", ">= 12.0", Operator::GreaterEqual),
+            ("pg", "< 2.0", Operator::Less),
+            ("thor", "<= 1.2.2", Operator::LessEqual),
+            ("json", "!= 2.7.0", Operator::NotEqual),
+            ("puma", "= 6.4.0", Operator::Equal),
+            ("rails", "~> 7.1.0", Operator::Pessimistic),
+        ];
+        for (name, requirement, operator) in expected {
+            let gem = gem(&gemfile_data, name);
+            assert_eq!(gem.requirement(), requirement);
+            assert_eq!(gem.version_requirement().constraints[0].operator, operator);
+        }
+        // 演算子がバージョンに混ざらないか
+        assert_eq!(gem(&gemfile_data, "thor").version, "");
+        assert_eq!(gem(&gemfile_data, "puma").version, "6.4.0");
+        assert_eq!(gem(&gemfile_data, "rails").version, "7.1.0");
+
+        // 制約を満たすバージョンが選択されるか
+        let options = InstallOptions { allow_insecure: true, ..Default::default() };
+        gemfile_data.resolve_versions(&options).await.unwrap();
+        assert_eq!(gem(&gemfile_data, "rake").version, "13.0.1");
+        assert_eq!(gem(&gemfile_data, "pg").version, "1.5.4");
+        assert_eq!(gem(&gemfile_data, "thor").version, "1.2.2");
+        assert_eq!(gem(&gemfile_data, "json").version, "2.6.3");
+        assert_eq!(gem(&gemfile_data, "rails").version, "7.1.3");
+    }
+
+    ///
+    /// `version:`のキーワード引数で指定されたバージョンのテスト
+    ///
+    #[test]
+    pub fn parse_version_keyword_test() {
+        let gemfile_data = GemfileData::parse_unresolved("
+gem 'foo', version: '1.2.3'
+gem 'bar', version: '~> 1.0', require: false
+gem 'baz', :version => '2.0.0'
+").unwrap();
+
+        assert_eq!(gemfile_data.gems[0].version, "1.2.3");
+        assert_eq!(gemfile_data.gems[0].requirement(), "1.2.3");
+        // 制約として扱い、バージョンが決まらない場合は後で取得するか
+        assert_eq!(gemfile_data.gems[1].version, "");
+        assert_eq!(gemfile_data.gems[1].requirement(), "~> 1.0");
+        assert_eq!(gemfile_data.gems[1].options.get("require").map(String::as_str), Some("false"));
+        assert_eq!(gemfile_data.gems[2].version, "2.0.0");
+    }
+
+    ///
+    /// 1行に複数の制約が書かれたGemのテスト
+    ///
+    #[tokio::test]
+    pub async fn parse_multiple_requirements_test() {
+        let server = MockServer::start(|request| match request.path.as_str() {
+            "/info/rake-compiler" => MockResponse::new(200, "---\n1.0.5 |\n1.0.6 |\n1.0.7 |\n1.2.5 |\n2.0.0 |\n"),
+            "/info/pinned" => MockResponse::new(200, "---\n1.0.0 |\n1.0.3 |\n1.1.0 |\n"),
+            _ => MockResponse::not_found(),
+        }).await;
+        let mut gemfile_data = GemfileData::parse_unresolved(&format!("source '{}'
+gem 'rake-compiler', '~> 1.0', '>= 1.0.7'
+gem 'pinned', '>= 1.0', '< 1.1', require: false
+", server.url)).unwrap();
+
+        let gem = &gemfile_data.gems[0];
+        assert_eq!(gem.requirements, vec!["~> 1.0".to_string(), ">= 1.0.7".to_string()]);
+        assert_eq!(gem.requirement(), "~> 1.0, >= 1.0.7");
+        assert_eq!(gem.version_requirement().constraints.len(), 2);
+        assert_eq!(gem.version, "");
+        assert_eq!(gemfile_data.gems[1].requirements.len(), 2);
+
+        // すべての制約を満たすバージョンが選択されるか
+        let options = InstallOptions { allow_insecure: true, ..Default::default() };
+        gemfile_data.resolve_versions(&options).await.unwrap();
+        assert_eq!(gemfile_data.gems[0].version, "1.2.5");
+        assert_eq!(gemfile_data.gems[1].version, "1.0.3");
+    }
+
+    ///
+    /// `~>`で書かれたバージョンを制約を満たす最新のバージョンに置き換えるテスト
+    ///
+    #[tokio::test]
+    pub async fn resolve_pessimistic_test() {
+        let server = MockServer::start(|request| match request.path.as_str() {
+            "/info/i18n" => MockResponse::new(200, "---\n1.8.5 |\n1.8.11 |\n1.8.11-java |\n1.8.12.rc1 |\n1.9.0 |\n"),
+            "/info/missing" => MockResponse::new(200, "---\n1.0.0 |\n"),
+            _ => MockResponse::not_found(),
+        }).await;
+        let options = InstallOptions { allow_insecure: true, ..Default::default() };
+        let gemfile = |body: &str| format!("source '{}'\n{}", server.url, body);
+
+        // 制約を満たす最新のバージョンが選択され、正確なバージョンはそのままか
+        let mut gemfile_data = GemfileData::parse_unresolved(&gemfile("gem 'i18n', '~> 1.8.5'\ngem 'pinned', '2.0.0'\n")).unwrap();
+        assert_eq!(gemfile_data.gems[0].version, "1.8.5");
+        gemfile_data.resolve_versions(&options).await.unwrap();
+        assert_eq!(gemfile_data.gems[0].version, "1.8.11");
+        assert_eq!(gemfile_data.gems[1].version, "2.0.0");
+        assert_eq!(server.request_count("/info/pinned"), 0);
+
+        // ソースに接続できない場合は書かれたバージョンのままか
+        let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let mut gemfile_data = GemfileData::parse_unresolved(&format!("source 'http://{}'\ngem 'offline', '~> 3.1.0'\n", closed)).unwrap();
+        gemfile_data.resolve_versions(&options).await.unwrap();
+        assert_eq!(gemfile_data.gems[0].version, "3.1.0");
+
+        // 接続できても取得に失敗した場合は書かれたバージョンを使用せずにエラーになるか
+        let mut gemfile_data = GemfileData::parse_unresolved(&gemfile("gem 'unknown', '~> 3.1.0'\n")).unwrap();
+        assert!(gemfile_data.resolve_versions(&options).await.is_err());
+        let mut gemfile_data = GemfileData::parse_unresolved(&gemfile("gem 'i18n', '~> 1.8.5'\n")).unwrap();
+        assert!(gemfile_data.resolve_versions(&InstallOptions::default()).await.is_err());
+
+        // 制約を満たすバージョンがない場合はエラーになるか
+        let mut gemfile_data = GemfileData::parse_unresolved(&gemfile("gem 'missing', '~> 2.0.0'\n")).unwrap();
+        assert!(gemfile_data.resolve_versions(&options).await.is_err());
+    }
+
+    ///
+    /// 末尾にスラッシュがあるソースのテスト
+    ///
+    #[test]
+    pub fn parse_trailing_slash_test() {
+        let gemfile_data = GemfileData::parse_unresolved("
+source 'https://rubygems.org/'
+
+gem 'rake', '13.0.1'
+
+source \"https://gems.example.com/\" do
+  gem 'private', '1.0.0'
+end
+").unwrap();
+
+        assert_eq!(gemfile_data.source, "https://rubygems.org");
+        assert_eq!(gemfile_data.gems[1].source, Some("https://gems.example.com".to_string()));
+    }
+
+    ///
+    /// キーワード引数で指定されたグループのテスト
+    ///
+    #[test]
+    pub fn parse_inline_group_test() {
+        let gemfile_data = GemfileData::parse_unresolved("
+gem 'rails', '7.1.0'
+gem 'rspec', '3.13.0', group: :test
+gem 'pry', '0.14.2', groups: [:development, :test]
+gem 'debug', '1.9.0', :group => 'development'
+group :development do
+  gem 'rubocop', '1.60.0', group: :lint
+end").unwrap();
+
+        let groups: Vec<Vec<String>> = gemfile_data.gems.iter().map(|gem| gem.groups.clone()).collect();
+        // グループの指定がないGemは空になるか
+        assert!(groups[0].is_empty());
+        assert_eq!(groups[1], vec!["test".to_string()]);
+        assert_eq!(groups[2], vec!["development".to_string(), "test".to_string()]);
+        assert_eq!(groups[3], vec!["development".to_string()]);
+        // ブロックとキーワード引数のグループが合わせられるか
+        assert_eq!(groups[4], vec!["development".to_string(), "lint".to_string()]);
+    }
+
+    ///
+    /// `=`で完全一致を指定したバージョンのテスト
+    ///
+    #[tokio::test]
+    pub async fn parse_explicit_equals_test() {
+        let server = MockServer::start(|_| MockResponse::not_found()).await;
+        let mut gemfile_data = GemfileData::parse_unresolved(&format!("source '{}'
+gem \"foo\", \"= 1.2.3\"
+gem 'bar', '=2.0.1'
+", server.url)).unwrap();
+
+        // `=`が取り除かれたバージョンになるか
+        assert_eq!(gemfile_data.gems[0].version, "1.2.3");
+        assert_eq!(gemfile_data.gems[0].requirement(), "= 1.2.3");
+        assert!(gemfile_data.gems[0].is_exact());
+        assert_eq!(gemfile_data.gems[1].version, "2.0.1");
+
+        // 完全一致のバージョンはAPIに問い合わせずにそのまま使用されるか
+        let options = InstallOptions { allow_insecure: true, ..Default::default() };
+        gemfile_data.resolve_versions(&options).await.unwrap();
+        assert_eq!(gemfile_data.gems[0].version, "1.2.3");
+        assert_eq!(gemfile_data.gems[1].version, "2.0.1");
+        assert!(server.requests().is_empty());
+    }
+
+    ///
+    /// 行末と行全体のコメントを取り除くテスト
+    ///
+    #[test]
+    pub fn parse_inline_comment_test() {
+        let gemfile_data = GemfileData::parse_unresolved("
+source 'https://rubygems.org' # main registry
+# gem 'commented', '1.0.0'
+    # gem 'indented_comment', '1.0.0'
+gem \"redcarpet\", \"~> 3.6.0\" # understands github markdown
+gem 'hash', '1.0.0', require: 'lib#name' # keeps '#' in strings
+gem 'escaped', '2.0.0', require: \"a\\\"#b\"
+group :test do # test only
+  gem 'rspec', '3.13.0'
+end # test
+gem 'after_group', '1.0.0'").unwrap();
+
+        assert_eq!(gemfile_data.source, "https://rubygems.org");
+        let names: Vec<&str> = gemfile_data.gems.iter().map(|gem| gem.name.as_str()).collect();
+        assert_eq!(names, vec!["redcarpet", "hash", "escaped", "rspec", "after_group"]);
+
+        // コメントが制約やオプションに混ざらないか
+        assert_eq!(gemfile_data.gems[0].requirement(), "~> 3.6.0");
+        assert_eq!(gemfile_data.gems[0].version, "3.6.0");
+        assert_eq!(gemfile_data.gems[1].options.get("require").map(String::as_str), Some("lib#name"));
+        assert_eq!(gemfile_data.gems[2].version, "2.0.0");
+        assert!(gemfile_data.gems[2].options.contains_key("require"));
+        // コメント付きのブロックの開始と終了が認識されるか
+        assert_eq!(gemfile_data.gems[3].groups, vec!["test".to_string()]);
+        assert!(gemfile_data.gems[4].groups.is_empty());
+    }
+
+    ///
+    /// タブや空白の混在したインデントのテスト
+    ///
+    #[test]
+    pub fn parse_tab_indent_test() {
+        let gemfile_data = GemfileData::parse_unresolved("source 'https://rubygems.org'
+group :test do
+\tgem 'rspec', '3.13.0'
+ \t gem 'pry', '0.14.2'
+\tend
+\u{3000}gem 'rake', '13.0.1'").unwrap();
+
+        let names: Vec<&str> = gemfile_data.gems.iter().map(|gem| gem.name.as_str()).collect();
+        assert_eq!(names, vec!["rspec", "pry", "rake"]);
+        assert_eq!(gemfile_data.gems[1].version, "0.14.2");
+        // タブでインデントされたendでブロックが閉じられるか
+        assert!(gemfile_data.gems[2].groups.is_empty());
+    }
+
+    ///
+    /// バージョンにRubyの式が書かれたGemのテスト
+    ///
+    #[tokio::test]
+    pub async fn parse_version_expression_test() {
+        let server = MockServer::start(|request| match request.path.as_str() {
+            "/info/concurrent-ruby" => MockResponse::new(200, "---\n1.2.3 |\n1.3.4 |\n"),
+            _ => MockResponse::not_found(),
+        }).await;
+        let mut gemfile_data = GemfileData::parse_unresolved(&format!("source '{}'
+gem 'concurrent-ruby', Concurrent::VERSION, options
+gem 'concurrent-ruby-ext', '1.3.4'
+", server.url)).unwrap();
+
+        // 式が記録され、制約として扱われないか
+        assert_eq!(gemfile_data.gems[0].version_expression, Some("Concurrent::VERSION".to_string()));
+        assert_eq!(gemfile_data.gems[0].requirement(), "");
+        assert_eq!(gemfile_data.gems[1].version_expression, None);
+
+        // 最新のバージョンを使用し、警告が通知されるか
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let recorded = Arc::clone(&events);
+        let on_event: EventHandler = Arc::new(move |event| recorded.lock().unwrap().push(event));
+        let options = InstallOptions { allow_insecure: true, on_event: Some(on_event), ..Default::default() };
+        gemfile_data.resolve_versions(&options).await.unwrap();
+        assert_eq!(gemfile_data.gems[0].version, "1.3.4");
+        let events = events.lock().unwrap();
+        assert_eq!(events.len(), 1);
+        assert!(matches!(&events[0], InstallEvent::Warning { gem, message } if gem == "concurrent-ruby" && message.contains("Concurrent::VERSION")));
+    }
+
+    ///
+    /// platformsのブロックのテスト
+    ///
+    #[test]
+    pub fn parse_platforms_block_test() {
+        let gemfile_data = GemfileData::parse_unresolved("
+gem 'rake', '13.0.1'
+platforms :jruby do
+  gem 'jruby-openssl', '0.14.2'
+end
+platform :ruby do
+  group :test do
+    gem 'byebug', '11.1.3'
+  end
+end
+gem 'nokogiri', '1.15.0', platforms: [:x86_64_linux]").unwrap();
+
+        // ブロックのプラットフォームが含まれるGemに設定されるか
+        let platforms: Vec<Vec<String>> = gemfile_data.gems.iter().map(|gem| gem.platforms.clone()).collect();
+        assert!(platforms[0].is_empty());
+        assert_eq!(platforms[1], vec!["jruby".to_string()]);
+        assert_eq!(platforms[2], vec!["ruby".to_string()]);
+        assert_eq!(gemfile_data.gems[2].groups, vec!["test".to_string()]);
+        assert_eq!(platforms[3], vec!["x86_64_linux".to_string()]);
+
+        // mriを対象にした場合はjruby向けのGemを除くか
+        let options = InstallOptions { target_platform: Some("mri".to_string()), ..Default::default() };
+        let included: Vec<&str> = gemfile_data.gems.iter().filter(|gem| options.includes_gem(gem)).map(|gem| gem.name.as_str()).collect();
+        assert_eq!(included, vec!["rake", "byebug"]);
+        // 指定しない場合はすべてを対象にするか
+        assert!(gemfile_data.gems.iter().all(|gem| InstallOptions::default().includes_gem(gem)));
+    }
+
+    ///
+    /// 解釈できない行を警告として記録するテスト
+    ///
+    #[test]
+    pub fn parse_warnings_test() {
+        let gemfile_data = GemfileData::parse_unresolved("source 'https://rubygems.org'
+ruby '3.3.0'
+gemspec
+gem 'rails', '7.1.0'
+gem 'local', path: '../local'
+gem 'forked', git: 'https://github.com/org/forked.git'
+if ENV['EXTRA']
+  gem 'extra', '1.0.0'
+end
+gem name_variable
+gem 'concurrent-ruby', Concurrent::VERSION").unwrap();
+
+        // パースは成功し、Gemはそのまま含まれるか
+        assert_eq!(gemfile_data.gems.len(), 5);
+
+        // 行番号と行のテキストが記録されるか
+        let warnings: Vec<(usize, &str)> = gemfile_data.warnings.iter().map(|warning| (warning.line_number, warning.line.as_str())).collect();
+        assert_eq!(warnings, vec![
+            (3, "gemspec"),
+            (6, "gem 'forked', git: 'https://github.com/org/forked.git'"),
+            (7, "if ENV['EXTRA']"),
+            (10, "gem name_variable"),
+            (11, "gem 'concurrent-ruby', Concurrent::VERSION"),
+        ]);
+        assert!(gemfile_data.warnings[1].message.contains("forked"));
+        assert!(gemfile_data.warnings[1].message.contains("git"));
+    }
+
+    ///
+    /// `require:`に配列で指定されたパスのテスト
+    ///
+    #[test]
+    pub fn parse_require_paths_test() {
+        let gemfile_data = GemfileData::parse_unresolved("
+gem 'rack-cache', '1.17.0', require: ['rack/cache', \"rack/cache/key\"], group: :test
+gem 'sinatra', '4.0.0', require: 'sinatra/base'
+gem 'bootsnap', '1.18.3', require: false
+").unwrap();
+
+        // 配列のすべてのパスが取得され、名前やバージョンなどが変わらないか
+        let gem = &gemfile_data.gems[0];
+        assert_eq!(gem.name, "rack-cache");
+        assert_eq!(gem.version, "1.17.0");
+        assert_eq!(gem.requirement(), "1.17.0");
+        assert_eq!(gem.groups, vec!["test".to_string()]);
+        assert_eq!(gem.require_paths, vec!["rack/cache".to_string(), "rack/cache/key".to_string()]);
+        assert_eq!(gem.options.get("require").map(String::as_str), Some("['rack/cache', \"rack/cache/key\"]"));
+
+        // 1つの文字列やfalseの場合
+        assert_eq!(gemfile_data.gems[1].require_paths, vec!["sinatra/base".to_string()]);
+        assert!(gemfile_data.gems[2].require_paths.is_empty());
+    }
+}
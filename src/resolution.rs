@@ -0,0 +1,139 @@
+//!
+//! 解決済みのGemの依存関係を扱います
+//!
+use serde::{Deserialize, Serialize};
+
+///
+/// 実行時の依存関係
+///
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Dependency {
+    /// 依存先のGemの名前
+    pub name: String,
+    /// 依存先のバージョンの制約(例: `>= 2.0`)
+    pub requirement: String,
+}
+
+///
+/// バージョンが解決されたGem
+///
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ResolvedGem {
+    /// Gemの名前
+    pub name: String,
+    /// 解決されたバージョン
+    pub version: String,
+    /// Gemを取得するソース
+    pub source: String,
+    /// 実行時の依存関係
+    pub dependencies: Vec<Dependency>,
+}
+
+///
+/// Gemfileのバージョンを解決した結果
+///
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Resolution {
+    /// デフォルトのソース
+    pub source: String,
+    /// 解決されたGemの一覧
+    pub gems: Vec<ResolvedGem>,
+}
+
+///
+/// JSONに出力するノード
+///
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct GraphNode {
+    name: String,
+    version: String,
+    source: String,
+}
+
+///
+/// JSONに出力するエッジ
+///
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct GraphEdge {
+    from: String,
+    to: String,
+    requirement: String,
+}
+
+///
+/// JSONに出力する依存関係のグラフ
+///
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Graph {
+    nodes: Vec<GraphNode>,
+    edges: Vec<GraphEdge>,
+}
+
+///
+/// 解決済みの依存関係のグラフをJSONに変換する
+///
+/// ノードには名前・バージョン・ソースを、エッジには実行時の依存関係を出力する
+///
+/// * resolved - 解決済みのGemの一覧
+///
+/// return - JSONの文字列
+///
+pub fn resolution_to_json(resolved: &[ResolvedGem]) -> String {
+    let nodes = resolved.iter()
+        .map(|gem| GraphNode {
+            name: gem.name.clone(),
+            version: gem.version.clone(),
+            source: gem.source.clone(),
+        })
+        .collect();
+    let edges = resolved.iter()
+        .flat_map(|gem| gem.dependencies.iter().map(|dependency| GraphEdge {
+            from: gem.name.clone(),
+            to: dependency.name.clone(),
+            requirement: dependency.requirement.clone(),
+        }))
+        .collect();
+
+    serde_json::to_string(&Graph { nodes, edges }).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+    use crate::resolution::{resolution_to_json, Dependency, ResolvedGem};
+
+    ///
+    /// 依存関係のグラフのJSONのテスト
+    ///
+    #[test]
+    pub fn resolution_to_json_test() {
+        let source = "https://rubygems.org".to_string();
+        let resolved = vec![
+            ResolvedGem {
+                name: "rspec".to_string(),
+                version: "3.13.0".to_string(),
+                source: source.clone(),
+                dependencies: vec![
+                    Dependency { name: "rspec-core".to_string(), requirement: "~> 3.13.0".to_string() },
+                ],
+            },
+            ResolvedGem {
+                name: "rspec-core".to_string(),
+                version: "3.13.2".to_string(),
+                source: source.clone(),
+                dependencies: Vec::new(),
+            },
+        ];
+
+        let json: Value = serde_json::from_str(&resolution_to_json(&resolved)).unwrap();
+        assert_eq!(json, json!({
+            "nodes": [
+                { "name": "rspec", "version": "3.13.0", "source": "https://rubygems.org" },
+                { "name": "rspec-core", "version": "3.13.2", "source": "https://rubygems.org" },
+            ],
+            "edges": [
+                { "from": "rspec", "to": "rspec-core", "requirement": "~> 3.13.0" },
+            ],
+        }));
+    }
+}
@@ -0,0 +1,604 @@
+//!
+//! バージョンの制約を満たすGemのバージョンを解決します
+//!
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt::Debug;
+use std::future::Future;
+use std::pin::Pin;
+use futures::future::join_all;
+use crate::compact_index::{fetch_info, IndexedVersion};
+use crate::gem_version::GemVersion;
+use crate::options::InstallOptions;
+use crate::parser::GemfileData;
+use crate::resolution::ResolvedGem;
+use crate::version::{Version, VersionRequirement};
+
+/// `VersionResolver::resolve`が返すFuture
+pub type ResolveFuture<'a> = Pin<Box<dyn Future<Output = Result<String, Box<dyn Error>>> + Send + 'a>>;
+
+///
+/// 制約を満たすバージョンが複数ある場合の選択方法
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ResolutionStrategy {
+    /// 最新のバージョンを選択する
+    #[default]
+    Highest,
+    /// 最も古いバージョンを選択する(サポートする最小のバージョンでのテスト向け)
+    Lowest,
+}
+
+impl ResolutionStrategy {
+    ///
+    /// 2つのバージョンのうち、`version`を`current`より優先するかを確認する
+    ///
+    /// * version - 比較するバージョン
+    /// * current - 現在選択しているバージョン
+    ///
+    pub fn prefers(&self, version: &Version, current: &Version) -> bool {
+        match self {
+            ResolutionStrategy::Highest => version > current,
+            ResolutionStrategy::Lowest => version < current,
+        }
+    }
+}
+
+///
+/// Gemのバージョンを解決する処理
+///
+/// 独自のレジストリなどに合わせてバージョンの解決方法を変更する場合に実装し、`InstallOptions.resolver`に設定する
+///
+pub trait VersionResolver: Debug + Send + Sync {
+    ///
+    /// 制約を満たすバージョンを解決する
+    ///
+    /// * source - ソースのURL
+    /// * gem_name - Gemの名前
+    /// * requirement - バージョンの制約
+    /// * options - インストール処理のオプション
+    ///
+    /// return - 成功すると解決したバージョンを返す
+    ///
+    fn resolve<'a>(&'a self, source: &'a str, gem_name: &'a str, requirement: &'a VersionRequirement, options: &'a InstallOptions) -> ResolveFuture<'a>;
+}
+
+///
+/// RubyGemsのAPIを使用する標準のバージョンの解決処理
+///
+/// Compact Indexから制約を満たす最新のバージョンを選択する。
+/// 制約がない場合も`>= 0`として扱い、yankされたバージョンとプレリリースを同じ規則で除く。
+/// `version_endpoint`が変更されている場合は、Compact Indexの代わりにそのAPIから選択する
+///
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RubyGemsResolver;
+
+impl VersionResolver for RubyGemsResolver {
+    fn resolve<'a>(&'a self, source: &'a str, gem_name: &'a str, requirement: &'a VersionRequirement, options: &'a InstallOptions) -> ResolveFuture<'a> {
+        if options.has_custom_version_endpoint() {
+            return Box::pin(async move {
+                Ok(GemVersion::get_matching_version(source, gem_name, requirement, options).await?.version)
+            });
+        }
+        Box::pin(resolve_version(source, gem_name, requirement, options))
+    }
+}
+
+///
+/// 制約を満たす最新のバージョンをCompact Indexから解決する
+///
+/// yankされたバージョンとプラットフォーム固有のバージョンは選択しない。
+/// `resolution_strategy`が`Lowest`の場合は最も古いバージョンを解決する
+///
+/// * source - ソースのURL
+/// * gem_name - Gemの名前
+/// * requirement - バージョンの制約
+/// * options - インストール処理のオプション
+///
+/// return - 成功すると解決したバージョンを返す
+///
+pub async fn resolve_version(source: &str, gem_name: &str, requirement: &VersionRequirement, options: &InstallOptions) -> Result<String, Box<dyn Error>> {
+    let versions = fetch_info(source, gem_name, options).await?;
+    let preferred = options.preferred_versions.get(gem_name).map(String::as_str);
+    match select_version_with_strategy(&versions, requirement, preferred, options.resolution_strategy) {
+        Some(version) => Ok(version.to_string()),
+        None => Err(format!("No version of {} satisfies {}", gem_name, requirement).into()),
+    }
+}
+
+///
+/// 複数のソースから解決したバージョン
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourcedVersion {
+    /// 解決したバージョン
+    pub version: String,
+    /// バージョンを提供しているソース
+    pub source: String,
+}
+
+///
+/// 複数のソースのバージョンを合わせて、制約を満たす最新のバージョンを解決する
+///
+/// 同じバージョンが複数のソースにある場合は、先に指定されたソースを選択する。
+/// 一部のソースへのリクエストが失敗しても、他のソースで解決できればエラーにしない
+///
+/// * sources - ソースのURLの一覧
+/// * gem_name - Gemの名前
+/// * requirement - バージョンの制約
+/// * options - インストール処理のオプション
+///
+/// return - 成功すると解決したバージョンとソースを返す
+///
+pub async fn resolve_across_sources(sources: &[String], gem_name: &str, requirement: &VersionRequirement, options: &InstallOptions) -> Result<SourcedVersion, Box<dyn Error>> {
+    let results = join_all(sources.iter().map(|source| fetch_info(source, gem_name, options))).await;
+    let preferred = options.preferred_versions.get(gem_name).map(String::as_str);
+    let is_preferred = |version: &Version| preferred.is_some_and(|preferred| version.as_str() == preferred);
+
+    let mut selected: Option<(Version, &String)> = None;
+    let mut last_error = None;
+    for (source, result) in sources.iter().zip(results) {
+        let versions = match result {
+            Ok(versions) => versions,
+            Err(error) => {
+                last_error = Some(error);
+                continue;
+            }
+        };
+        let Some(version) = select_version_with_strategy(&versions, requirement, preferred, options.resolution_strategy) else {
+            continue;
+        };
+        // 優先するバージョンが見つかった場合は、より新しいバージョンがあっても変更しない
+        if selected.as_ref().is_none_or(|(current, _)| !is_preferred(current) && (is_preferred(&version) || options.resolution_strategy.prefers(&version, current))) {
+            selected = Some((version, source));
+        }
+    }
+
+    match (selected, last_error) {
+        (Some((version, source)), _) => Ok(SourcedVersion { version: version.to_string(), source: source.clone() }),
+        (None, Some(error)) if sources.len() == 1 => Err(error),
+        (None, _) => Err(format!("No version of {} satisfies {} in any source", gem_name, requirement).into()),
+    }
+}
+
+///
+/// インストールせずに、Gemfileのすべての依存関係を推移的に解決する
+///
+/// Compact Indexの依存関係をたどり、Gemごとに1つのバージョンを選択する。
+/// 先に解決したバージョンが後の制約を満たさない場合はエラーにする
+///
+/// * gemfile_data - Gemfileの読み込み済みデータ
+/// * source - Gemを取得するソース(`source`のブロックで指定されたGemと、その依存先はそのソースを使用する)
+/// * options - インストール処理のオプション
+///
+/// return - 重複を除いた、依存関係を含む解決済みのGemの一覧(解決した順)
+///
+pub async fn resolve_transitive(gemfile_data: &GemfileData, source: &str, options: &InstallOptions) -> Result<Vec<ResolvedGem>, Box<dyn Error>> {
+    // 解決するGemの名前、制約、ソース
+    let mut queue = VecDeque::new();
+    // Gitやローカルのパスから取得するGemはレジストリで解決しない
+    for gem in gemfile_data.gems.iter().filter(|gem| gem.is_registry() && options.includes_gem(gem)) {
+        // `~>`などの制約は書かれたままの制約で解決し、制約がない場合のみ記録されたバージョンを使用する
+        let requirement = if gem.requirements.is_empty() && !gem.version.is_empty() {
+            VersionRequirement::parse(&gem.version)?
+        } else {
+            gem.version_requirement()
+        };
+        queue.push_back((gem.name.clone(), requirement, gem.source.clone().unwrap_or(source.to_string())));
+    }
+
+    let mut resolved: Vec<ResolvedGem> = Vec::new();
+    while let Some((name, requirement, gem_source)) = queue.pop_front() {
+        // 解決済みの場合は制約を満たすかのみを確認する
+        if let Some(existing) = resolved.iter().find(|gem| gem.name == name) {
+            if !requirement.matches(&Version::parse(&existing.version)?) {
+                return Err(format!("{} {} does not satisfy {}", name, existing.version, requirement).into());
+            }
+            continue;
+        }
+
+        let versions = fetch_info(&gem_source, &name, options).await?;
+        let preferred = options.preferred_versions.get(&name).map(String::as_str);
+        let Some(version) = select_version_with_strategy(&versions, &requirement, preferred, options.resolution_strategy) else {
+            return Err(format!("No version of {} satisfies {}", name, requirement).into());
+        };
+        let dependencies = versions.iter()
+            .find(|indexed| indexed.platform.is_none() && Version::parse(&indexed.version).is_ok_and(|indexed| indexed == version))
+            .map(|indexed| indexed.dependencies.clone())
+            .unwrap_or_default();
+
+        for dependency in &dependencies {
+            queue.push_back((dependency.name.clone(), VersionRequirement::parse(&dependency.requirement)?, gem_source.clone()));
+        }
+        resolved.push(ResolvedGem {
+            name,
+            version: version.to_string(),
+            source: gem_source,
+            dependencies,
+        });
+    }
+    Ok(resolved)
+}
+
+///
+/// バージョンの一覧から制約を満たす最新のバージョンを選択する
+///
+/// * versions - Compact Indexのバージョンの一覧
+/// * requirement - バージョンの制約
+///
+/// return - 選択したバージョン
+///
+pub fn select_version(versions: &[IndexedVersion], requirement: &VersionRequirement) -> Option<Version> {
+    select_preferred_version(versions, requirement, None)
+}
+
+///
+/// バージョンの一覧から制約を満たすバージョンを選択する
+///
+/// 優先するバージョンが制約を満たして一覧にある場合はそれを、ない場合は最新のバージョンを選択する
+///
+/// * versions - Compact Indexのバージョンの一覧
+/// * requirement - バージョンの制約
+/// * preferred - 優先するバージョン
+///
+/// return - 選択したバージョン
+///
+pub fn select_preferred_version(versions: &[IndexedVersion], requirement: &VersionRequirement, preferred: Option<&str>) -> Option<Version> {
+    select_version_with_strategy(versions, requirement, preferred, ResolutionStrategy::Highest)
+}
+
+///
+/// バージョンの一覧から、選択方法に従って制約を満たすバージョンを選択する
+///
+/// 優先するバージョンが制約を満たして一覧にある場合は、選択方法に関わらずそれを選択する
+///
+/// * versions - Compact Indexのバージョンの一覧
+/// * requirement - バージョンの制約
+/// * preferred - 優先するバージョン
+/// * strategy - 最新と最も古いバージョンのどちらを選択するか
+///
+/// return - 選択したバージョン
+///
+pub fn select_version_with_strategy(versions: &[IndexedVersion], requirement: &VersionRequirement, preferred: Option<&str>, strategy: ResolutionStrategy) -> Option<Version> {
+    let candidates: Vec<Version> = versions.iter()
+        .filter(|indexed| !indexed.yanked && indexed.platform.is_none())
+        .filter_map(|indexed| Version::parse(&indexed.version).ok())
+        .filter(|version| requirement.allows_prerelease() || !version.is_prerelease())
+        .filter(|version| requirement.matches(version))
+        .collect();
+
+    let preferred = preferred.and_then(|preferred| Version::parse(preferred).ok());
+    match preferred {
+        Some(preferred) if candidates.contains(&preferred) => Some(preferred),
+        _ => match strategy {
+            ResolutionStrategy::Highest => candidates.into_iter().max(),
+            ResolutionStrategy::Lowest => candidates.into_iter().min(),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use crate::install_gems_with_options;
+    use crate::options::InstallOptions;
+    use crate::parser::GemfileData;
+    use crate::resolver::RubyGemsResolver;
+    use crate::resolution::Dependency;
+    use crate::resolver::{resolve_across_sources, resolve_transitive, resolve_version, ResolutionStrategy, ResolveFuture, SourcedVersion, VersionResolver};
+    use crate::test_util::{test_directory, GemBuilder, MockResponse, MockServer};
+    use crate::version::VersionRequirement;
+
+    ///
+    /// 常に固定のバージョンを返すテスト用の解決処理
+    ///
+    #[derive(Debug)]
+    struct FixedResolver;
+
+    impl VersionResolver for FixedResolver {
+        fn resolve<'a>(&'a self, _source: &'a str, gem_name: &'a str, _requirement: &'a VersionRequirement, _options: &'a InstallOptions) -> ResolveFuture<'a> {
+            Box::pin(async move {
+                match gem_name {
+                    "fixed" => Ok("4.2.0".to_string()),
+                    _ => Ok("0.1.0".to_string()),
+                }
+            })
+        }
+    }
+
+    ///
+    /// yankされたバージョンを除いて解決するかのテスト
+    ///
+    #[tokio::test]
+    pub async fn yanked_version_test() {
+        let server = MockServer::start(|request| {
+            match request.path.as_str() {
+                "/info/shiny" => MockResponse::new(200, "---\n1.0.0 |checksum:a\n1.1.0 |checksum:b\n-1.2.0 |checksum:c\n1.3.0.rc1 |checksum:d\n2.0.0 |checksum:e\n"),
+                _ => MockResponse::not_found(),
+            }
+        }).await;
+        let options = InstallOptions { allow_insecure: true, ..Default::default() };
+
+        // 制約を満たす最新の1.2.0はyankされているため1.1.0を選択するか
+        let requirement = VersionRequirement::parse("~> 1.0").unwrap();
+        let version = resolve_version(&server.url, "shiny", &requirement, &options).await.unwrap();
+        assert_eq!(version, "1.1.0");
+
+        // yankされたバージョンのみが制約を満たす場合はエラーになるか
+        let requirement = VersionRequirement::parse("= 1.2.0").unwrap();
+        assert!(resolve_version(&server.url, "shiny", &requirement, &options).await.is_err());
+    }
+
+    ///
+    /// 独自の解決処理がインストールで使用されるかのテスト
+    ///
+    #[tokio::test]
+    pub async fn custom_resolver_test() {
+        let directory = test_directory("custom_resolver");
+        let bodies = [
+            ("fixed", GemBuilder::new("fixed", "4.2.0").build()),
+            ("other", GemBuilder::new("other", "0.1.0").build()),
+        ];
+        let server = MockServer::start(move |request| {
+            bodies.iter()
+                .find(|(name, _)| request.path.starts_with(&format!("/downloads/{}-", name)))
+                .map(|(_, body)| MockResponse::new(200, body.clone()))
+                .unwrap_or_else(MockResponse::not_found)
+        }).await;
+
+        // `~>`でバージョンが書かれたGemも解決処理を使用する
+        let gemfile = format!("source '{}'\ngem 'fixed'\ngem 'other', '~> 0.1'\n", server.url);
+        let gemfile_data = GemfileData::parse_unresolved(&gemfile).unwrap();
+        let options = InstallOptions {
+            allow_insecure: true,
+            verify_checksums: false,
+            resolver: Some(Arc::new(FixedResolver)),
+            ..Default::default()
+        };
+        let info = install_gems_with_options(gemfile_data, &directory.join("gems"), &directory.join("cache"), &options).await.unwrap();
+
+        // 解決処理が返したバージョンがインストールされ、APIは使用されていないか
+        let mut installed = info.install_gems.clone();
+        installed.sort();
+        assert_eq!(installed, vec!["fixed-4.2.0".to_string(), "other-0.1.0".to_string()]);
+        assert!(server.requests().iter().all(|request| request.path.starts_with("/downloads/")));
+
+        // 同じ解決処理を指すオプションのみが等しいか
+        assert_eq!(options.clone(), options);
+        assert_ne!(InstallOptions { resolver: Some(Arc::new(FixedResolver)), ..options.clone() }, options);
+    }
+
+    ///
+    /// 解決処理のFutureを別のスレッドで実行できるかのテスト
+    ///
+    #[tokio::test(flavor = "multi_thread")]
+    pub async fn resolve_future_send_test() {
+        let resolver: Arc<dyn VersionResolver> = Arc::new(FixedResolver);
+        let version = tokio::spawn(async move {
+            let options = InstallOptions::default();
+            let requirement = VersionRequirement::default();
+            resolver.resolve("https://rubygems.org", "fixed", &requirement, &options).await.map_err(|error| error.to_string())
+        }).await.unwrap();
+        assert_eq!(version, Ok("4.2.0".to_string()));
+    }
+
+    ///
+    /// 変更したバージョンを取得するAPIがインストール時の解決に使用されるかのテスト
+    ///
+    #[tokio::test]
+    pub async fn version_endpoint_install_test() {
+        let directory = test_directory("version_endpoint_install");
+        let bodies = [
+            ("custom", GemBuilder::new("custom", "3.1.4").build()),
+            ("listed", GemBuilder::new("listed", "1.2.0").build()),
+        ];
+        let server = MockServer::start(move |request| {
+            match request.path.as_str() {
+                // 1つのバージョンを返すAPI
+                "/registry/gems/custom/latest" => MockResponse::new(200, "{\"version\":\"3.1.4\"}"),
+                // バージョンの一覧を返すAPI
+                "/registry/gems/listed/latest" => MockResponse::new(200, "[{\"number\":\"2.0.0\"},{\"number\":\"1.2.0\"},{\"number\":\"1.0.0\"}]"),
+                path => bodies.iter()
+                    .find(|(name, _)| path.starts_with(&format!("/downloads/{}-", name)))
+                    .map(|(_, body)| MockResponse::new(200, body.clone()))
+                    .unwrap_or_else(MockResponse::not_found),
+            }
+        }).await;
+
+        let gemfile = format!("source '{}'\ngem 'custom'\ngem 'listed', '~> 1.0'\n", server.url);
+        let gemfile_data = GemfileData::parse_unresolved(&gemfile).unwrap();
+        let options = InstallOptions {
+            allow_insecure: true,
+            verify_checksums: false,
+            version_endpoint: "/registry/gems/{name}/latest".to_string(),
+            ..Default::default()
+        };
+        let info = install_gems_with_options(gemfile_data, &directory.join("gems"), &directory.join("cache"), &options).await.unwrap();
+
+        // テンプレートのAPIで解決し、Compact Indexとバージョン一覧のAPIは使用されていないか
+        let mut installed = info.install_gems.clone();
+        installed.sort();
+        assert_eq!(installed, vec!["custom-3.1.4".to_string(), "listed-1.2.0".to_string()]);
+        assert!(server.requests().iter().all(|request| request.path.starts_with("/downloads/") || request.path.starts_with("/registry/")));
+    }
+
+    ///
+    /// 変更したバージョンを取得するAPIとチェックサムの検証を併用するテスト
+    ///
+    #[tokio::test]
+    pub async fn version_endpoint_checksum_test() {
+        let directory = test_directory("version_endpoint_checksum");
+        let body = GemBuilder::new("custom", "3.1.4").build();
+        let sha = ring::digest::digest(&ring::digest::SHA256, &body).as_ref().iter().map(|byte| format!("{:02x}", byte)).collect::<String>();
+        let server = MockServer::start(move |request| {
+            match request.path.as_str() {
+                "/registry/gems/custom/latest" => MockResponse::new(200, "{\"version\":\"3.1.4\"}"),
+                "/registry/gems/unlisted/latest" => MockResponse::new(200, "{\"version\":\"1.0.0\"}"),
+                // チェックサムは標準のバージョン一覧のAPIからのみ取得できる
+                "/api/v1/versions/custom.json" => MockResponse::new(200, format!(r#"[{{"number":"3.1.4","platform":"ruby","sha":"{}"}}]"#, sha)),
+                "/downloads/custom-3.1.4.gem" => MockResponse::new(200, body.clone()),
+                "/downloads/unlisted-1.0.0.gem" => MockResponse::new(200, GemBuilder::new("unlisted", "1.0.0").build()),
+                _ => MockResponse::not_found(),
+            }
+        }).await;
+        // verify_checksumsはデフォルトのまま有効にする
+        let options = InstallOptions {
+            allow_insecure: true,
+            version_endpoint: "/registry/gems/{name}/latest".to_string(),
+            ..Default::default()
+        };
+
+        // チェックサムを標準のAPIから取得して検証し、インストールできるか
+        let gemfile_data = GemfileData::parse_unresolved(&format!("source '{}'\ngem 'custom'\n", server.url)).unwrap();
+        let info = install_gems_with_options(gemfile_data, &directory.join("gems"), &directory.join("cache"), &options).await.unwrap();
+        assert_eq!(info.install_gems, vec!["custom-3.1.4".to_string()]);
+        assert_eq!(server.request_count("/api/v1/versions/custom.json"), 1);
+
+        // チェックサムを取得できない場合は、verify_checksums(false)が必要であることを示すエラーになるか
+        let gemfile_data = GemfileData::parse_unresolved(&format!("source '{}'\ngem 'unlisted'\n", server.url)).unwrap();
+        let info = install_gems_with_options(gemfile_data, &directory.join("gems"), &directory.join("cache"), &options).await.unwrap();
+        assert!(info.install_gems.is_empty());
+        assert_eq!(info.failed_gems[0].gem_name, "unlisted-1.0.0");
+        assert!(info.failed_gems[0].error.contains("verify_checksums(false)"), "unexpected error: {}", info.failed_gems[0].error);
+    }
+
+    ///
+    /// 複数のソースから最新のバージョンを選択するテスト
+    ///
+    #[tokio::test]
+    pub async fn resolve_across_sources_test() {
+        let private = MockServer::start(|request| match request.path.as_str() {
+            "/info/shared" => MockResponse::new(200, "---\n1.0.0 |\n1.4.0 |\n"),
+            "/info/internal" => MockResponse::new(200, "---\n0.3.0 |\n"),
+            _ => MockResponse::not_found(),
+        }).await;
+        let public = MockServer::start(|request| match request.path.as_str() {
+            "/info/shared" => MockResponse::new(200, "---\n1.2.0 |\n1.5.0 |\n2.0.0 |\n"),
+            _ => MockResponse::not_found(),
+        }).await;
+        let sources = vec![private.url.clone(), public.url.clone()];
+        let options = InstallOptions { allow_insecure: true, ..Default::default() };
+
+        // 両方のソースのバージョンから最新のものを選択し、ソースを記録するか
+        let requirement = VersionRequirement::parse("~> 1.0").unwrap();
+        let resolved = resolve_across_sources(&sources, "shared", &requirement, &options).await.unwrap();
+        assert_eq!(resolved, SourcedVersion { version: "1.5.0".to_string(), source: public.url.clone() });
+
+        // 一方のソースにしかないGemも解決できるか
+        let resolved = resolve_across_sources(&sources, "internal", &VersionRequirement::any(), &options).await.unwrap();
+        assert_eq!(resolved, SourcedVersion { version: "0.3.0".to_string(), source: private.url.clone() });
+    }
+
+    ///
+    /// 制約のないGemが最新の安定版に解決されるかのテスト
+    ///
+    #[tokio::test]
+    pub async fn bare_gem_test() {
+        let server = MockServer::start(|request| {
+            match request.path.as_str() {
+                "/info/bare" => MockResponse::new(200, "---\n1.0.0 |checksum:a\n1.1.0 |checksum:b\n-1.2.0 |checksum:c\n2.0.0.rc1 |checksum:d\n"),
+                _ => MockResponse::not_found(),
+            }
+        }).await;
+        let options = InstallOptions { allow_insecure: true, ..Default::default() };
+
+        // `>= 0`として、yankされたバージョンとプレリリースを除いて解決するか
+        let version = RubyGemsResolver.resolve(&server.url, "bare", &VersionRequirement::any(), &options).await.unwrap();
+        assert_eq!(version, "1.1.0");
+
+        let mut gemfile_data = GemfileData::parse_unresolved(&format!("source '{}'\ngem 'bare'\n", server.url)).unwrap();
+        gemfile_data.resolve_versions(&options).await.unwrap();
+        assert_eq!(gemfile_data.gems[0].version, "1.1.0");
+    }
+
+    ///
+    /// 優先するバージョンを選択するテスト
+    ///
+    #[tokio::test]
+    pub async fn preferred_versions_test() {
+        let server = MockServer::start(|request| match request.path.as_str() {
+            "/info/gradual" => MockResponse::new(200, "---\n1.0.0 |\n1.1.0 |\n1.2.0 |\n2.0.0 |\n"),
+            _ => MockResponse::not_found(),
+        }).await;
+        let requirement = VersionRequirement::parse(">= 1.0").unwrap();
+        let options = |preferred: &str| InstallOptions {
+            allow_insecure: true,
+            preferred_versions: [("gradual".to_string(), preferred.to_string())].into_iter().collect(),
+            ..Default::default()
+        };
+
+        // 制約を満たす場合は新しいバージョンより優先されるか
+        assert_eq!(resolve_version(&server.url, "gradual", &requirement, &options("1.1.0")).await.unwrap(), "1.1.0");
+        let sourced = resolve_across_sources(std::slice::from_ref(&server.url), "gradual", &requirement, &options("1.1.0")).await.unwrap();
+        assert_eq!(sourced.version, "1.1.0");
+
+        // 制約を満たさない場合や存在しない場合は最新のバージョンになるか
+        let requirement = VersionRequirement::parse(">= 1.2").unwrap();
+        assert_eq!(resolve_version(&server.url, "gradual", &requirement, &options("1.1.0")).await.unwrap(), "2.0.0");
+        assert_eq!(resolve_version(&server.url, "gradual", &requirement, &options("1.5.0")).await.unwrap(), "2.0.0");
+    }
+
+    ///
+    /// 推移的な依存関係を解決するテスト
+    ///
+    #[tokio::test]
+    pub async fn resolve_transitive_test() {
+        let server = MockServer::start(|request| match request.path.as_str() {
+            "/info/app" => MockResponse::new(200, "---\n1.0.0 rack:>= 2.0&< 4,json:>= 0|checksum:a\n"),
+            "/info/rack" => MockResponse::new(200, "---\n2.2.0 |\n3.0.0 rack-session:>= 1.0|\n4.0.0 |\n"),
+            "/info/rack-session" => MockResponse::new(200, "---\n1.0.0 rack:>= 3.0|\n"),
+            "/info/json" => MockResponse::new(200, "---\n2.6.0 |\n2.7.0 |\n"),
+            "/info/puma" => MockResponse::new(200, "---\n6.0.0 |\n6.4.2 |\n7.0.0 |\n"),
+            _ => MockResponse::not_found(),
+        }).await;
+        let gemfile_data = GemfileData::parse_unresolved("gem 'app'\ngem 'json', '2.6.0'\ngem 'puma', '~> 6.0'\ngem 'forked', git: 'https://example.com/org/forked.git'\n").unwrap();
+        let options = InstallOptions { allow_insecure: true, ..Default::default() };
+
+        let resolved = resolve_transitive(&gemfile_data, &server.url, &options).await.unwrap();
+
+        // 依存先を含めて重複なく解決されるか
+        let versions: Vec<(&str, &str)> = resolved.iter().map(|gem| (gem.name.as_str(), gem.version.as_str())).collect();
+        // `~>`の制約は書かれたバージョンに固定せずに解決し、Gitから取得するGemは含まないか
+        assert_eq!(versions, vec![("app", "1.0.0"), ("json", "2.6.0"), ("puma", "6.4.2"), ("rack", "3.0.0"), ("rack-session", "1.0.0")]);
+        assert_eq!(resolved[0].dependencies, vec![
+            Dependency { name: "rack".to_string(), requirement: ">= 2.0, < 4".to_string() },
+            Dependency { name: "json".to_string(), requirement: ">= 0".to_string() },
+        ]);
+        assert!(resolved.iter().all(|gem| gem.source == server.url));
+        assert_eq!(server.request_count("/info/forked"), 0);
+        // ダウンロードは行わないか
+        assert!(server.requests().iter().all(|request| request.path.starts_with("/info/")));
+    }
+
+    ///
+    /// 制約を満たす最も古いバージョンを選択するテスト
+    ///
+    #[tokio::test]
+    pub async fn lowest_resolution_strategy_test() {
+        let directory = test_directory("lowest_resolution_strategy");
+        let rack = GemBuilder::new("rack", "1.2.0").build();
+        let server = MockServer::start(move |request| {
+            match request.path.as_str() {
+                "/info/rack" => MockResponse::new(200, "---\n1.1.0 |\n1.2.0 |\n1.2.5 |\n1.3.0 |\n2.0.0 |\n"),
+                "/downloads/rack-1.2.0.gem" => MockResponse::new(200, rack.clone()),
+                _ => MockResponse::not_found(),
+            }
+        }).await;
+        let requirement = VersionRequirement::parse("~> 1.2").unwrap();
+        let options = InstallOptions { allow_insecure: true, verify_checksums: false, ..Default::default() };
+
+        // デフォルトでは最新のバージョンを選択するか
+        assert_eq!(resolve_version(&server.url, "rack", &requirement, &options).await.unwrap(), "1.3.0");
+
+        // `Lowest`では制約を満たす最も古いバージョンを選択するか
+        let options = options.resolution_strategy(ResolutionStrategy::Lowest);
+        assert_eq!(resolve_version(&server.url, "rack", &requirement, &options).await.unwrap(), "1.2.0");
+        let sources = [server.url.clone()];
+        assert_eq!(resolve_across_sources(&sources, "rack", &requirement, &options).await.unwrap().version, "1.2.0");
+
+        // インストールでも最も古いバージョンが使用されるか
+        let gemfile_data = GemfileData::parse_unresolved(&format!("source '{}'\ngem 'rack', '~> 1.2'\n", server.url)).unwrap();
+        let info = install_gems_with_options(gemfile_data, &directory.join("gems"), &directory.join("cache"), &options).await.unwrap();
+        assert_eq!(info.install_gems, vec!["rack-1.2.0".to_string()]);
+    }
+}
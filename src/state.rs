@@ -0,0 +1,301 @@
+//!
+//! 中断したインストールを再開するための状態を扱います
+//!
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use serde::{Deserialize, Serialize};
+use crate::events::InstallEvent;
+use crate::options::InstallOptions;
+use crate::parser::GemfileData;
+use crate::{install_gems_with_options, InstallInfo};
+
+///
+/// JSONに保存できるインストールの進行状況
+///
+/// Gemは名前で管理する
+///
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct InstallState {
+    /// インストールが完了したGemの一覧
+    pub completed: Vec<String>,
+    /// まだインストールしていないGemの一覧
+    pub pending: Vec<String>,
+    /// インストールに失敗したGemの一覧。再開時にもう一度インストールする
+    pub failed: Vec<String>,
+}
+
+impl InstallState {
+    ///
+    /// すべてのGemが未処理の状態を作成する
+    ///
+    /// * gemfile_data - Gemfileの読み込み済みデータ
+    ///
+    /// return - インストールの状態
+    ///
+    pub fn new(gemfile_data: &GemfileData) -> InstallState {
+        InstallState {
+            pending: gemfile_data.gems.iter().map(|gem| gem.name.clone()).collect(),
+            ..Default::default()
+        }
+    }
+
+    ///
+    /// JSONの文字列に変換する
+    ///
+    pub fn to_json(&self) -> Result<String, Box<dyn Error>> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    ///
+    /// JSONの文字列から読み込む
+    ///
+    /// * json - `to_json`で作成した文字列
+    ///
+    /// return - インストールの状態
+    ///
+    pub fn from_json(json: &str) -> Result<InstallState, Box<dyn Error>> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    ///
+    /// 完了しているGemかを確認する
+    ///
+    pub fn is_completed(&self, gem_name: &str) -> bool {
+        self.completed.iter().any(|completed| completed == gem_name)
+    }
+
+    ///
+    /// Gemのインストールが完了したことを記録する
+    ///
+    /// * gem_name - Gemの名前
+    ///
+    pub fn mark_completed(&mut self, gem_name: &str) {
+        self.pending.retain(|pending| pending != gem_name);
+        self.failed.retain(|failed| failed != gem_name);
+        if !self.is_completed(gem_name) {
+            self.completed.push(gem_name.to_string());
+        }
+    }
+
+    ///
+    /// Gemのインストールに失敗したことを記録する
+    ///
+    /// * gem_name - Gemの名前
+    ///
+    pub fn mark_failed(&mut self, gem_name: &str) {
+        self.pending.retain(|pending| pending != gem_name);
+        if !self.failed.iter().any(|failed| failed == gem_name) {
+            self.failed.push(gem_name.to_string());
+        }
+    }
+}
+
+///
+/// インストール中にGemごとに更新される状態
+///
+/// 処理が中断された場合も、それまでに完了したGemが記録される
+///
+pub type SharedInstallState = Arc<Mutex<InstallState>>;
+
+///
+/// イベントの`{name}-{version}`の形式のGemから、対象のGemの名前を探す
+///
+/// * label - イベントのGem
+/// * names - 今回インストールするGemの名前の一覧
+///
+/// return - Gemの名前
+///
+fn find_gem_name<'a>(label: &str, names: &'a [String]) -> Option<&'a String> {
+    names.iter()
+        .filter(|name| {
+            label.strip_prefix(name.as_str())
+                .and_then(|rest| rest.strip_prefix('-'))
+                .is_some_and(|version| version.is_empty() || version.starts_with(|c: char| c.is_ascii_digit()))
+        })
+        .max_by_key(|name| name.len())
+}
+
+///
+/// 状態を元にインストールを再開する
+///
+/// 完了しているGemを除いてインストールする
+///
+/// 状態はGemごとのイベントに合わせて更新するため、処理が中断された場合も完了したGemは再開時に除かれる
+///
+/// * state - インストールの状態
+/// * gemfile_data - Gemfileの読み込み済みデータ
+/// * install_dictionary - Gemのインストール先のディレクトリ
+/// * cache_directory - Gemのダウンロード先のキャッシュディレクトリ
+/// * options - インストール処理のオプション
+///
+/// return - 今回インストールした結果
+///
+pub async fn resume_install(state: &SharedInstallState, mut gemfile_data: GemfileData, install_dictionary: &Path, cache_directory: &Path, options: &InstallOptions) -> Result<InstallInfo, Box<dyn Error>> {
+    {
+        let state = state.lock().map_err(|error| error.to_string())?;
+        gemfile_data.gems.retain(|gem| !state.is_completed(&gem.name));
+    }
+    let attempted: Vec<String> = gemfile_data.gems.iter().map(|gem| gem.name.clone()).collect();
+
+    // Gemごとの完了・失敗を状態に記録してから、元のハンドラに通知する
+    let recorder = Arc::clone(state);
+    let names = attempted.clone();
+    let on_event = options.on_event.clone();
+    let options = InstallOptions {
+        on_event: Some(Arc::new(move |event: InstallEvent| {
+            if let Ok(mut state) = recorder.lock() {
+                match &event {
+                    InstallEvent::Installed { gem } => {
+                        if let Some(name) = find_gem_name(gem, &names) {
+                            state.mark_completed(name);
+                        }
+                    }
+                    InstallEvent::Failed { gem, .. } => {
+                        if let Some(name) = find_gem_name(gem, &names) {
+                            state.mark_failed(name);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            if let Some(on_event) = &on_event {
+                on_event(event);
+            }
+        })),
+        ..options.clone()
+    };
+
+    let info = install_gems_with_options(gemfile_data, install_dictionary, cache_directory, &options).await?;
+
+    // イベントを伴わないGem(ローカルのGemなど)を含めて今回の結果を反映する
+    let installed: BTreeSet<&String> = info.installed.iter().map(|installed| &installed.name).collect();
+    let mut state = state.lock().map_err(|error| error.to_string())?;
+    for name in attempted {
+        if installed.contains(&name) {
+            state.mark_completed(&name);
+        } else {
+            state.mark_failed(&name);
+        }
+    }
+    Ok(info)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::options::InstallOptions;
+    use crate::parser::GemfileData;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+    use crate::state::{resume_install, InstallState};
+    use crate::test_util::{test_directory, GemBuilder, MockResponse, MockServer};
+
+    ///
+    /// 保存した状態からインストールを再開するテスト
+    ///
+    #[tokio::test]
+    pub async fn resume_install_test() {
+        let directory = test_directory("resume_install");
+        let names = ["alpha", "beta", "gamma"];
+        let gems: Vec<Vec<u8>> = names.iter().map(|name| GemBuilder::new(name, "1.0.0").build()).collect();
+        let start_server = |available: Vec<&'static str>| {
+            let gems = gems.clone();
+            MockServer::start(move |request| {
+                names.iter().zip(gems.iter())
+                    .filter(|(name, _)| available.contains(name))
+                    .find(|(name, _)| request.path == format!("/downloads/{}-1.0.0.gem", name))
+                    .map(|(_, gem)| MockResponse::new(200, gem.clone()))
+                    .unwrap_or_else(MockResponse::not_found)
+            })
+        };
+        let gemfile = |url: &str| format!("source '{}'\ngem 'alpha', '1.0.0'\ngem 'beta', '1.0.0'\ngem 'gamma', '1.0.0'\n", url);
+        let options = InstallOptions { allow_insecure: true, verify_checksums: false, ..Default::default() };
+
+        // gammaの取得に失敗して中断する
+        let server = start_server(vec!["alpha", "beta"]).await;
+        let gemfile_data = GemfileData::parse_unresolved(&gemfile(&server.url)).unwrap();
+        let state = Arc::new(Mutex::new(InstallState::new(&gemfile_data)));
+        assert_eq!(state.lock().unwrap().pending.len(), 3);
+        resume_install(&state, gemfile_data, &directory.join("gems"), &directory.join("cache"), &options).await.unwrap();
+        let json = state.lock().unwrap().to_json().unwrap();
+
+        // 保存した状態から再開し、残りのGemのみを処理するか
+        let state = Arc::new(Mutex::new(InstallState::from_json(&json).unwrap()));
+        assert_eq!(state.lock().unwrap().completed.len(), 2);
+        assert_eq!(state.lock().unwrap().failed, vec!["gamma".to_string()]);
+        let server = start_server(names.to_vec()).await;
+        let gemfile_data = GemfileData::parse_unresolved(&gemfile(&server.url)).unwrap();
+        let info = resume_install(&state, gemfile_data, &directory.join("gems"), &directory.join("cache"), &options).await.unwrap();
+
+        assert_eq!(info.install_gems, vec!["gamma-1.0.0".to_string()]);
+        assert_eq!(server.requests().len(), 1);
+        assert_eq!(server.requests()[0].path, "/downloads/gamma-1.0.0.gem");
+        let state = state.lock().unwrap();
+        assert!(state.pending.is_empty());
+        assert!(state.failed.is_empty());
+        assert_eq!(state.completed.len(), 3);
+    }
+
+    ///
+    /// 途中で中断したインストールの状態から再開するテスト
+    ///
+    #[tokio::test]
+    pub async fn resume_interrupted_install_test() {
+        let directory = test_directory("resume_interrupted_install");
+        let names = ["alpha", "beta", "gamma"];
+        let gems: Vec<Vec<u8>> = names.iter().map(|name| GemBuilder::new(name, "1.0.0").build()).collect();
+        let start_server = |stall: bool| {
+            let gems = gems.clone();
+            MockServer::start(move |request| {
+                names.iter().zip(gems.iter())
+                    .find(|(name, _)| request.path == format!("/downloads/{}-1.0.0.gem", name))
+                    .map(|(name, gem)| {
+                        let response = MockResponse::new(200, gem.clone());
+                        // gammaは本体の途中で応答が止まる
+                        if stall && *name == "gamma" { response.stall_after(16) } else { response }
+                    })
+                    .unwrap_or_else(MockResponse::not_found)
+            })
+        };
+        let gemfile = |url: &str| format!("source '{}'\ngem 'alpha', '1.0.0'\ngem 'beta', '1.0.0'\ngem 'gamma', '1.0.0'\n", url);
+        let options = InstallOptions { allow_insecure: true, verify_checksums: false, ..Default::default() };
+
+        // alphaとbetaの完了後、gammaのダウンロード中に処理を中断する
+        let server = start_server(true).await;
+        let gemfile_data = GemfileData::parse_unresolved(&gemfile(&server.url)).unwrap();
+        let state = Arc::new(Mutex::new(InstallState::new(&gemfile_data)));
+        let (gems_directory, cache_directory) = (directory.join("gems"), directory.join("cache"));
+        let completed = async {
+            while state.lock().unwrap().completed.len() < 2 {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        };
+        tokio::select! {
+            _ = resume_install(&state, gemfile_data, &gems_directory, &cache_directory, &options) => panic!("install should be interrupted"),
+            result = tokio::time::timeout(Duration::from_secs(10), completed) => result.unwrap(),
+        }
+        let json = state.lock().unwrap().to_json().unwrap();
+
+        // 中断した時点までの進行状況が保存されているか
+        let state = Arc::new(Mutex::new(InstallState::from_json(&json).unwrap()));
+        {
+            let state = state.lock().unwrap();
+            let mut completed = state.completed.clone();
+            completed.sort();
+            assert_eq!(completed, vec!["alpha".to_string(), "beta".to_string()]);
+            assert_eq!(state.pending, vec!["gamma".to_string()]);
+            assert!(state.failed.is_empty());
+        }
+
+        // 再開すると残りのGemのみを処理するか
+        let server = start_server(false).await;
+        let gemfile_data = GemfileData::parse_unresolved(&gemfile(&server.url)).unwrap();
+        let info = resume_install(&state, gemfile_data, &directory.join("gems"), &directory.join("cache"), &options).await.unwrap();
+        assert_eq!(info.install_gems, vec!["gamma-1.0.0".to_string()]);
+        assert_eq!(server.requests().len(), 1);
+        let state = state.lock().unwrap();
+        assert!(state.pending.is_empty());
+        assert_eq!(state.completed.len(), 3);
+    }
+}
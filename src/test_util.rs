@@ -0,0 +1,402 @@
+//!
+//! テストで使用するユーティリティ
+//!
+#![allow(dead_code)]
+use std::fs::{create_dir_all, remove_dir_all, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time::Duration;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use tar::{Builder, Header};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::sleep;
+
+///
+/// テスト用の作業ディレクトリを空の状態で作成する
+///
+/// * name - ディレクトリ名
+///
+/// return - 作成したディレクトリのパス
+///
+pub(crate) fn test_directory(name: &str) -> PathBuf {
+    let directory = Path::new("./target/test_work").join(name);
+    if directory.exists() {
+        remove_dir_all(&directory).unwrap();
+    }
+    create_dir_all(&directory).unwrap();
+    directory
+}
+
+///
+/// tarアーカイブを作成する
+///
+/// * entries - エントリ名と内容の一覧
+///
+/// return - tarのバイト列
+///
+pub(crate) fn build_tar(entries: &[(String, Vec<u8>)]) -> Vec<u8> {
+    let mut builder = Builder::new(Vec::new());
+    for (name, content) in entries {
+        let mut header = Header::new_gnu();
+        header.set_size(content.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder.append_data(&mut header, name, content.as_slice()).unwrap();
+    }
+    builder.into_inner().unwrap()
+}
+
+///
+/// gzipで圧縮する
+///
+/// * data - 圧縮するデータ
+///
+/// return - 圧縮後のバイト列
+///
+pub(crate) fn gzip(data: &[u8]) -> Vec<u8> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data).unwrap();
+    encoder.finish().unwrap()
+}
+
+///
+/// テスト用の.gemファイルを組み立てる
+///
+pub(crate) struct GemBuilder {
+    name: String,
+    version: String,
+    payload_name: String,
+    metadata: String,
+    files: Vec<(String, Vec<u8>)>,
+    extra_entries: Vec<(String, Vec<u8>)>,
+}
+
+impl GemBuilder {
+    ///
+    /// 新しいGemBuilderを作成する
+    ///
+    /// * name - Gemの名前
+    /// * version - Gemのバージョン
+    ///
+    pub(crate) fn new(name: &str, version: &str) -> GemBuilder {
+        GemBuilder {
+            name: name.to_string(),
+            version: version.to_string(),
+            payload_name: "data.tar.gz".to_string(),
+            metadata: String::new(),
+            files: vec![(format!("lib/{}.rb", name), format!("module {}\nend\n", name).into_bytes())],
+            extra_entries: Vec::new(),
+        }
+    }
+
+    ///
+    /// 本体のアーカイブ名を変更する
+    ///
+    pub(crate) fn payload_name(mut self, payload_name: &str) -> GemBuilder {
+        self.payload_name = payload_name.to_string();
+        self
+    }
+
+    ///
+    /// メタデータのYAMLに内容を追加する
+    ///
+    pub(crate) fn metadata(mut self, yaml: &str) -> GemBuilder {
+        self.metadata.push_str(yaml);
+        self
+    }
+
+    ///
+    /// 本体のアーカイブにファイルを追加する
+    ///
+    pub(crate) fn file(mut self, name: &str, content: &[u8]) -> GemBuilder {
+        self.files.push((name.to_string(), content.to_vec()));
+        self
+    }
+
+    ///
+    /// .gemのtarに任意のエントリを追加する
+    ///
+    pub(crate) fn entry(mut self, name: &str, content: &[u8]) -> GemBuilder {
+        self.extra_entries.push((name.to_string(), content.to_vec()));
+        self
+    }
+
+    ///
+    /// .gemファイルのバイト列を作成する
+    ///
+    pub(crate) fn build(&self) -> Vec<u8> {
+        let metadata = format!(
+            "--- !ruby/object:Gem::Specification\nname: {}\nversion: !ruby/object:Gem::Version\n  version: {}\n{}",
+            self.name, self.version, self.metadata
+        );
+        let mut entries = vec![
+            ("metadata.gz".to_string(), gzip(metadata.as_bytes())),
+            (self.payload_name.clone(), gzip(&build_tar(&self.files))),
+            ("checksums.yaml.gz".to_string(), gzip(b"---\n")),
+        ];
+        entries.extend(self.extra_entries.iter().cloned());
+        build_tar(&entries)
+    }
+
+    ///
+    /// .gemファイルをディレクトリに書き出す
+    ///
+    /// * directory - 書き出し先のディレクトリ
+    ///
+    /// return - 書き出したファイルのパス
+    ///
+    pub(crate) fn write(&self, directory: &Path) -> PathBuf {
+        create_dir_all(directory).unwrap();
+        let path = directory.join(format!("{}-{}.gem", self.name, self.version));
+        File::create(&path).unwrap().write_all(&self.build()).unwrap();
+        path
+    }
+}
+
+///
+/// モックサーバーが受け取ったリクエスト
+///
+#[derive(Debug, Clone)]
+pub(crate) struct MockRequest {
+    /// HTTPメソッド
+    pub method: String,
+    /// クエリを含むパス
+    pub path: String,
+    /// ヘッダーの一覧(名前は小文字)
+    pub headers: Vec<(String, String)>,
+}
+
+impl MockRequest {
+    ///
+    /// ヘッダーの値を取得する
+    ///
+    pub(crate) fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+///
+/// モックサーバーが返すレスポンス
+///
+#[derive(Debug, Clone)]
+pub(crate) struct MockResponse {
+    /// ステータスコード
+    pub status: u16,
+    /// ヘッダーの一覧
+    pub headers: Vec<(String, String)>,
+    /// 本文
+    pub body: Vec<u8>,
+    /// レスポンスを返すまでの待ち時間
+    pub delay: Option<Duration>,
+    /// 本文をこのバイト数まで送信した後、応答を止める
+    pub stall_after: Option<usize>,
+    /// 本文をこのバイト数まで送信した後、接続を閉じる
+    pub truncate_after: Option<usize>,
+}
+
+impl MockResponse {
+    ///
+    /// ステータスコードと本文からレスポンスを作成する
+    ///
+    pub(crate) fn new(status: u16, body: impl Into<Vec<u8>>) -> MockResponse {
+        MockResponse {
+            status,
+            headers: Vec::new(),
+            body: body.into(),
+            delay: None,
+            stall_after: None,
+            truncate_after: None,
+        }
+    }
+
+    ///
+    /// 404のレスポンスを作成する
+    ///
+    pub(crate) fn not_found() -> MockResponse {
+        MockResponse::new(404, "Not Found")
+    }
+
+    ///
+    /// レスポンスを返すまでの待ち時間を設定する
+    ///
+    pub(crate) fn delay(mut self, delay: Duration) -> MockResponse {
+        self.delay = Some(delay);
+        self
+    }
+
+    ///
+    /// 本文の途中で応答を止めるように設定する
+    ///
+    pub(crate) fn stall_after(mut self, bytes: usize) -> MockResponse {
+        self.stall_after = Some(bytes);
+        self
+    }
+
+    ///
+    /// 本文の途中で接続を閉じるように設定する
+    ///
+    pub(crate) fn truncate_after(mut self, bytes: usize) -> MockResponse {
+        self.truncate_after = Some(bytes);
+        self
+    }
+
+    ///
+    /// ヘッダーを追加する
+    ///
+    pub(crate) fn header(mut self, name: &str, value: &str) -> MockResponse {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+}
+
+/// モックサーバーのリクエストを処理する関数
+type MockHandler = dyn Fn(&MockRequest) -> MockResponse + Send + Sync;
+
+///
+/// テスト用のHTTPサーバー
+///
+pub(crate) struct MockServer {
+    /// サーバーのURL
+    pub url: String,
+    /// 受け取ったリクエストの一覧
+    requests: Arc<StdMutex<Vec<MockRequest>>>,
+    /// 同時に処理したリクエスト数の最大値
+    max_in_flight: Arc<AtomicUsize>,
+}
+
+///
+/// 同時に処理しているリクエスト数
+///
+struct InFlight {
+    current: AtomicUsize,
+    max: Arc<AtomicUsize>,
+}
+
+impl MockServer {
+    ///
+    /// モックサーバーを起動する
+    ///
+    /// * handler - リクエストを処理する関数
+    ///
+    pub(crate) async fn start(handler: impl Fn(&MockRequest) -> MockResponse + Send + Sync + 'static) -> MockServer {
+        MockServer::start_at("127.0.0.1:0", handler).await
+    }
+
+    ///
+    /// アドレスを指定してモックサーバーを起動する
+    ///
+    /// * address - 待ち受けるアドレス(`127.0.0.1:0`の場合は空いているポート)
+    /// * handler - リクエストを処理する関数
+    ///
+    pub(crate) async fn start_at(address: &str, handler: impl Fn(&MockRequest) -> MockResponse + Send + Sync + 'static) -> MockServer {
+        let listener = TcpListener::bind(address).await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let requests: Arc<StdMutex<Vec<MockRequest>>> = Arc::new(StdMutex::new(Vec::new()));
+        let handler: Arc<MockHandler> = Arc::new(handler);
+        let max_in_flight = Arc::new(AtomicUsize::new(0));
+        let in_flight = Arc::new(InFlight { current: AtomicUsize::new(0), max: Arc::clone(&max_in_flight) });
+
+        let server_requests = Arc::clone(&requests);
+        tokio::spawn(async move {
+            loop {
+                let Ok((stream, _)) = listener.accept().await else {
+                    return;
+                };
+                let requests = Arc::clone(&server_requests);
+                let handler = Arc::clone(&handler);
+                let in_flight = Arc::clone(&in_flight);
+                tokio::spawn(async move {
+                    let _ = handle_connection(stream, requests, handler, in_flight).await;
+                });
+            }
+        });
+
+        MockServer { url, requests, max_in_flight }
+    }
+
+    ///
+    /// 同時に処理したリクエスト数の最大値を取得する
+    ///
+    pub(crate) fn max_in_flight(&self) -> usize {
+        self.max_in_flight.load(Ordering::SeqCst)
+    }
+
+    ///
+    /// 受け取ったリクエストの一覧を取得する
+    ///
+    pub(crate) fn requests(&self) -> Vec<MockRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    ///
+    /// 指定したパスへのリクエスト数を取得する
+    ///
+    pub(crate) fn request_count(&self, path: &str) -> usize {
+        self.requests().iter().filter(|request| request.path == path).count()
+    }
+}
+
+///
+/// 1つの接続を処理する
+///
+async fn handle_connection(mut stream: TcpStream, requests: Arc<StdMutex<Vec<MockRequest>>>, handler: Arc<MockHandler>, in_flight: Arc<InFlight>) -> std::io::Result<()> {
+    // ヘッダーの終わりまで読み込む
+    let mut buffer = Vec::new();
+    let mut chunk = [0u8; 1024];
+    while !buffer.windows(4).any(|window| window == b"\r\n\r\n") {
+        let read = stream.read(&mut chunk).await?;
+        if read == 0 {
+            return Ok(());
+        }
+        buffer.extend_from_slice(&chunk[..read]);
+    }
+
+    // リクエストを解析
+    let text = String::from_utf8_lossy(&buffer).to_string();
+    let mut lines = text.split("\r\n");
+    let request_line = lines.next().unwrap_or_default();
+    let mut parts = request_line.split(' ');
+    let method = parts.next().unwrap_or_default().to_string();
+    let path = parts.next().unwrap_or_default().to_string();
+    let headers = lines
+        .take_while(|line| !line.is_empty())
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_lowercase(), value.trim().to_string()))
+        .collect();
+    let request = MockRequest { method, path, headers };
+    requests.lock().unwrap().push(request.clone());
+
+    // レスポンスを返す
+    let current = in_flight.current.fetch_add(1, Ordering::SeqCst) + 1;
+    in_flight.max.fetch_max(current, Ordering::SeqCst);
+    let response = handler(&request);
+    if let Some(delay) = response.delay {
+        sleep(delay).await;
+    }
+    in_flight.current.fetch_sub(1, Ordering::SeqCst);
+    let mut head = format!("HTTP/1.1 {} Mock\r\nContent-Length: {}\r\nConnection: close\r\n", response.status, response.body.len());
+    for (name, value) in &response.headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str("\r\n");
+    stream.write_all(head.as_bytes()).await?;
+    if let Some(stall_after) = response.stall_after {
+        stream.write_all(&response.body[..stall_after.min(response.body.len())]).await?;
+        stream.flush().await?;
+        sleep(Duration::from_secs(60)).await;
+    }
+    if let Some(truncate_after) = response.truncate_after {
+        stream.write_all(&response.body[..truncate_after.min(response.body.len())]).await?;
+        return stream.shutdown().await;
+    }
+    if request.method != "HEAD" {
+        stream.write_all(&response.body).await?;
+    }
+    stream.shutdown().await
+}
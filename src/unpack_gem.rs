@@ -2,13 +2,38 @@
 //!  .gemのファイルを解凍します
 //!
 use std::error::Error;
-use std::fs::{create_dir_all, remove_dir_all, File};
+use std::fs::{read_dir, File};
+use std::io::Read;
 use std::path::{Path, PathBuf};
+use flate2::read::GzDecoder;
+use serde::{Deserialize, Serialize};
 use tar::Archive;
+use crate::cleanup::recreate_directory;
+use crate::error::GemfileError;
 
 /// .gemファイル内にある本体のデータ
 const GEM_DATA_FILE: &str = "data.tar.gz";
 
+/// 本体のデータを探す際のファイル名の接頭辞
+const GEM_DATA_PREFIX: &str = "data.tar";
+
+/// 署名ファイルの拡張子
+const SIGNATURE_EXTENSION: &str = "sig";
+
+/// .gemファイル内にあるメタデータ
+const GEM_METADATA_FILE: &str = "metadata.gz";
+
+///
+/// Gemの署名の情報
+///
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct GemSignature {
+    /// 署名ファイルが含まれているか
+    pub signed: bool,
+    /// メタデータに埋め込まれた署名者の証明書(PEM形式)
+    pub signer: Option<String>,
+}
+
 ///
 /// .gemファイルを解凍する
 ///
@@ -17,22 +42,329 @@ const GEM_DATA_FILE: &str = "data.tar.gz";
 ///
 /// return - 解凍処理の結果
 ///
-pub fn unpack_gem(path: &Path, directory: &Path) -> Result<PathBuf, Box<dyn Error>> {
+pub fn unpack_gem(path: &Path, directory: &Path) -> Result<PathBuf, GemfileError> {
+    unpack_gem_with_payload(path, directory, None)
+}
+
+///
+/// 本体のデータのファイル名を指定して.gemファイルを解凍する
+///
+/// * path - .gemファイルのパス
+/// * directory - 解凍先のディレクトリ
+/// * payload_name - 本体のデータのファイル名。Noneの場合は自動で探す
+///
+/// return - 解凍処理の結果
+///
+pub fn unpack_gem_with_payload(path: &Path, directory: &Path, payload_name: Option<&str>) -> Result<PathBuf, GemfileError> {
+    unpack(path, directory, payload_name).map_err(|error| GemfileError::unpack_gem(path, error))
+}
+
+///
+/// .gemファイルを解凍し、本体のデータのパスを返す
+///
+fn unpack(path: &Path, directory: &Path, payload_name: Option<&str>) -> Result<PathBuf, Box<dyn Error>> {
     // 解凍先ディレクトリの作成
-    if directory.exists() {
-        remove_dir_all(directory)?;
-    }
-    create_dir_all(directory)?;
+    recreate_directory(directory)?;
 
     // .gemファイルの解凍
     let gem_file = File::open(path)?;
     let mut archive = Archive::new(gem_file);
     archive.unpack(directory)?;
 
+    // 指定されたファイル名のパスを返す
+    if let Some(payload_name) = payload_name {
+        let data_path = directory.join(payload_name);
+        if !data_path.exists() {
+            return Err(format!("{} not found", payload_name).into());
+        }
+        return Ok(data_path);
+    }
+
     // data.tar.gzのパスを返す
+    find_payload(directory)
+}
+
+///
+/// 解凍したディレクトリから本体のデータを探す
+///
+/// * directory - .gemファイルを解凍したディレクトリ
+///
+/// return - 本体のデータのパス
+///
+fn find_payload(directory: &Path) -> Result<PathBuf, Box<dyn Error>> {
+    // 通常のファイル名を優先
     let data_path = directory.join(GEM_DATA_FILE);
-    if !data_path.exists() {
-        return Err("data.tar.gz not found".into());
+    if data_path.exists() {
+        return Ok(data_path);
+    }
+
+    // data.tarから始まるファイルを探す
+    let mut candidates: Vec<PathBuf> = read_dir(directory)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file())
+        .filter(|path| {
+            let Some(file_name) = path.file_name() else {
+                return false;
+            };
+            file_name.to_string_lossy().starts_with(GEM_DATA_PREFIX)
+                && path.extension().is_none_or(|extension| extension != SIGNATURE_EXTENSION)
+        })
+        .collect();
+    candidates.sort();
+
+    match candidates.into_iter().next() {
+        Some(path) => Ok(path),
+        None => Err("data.tar.gz not found".into()),
+    }
+}
+
+///
+/// 解凍した.gemファイルから署名の情報を取得する
+///
+/// 署名の検証は行わず、署名ファイルの有無と証明書のみを確認する
+///
+/// * directory - .gemファイルを解凍したディレクトリ
+///
+/// return - 署名の情報
+///
+pub fn read_signature(directory: &Path) -> Result<GemSignature, Box<dyn Error>> {
+    // .sigのファイルが含まれているかを確認
+    let signed = read_dir(directory)?
+        .filter_map(|entry| entry.ok())
+        .any(|entry| entry.path().extension().is_some_and(|extension| extension == SIGNATURE_EXTENSION));
+    if !signed {
+        return Ok(GemSignature::default());
+    }
+
+    // メタデータから証明書を取得
+    let metadata_path = directory.join(GEM_METADATA_FILE);
+    let signer = if metadata_path.exists() {
+        let mut metadata = String::new();
+        GzDecoder::new(File::open(metadata_path)?).read_to_string(&mut metadata)?;
+        find_certificate(&metadata)
+    } else {
+        None
+    };
+
+    Ok(GemSignature { signed, signer })
+}
+
+///
+/// メタデータのcert_chainから最初の証明書を取得する
+///
+/// * metadata - メタデータのYAML
+///
+/// return - PEM形式の証明書
+///
+fn find_certificate(metadata: &str) -> Option<String> {
+    let (_, cert_chain) = metadata.split_once("cert_chain:")?;
+    let mut certificate: Vec<&str> = Vec::new();
+    for line in cert_chain.lines().map(|line| line.trim()) {
+        if line.starts_with("-----BEGIN") {
+            certificate.clear();
+        }
+        certificate.push(line);
+        if line.starts_with("-----END") {
+            return Some(certificate.join("\n"));
+        }
+    }
+    None
+}
+
+///
+/// .gemファイルからメタデータ(gemspec)のみを取り出す
+///
+/// 本体のデータは解凍せず、metadata.gzのみを読み込む
+///
+/// * gem_path - .gemファイルのパス
+///
+/// return - メタデータのYAMLの文字列
+///
+pub fn extract_gemspec(gem_path: &Path) -> Result<String, Box<dyn Error>> {
+    read_gemspec(File::open(gem_path)?)
+}
+
+///
+/// メモリ上の.gemファイルからメタデータ(gemspec)のみを取り出す
+///
+/// * gem - .gemファイルの内容
+///
+/// return - メタデータのYAMLの文字列
+///
+pub fn extract_gemspec_from_bytes(gem: &[u8]) -> Result<String, Box<dyn Error>> {
+    read_gemspec(gem)
+}
+
+///
+/// .gemファイルのtarを読み進めてmetadata.gzを展開する
+///
+fn read_gemspec<R: Read>(reader: R) -> Result<String, Box<dyn Error>> {
+    let mut archive = Archive::new(reader);
+
+    for entry in archive.entries()? {
+        let entry = entry?;
+        if entry.path()?.as_os_str() != GEM_METADATA_FILE {
+            continue;
+        }
+
+        // metadata.gzを展開して返す
+        let mut metadata = String::new();
+        GzDecoder::new(entry).read_to_string(&mut metadata)?;
+        return Ok(metadata);
+    }
+
+    Err("metadata.gz not found".into())
+}
+
+///
+/// .gemファイルの本体から1つのファイルの内容を取り出す
+///
+/// ディスクには展開せず、data.tar.gzをメモリ上で読み進めて対象のファイルのみを読み込む
+///
+/// * gem_path - .gemファイルのパス
+/// * inner_path - 本体のアーカイブ内のパス(例: `README.md`)
+///
+/// return - ファイルの内容
+///
+pub fn read_file_from_gem(gem_path: &Path, inner_path: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+    let inner_path = inner_path.trim_start_matches("./");
+    let gem_file = File::open(gem_path)?;
+    let mut archive = Archive::new(gem_file);
+
+    for entry in archive.entries()? {
+        let entry = entry?;
+        if entry.path()?.as_os_str() != GEM_DATA_FILE {
+            continue;
+        }
+
+        // data.tar.gzを展開しながら対象のファイルを探す
+        let mut data_archive = Archive::new(GzDecoder::new(entry));
+        for data_entry in data_archive.entries()? {
+            let mut data_entry = data_entry?;
+            if data_entry.path()?.to_string_lossy().trim_start_matches("./") != inner_path {
+                continue;
+            }
+            let mut content = Vec::new();
+            data_entry.read_to_end(&mut content)?;
+            return Ok(content);
+        }
+        return Err(format!("{} not found in {}", inner_path, gem_path.display()).into());
+    }
+
+    Err(format!("{} not found in {}", GEM_DATA_FILE, gem_path.display()).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::{test_directory, GemBuilder};
+    use crate::unpack_gem::{extract_gemspec, read_file_from_gem, read_signature, unpack_gem, unpack_gem_with_payload};
+    use crate::unpack_tar_gz::unpack_tar_gz;
+
+    ///
+    /// 本体のファイル名を指定した解凍のテスト
+    ///
+    #[test]
+    pub fn unpack_custom_payload_test() {
+        let directory = test_directory("unpack_custom_payload");
+        let gem_path = GemBuilder::new("custom", "1.0.0")
+            .payload_name("payload.tgz")
+            .write(&directory);
+
+        // 指定したファイル名が使用されているか
+        let payload = unpack_gem_with_payload(&gem_path, &directory.join("unpacked"), Some("payload.tgz")).unwrap();
+        assert_eq!(payload.file_name().unwrap(), "payload.tgz");
+
+        // 本体が解凍できるか
+        let install_directory = directory.join("install");
+        unpack_tar_gz(&payload, &directory.join("cache"), &install_directory).unwrap();
+        assert!(install_directory.join("lib/custom.rb").exists());
+
+        // 存在しないファイル名はエラーになるか
+        let result = unpack_gem_with_payload(&gem_path, &directory.join("unpacked"), Some("missing.tar.gz"));
+        assert!(result.is_err());
+    }
+
+    ///
+    /// 本体のファイル名を自動で探すテスト
+    ///
+    #[test]
+    pub fn unpack_detect_payload_test() {
+        let directory = test_directory("unpack_detect_payload");
+        let gem_path = GemBuilder::new("detect", "1.0.0")
+            .payload_name("data.tar.xz")
+            .entry("data.tar.xz.sig", b"signature")
+            .write(&directory);
+
+        let payload = unpack_gem(&gem_path, &directory.join("unpacked")).unwrap();
+        assert_eq!(payload.file_name().unwrap(), "data.tar.xz");
+    }
+
+    ///
+    /// 署名の検出のテスト
+    ///
+    #[test]
+    pub fn read_signature_test() {
+        let directory = test_directory("read_signature");
+
+        // 署名されていないGem
+        let gem_path = GemBuilder::new("unsigned", "1.0.0").write(&directory);
+        let unpacked = directory.join("unsigned");
+        unpack_gem(&gem_path, &unpacked).unwrap();
+        let signature = read_signature(&unpacked).unwrap();
+        assert!(!signature.signed);
+        assert!(signature.signer.is_none());
+
+        // 署名されたGem
+        let gem_path = GemBuilder::new("signed", "1.0.0")
+            .metadata("cert_chain:\n- |\n  -----BEGIN CERTIFICATE-----\n  TUlJ\n  -----END CERTIFICATE-----\n")
+            .entry("metadata.gz.sig", b"signature")
+            .entry("data.tar.gz.sig", b"signature")
+            .write(&directory);
+        let unpacked = directory.join("signed");
+        unpack_gem(&gem_path, &unpacked).unwrap();
+        let signature = read_signature(&unpacked).unwrap();
+        assert!(signature.signed);
+        assert_eq!(signature.signer.as_deref(), Some("-----BEGIN CERTIFICATE-----\nTUlJ\n-----END CERTIFICATE-----"));
+    }
+
+    ///
+    /// メタデータのみを取り出すテスト
+    ///
+    #[test]
+    pub fn extract_gemspec_test() {
+        let directory = test_directory("extract_gemspec");
+        let gem_path = GemBuilder::new("spec", "2.1.0")
+            .metadata("dependencies:\n- !ruby/object:Gem::Dependency\n  name: rake\n")
+            .write(&directory);
+
+        let gemspec = extract_gemspec(&gem_path).unwrap();
+        assert!(gemspec.starts_with("--- !ruby/object:Gem::Specification"));
+        assert!(gemspec.contains("name: spec"));
+        assert!(gemspec.contains("version: 2.1.0"));
+        assert!(gemspec.contains("name: rake"));
+
+        // 展開したファイルが作成されていないか
+        assert_eq!(std::fs::read_dir(&directory).unwrap().count(), 1);
+    }
+
+    ///
+    /// 本体から1つのファイルを取り出すテスト
+    ///
+    #[test]
+    pub fn read_file_from_gem_test() {
+        let directory = test_directory("read_file_from_gem");
+        let gem_path = GemBuilder::new("docs", "1.0.0")
+            .file("README.md", b"# docs\n")
+            .file("LICENSE", b"MIT License\n")
+            .write(&directory);
+
+        assert_eq!(read_file_from_gem(&gem_path, "LICENSE").unwrap(), b"MIT License\n");
+        assert_eq!(read_file_from_gem(&gem_path, "./README.md").unwrap(), b"# docs\n");
+        assert_eq!(read_file_from_gem(&gem_path, "lib/docs.rb").unwrap(), b"module docs\nend\n");
+        assert!(read_file_from_gem(&gem_path, "missing.txt").is_err());
+
+        // 展開したファイルが作成されていないか
+        assert_eq!(std::fs::read_dir(&directory).unwrap().count(), 1);
     }
-    Ok(data_path)
 }
@@ -2,11 +2,25 @@
 //! .tar.gzファイルを解凍します
 //!
 use std::error::Error;
-use std::fs::{create_dir_all, remove_dir_all, File};
-use std::io::copy;
-use std::path::{Path, PathBuf};
-use flate2::read::MultiGzDecoder;
+use std::fs::{canonicalize, create_dir_all, read_to_string, File};
+use std::io::{Read, Write};
+use std::path::{Component, Path, PathBuf};
+use flate2::bufread::MultiGzDecoder;
 use tar::Archive;
+use crate::buffer_pool::{SliceReader, DEFAULT_BUFFER_SIZE};
+use crate::download::file_sha256;
+use crate::cleanup::recreate_directory;
+use crate::error::GemfileError;
+use crate::options::InstallOptions;
+
+/// Windowsで長いパスを扱うための接頭辞
+const LONG_PATH_PREFIX: &str = r"\\?\";
+
+/// 本体に含まれるファイルごとのSHA256のマニフェスト(`sha256sum`の形式)
+///
+/// 照合するファイルと同じ本体から読み込むため、転送や保存時の偶発的な破損のみを検出する。
+/// 本体を書き換えられる攻撃者はマニフェストも書き換えられるため、改ざんの検出には`verify_file_digests_with_manifest`で外部のマニフェストと照合する
+pub const DIGEST_MANIFEST: &str = "SHA256SUMS";
 
 ///
 /// .tar.gzファイルを解凍する
@@ -17,11 +31,112 @@ use tar::Archive;
 ///
 /// return - 解凍処理の結果で、Gemfileが含まれている場合パスを返す
 ///
-pub fn unpack_tar_gz(tar_gz_path: &Path, cache_directory: &Path, directory: &Path) -> Result<Option<PathBuf>, Box<dyn Error>> {
-    // .gzファイルを解凍
-    let tar_file_path = unpack_gz(tar_gz_path, cache_directory)?;
+pub fn unpack_tar_gz(tar_gz_path: &Path, cache_directory: &Path, directory: &Path) -> Result<Option<PathBuf>, GemfileError> {
+    unpack_tar_gz_with_options(tar_gz_path, cache_directory, directory, &InstallOptions::default())
+}
+
+///
+/// オプションを指定して.tar.gzファイルを解凍する
+///
+/// * tar_gz_path - .tar.gzファイルのパス
+/// * cache_directory - 一時的に回答した.tarを置くキャッシュディレクトリ
+/// * directory - 解凍先のディレクトリ
+/// * options - インストール処理のオプション(`buffer_pool`、`max_entries`、`max_path_length`、`long_path_prefix`、`temp_dir`、`verify_file_digests`、`zip_output`を使用する)
+///
+/// return - 解凍処理の結果で、Gemfileが含まれている場合パスを返す(`zip_output`の場合は返さない)
+///
+pub fn unpack_tar_gz_with_options(tar_gz_path: &Path, cache_directory: &Path, directory: &Path, options: &InstallOptions) -> Result<Option<PathBuf>, GemfileError> {
+    // プールが指定されている場合は、解凍が終わるまでプールから借りたバッファを使用する
+    let mut pooled = options.buffer_pool.as_ref().map(|buffer_pool| buffer_pool.acquire());
+    let mut owned = Vec::new();
+    let buffer: &mut [u8] = match &mut pooled {
+        Some(pooled) => pooled,
+        None => {
+            owned.resize(DEFAULT_BUFFER_SIZE, 0);
+            &mut owned
+        }
+    };
+
+    // zipファイルに書き込む場合は`directory`をzipファイルのパスとして扱う
+    #[cfg(feature = "zip")]
+    if options.zip_output {
+        crate::zip_sink::write_tar_gz_zip(tar_gz_path, directory, options)
+            .map_err(|error| GemfileError::unpack_tar_gz(tar_gz_path, error))?;
+        return Ok(None);
+    }
+
+    // .gzファイルを解凍(一時ディレクトリが指定されている場合は、Gemごとのディレクトリに置く)
+    let tar_directory = match (&options.temp_dir, cache_directory.file_name()) {
+        (Some(temp_dir), Some(name)) => temp_dir.join(name),
+        (Some(temp_dir), None) => temp_dir.clone(),
+        (None, _) => cache_directory.to_path_buf(),
+    };
+    let tar_file_path = unpack_gz(tar_gz_path, &tar_directory, buffer)
+        .map_err(|error| GemfileError::unpack_tar_gz(tar_gz_path, error))?;
     // .tarファイルを解凍
-    unpack_tar(&tar_file_path, directory)
+    let gemfile = unpack_tar(&tar_file_path, directory, options, buffer)
+        .map_err(|error| GemfileError::unpack_tar_gz(tar_gz_path, error))?;
+
+    // マニフェストのSHA256と照合
+    if options.verify_file_digests {
+        let files = verify_file_digests(directory)
+            .map_err(|error| GemfileError::unpack_tar_gz(tar_gz_path, error))?;
+        if !files.is_empty() {
+            return Err(GemfileError::FileDigestMismatch { files });
+        }
+    }
+    Ok(gemfile)
+}
+
+///
+/// 展開したファイルを、本体に含まれるマニフェストに記録されたSHA256と照合する
+///
+/// マニフェストは`{SHA256}  {パス}`の行からなり、存在しない場合は何も確認しない。
+/// マニフェストも同じ本体に含まれるため、偶発的な破損のみを検出し、改ざんに対する保証にはならない
+///
+/// * directory - 展開先のディレクトリ
+///
+/// return - 一致しなかった、または存在しなかったファイルのパスの一覧
+///
+pub fn verify_file_digests(directory: &Path) -> Result<Vec<String>, Box<dyn Error>> {
+    let manifest_path = directory.join(DIGEST_MANIFEST);
+    if !manifest_path.is_file() {
+        return Ok(Vec::new());
+    }
+    verify_file_digests_with_manifest(directory, &read_to_string(manifest_path)?)
+}
+
+///
+/// 展開したファイルを、外部から取得したマニフェストに記録されたSHA256と照合する
+///
+/// 信頼できる経路で取得したマニフェストを使用すると、本体の改ざんも検出できる
+///
+/// * directory - 展開先のディレクトリ
+/// * manifest - `{SHA256}  {パス}`の行からなるマニフェストの内容
+///
+/// return - 一致しなかった、または存在しなかったファイルのパスの一覧
+///
+pub fn verify_file_digests_with_manifest(directory: &Path, manifest: &str) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut mismatched = Vec::new();
+    for line in manifest.lines() {
+        let Some((digest, path)) = line.trim().split_once(char::is_whitespace) else {
+            continue;
+        };
+        // バイナリモードの`*`を除く
+        let path = path.trim_start().trim_start_matches('*');
+        // 展開先の外を指すパスは照合しない
+        let relative = Path::new(path);
+        if relative.components().any(|component| !matches!(component, Component::Normal(_) | Component::CurDir)) {
+            mismatched.push(path.to_string());
+            continue;
+        }
+
+        let file_path = directory.join(relative);
+        if !file_path.is_file() || !file_sha256(&file_path)?.eq_ignore_ascii_case(digest) {
+            mismatched.push(path.to_string());
+        }
+    }
+    Ok(mismatched)
 }
 
 
@@ -30,17 +145,19 @@ pub fn unpack_tar_gz(tar_gz_path: &Path, cache_directory: &Path, directory: &Pat
 ///
 /// * gz_path - .gzファイルのパス
 /// * directory - 解凍先のディレクトリ
+/// * buffer - 読み込みと書き込みに分けて使用するバッファ
 ///
 /// return - 解凍後のファイルのパス
 ///
-fn unpack_gz(gz_path: &Path, directory: &Path) -> Result<PathBuf, Box<dyn Error>> {
+fn unpack_gz(gz_path: &Path, directory: &Path, buffer: &mut [u8]) -> Result<PathBuf, Box<dyn Error>> {
     if !directory.exists() {
         create_dir_all(directory)?;
     }
 
-    // gzipファイルを読み込み
+    // gzipファイルを読み込み(展開器の入力にもバッファの前半を使用する)
+    let (input, output) = buffer.split_at_mut(buffer.len().div_ceil(2));
     let gzip_file = File::open(gz_path)?;
-    let mut decoder = MultiGzDecoder::new(&gzip_file);
+    let mut decoder = MultiGzDecoder::new(SliceReader::new(gzip_file, input));
 
     // 出力ファイルを作成
     let gz_file_stem = gz_path.file_stem();
@@ -51,7 +168,13 @@ fn unpack_gz(gz_path: &Path, directory: &Path) -> Result<PathBuf, Box<dyn Error>
     let mut output_file = File::create(&output_file_path)?;
 
     // 書き込み
-    copy(&mut decoder, &mut output_file)?;
+    loop {
+        let length = decoder.read(output)?;
+        if length == 0 {
+            break;
+        }
+        output_file.write_all(&output[..length])?;
+    }
 
     Ok(output_file_path)
 }
@@ -61,27 +184,37 @@ fn unpack_gz(gz_path: &Path, directory: &Path) -> Result<PathBuf, Box<dyn Error>
 ///
 /// * tar_path - .tarファイルのパス
 /// * directory - 解凍先のディレクトリ
+/// * options - インストール処理のオプション
+/// * buffer - 読み込みに使用するバッファ
 ///
 /// return - Gemfileが含まれている場合パスを返す
 ///
-fn unpack_tar(tar_path: &Path, directory: &Path) -> Result<Option<PathBuf>, Box<dyn Error>> {
-    if directory.exists() {
-        remove_dir_all(directory)?;
-    }
-    create_dir_all(directory)?;
+fn unpack_tar(tar_path: &Path, directory: &Path, options: &InstallOptions, buffer: &mut [u8]) -> Result<Option<PathBuf>, Box<dyn Error>> {
+    recreate_directory(directory)?;
 
     // tar内にあるGemfileのパス
     let mut entry_gemfile: Option<PathBuf> = None;
 
+    // エントリが展開先の外に書き込まないよう、正規化したパスと比較する
+    let root = canonicalize(directory)?;
+
     // tarファイルを読み込み、解答
     let tar_file = File::open(tar_path)?;
-    let mut archive = Archive::new(tar_file);
+    let mut archive = Archive::new(SliceReader::new(tar_file, buffer));
     let entries = archive.entries()?;
 
-    for file in entries {
+    for (index, file) in entries.enumerate() {
+        // 大量の小さなファイルでiノードを使い切らないよう、エントリの数を制限する
+        if let Some(limit) = options.max_entries {
+            if index >= limit {
+                return Err(Box::new(GemfileError::EntryLimitExceeded { limit }));
+            }
+        }
         let mut file = file?;
 
-        let file_path = directory.join(file.path()?);
+        let entry = file.path()?.to_path_buf();
+        check_traversal(&root, directory, &entry)?;
+        let file_path = check_path_length(&directory.join(&entry), &entry, options)?;
         if let Some(parent) = file_path.parent() {
             if !parent.exists() {
                 create_dir_all(parent)?;
@@ -92,11 +225,246 @@ fn unpack_tar(tar_path: &Path, directory: &Path) -> Result<Option<PathBuf>, Box<
 
         // Gemfileの場合パスを保管
         if let Some(file_name) = file_path.file_name() {
-            if file_name == "Gemfile" {
-                entry_gemfile = Some(file_path);
+            if options.gemfile_names.iter().any(|name| file_name == name.as_str()) {
+                entry_gemfile = Some(directory.join(entry));
             }
         }
     }
 
     Ok(entry_gemfile)
-}
\ No newline at end of file
+}
+
+///
+/// エントリのパスが展開先のディレクトリの外を指していないかを確認する
+///
+/// `..`や絶対パスを含むエントリと、既に展開されたシンボリックリンクを経由して外に出るエントリをエラーにする
+///
+/// * root - 正規化した展開先のディレクトリ
+/// * directory - 展開先のディレクトリ
+/// * entry - tar内のエントリのパス
+///
+/// return - 確認の結果
+///
+fn check_traversal(root: &Path, directory: &Path, entry: &Path) -> Result<(), Box<dyn Error>> {
+    check_entry_path(entry)?;
+
+    // 存在する最も深い親ディレクトリを正規化して比較する
+    let mut existing = directory.join(entry);
+    while !existing.exists() {
+        if !existing.pop() {
+            return Ok(());
+        }
+    }
+    if !canonicalize(&existing)?.starts_with(root) {
+        return Err(Box::new(GemfileError::PathTraversal { entry: entry.display().to_string() }));
+    }
+    Ok(())
+}
+
+///
+/// エントリのパスが`..`や絶対パスを含まないかを確認する
+///
+/// * entry - tar内のエントリのパス
+///
+/// return - 含む場合は`PathTraversal`のエラー
+///
+pub(crate) fn check_entry_path(entry: &Path) -> Result<(), GemfileError> {
+    if entry.components().any(|component| !matches!(component, Component::Normal(_) | Component::CurDir)) {
+        return Err(GemfileError::PathTraversal { entry: entry.display().to_string() });
+    }
+    Ok(())
+}
+
+///
+/// 展開先のパスの長さが上限を超えていないかを確認する
+///
+/// 上限を超えた場合、Windowsで`long_path_prefix`が有効なときは`\\?\`を付けたパスを返し、それ以外はエラーにする
+///
+/// * file_path - 展開先のパス
+/// * entry - tar内のエントリのパス
+/// * options - インストール処理のオプション
+///
+/// return - 展開に使用するパス
+///
+fn check_path_length(file_path: &Path, entry: &Path, options: &InstallOptions) -> Result<PathBuf, Box<dyn Error>> {
+    let Some(limit) = options.max_path_length else {
+        return Ok(file_path.to_path_buf());
+    };
+    let absolute = std::path::absolute(file_path)?;
+    let length = absolute.as_os_str().len();
+    if length <= limit {
+        return Ok(file_path.to_path_buf());
+    }
+
+    if cfg!(windows) && options.long_path_prefix {
+        let mut prefixed = std::ffi::OsString::from(LONG_PATH_PREFIX);
+        prefixed.push(absolute.as_os_str());
+        return Ok(PathBuf::from(prefixed));
+    }
+    Err(Box::new(GemfileError::PathTooLong {
+        entry: entry.display().to_string(),
+        length,
+        limit,
+    }))
+}
+#[cfg(test)]
+mod tests {
+    use crate::error::GemfileError;
+    use crate::options::{InstallOptions, WINDOWS_MAX_PATH};
+    use crate::test_util::{build_tar, gzip, test_directory};
+    use crate::unpack_tar_gz::{unpack_tar_gz_with_options, verify_file_digests_with_manifest, DIGEST_MANIFEST};
+
+    ///
+    /// 展開先のパスの長さの上限のテスト
+    ///
+    #[test]
+    pub fn path_too_long_test() {
+        let directory = test_directory("path_too_long");
+        let long_entry = format!("{}/file.rb", vec!["nested_directory"; 20].join("/"));
+        let tar_gz_path = directory.join("data.tar.gz");
+        std::fs::write(&tar_gz_path, gzip(&build_tar(&[
+            ("lib/short.rb".to_string(), Vec::new()),
+            (long_entry.clone(), Vec::new()),
+        ]))).unwrap();
+
+        // 上限を超えるエントリを示すエラーになるか
+        let options = InstallOptions { max_path_length: Some(WINDOWS_MAX_PATH), ..Default::default() };
+        let error = unpack_tar_gz_with_options(&tar_gz_path, &directory.join("cache"), &directory.join("limited"), &options).unwrap_err();
+        let GemfileError::PathTooLong { entry, length, limit } = error else {
+            panic!("unexpected error: {}", error);
+        };
+        assert_eq!(entry, long_entry);
+        assert!(length > WINDOWS_MAX_PATH);
+        assert_eq!(limit, WINDOWS_MAX_PATH);
+
+        // 上限を指定しない場合は展開できるか
+        let options = InstallOptions::default();
+        assert!(unpack_tar_gz_with_options(&tar_gz_path, &directory.join("cache"), &directory.join("unlimited"), &options).is_ok());
+        assert!(directory.join("unlimited").join(&long_entry).exists());
+    }
+
+    ///
+    /// 解凍途中の.tarを一時ディレクトリに置くテスト
+    ///
+    #[test]
+    pub fn temp_dir_test() {
+        let directory = test_directory("unpack_temp_dir");
+        let tar_gz_path = directory.join("data.tar.gz");
+        std::fs::write(&tar_gz_path, gzip(&build_tar(&[("lib/fast.rb".to_string(), Vec::new())]))).unwrap();
+        let cache_directory = directory.join("cache/fast-1.0.0");
+        let options = InstallOptions { temp_dir: Some(directory.join("tmp")), ..Default::default() };
+
+        unpack_tar_gz_with_options(&tar_gz_path, &cache_directory, &directory.join("gems"), &options).unwrap();
+
+        // .tarは一時ディレクトリに書き込まれ、展開先は変わらないか
+        assert!(directory.join("tmp/fast-1.0.0/data.tar").exists());
+        assert!(!cache_directory.join("data.tar").exists());
+        assert!(directory.join("gems/lib/fast.rb").exists());
+    }
+
+    ///
+    /// マニフェストのSHA256と照合するテスト
+    ///
+    #[test]
+    pub fn verify_file_digests_test() {
+        let directory = test_directory("verify_file_digests");
+        let sha256 = |content: &[u8]| ring::digest::digest(&ring::digest::SHA256, content).as_ref().iter().map(|byte| format!("{:02x}", byte)).collect::<String>();
+        let manifest = format!("{}  lib/intact.rb\n{}  lib/tampered.rb\n", sha256(b"intact"), sha256(b"original"));
+        let tar_gz_path = directory.join("data.tar.gz");
+        std::fs::write(&tar_gz_path, gzip(&build_tar(&[
+            ("lib/intact.rb".to_string(), b"intact".to_vec()),
+            ("lib/tampered.rb".to_string(), b"tampered".to_vec()),
+            (DIGEST_MANIFEST.to_string(), manifest.into_bytes()),
+        ]))).unwrap();
+
+        // 改ざんされたファイルのみが報告されるか
+        let options = InstallOptions { verify_file_digests: true, ..Default::default() };
+        let error = unpack_tar_gz_with_options(&tar_gz_path, &directory.join("cache"), &directory.join("verified"), &options).unwrap_err();
+        let GemfileError::FileDigestMismatch { files } = error else {
+            panic!("unexpected error: {}", error);
+        };
+        assert_eq!(files, vec!["lib/tampered.rb".to_string()]);
+
+        // 無効の場合は照合しないか
+        let options = InstallOptions::default();
+        assert!(unpack_tar_gz_with_options(&tar_gz_path, &directory.join("cache"), &directory.join("unverified"), &options).is_ok());
+
+        // 本体のマニフェストと一緒に書き換えられた場合も、外部のマニフェストでは検出できるか
+        let external = format!("{}  lib/intact.rb\n{}  lib/tampered.rb\n", sha256(b"intact"), sha256(b"original"));
+        let files = verify_file_digests_with_manifest(&directory.join("unverified"), &external).unwrap();
+        assert_eq!(files, vec!["lib/tampered.rb".to_string()]);
+    }
+
+    ///
+    /// 展開先の外を指すエントリを拒否するテスト
+    ///
+    #[test]
+    pub fn path_traversal_test() {
+        let directory = test_directory("path_traversal");
+        // tarクレートのBuilderは`..`を含むパスを拒否するため、ヘッダーに直接書き込む
+        let mut builder = tar::Builder::new(Vec::new());
+        let content = b"escaped";
+        let mut header = tar::Header::new_gnu();
+        let name = b"../../escaped.rb";
+        header.as_gnu_mut().unwrap().name[..name.len()].copy_from_slice(name);
+        header.set_size(content.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder.append(&header, content.as_slice()).unwrap();
+        let tar_gz_path = directory.join("data.tar.gz");
+        std::fs::write(&tar_gz_path, gzip(&builder.into_inner().unwrap())).unwrap();
+
+        let gems_directory = directory.join("gems/evil-1.0.0");
+        let error = unpack_tar_gz_with_options(&tar_gz_path, &directory.join("cache"), &gems_directory, &InstallOptions::default()).unwrap_err();
+        let GemfileError::PathTraversal { entry } = error else {
+            panic!("unexpected error: {}", error);
+        };
+        assert_eq!(entry, "../../escaped.rb");
+        assert!(!directory.join("escaped.rb").exists());
+    }
+
+    ///
+    /// gems.rbをGemfileとして扱うかを設定するテスト
+    ///
+    #[test]
+    pub fn gemfile_names_test() {
+        let directory = test_directory("unpack_gemfile_names");
+        let tar_gz_path = directory.join("data.tar.gz");
+        std::fs::write(&tar_gz_path, gzip(&build_tar(&[("gems.rb".to_string(), b"gem 'rake'\n".to_vec())]))).unwrap();
+
+        // デフォルトではgems.rbも見つかるか
+        let options = InstallOptions::default();
+        let gemfile = unpack_tar_gz_with_options(&tar_gz_path, &directory.join("cache"), &directory.join("default"), &options).unwrap();
+        assert_eq!(gemfile, Some(directory.join("default/gems.rb")));
+
+        // Gemfileのみを指定した場合は見つからないか
+        let options = InstallOptions { gemfile_names: vec!["Gemfile".to_string()], ..Default::default() };
+        let gemfile = unpack_tar_gz_with_options(&tar_gz_path, &directory.join("cache"), &directory.join("gemfile_only"), &options).unwrap();
+        assert_eq!(gemfile, None);
+    }
+
+    ///
+    /// エントリの数の上限を超えた場合に展開を中止するテスト
+    ///
+    #[test]
+    pub fn max_entries_test() {
+        let directory = test_directory("unpack_max_entries");
+        let entries: Vec<(String, Vec<u8>)> = (0..50).map(|index| (format!("lib/file{}.rb", index), Vec::new())).collect();
+        let tar_gz_path = directory.join("data.tar.gz");
+        std::fs::write(&tar_gz_path, gzip(&build_tar(&entries))).unwrap();
+
+        // 上限を超えた時点でエラーになるか
+        let options = InstallOptions { max_entries: Some(10), ..Default::default() };
+        let error = unpack_tar_gz_with_options(&tar_gz_path, &directory.join("cache"), &directory.join("limited"), &options).unwrap_err();
+        let GemfileError::EntryLimitExceeded { limit } = error else {
+            panic!("unexpected error: {}", error);
+        };
+        assert_eq!(limit, 10);
+        assert!(!directory.join("limited/lib/file10.rb").exists());
+
+        // デフォルトの上限では展開できるか
+        let options = InstallOptions::default();
+        unpack_tar_gz_with_options(&tar_gz_path, &directory.join("cache"), &directory.join("default"), &options).unwrap();
+        assert!(directory.join("default/lib/file49.rb").exists());
+    }
+}
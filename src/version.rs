@@ -0,0 +1,372 @@
+//!
+//! Gemのバージョンの比較を行います
+//!
+use std::cmp::Ordering;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
+///
+/// バージョンを構成する要素
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    /// 数値の要素
+    Number(u64),
+    /// 文字列の要素(プレリリースを表す)
+    Text(String),
+}
+
+///
+/// RubyGemsの規則で比較できるバージョン
+///
+#[derive(Debug, Clone)]
+pub struct Version {
+    /// 元の文字列
+    original: String,
+    /// 比較に使用する要素
+    segments: Vec<Segment>,
+}
+
+impl Version {
+    ///
+    /// 文字列からバージョンを作成する
+    ///
+    /// * version - バージョンの文字列
+    ///
+    /// return - 成功するとバージョンを返す
+    ///
+    pub fn parse(version: &str) -> Result<Version, Box<dyn Error>> {
+        let trimmed = version.trim();
+        if trimmed.is_empty() || !trimmed.chars().all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-') {
+            return Err(format!("Invalid version {}", version).into());
+        }
+
+        // 数字と文字の境界で要素を分割
+        let mut segments = Vec::new();
+        for part in trimmed.replace('-', ".pre.").split('.') {
+            let mut current = String::new();
+            for c in part.chars() {
+                if !current.is_empty() && current.chars().all(|p| p.is_ascii_digit()) != c.is_ascii_digit() {
+                    segments.push(to_segment(&current));
+                    current.clear();
+                }
+                current.push(c);
+            }
+            if !current.is_empty() {
+                segments.push(to_segment(&current));
+            }
+        }
+        if segments.is_empty() {
+            return Err(format!("Invalid version {}", version).into());
+        }
+
+        Ok(Version {
+            original: trimmed.to_string(),
+            segments,
+        })
+    }
+
+    ///
+    /// プレリリースのバージョンかを確認する
+    ///
+    pub fn is_prerelease(&self) -> bool {
+        self.segments.iter().any(|segment| matches!(segment, Segment::Text(_)))
+    }
+
+    ///
+    /// 元の文字列を取得する
+    ///
+    pub fn as_str(&self) -> &str {
+        &self.original
+    }
+
+    ///
+    /// `~>`の上限となるバージョンを取得する(例: `1.2.3` → `1.3`、`1.2` → `2`)
+    ///
+    fn bump(&self) -> Version {
+        // プレリリースの要素を除く
+        let mut segments: Vec<Segment> = self.segments.iter()
+            .take_while(|segment| matches!(segment, Segment::Number(_)))
+            .cloned()
+            .collect();
+        if segments.len() > 1 {
+            segments.pop();
+        }
+        if let Some(Segment::Number(last)) = segments.last_mut() {
+            *last += 1;
+        }
+
+        let original = segments.iter()
+            .map(|segment| match segment {
+                Segment::Number(number) => number.to_string(),
+                Segment::Text(text) => text.clone(),
+            })
+            .collect::<Vec<String>>()
+            .join(".");
+        Version { original, segments }
+    }
+}
+
+///
+/// バージョンの比較演算子
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operator {
+    /// `=`
+    Equal,
+    /// `!=`
+    NotEqual,
+    /// `>`
+    Greater,
+    /// `<`
+    Less,
+    /// `>=`
+    GreaterEqual,
+    /// `<=`
+    LessEqual,
+    /// `~>`
+    Pessimistic,
+}
+
+impl Operator {
+    ///
+    /// 演算子の文字列を取得する
+    ///
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Operator::Equal => "=",
+            Operator::NotEqual => "!=",
+            Operator::Greater => ">",
+            Operator::Less => "<",
+            Operator::GreaterEqual => ">=",
+            Operator::LessEqual => "<=",
+            Operator::Pessimistic => "~>",
+        }
+    }
+}
+
+///
+/// 演算子とバージョンの組み合わせによる1つの制約
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Constraint {
+    /// 比較演算子
+    pub operator: Operator,
+    /// 比較するバージョン
+    pub version: Version,
+}
+
+impl Constraint {
+    ///
+    /// `>= 1.0`などの文字列から制約を作成する
+    ///
+    /// 演算子が省略された場合は`=`として扱う
+    ///
+    /// * constraint - 制約の文字列
+    ///
+    /// return - 成功すると制約を返す
+    ///
+    pub fn parse(constraint: &str) -> Result<Constraint, Box<dyn Error>> {
+        let constraint = constraint.trim();
+        // 2文字の演算子を先に確認する
+        let operators = [
+            ("~>", Operator::Pessimistic),
+            (">=", Operator::GreaterEqual),
+            ("<=", Operator::LessEqual),
+            ("!=", Operator::NotEqual),
+            (">", Operator::Greater),
+            ("<", Operator::Less),
+            ("=", Operator::Equal),
+        ];
+        let (operator, version) = operators.iter()
+            .find_map(|(prefix, operator)| constraint.strip_prefix(prefix).map(|version| (*operator, version)))
+            .unwrap_or((Operator::Equal, constraint));
+
+        Ok(Constraint {
+            operator,
+            version: Version::parse(version)?,
+        })
+    }
+
+    ///
+    /// バージョンが制約を満たすかを確認する
+    ///
+    pub fn matches(&self, version: &Version) -> bool {
+        match self.operator {
+            Operator::Equal => version == &self.version,
+            Operator::NotEqual => version != &self.version,
+            Operator::Greater => version > &self.version,
+            Operator::Less => version < &self.version,
+            Operator::GreaterEqual => version >= &self.version,
+            Operator::LessEqual => version <= &self.version,
+            Operator::Pessimistic => version >= &self.version && version < &self.version.bump(),
+        }
+    }
+}
+
+impl Display for Constraint {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.operator.as_str(), self.version)
+    }
+}
+
+///
+/// すべてを満たす必要がある制約の組み合わせ
+///
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct VersionRequirement {
+    /// 制約の一覧。空の場合はすべてのバージョン(`>= 0`)を許可する
+    pub constraints: Vec<Constraint>,
+}
+
+impl VersionRequirement {
+    ///
+    /// `~> 1.0, >= 1.0.7`のようなカンマ区切りの文字列から制約を作成する
+    ///
+    /// * requirement - 制約の文字列
+    ///
+    /// return - 成功すると制約を返す
+    ///
+    pub fn parse(requirement: &str) -> Result<VersionRequirement, Box<dyn Error>> {
+        let constraints = requirement.split(',')
+            .filter(|constraint| !constraint.trim().is_empty())
+            .map(Constraint::parse)
+            .collect::<Result<Vec<Constraint>, Box<dyn Error>>>()?;
+        Ok(VersionRequirement { constraints })
+    }
+
+    ///
+    /// すべてのバージョンを許可する制約(`>= 0`)を作成する
+    ///
+    pub fn any() -> VersionRequirement {
+        VersionRequirement::default()
+    }
+
+    ///
+    /// バージョンがすべての制約を満たすかを確認する
+    ///
+    pub fn matches(&self, version: &Version) -> bool {
+        self.constraints.iter().all(|constraint| constraint.matches(version))
+    }
+
+    ///
+    /// プレリリースのバージョンを選択してよいかを確認する
+    ///
+    /// 制約にプレリリースのバージョンが含まれる場合のみ許可する
+    ///
+    pub fn allows_prerelease(&self) -> bool {
+        self.constraints.iter().any(|constraint| constraint.version.is_prerelease())
+    }
+}
+
+impl Display for VersionRequirement {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.constraints.is_empty() {
+            return write!(f, ">= 0");
+        }
+        let constraints: Vec<String> = self.constraints.iter().map(|constraint| constraint.to_string()).collect();
+        write!(f, "{}", constraints.join(", "))
+    }
+}
+
+///
+/// 文字列を要素に変換する
+///
+fn to_segment(value: &str) -> Segment {
+    match value.parse::<u64>() {
+        Ok(number) => Segment::Number(number),
+        Err(_) => Segment::Text(value.to_string()),
+    }
+}
+
+impl Display for Version {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.original)
+    }
+}
+
+impl Ord for Version {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let length = self.segments.len().max(other.segments.len());
+        for index in 0..length {
+            // 足りない要素は0として扱う
+            let left = self.segments.get(index).cloned().unwrap_or(Segment::Number(0));
+            let right = other.segments.get(index).cloned().unwrap_or(Segment::Number(0));
+            let ordering = match (left, right) {
+                (Segment::Number(left), Segment::Number(right)) => left.cmp(&right),
+                (Segment::Text(left), Segment::Text(right)) => left.cmp(&right),
+                // 文字列の要素はプレリリースのため数値より小さい
+                (Segment::Number(_), Segment::Text(_)) => Ordering::Greater,
+                (Segment::Text(_), Segment::Number(_)) => Ordering::Less,
+            };
+            if ordering != Ordering::Equal {
+                return ordering;
+            }
+        }
+        Ordering::Equal
+    }
+}
+
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Version {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Version {}
+
+#[cfg(test)]
+mod tests {
+    use crate::version::{Version, VersionRequirement};
+
+    ///
+    /// バージョンの比較のテスト
+    ///
+    #[test]
+    pub fn compare_test() {
+        let version = |value: &str| Version::parse(value).unwrap();
+
+        assert!(version("1.10.0") > version("1.9.9"));
+        assert!(version("2.0") == version("2.0.0"));
+        assert!(version("1.0.0.rc1") < version("1.0.0"));
+        assert!(version("1.0.0.beta") < version("1.0.0.rc1"));
+        assert!(version("1.0.0.rc1").is_prerelease());
+        assert!(!version("13.0.1").is_prerelease());
+        assert!(Version::parse("Concurrent::VERSION").is_err());
+    }
+
+    ///
+    /// バージョンの制約のテスト
+    ///
+    #[test]
+    pub fn requirement_test() {
+        let version = |value: &str| Version::parse(value).unwrap();
+        let requirement = |value: &str| VersionRequirement::parse(value).unwrap();
+
+        // ~> は最後の要素を除いた範囲で許可する
+        assert!(requirement("~> 1.8.5").matches(&version("1.8.9")));
+        assert!(!requirement("~> 1.8.5").matches(&version("1.9.0")));
+        assert!(!requirement("~> 1.8.5").matches(&version("1.8.4")));
+        assert!(requirement("~> 1.0").matches(&version("1.9.9")));
+        assert!(!requirement("~> 1.0").matches(&version("2.0.0")));
+
+        // 複数の制約はすべて満たす必要がある
+        assert!(requirement("~> 1.0, >= 1.0.7").matches(&version("1.0.7")));
+        assert!(!requirement("~> 1.0, >= 1.0.7").matches(&version("1.0.6")));
+        assert!(requirement("!= 1.1.0").matches(&version("1.2.0")));
+        assert!(!requirement("!= 1.1.0").matches(&version("1.1")));
+        assert!(requirement("1.2.3").matches(&version("1.2.3")));
+        assert!(VersionRequirement::any().matches(&version("0.0.1")));
+
+        // プレリリースの許可
+        assert!(!requirement(">= 1.0").allows_prerelease());
+        assert!(requirement(">= 2.0.0.rc1").allows_prerelease());
+        assert_eq!(requirement("~>1.0,>= 1.0.7").to_string(), "~> 1.0, >= 1.0.7");
+    }
+}
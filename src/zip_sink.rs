@@ -0,0 +1,584 @@
+//!
+//! 解凍したGemの内容をzipファイルに書き込みます
+//!
+use std::error::Error;
+use std::fs::{create_dir_all, File};
+use std::io::{copy, BufWriter, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use flate2::read::MultiGzDecoder;
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+use tar::{Archive, EntryType};
+use crate::cleanup::{CleanupGuard, PART_EXTENSION};
+use crate::error::GemfileError;
+use crate::options::{InstallOptions, DEFAULT_GEMFILE_NAMES, DEFAULT_MAX_ENTRIES};
+use crate::unpack_tar_gz::check_entry_path;
+
+/// zipファイルの拡張子
+pub const ZIP_EXTENSION: &str = "zip";
+
+/// ローカルファイルヘッダーのシグネチャ
+const LOCAL_HEADER_SIGNATURE: u32 = 0x04034b50;
+/// データ記述子のシグネチャ
+const DATA_DESCRIPTOR_SIGNATURE: u32 = 0x08074b50;
+/// セントラルディレクトリのシグネチャ
+const CENTRAL_HEADER_SIGNATURE: u32 = 0x02014b50;
+/// セントラルディレクトリの終端のシグネチャ
+const END_OF_CENTRAL_SIGNATURE: u32 = 0x06054b50;
+/// 展開に必要なバージョン(2.0)
+const VERSION_NEEDED: u16 = 20;
+/// 作成したシステム(UNIX)とバージョン。外部属性にパーミッションを保存するために使用する
+const VERSION_MADE_BY: u16 = (3 << 8) | VERSION_NEEDED;
+/// CRCとサイズをデータの後のデータ記述子に書き込むことを示すフラグ
+const FLAG_DATA_DESCRIPTOR: u16 = 0x0008;
+/// ファイル名がUTF-8であることを示すフラグ
+const FLAG_UTF8: u16 = 0x0800;
+/// Deflateによる圧縮
+const METHOD_DEFLATED: u16 = 8;
+/// 更新日(1980-01-01)
+const DOS_DATE: u16 = (1 << 5) | 1;
+/// 通常のファイルを示すモード
+const MODE_FILE: u32 = 0o100000;
+/// ディレクトリを示すモード
+const MODE_DIRECTORY: u32 = 0o040000;
+/// シンボリックリンクを示すモード
+const MODE_SYMLINK: u32 = 0o120000;
+
+///
+/// セントラルディレクトリに書き込むエントリの情報
+///
+struct CentralEntry {
+    name: String,
+    crc: u32,
+    compressed_size: u32,
+    size: u32,
+    mode: u32,
+    offset: u32,
+}
+
+///
+/// tarのエントリを書き込むzipファイル
+///
+/// 1つのGemごとに作成するか、`prefix`を指定して複数のGemを1つのzipファイルにまとめる。
+/// 各エントリはメモリに読み込まずに圧縮しながら書き込み、CRCとサイズはデータ記述子に書き込む
+///
+pub struct ZipSink<W: Write> {
+    writer: W,
+    offset: u64,
+    entries: Vec<CentralEntry>,
+    /// 1つのtarから書き込むエントリの数の上限
+    max_entries: Option<usize>,
+    /// Gemfileとして扱うファイル名の一覧
+    gemfile_names: Vec<String>,
+}
+
+impl ZipSink<BufWriter<File>> {
+    ///
+    /// zipファイルを作成する
+    ///
+    /// * path - 作成するzipファイルのパス
+    ///
+    /// return - 作成したZipSink
+    ///
+    pub fn create(path: &Path) -> Result<ZipSink<BufWriter<File>>, Box<dyn Error>> {
+        if let Some(parent) = path.parent() {
+            create_dir_all(parent)?;
+        }
+        Ok(ZipSink::new(BufWriter::new(File::create(path)?)))
+    }
+}
+
+impl<W: Write> ZipSink<W> {
+    ///
+    /// 書き込み先を指定してZipSinkを作成する
+    ///
+    /// * writer - zipの書き込み先
+    ///
+    pub fn new(writer: W) -> ZipSink<W> {
+        ZipSink {
+            writer,
+            offset: 0,
+            entries: Vec::new(),
+            max_entries: Some(DEFAULT_MAX_ENTRIES),
+            gemfile_names: DEFAULT_GEMFILE_NAMES.iter().map(|name| name.to_string()).collect(),
+        }
+    }
+
+    ///
+    /// 展開と同じエントリの数の上限とGemfileのファイル名を使用する
+    ///
+    /// * options - インストール処理のオプション(`max_entries`、`gemfile_names`を使用する)
+    ///
+    /// return - 設定したZipSink
+    ///
+    pub fn with_options(mut self, options: &InstallOptions) -> ZipSink<W> {
+        self.max_entries = options.max_entries;
+        self.gemfile_names = options.gemfile_names.clone();
+        self
+    }
+
+    ///
+    /// .tar.gzファイルのすべてのエントリを書き込む
+    ///
+    /// * tar_gz_path - .tar.gzファイルのパス
+    /// * prefix - エントリのパスの前に付けるディレクトリ名(例: Gemの名前)
+    ///
+    /// return - 書き込み処理の結果で、Gemfileが含まれている場合はzip内のパスを返す
+    ///
+    pub fn add_tar_gz(&mut self, tar_gz_path: &Path, prefix: Option<&str>) -> Result<Option<String>, Box<dyn Error>> {
+        let decoder = MultiGzDecoder::new(File::open(tar_gz_path)?);
+        self.add_tar(decoder, prefix)
+    }
+
+    ///
+    /// tarのすべてのエントリを書き込む
+    ///
+    /// 展開する場合と同じく、展開先の外を指すエントリとエントリの数の上限を超えるtarはエラーにする
+    ///
+    /// * reader - tarの読み込み元
+    /// * prefix - エントリのパスの前に付けるディレクトリ名
+    ///
+    /// return - 書き込み処理の結果で、Gemfileが含まれている場合はzip内のパスを返す
+    ///
+    pub fn add_tar<R: Read>(&mut self, reader: R, prefix: Option<&str>) -> Result<Option<String>, Box<dyn Error>> {
+        let mut entry_gemfile = None;
+        let mut archive = Archive::new(reader);
+
+        for (index, entry) in archive.entries()?.enumerate() {
+            // 大量の小さなファイルを含むtarを書き込まないよう、エントリの数を制限する
+            if let Some(limit) = self.max_entries {
+                if index >= limit {
+                    return Err(Box::new(GemfileError::EntryLimitExceeded { limit }));
+                }
+            }
+            let mut entry = entry?;
+            let entry_path = entry.path()?.to_path_buf();
+            check_entry_path(&entry_path)?;
+            let path = entry_path.to_string_lossy().trim_end_matches('/').to_string();
+            let name = match prefix {
+                Some(prefix) => format!("{}/{}", prefix.trim_end_matches('/'), path),
+                None => path,
+            };
+            let permissions = entry.header().mode()? & 0o7777;
+
+            match entry.header().entry_type() {
+                EntryType::Directory => self.add_directory(&name, permissions)?,
+                EntryType::Symlink => {
+                    let Some(target) = entry.link_name()? else {
+                        continue;
+                    };
+                    check_link_target(&entry_path, &target)?;
+                    let target = target.to_string_lossy().to_string();
+                    self.add_entry(&name, MODE_SYMLINK | permissions, &mut target.as_bytes())?;
+                }
+                EntryType::Regular | EntryType::Continuous => {
+                    self.add_entry(&name, MODE_FILE | permissions, &mut entry)?;
+
+                    // Gemfileの場合パスを保管
+                    if name.rsplit('/').next().is_some_and(|file_name| self.gemfile_names.iter().any(|gemfile_name| gemfile_name == file_name)) {
+                        entry_gemfile = Some(name);
+                    }
+                }
+                // その他の種類(デバイスファイルなど)は書き込まない
+                _ => {}
+            }
+        }
+
+        Ok(entry_gemfile)
+    }
+
+    ///
+    /// ファイルを書き込む
+    ///
+    /// * name - zip内のパス
+    /// * permissions - パーミッション(例: `0o644`)
+    /// * content - ファイルの内容
+    ///
+    pub fn add_file(&mut self, name: &str, permissions: u32, content: &[u8]) -> Result<(), Box<dyn Error>> {
+        self.add_entry(name, MODE_FILE | permissions, &mut &content[..])
+    }
+
+    ///
+    /// ディレクトリを書き込む
+    ///
+    /// * name - zip内のパス
+    /// * permissions - パーミッション(例: `0o755`)
+    ///
+    pub fn add_directory(&mut self, name: &str, permissions: u32) -> Result<(), Box<dyn Error>> {
+        self.add_entry(&format!("{}/", name.trim_end_matches('/')), MODE_DIRECTORY | permissions, &mut &[][..])
+    }
+
+    ///
+    /// セントラルディレクトリを書き込み、zipファイルを完成させる
+    ///
+    /// return - 書き込み先
+    ///
+    pub fn finish(mut self) -> Result<W, Box<dyn Error>> {
+        let central_offset = to_u32(self.offset)?;
+        let mut central = Vec::new();
+        for entry in &self.entries {
+            central.extend_from_slice(&CENTRAL_HEADER_SIGNATURE.to_le_bytes());
+            central.extend_from_slice(&VERSION_MADE_BY.to_le_bytes());
+            central.extend_from_slice(&VERSION_NEEDED.to_le_bytes());
+            central.extend_from_slice(&(FLAG_UTF8 | FLAG_DATA_DESCRIPTOR).to_le_bytes());
+            central.extend_from_slice(&METHOD_DEFLATED.to_le_bytes());
+            central.extend_from_slice(&0u16.to_le_bytes());
+            central.extend_from_slice(&DOS_DATE.to_le_bytes());
+            central.extend_from_slice(&entry.crc.to_le_bytes());
+            central.extend_from_slice(&entry.compressed_size.to_le_bytes());
+            central.extend_from_slice(&entry.size.to_le_bytes());
+            central.extend_from_slice(&name_length(&entry.name)?.to_le_bytes());
+            // 拡張フィールド、コメント、ディスク番号、内部属性
+            central.extend_from_slice(&[0; 8]);
+            // 外部属性の上位16ビットにUNIXのモードを保存
+            let directory_flag = if entry.mode & MODE_DIRECTORY == MODE_DIRECTORY && entry.mode & MODE_FILE == 0 { 0x10 } else { 0 };
+            central.extend_from_slice(&((entry.mode << 16) | directory_flag).to_le_bytes());
+            central.extend_from_slice(&entry.offset.to_le_bytes());
+            central.extend_from_slice(entry.name.as_bytes());
+        }
+
+        let count = u16::try_from(self.entries.len()).map_err(|_| "Too many entries for zip")?;
+        let mut end = Vec::new();
+        end.extend_from_slice(&END_OF_CENTRAL_SIGNATURE.to_le_bytes());
+        end.extend_from_slice(&[0; 4]);
+        end.extend_from_slice(&count.to_le_bytes());
+        end.extend_from_slice(&count.to_le_bytes());
+        end.extend_from_slice(&to_u32(central.len() as u64)?.to_le_bytes());
+        end.extend_from_slice(&central_offset.to_le_bytes());
+        end.extend_from_slice(&0u16.to_le_bytes());
+
+        self.writer.write_all(&central)?;
+        self.writer.write_all(&end)?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    ///
+    /// ローカルファイルヘッダー、圧縮したデータ、データ記述子を書き込む
+    ///
+    /// 内容は読み込みながら圧縮して書き込み、CRCとサイズは書き込んだ後にデータ記述子に記録する
+    ///
+    fn add_entry(&mut self, name: &str, mode: u32, content: &mut impl Read) -> Result<(), Box<dyn Error>> {
+        let offset = to_u32(self.offset)?;
+        let mut header = Vec::new();
+        header.extend_from_slice(&LOCAL_HEADER_SIGNATURE.to_le_bytes());
+        header.extend_from_slice(&VERSION_NEEDED.to_le_bytes());
+        header.extend_from_slice(&(FLAG_UTF8 | FLAG_DATA_DESCRIPTOR).to_le_bytes());
+        header.extend_from_slice(&METHOD_DEFLATED.to_le_bytes());
+        header.extend_from_slice(&0u16.to_le_bytes());
+        header.extend_from_slice(&DOS_DATE.to_le_bytes());
+        // CRCとサイズはデータ記述子に書き込む
+        header.extend_from_slice(&[0; 12]);
+        header.extend_from_slice(&name_length(name)?.to_le_bytes());
+        header.extend_from_slice(&0u16.to_le_bytes());
+        header.extend_from_slice(name.as_bytes());
+        self.writer.write_all(&header)?;
+
+        // 読み込んだ内容のCRCとサイズを計算しながら圧縮する
+        let mut reader = HashingReader { reader: content, hasher: crc32fast::Hasher::new(), size: 0 };
+        let mut encoder = DeflateEncoder::new(CountingWriter { writer: &mut self.writer, count: 0 }, Compression::default());
+        copy(&mut reader, &mut encoder)?;
+        let compressed_size = encoder.finish()?.count;
+        let entry = CentralEntry {
+            name: name.to_string(),
+            crc: reader.hasher.finalize(),
+            compressed_size: to_u32(compressed_size)?,
+            size: to_u32(reader.size)?,
+            mode,
+            offset,
+        };
+
+        let mut descriptor = Vec::new();
+        descriptor.extend_from_slice(&DATA_DESCRIPTOR_SIGNATURE.to_le_bytes());
+        descriptor.extend_from_slice(&entry.crc.to_le_bytes());
+        descriptor.extend_from_slice(&entry.compressed_size.to_le_bytes());
+        descriptor.extend_from_slice(&entry.size.to_le_bytes());
+        self.writer.write_all(&descriptor)?;
+
+        self.offset += header.len() as u64 + compressed_size + descriptor.len() as u64;
+        self.entries.push(entry);
+        Ok(())
+    }
+}
+
+///
+/// 読み込んだ内容のCRCとサイズを計算するReader
+///
+struct HashingReader<'a, R: Read> {
+    reader: &'a mut R,
+    hasher: crc32fast::Hasher,
+    size: u64,
+}
+
+impl<R: Read> Read for HashingReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let length = self.reader.read(buf)?;
+        self.hasher.update(&buf[..length]);
+        self.size += length as u64;
+        Ok(length)
+    }
+}
+
+///
+/// 書き込んだバイト数を数えるWriter
+///
+struct CountingWriter<'a, W: Write> {
+    writer: &'a mut W,
+    count: u64,
+}
+
+impl<W: Write> Write for CountingWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let length = self.writer.write(buf)?;
+        self.count += length as u64;
+        Ok(length)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+///
+/// .tar.gzファイルを展開せずにzipファイルに書き込む
+///
+/// 書き込みが完了するまでは一時ファイルに書き込み、失敗した場合は途中のzipファイルを残さない
+///
+/// * tar_gz_path - .tar.gzファイルのパス
+/// * zip_path - 作成するzipファイルのパス
+/// * options - インストール処理のオプション(`max_entries`、`gemfile_names`を使用する)
+///
+/// return - 書き込み処理の結果で、Gemfileが含まれている場合はzip内のパスを返す
+///
+pub fn write_tar_gz_zip(tar_gz_path: &Path, zip_path: &Path, options: &InstallOptions) -> Result<Option<String>, Box<dyn Error>> {
+    let mut part_path = zip_path.as_os_str().to_owned();
+    part_path.push(format!(".{}", PART_EXTENSION));
+    let part = CleanupGuard::new(PathBuf::from(part_path));
+    let mut sink = ZipSink::create(part.path())?.with_options(options);
+    let gemfile = sink.add_tar_gz(tar_gz_path, None)?;
+    sink.finish()?;
+    part.persist(zip_path)?;
+    Ok(gemfile)
+}
+
+///
+/// Gemの展開先のディレクトリに対応するzipファイルのパスを取得する
+///
+/// * directory - Gemの展開先のディレクトリ
+///
+/// return - `{directory}.zip`のパス
+///
+pub fn zip_path(directory: &Path) -> PathBuf {
+    let mut path = directory.as_os_str().to_owned();
+    path.push(format!(".{}", ZIP_EXTENSION));
+    PathBuf::from(path)
+}
+
+///
+/// zip内のパスの長さを確認する
+///
+/// * name - zip内のパス
+///
+/// return - 65535バイトを超える場合はエラー
+///
+fn name_length(name: &str) -> Result<u16, Box<dyn Error>> {
+    u16::try_from(name.len()).map_err(|_| format!("Entry name of {} bytes is too long for zip", name.len()).into())
+}
+
+///
+/// シンボリックリンクのリンク先がzipの外を指していないかを確認する
+///
+/// * entry - tar内のシンボリックリンクのパス
+/// * target - リンク先のパス
+///
+/// return - 絶対パスや、リンクの置かれたディレクトリから`..`で外に出る場合はエラー
+///
+fn check_link_target(entry: &Path, target: &Path) -> Result<(), Box<dyn Error>> {
+    let traversal = || Box::new(GemfileError::PathTraversal { entry: entry.display().to_string() });
+    // リンクが置かれたディレクトリの深さから、リンク先をたどる
+    let mut depth = entry.components().filter(|component| matches!(component, Component::Normal(_))).count() as isize - 1;
+    for component in target.components() {
+        match component {
+            Component::Normal(_) => depth += 1,
+            Component::CurDir => {}
+            Component::ParentDir => depth -= 1,
+            Component::RootDir | Component::Prefix(_) => return Err(traversal()),
+        }
+        if depth < 0 {
+            return Err(traversal());
+        }
+    }
+    Ok(())
+}
+
+///
+/// zip64に対応していないため、4GBを超える場合はエラーにする
+///
+fn to_u32(value: u64) -> Result<u32, Box<dyn Error>> {
+    u32::try_from(value).map_err(|_| "Zip archive larger than 4GB is not supported".into())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    use flate2::read::{DeflateDecoder, MultiGzDecoder};
+    use tar::{Archive, Builder, EntryType, Header};
+    use crate::error::GemfileError;
+    use crate::install_gems_with_options;
+    use crate::options::InstallOptions;
+    use crate::parser::{Gem, GemfileData};
+    use crate::test_util::{build_tar, test_directory, GemBuilder, MockResponse, MockServer};
+    use crate::unpack_gem::unpack_gem;
+    use crate::zip_sink::ZipSink;
+
+    ///
+    /// zipのセントラルディレクトリからエントリの名前、モード、内容を読み込む
+    ///
+    fn read_zip(zip: &[u8]) -> Vec<(String, u32, Vec<u8>)> {
+        let u16_at = |offset: usize| u16::from_le_bytes([zip[offset], zip[offset + 1]]) as usize;
+        let u32_at = |offset: usize| u32::from_le_bytes(zip[offset..offset + 4].try_into().unwrap()) as usize;
+
+        let end = zip.len() - 22;
+        assert_eq!(u32_at(end), 0x06054b50);
+        let mut offset = u32_at(end + 16);
+        let mut entries = Vec::new();
+        for _ in 0..u16_at(end + 10) {
+            let name_length = u16_at(offset + 28);
+            let name = String::from_utf8(zip[offset + 46..offset + 46 + name_length].to_vec()).unwrap();
+            let mode = (u32_at(offset + 38) >> 16) as u32;
+            let local = u32_at(offset + 42);
+            let data_start = local + 30 + u16_at(local + 26) + u16_at(local + 28);
+            let data = &zip[data_start..data_start + u32_at(offset + 20)];
+            let mut content = Vec::new();
+            match u16_at(offset + 10) {
+                8 => { DeflateDecoder::new(data).read_to_end(&mut content).unwrap(); }
+                _ => content.extend_from_slice(data),
+            }
+            entries.push((name, mode, content));
+            offset += 46 + name_length + u16_at(offset + 30) + u16_at(offset + 32);
+        }
+        entries
+    }
+
+    ///
+    /// Gemの内容をzipに書き込むテスト
+    ///
+    #[test]
+    pub fn zip_sink_test() {
+        let directory = test_directory("zip_sink");
+        let gem_path = GemBuilder::new("zipped", "1.0.0")
+            .file("bin/zipped", b"#!/usr/bin/env ruby\n")
+            .file("Gemfile", b"source 'https://rubygems.org'\n")
+            .write(&directory);
+        let data_path = unpack_gem(&gem_path, &directory.join("cache")).unwrap();
+
+        // zipに書き込み
+        let zip_path = directory.join("zipped-1.0.0.zip");
+        let mut sink = ZipSink::create(&zip_path).unwrap();
+        let gemfile = sink.add_tar_gz(&data_path, None).unwrap();
+        sink.finish().unwrap();
+        assert_eq!(gemfile.as_deref(), Some("Gemfile"));
+
+        // tarのエントリと一致するか
+        let mut archive = Archive::new(MultiGzDecoder::new(std::fs::File::open(&data_path).unwrap()));
+        let tar_entries: Vec<(String, u32, Vec<u8>)> = archive.entries().unwrap().map(|entry| {
+            let mut entry = entry.unwrap();
+            let name = entry.path().unwrap().to_string_lossy().to_string();
+            let mode = entry.header().mode().unwrap();
+            let mut content = Vec::new();
+            entry.read_to_end(&mut content).unwrap();
+            (name, 0o100000 | mode, content)
+        }).collect();
+        let zip_entries = read_zip(&std::fs::read(&zip_path).unwrap());
+        assert_eq!(zip_entries, tar_entries);
+
+        // 実行権限が保持されるか
+        let mut builder = Builder::new(Vec::new());
+        let mut header = Header::new_gnu();
+        header.set_size(3);
+        header.set_mode(0o755);
+        header.set_cksum();
+        builder.append_data(&mut header, "exe/run", &b"run"[..]).unwrap();
+        let mut sink = ZipSink::new(Vec::new());
+        sink.add_tar(builder.into_inner().unwrap().as_slice(), None).unwrap();
+        assert_eq!(read_zip(&sink.finish().unwrap()), vec![("exe/run".to_string(), 0o100755, b"run".to_vec())]);
+
+        // 複数のGemを1つのzipにまとめられるか
+        let mut sink = ZipSink::new(Vec::new());
+        sink.add_tar(build_tar(&[("a.rb".to_string(), b"a".to_vec())]).as_slice(), Some("first")).unwrap();
+        sink.add_tar(build_tar(&[("b.rb".to_string(), b"b".to_vec())]).as_slice(), Some("second")).unwrap();
+        let names: Vec<String> = read_zip(&sink.finish().unwrap()).into_iter().map(|(name, _, _)| name).collect();
+        assert_eq!(names, vec!["first/a.rb", "second/b.rb"]);
+    }
+
+    ///
+    /// 展開と同じ制限でエントリを確認するテスト
+    ///
+    #[test]
+    pub fn zip_sink_limits_test() {
+        // 展開先の外を指すエントリを拒否するか
+        let mut builder = Builder::new(Vec::new());
+        let mut header = Header::new_gnu();
+        let name = b"../escaped.rb";
+        header.as_gnu_mut().unwrap().name[..name.len()].copy_from_slice(name);
+        header.set_size(1);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder.append(&header, &b"x"[..]).unwrap();
+        let error = ZipSink::new(Vec::new()).add_tar(builder.into_inner().unwrap().as_slice(), None).unwrap_err();
+        assert!(matches!(error.downcast_ref::<GemfileError>(), Some(GemfileError::PathTraversal { .. })), "{}", error);
+
+        // zipの外を指すシンボリックリンクを拒否するか
+        let mut builder = Builder::new(Vec::new());
+        let mut header = Header::new_gnu();
+        header.set_entry_type(EntryType::Symlink);
+        header.set_size(0);
+        header.set_mode(0o777);
+        builder.append_link(&mut header, "lib/link", "../../etc/passwd").unwrap();
+        let error = ZipSink::new(Vec::new()).add_tar(builder.into_inner().unwrap().as_slice(), None).unwrap_err();
+        assert!(matches!(error.downcast_ref::<GemfileError>(), Some(GemfileError::PathTraversal { .. })), "{}", error);
+
+        // エントリの数の上限を超えた場合はエラーになるか
+        let entries: Vec<(String, Vec<u8>)> = (0..20).map(|index| (format!("lib/file{}.rb", index), Vec::new())).collect();
+        let options = InstallOptions { max_entries: Some(10), ..Default::default() };
+        let error = ZipSink::new(Vec::new()).with_options(&options).add_tar(build_tar(&entries).as_slice(), None).unwrap_err();
+        assert!(matches!(error.downcast_ref::<GemfileError>(), Some(GemfileError::EntryLimitExceeded { limit: 10 })), "{}", error);
+
+        // 65535バイトを超える名前は切り詰めずにエラーになるか
+        let mut sink = ZipSink::new(Vec::new());
+        assert!(sink.add_file(&"a".repeat(70_000), 0o644, b"long").is_err());
+
+        // Gemfileとして扱うファイル名の設定が使用されるか
+        let tar = build_tar(&[("gems.rb".to_string(), b"gem 'rake'\n".to_vec())]);
+        assert_eq!(ZipSink::new(Vec::new()).add_tar(tar.as_slice(), None).unwrap().as_deref(), Some("gems.rb"));
+        let options = InstallOptions { gemfile_names: vec!["Gemfile".to_string()], ..Default::default() };
+        assert_eq!(ZipSink::new(Vec::new()).with_options(&options).add_tar(tar.as_slice(), None).unwrap(), None);
+    }
+
+    ///
+    /// インストールでGemをzipファイルに書き込むテスト
+    ///
+    #[tokio::test]
+    pub async fn zip_output_install_test() {
+        let directory = test_directory("zip_output_install");
+        let body = GemBuilder::new("zipped", "1.0.0").file("lib/zipped/extra.rb", b"module Zipped; end\n").build();
+        let server = MockServer::start(move |request| match request.path.as_str() {
+            "/downloads/zipped-1.0.0.gem" => MockResponse::new(200, body.clone()),
+            _ => MockResponse::not_found(),
+        }).await;
+        let gemfile_data = GemfileData {
+            source: server.url.clone(),
+            gems: vec![Gem { name: "zipped".to_string(), version: "1.0.0".to_string(), ..Default::default() }],
+            ..Default::default()
+        };
+        let options = InstallOptions::default().allow_insecure(true).verify_checksums(false).zip_output(true);
+        let info = install_gems_with_options(gemfile_data, &directory.join("gems"), &directory.join("cache"), &options).await.unwrap();
+
+        // 展開せずにzipファイルに書き込まれるか
+        let zip_path = directory.join("gems/zipped-1.0.0.zip");
+        assert_eq!(info.installed[0].install_path, zip_path);
+        assert!(!directory.join("gems/zipped-1.0.0").exists());
+        let entries = read_zip(&std::fs::read(&zip_path).unwrap());
+        assert!(entries.iter().any(|(name, _, content)| name == "lib/zipped/extra.rb" && content == b"module Zipped; end\n"));
+    }
+}